@@ -0,0 +1,110 @@
+//! Benchmarks of the emulation core
+//!
+//! Run with `cargo bench`, optionally followed by `-- <filter>`
+//! to run only benchmarks with names containing the filter.
+
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use gbrust::gameboy::bus::testbus::Testbus;
+use gbrust::gameboy::cpu::cpu::CPU;
+use gbrust::gameboy::cpu::instruction::Instruction;
+use gbrust::gameboy::emulator::Emulator;
+use gbrust::gameboy::model::Model;
+use gbrust::gameboy::ppu::{Renderer, CYCLES_PER_FRAME};
+
+/// Samples taken per benchmark, of which the median is
+/// reported
+const SAMPLES: usize = 11;
+
+/// Fixed instruction mix, looping forever (as in cpubench).
+const CODE: &[u8] = &[
+    0x06, 0x00, // 0000: LD B,$00
+    0x3C, // 0002: INC A
+    0x80, // 0003: ADD A,B
+    0xA9, // 0004: XOR C
+    0xC5, // 0005: PUSH BC
+    0xD1, // 0006: POP DE
+    0xCD, 0x10, 0x00, // 0007: CALL $0010
+    0x05, // 000A: DEC B
+    0x20, 0xF5, // 000B: JR NZ,$0002
+    0x18, 0xF1, // 000D: JR $0000
+    0x00, // 000F: NOP
+    0xFE, 0x10, // 0010: CP $10
+    0xC9, // 0012: RET
+];
+
+/// Cartridge running CODE, at the start of ROM, with the
+/// LCD on as the boot ROM leaves it.
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 32 * 1024];
+    rom[..CODE.len()].copy_from_slice(CODE);
+    // JP $0000
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x00, 0x00]);
+    rom
+}
+
+/// Times 'iters' runs of 'f' SAMPLES times and prints the
+/// median time per run, if 'name' passes the filter.
+fn bench(filter: &Option<String>, name: &str, unit: &str, iters: u32, mut f: impl FnMut()) {
+    if filter.as_ref().is_some_and(|f| !name.contains(f.as_str())) {
+        return;
+    }
+
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..iters {
+                f();
+            }
+            start.elapsed() / iters
+        })
+        .collect();
+    samples.sort();
+    let median = samples[SAMPLES / 2];
+    println!(
+        "{:<16} {:>12.3} us/{}",
+        name,
+        median.as_secs_f64() * 1e6,
+        unit
+    );
+}
+
+fn main() {
+    // Cargo passes --bench, anything else is a filter
+    let filter = env::args().skip(1).find(|a| !a.starts_with('-'));
+
+    bench(&filter, "decode", "256 instrs", 1000, || {
+        for op in 0..=0xFF_u8 {
+            let bytes = [op, 0x34, 0x12];
+            let _ = black_box(Instruction::decode(&mut bytes.into_iter(), 0));
+        }
+    });
+
+    let mut cpu = CPU::new(Box::new(Testbus::from(CODE)));
+    cpu.regs.sp = 0xFFFE;
+    bench(&filter, "cpu_dispatch", "10k instrs", 100, || {
+        for _ in 0..10_000 {
+            cpu.step().unwrap();
+        }
+    });
+
+    let rom = rom();
+    for (name, model, renderer) in [
+        ("frame_dmg", Model::Dmg, Renderer::Scanline),
+        ("frame_cgb", Model::Cgb, Renderer::Scanline),
+        ("frame_dmg_fifo", Model::Dmg, Renderer::Fifo),
+    ] {
+        let mut emu = Emulator::with_model(&rom, None, model).unwrap();
+        emu.set_renderer(renderer);
+        bench(&filter, name, "frame", 20, || {
+            black_box(emu.run_frame().unwrap());
+        });
+    }
+
+    let mut emu = Emulator::with_model(&rom, None, Model::Dmg).unwrap();
+    bench(&filter, "run_cycles", "frame", 20, || {
+        black_box(emu.run_cycles(CYCLES_PER_FRAME).unwrap());
+    });
+}
@@ -0,0 +1,52 @@
+use std::env;
+use std::time::Instant;
+
+use anyhow::Result;
+
+use gbrust::gameboy::bus::testbus::Testbus;
+use gbrust::gameboy::cpu::cpu::CPU;
+
+/// Fixed instruction mix, looping forever.
+const CODE: &[u8] = &[
+    0x06, 0x00, // 0000: LD B,$00
+    0x3C, // 0002: INC A
+    0x80, // 0003: ADD A,B
+    0xA9, // 0004: XOR C
+    0xC5, // 0005: PUSH BC
+    0xD1, // 0006: POP DE
+    0xCD, 0x10, 0x00, // 0007: CALL $0010
+    0x05, // 000A: DEC B
+    0x20, 0xF5, // 000B: JR NZ,$0002
+    0x18, 0xF1, // 000D: JR $0000
+    0x00, // 000F: NOP
+    0xFE, 0x10, // 0010: CP $10
+    0xC9, // 0012: RET
+];
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let count: usize = match args.get(1) {
+        Some(s) => s.parse()?,
+        None => 10_000_000,
+    };
+
+    println!(" --- Raw CPU benchmark --- ");
+
+    let mut cpu = CPU::new(Box::new(Testbus::from(CODE)));
+    cpu.regs.sp = 0xFFFE;
+
+    let start = Instant::now();
+    for _ in 0..count {
+        cpu.step()?;
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} instructions, {} cycles in {:.3}s: {:.0} instructions/s",
+        count,
+        cpu.get_cycles(),
+        elapsed.as_secs_f64(),
+        count as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
@@ -1,26 +1,58 @@
 use std::fs;
-use std::io::{stdin, Read};
+use std::io::{stdin, stdout, BufRead, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 
+use gbrust::gameboy::apu::CPU_CLOCK;
 use gbrust::gameboy::bus::bus::Bus;
 use gbrust::gameboy::bus::gbbus::Gameboybus;
+use gbrust::gameboy::bus::hookbus::HookBus;
 use gbrust::gameboy::bus::testbus::Testbus;
-use gbrust::gameboy::cpu::cpu::CPU;
+use gbrust::gameboy::cartridge::battery::BatterySave;
+use gbrust::gameboy::cartridge::header::{CartridgeHeader, Validation};
+use gbrust::gameboy::config::Config;
+use gbrust::gameboy::cpu::cpu::{TraceFormat, CPU};
+use gbrust::gameboy::debugger::gdb::GdbStub;
+use gbrust::gameboy::debugger::monitor::Monitor;
+use gbrust::gameboy::emulator::Emulator;
+use gbrust::gameboy::error::FaultPolicy;
+use gbrust::gameboy::link::tcp::TcpLink;
+use gbrust::gameboy::model::Model;
+use gbrust::gameboy::ppu::{Renderer, TileData, TileMap, CYCLES_PER_FRAME, LCD_HEIGHT, LCD_WIDTH};
+use gbrust::gameboy::screenshot::{save_image, write_png};
+use gbrust::gameboy::serial::StdoutSerial;
+use gbrust::gameboy::testrunner::{TestResult, TestRunner};
+use gbrust::gameboy::video::{Palette, PostProcess};
+use gbrust::gameboy::wav::AudioRecorder;
 
 #[derive(Parser)]
 #[command(
     about = "A crude ROM debugger for development purposes",
     long_about = None)]
 struct Args {
-    /// ROM filename to load.
-    filename: String,
+    /// ROM filename to load, defaults to the ROM in the
+    /// configuration file.
+    filename: Option<String>,
 
-    /// Boot ROM to optionally load
+    /// Configuration file to load instead of the default
+    /// (~/.config/gbrust/config.toml). Options given on the
+    /// command line take precedence over the file.
+    #[arg(short, long)]
+    config: Option<String>,
+
+    /// Boot ROM to optionally load. Without one, execution
+    /// starts at 0x0100 in the post-boot state.
     #[arg(short, long)]
     bootrom: Option<String>,
 
+    /// Let the boot ROM start cartridges without a valid
+    /// logo or header checksum (e.g. homebrew).
+    #[arg(long)]
+    lenient_boot: bool,
+
     /// Wait for keystroke after each CPU step.
     #[arg(short, long)]
     pause: bool,
@@ -28,32 +60,251 @@ struct Args {
     /// Use testing address bus
     #[arg(short, long)]
     testbus: bool,
+
+    /// Only print a Gameboy Doctor trace log.
+    #[arg(short, long)]
+    doctor: bool,
+
+    /// Only print a trace log in this format (doctor or
+    /// full).
+    #[arg(long)]
+    trace: Option<TraceFormat>,
+
+    /// What to do when the CPU faults (e.g. on an invalid
+    /// opcode): stop, or skip the instruction and log the fault.
+    #[arg(long, default_value = "stop")]
+    on_fault: FaultPolicy,
+
+    /// Print serial port output to stdout.
+    #[arg(short, long)]
+    serial: bool,
+
+    /// Link cable over TCP: wait for a partner on this
+    /// address (e.g. 0.0.0.0:5000).
+    #[arg(long)]
+    link_listen: Option<String>,
+
+    /// Link cable over TCP: connect to a partner at this
+    /// address.
+    #[arg(long)]
+    link_connect: Option<String>,
+
+    /// Cheat file to load: one Game Genie or GameShark code
+    /// per line. GameShark codes are written once per frame,
+    /// so they only take effect in headless mode.
+    #[arg(long)]
+    cheats: Option<String>,
+
+    /// Directory for battery save (.sav) files,
+    /// defaults to the directory of the ROM.
+    #[arg(long)]
+    save_dir: Option<String>,
+
+    /// How to treat a ROM with header problems (e.g. a bad
+    /// checksum or a truncated image): strict refuses to
+    /// load it, lenient prints warnings.
+    #[arg(long, default_value = "lenient")]
+    validate: Validation,
+
+    /// Hardware model (dmg or cgb), detected from the
+    /// cartridge header by default.
+    #[arg(short, long)]
+    model: Option<Model>,
+
+    /// Wait for a GDB connection on this address
+    /// (e.g. localhost:2345) and let it control execution.
+    #[arg(long)]
+    gdb: Option<String>,
+
+    /// Interactive monitor: breakpoints, watchpoints,
+    /// stepping and memory inspection from the console.
+    #[arg(long)]
+    monitor: bool,
+
+    /// Run a test ROM without a frontend, then print the
+    /// serial output and framebuffer hash. Exits with 0 on
+    /// pass, 1 on failure and 2 if no result was reported.
+    #[arg(long, conflicts_with = "gdb")]
+    headless: bool,
+
+    /// Frames to run in headless mode.
+    #[arg(long, default_value_t = 60 * 60)]
+    frames: usize,
+
+    /// Cycles to run in headless mode, overrides --frames.
+    #[arg(long)]
+    cycles: Option<usize>,
+
+    /// Expected framebuffer hash (hex) in headless mode. The
+    /// run passes if the hash matches after the limit.
+    #[arg(long, value_parser = parse_hash)]
+    expect_hash: Option<u64>,
+
+    /// Write the last frame to this PNG file in headless mode.
+    #[arg(long)]
+    screenshot: Option<String>,
+
+    /// DMG palette for screenshots: grey, green, pocket or 4
+    /// RGB colors (e.g. e0f8d0,88c070,346856,081820).
+    #[arg(long)]
+    palette: Option<Palette>,
+
+    /// PPU renderer: scanline, or fifo for dot-accurate
+    /// mid-line raster effects.
+    #[arg(long)]
+    renderer: Option<Renderer>,
+
+    /// Write the tiles, background maps and sprites to PNG
+    /// files in this directory in headless mode.
+    #[arg(long)]
+    dump_vram: Option<String>,
+
+    /// Record the audio output to this WAV file in headless
+    /// mode.
+    #[arg(long)]
+    wav: Option<String>,
+
+    /// With --wav, also record each sound channel to a file
+    /// of its own (<name>-ch1.wav etc.).
+    #[arg(long)]
+    wav_channels: bool,
+
+    /// Profile the run in headless mode and write a report
+    /// of the hottest code and time per subsystem to this file.
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Profile the run in headless mode and write the call
+    /// stacks to this file, in the folded format of
+    /// flamegraph.pl and inferno.
+    #[arg(long)]
+    flamegraph: Option<String>,
+
+    /// Keep track of the ROM executed, read and written in
+    /// headless mode and write the code/data log to this
+    /// file: one byte per ROM byte, with bit 0 set if
+    /// executed, bit 1 if read and bit 2 if written.
+    #[arg(long)]
+    coverage: Option<String>,
+
+    /// Keep track of ROM coverage in headless mode and write
+    /// a summary and the executed ranges to this file.
+    #[arg(long)]
+    coverage_report: Option<String>,
+
+    /// Sound channels (1-4) to mute, e.g. 1,3.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=4))]
+    mute: Vec<u8>,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn parse_hash(s: &str) -> Result<u64> {
+    Ok(u64::from_str_radix(s.trim_start_matches("0x"), 16)?)
+}
+
+/// Writes VRAM and OAM viewer images and an OAM listing to
+/// 'dir'.
+fn dump_vram(bus: &Gameboybus, dir: &Path) -> Result<()> {
+    let ppu = bus.ppu();
+    fs::create_dir_all(dir)?;
 
-    let rom = fs::read(args.filename)?;
+    save_image(&dir.join("tiles.png"), &ppu.dump_tiles())?;
 
-    let mut bus: Box<dyn Bus> = if args.testbus {
-        Box::new(Testbus::new())
+    // Tile data as currently selected in LCDC
+    let tiledata = if bus.peek(0xFF40) & 0x10 != 0 {
+        TileData::Unsigned
     } else {
-        if let Some(brfile) = args.bootrom {
-            let bootrom = fs::read(brfile)?;
-            Box::new(Gameboybus::new(&rom, Some(bootrom.as_slice())))
-        } else {
-            Box::new(Gameboybus::new(&rom, None))
-        }
+        TileData::Signed
     };
+    for map in [TileMap::Low, TileMap::High] {
+        let path = dir.join(format!("bg-{:04X}.png", map.addr()));
+        save_image(&path, &ppu.dump_bg_map(map, tiledata))?;
+    }
 
-    if args.testbus {
-        bus.write_slice(&rom, 0);
+    // Sprites in a grid of 8x5, 16x16 pixels each
+    let oam = ppu.dump_oam();
+    let mut grid = vec![0; 128 * 80];
+    let mut listing = String::new();
+    for (i, s) in oam.iter().enumerate() {
+        let (ox, oy) = (i % 8 * 16, i / 8 * 16);
+        for (j, &px) in s.image.pixels.iter().enumerate() {
+            grid[(oy + j / 8) * 128 + ox + 4 + j % 8] = px;
+        }
+        listing += &format!(
+            "{:2}: X:{:4} Y:{:4} tile:{:02X} attr:{:02X} palette:{} bank:{}{}{}{}\n",
+            i,
+            s.x,
+            s.y,
+            s.tile,
+            s.attr,
+            s.palette,
+            s.bank,
+            if s.xflip { " xflip" } else { "" },
+            if s.yflip { " yflip" } else { "" },
+            if s.behind_bg { " behind-bg" } else { "" },
+        );
+    }
+    write_png(&dir.join("oam.png"), 128, 80, &grid)?;
+    fs::write(dir.join("oam.txt"), listing)?;
+    Ok(())
+}
 
-        // Indicate start of VBlank for testing purposes
-        bus.write(0xFF44, 0x90);
+/// Steps the CPU, then calls 'flush' with whether execution
+/// failed.
+fn step<B: Bus>(
+    cpu: &mut CPU<B>,
+    flush: &mut impl FnMut(&mut CPU<B>, bool) -> Result<()>,
+) -> Result<()> {
+    let result = cpu.step();
+    flush(cpu, result.is_err())?;
+    if result.is_err() {
+        eprintln!("{}", Monitor::state(cpu));
     }
+    Ok(result?)
+}
 
-    let mut cpu = CPU::new(bus);
+/// Runs the CPU under the debugger selected in 'args': GDB,
+/// the monitor, a trace log or single stepping. 'flush' is
+/// called after every step, with true if it must persist
+/// state right away (e.g. battery RAM when execution ends).
+fn debug<B: Bus>(
+    cpu: &mut CPU<B>,
+    args: &Args,
+    mut flush: impl FnMut(&mut CPU<B>, bool) -> Result<()>,
+) -> Result<()> {
+    if let Some(addr) = &args.gdb {
+        let listener = TcpListener::bind(addr).context(format!("Binding {}", addr))?;
+        println!("Waiting for GDB to connect on {}", listener.local_addr()?);
+        GdbStub::new(cpu).listen(&listener, |peer| println!("GDB connected from {}", peer))?;
+        return flush(cpu, true);
+    }
+
+    if args.monitor {
+        let mut monitor = Monitor::new();
+        println!("{}", Monitor::state(cpu));
+        loop {
+            print!("> ");
+            stdout().flush()?;
+            let mut line = String::new();
+            if stdin().lock().read_line(&mut line)? == 0 {
+                break;
+            }
+            match monitor.command(cpu, &line) {
+                Ok(Some(out)) => println!("{}", out),
+                Ok(None) => break,
+                Err(e) => println!("Error: {:#}", e),
+            }
+        }
+        return flush(cpu, true);
+    }
+
+    let trace = args.trace.or(args.doctor.then_some(TraceFormat::Doctor));
+    if let Some(format) = trace {
+        cpu.set_trace_format(format);
+        cpu.set_trace_sink(|line| println!("{}", line));
+        loop {
+            step(cpu, &mut flush)?;
+        }
+    }
 
     loop {
         println!("Cycle: {}", cpu.get_cycles());
@@ -64,6 +315,198 @@ fn main() -> Result<()> {
             let _ = stdin().read(&mut [0u8]).unwrap();
         }
 
-        cpu.step()?;
+        step(cpu, &mut flush)?;
     }
 }
+
+fn main() -> Result<()> {
+    let mut args = Args::parse();
+    let config = Config::load_or_default(args.config.as_deref().map(Path::new))?;
+
+    // Command line options override the configuration file
+    let path_arg =
+        |arg: Option<String>, cfg: &Option<PathBuf>| arg.map(PathBuf::from).or(cfg.clone());
+    let Some(filename) = path_arg(args.filename.take(), &config.rom) else {
+        bail!("No ROM given on the command line or in the configuration file");
+    };
+    let bootrom = path_arg(args.bootrom.take(), &config.bootrom);
+    let save_dir = path_arg(args.save_dir.take(), &config.save_dir);
+    let cheats = path_arg(args.cheats.take(), &config.debug.cheats);
+    args.model = args.model.or(config.model);
+    args.trace = args.trace.or(config.debug.trace);
+    args.serial |= config.debug.serial;
+    let palette = args.palette.unwrap_or(config.video.palette);
+    if args.mute.is_empty() {
+        args.mute = config.audio.mute.iter().map(|&ch| ch as u8).collect();
+    }
+
+    let rom = fs::read(&filename)?;
+
+    if args.testbus {
+        if args.headless {
+            bail!("Headless mode needs the Game Boy bus");
+        }
+        let mut bus = Testbus::new();
+        bus.write_slice(&rom, 0);
+
+        // Indicate start of VBlank for testing purposes
+        bus.write(0xFF44, 0x90);
+
+        let mut cpu = CPU::new(bus);
+        cpu.set_fault_policy(args.on_fault);
+        cpu.set_fault_sink(|e| eprintln!("{} (skipped)", e));
+        return debug(&mut cpu, &args, |_, _| Ok(()));
+    }
+
+    let header = CartridgeHeader::from_rom(&rom)?;
+    for warning in header.validate(args.validate)? {
+        eprintln!("Warning: {}", warning);
+    }
+    let model = match args.model {
+        Some(m) => m,
+        None => Model::detect(&header),
+    };
+    let bootrom = bootrom.map(fs::read).transpose()?;
+    let mut emu = Emulator::with_model(&rom, bootrom.as_deref(), model)?;
+    emu.bus().set_lenient_boot(args.lenient_boot);
+
+    if args.serial {
+        emu.bus().set_serial_output(Box::new(StdoutSerial));
+    }
+
+    if let Some(addr) = &args.link_listen {
+        emu.bus().set_serial_link(Box::new(TcpLink::listen(addr)?));
+    } else if let Some(addr) = &args.link_connect {
+        emu.bus().set_serial_link(Box::new(TcpLink::connect(addr)?));
+    }
+
+    if let Some(path) = &cheats {
+        let ids = emu.add_cheat_file(&fs::read_to_string(path)?)?;
+        println!("Loaded {} cheat(s)", ids.len());
+    }
+
+    emu.set_input_latch(config.latch_input);
+    emu.set_audio_sample_rate(config.audio.sample_rate);
+    emu.set_renderer(args.renderer.unwrap_or(config.video.renderer));
+    for &ch in &args.mute {
+        emu.set_channel_muted(ch as usize - 1, true);
+    }
+
+    emu.cpu().set_fault_policy(args.on_fault);
+    emu.cpu().set_fault_sink(|e| eprintln!("{} (skipped)", e));
+
+    let mut battery = None;
+    if header.has_battery() {
+        let mut save = BatterySave::new(BatterySave::path_for_rom(&filename, save_dir.as_deref()));
+        if save.load(emu.bus().cartridge())? {
+            println!("Loaded {}", save.path().display());
+        }
+        battery = Some((save, CPU_CLOCK));
+    }
+
+    if !args.headless {
+        // Flushes battery RAM to disk about once per emulated
+        // second and when execution ends.
+        let flush = |cpu: &mut CPU<HookBus<Gameboybus>>, now: bool| -> Result<()> {
+            if let Some((save, next_flush)) = &mut battery {
+                if now || cpu.get_cycles() >= *next_flush {
+                    save.flush(cpu.bus.inner_mut().cartridge())?;
+                    *next_flush = cpu.get_cycles() + CPU_CLOCK;
+                }
+            }
+            Ok(())
+        };
+        return debug(emu.cpu(), &args, flush);
+    }
+
+    let mut recorder = None;
+    if let Some(path) = &args.wav {
+        emu.bus().apu().set_capture_channels(args.wav_channels);
+        recorder = Some(AudioRecorder::create(
+            Path::new(path),
+            config.audio.sample_rate,
+            args.wav_channels,
+        )?);
+    }
+
+    if args.profile.is_some() || args.flamegraph.is_some() {
+        emu.enable_profiler();
+    }
+    if args.coverage.is_some() || args.coverage_report.is_some() {
+        emu.enable_coverage();
+    }
+
+    // Runs frame by frame to drain the audio buffers
+    let mut runner = TestRunner::new(emu);
+    let end = runner.cpu().get_cycles() + args.cycles.unwrap_or(args.frames * CYCLES_PER_FRAME);
+    let mut result = TestResult::Timeout;
+    while result == TestResult::Timeout && runner.cpu().get_cycles() < end {
+        let cycles = (end - runner.cpu().get_cycles()).min(CYCLES_PER_FRAME);
+        result = match runner.run_cycles(cycles) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("{}", Monitor::state(runner.cpu()));
+                return Err(e);
+            }
+        };
+        if let Some(rec) = &mut recorder {
+            let bus = runner.emulator().bus();
+            let mixed = bus.take_audio_samples();
+            let channels = bus.apu().take_channel_samples();
+            rec.write(&mixed, &channels)?;
+        }
+    }
+    if let Some(rec) = recorder {
+        rec.finish()?;
+    }
+    let hash = runner.frame_hash();
+    if let Some(expected) = args.expect_hash {
+        if result == TestResult::Timeout {
+            result = if hash == expected {
+                TestResult::Passed
+            } else {
+                TestResult::Failed
+            };
+        }
+    }
+    let emu = runner.emulator();
+    if let Some((save, _)) = &mut battery {
+        save.flush(emu.bus().cartridge())?;
+    }
+    if let Some(path) = &args.screenshot {
+        let mut video = PostProcess::new();
+        video.set_palette(palette);
+        let bus = emu.bus();
+        let pixels = video.process(bus.frame(), bus.cgb_mode());
+        write_png(Path::new(path), LCD_WIDTH, LCD_HEIGHT, pixels)?;
+    }
+    if let Some(dir) = &args.dump_vram {
+        dump_vram(emu.bus(), Path::new(dir))?;
+    }
+    if let Some(profiler) = emu.take_profiler() {
+        if let Some(path) = &args.profile {
+            fs::write(path, profiler.report())?;
+        }
+        if let Some(path) = &args.flamegraph {
+            fs::write(path, profiler.folded())?;
+        }
+    }
+    if let Some(coverage) = emu.take_coverage() {
+        if let Some(path) = &args.coverage {
+            fs::write(path, coverage.cdl(rom.len()))?;
+        }
+        if let Some(path) = &args.coverage_report {
+            fs::write(path, coverage.report())?;
+        }
+    }
+
+    print!("{}", runner.serial_output());
+    println!();
+    println!("Frame hash: {:016X}", hash);
+    println!("Result: {:?}", result);
+    std::process::exit(match result {
+        TestResult::Passed => 0,
+        TestResult::Failed => 1,
+        TestResult::Timeout => 2,
+    });
+}
@@ -1,23 +1,73 @@
-use std::env;
 use std::fs;
 
 use anyhow::{bail, Result};
+use clap::Parser;
 
-use gbrust::gameboy::cpu::instruction::Instruction;
+use gbrust::gameboy::cpu::instruction::{DecodeErr, Instruction};
+
+/// Size of a ROM bank as mapped at 0x4000 - 0x7FFF
+const BANK_SIZE: usize = 0x4000;
+
+#[derive(Parser)]
+#[command(about = "Disassembles a ROM (or a range of it)", long_about = None)]
+struct Args {
+    /// ROM filename to disassemble.
+    filename: String,
+
+    /// ROM offset to start at (hex).
+    #[arg(short, long, value_parser = parse_hex, default_value = "0")]
+    start: usize,
+
+    /// ROM offset to stop at, exclusive (hex). Defaults
+    /// to the end of the ROM.
+    #[arg(short, long, value_parser = parse_hex)]
+    end: Option<usize>,
+}
+
+fn parse_hex(s: &str) -> Result<usize> {
+    let digits = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    Ok(usize::from_str_radix(digits, 16)?)
+}
+
+/// Address a ROM offset appears at on the CPU bus; banks
+/// beyond the first are mapped at 0x4000.
+fn cpu_addr(offset: usize) -> u16 {
+    if offset < BANK_SIZE {
+        offset as u16
+    } else {
+        (BANK_SIZE + offset % BANK_SIZE) as u16
+    }
+}
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let args = Args::parse();
 
-    if args.len() < 2 {
-        bail!("Syntax: {} <filename>", args[0]);
+    let f = fs::read(&args.filename)?;
+    let end = args.end.unwrap_or(f.len()).min(f.len());
+    if args.start > end {
+        bail!("Start {:X} is beyond end {:X}", args.start, end);
     }
 
-    let f = fs::read(&args[1])?;
-    let mut fiter = f.into_iter();
-    let mut pos = 0;
-    while let Ok(ins) = Instruction::decode(&mut fiter) {
-        println!("{:04X} {}", pos, ins);
-        pos += ins.len;
+    let mut pos = args.start;
+    while pos < end {
+        let addr = cpu_addr(pos);
+        let mut fiter = f[pos..end].iter().copied();
+        match Instruction::decode(&mut fiter, addr) {
+            Ok(ins) => {
+                println!("{:04X} {:02X?} {}", pos, ins.bytes(), ins.fmt_disasm(addr));
+                pos += ins.len;
+            }
+            Err(e) => match e.downcast_ref::<DecodeErr>() {
+                Some(DecodeErr::UndefinedOpcode { opcode, .. }) => {
+                    println!("{:04X} [{:02X}] INVALID", pos, opcode);
+                    pos += 1;
+                }
+                _ => break,
+            },
+        }
     }
     Ok(())
 }
@@ -0,0 +1,129 @@
+use std::fs;
+
+use anyhow::Result;
+use clap::Parser;
+
+use gbrust::gameboy::cartridge::header::CartridgeHeader;
+
+#[derive(Parser)]
+#[command(
+    about = "Shows the cartridge header of ROMs and checks them for problems",
+    long_about = None)]
+struct Args {
+    /// ROM filenames to inspect.
+    #[arg(required = true)]
+    filenames: Vec<String>,
+
+    /// Exit with 1 if any ROM has problems (e.g. a bad
+    /// checksum or a truncated image).
+    #[arg(long)]
+    strict: bool,
+
+    /// Only list the ROMs with problems, and the problems.
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+/// Formats a size in bytes as KiB or MiB.
+fn size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{} MiB", bytes / 1024 / 1024)
+    } else {
+        format!("{} KiB", bytes / 1024)
+    }
+}
+
+fn print_info(h: &CartridgeHeader) {
+    let (code, name) = h.licensee();
+    println!("  Title:           {}", h.title);
+    println!(
+        "  Licensee:        {} ({})",
+        code,
+        name.unwrap_or("unknown")
+    );
+    println!(
+        "  Cartridge type:  {:02X} ({})",
+        h.cart_type,
+        h.cart_type_name().unwrap_or("unknown")
+    );
+    match h.rom_size_bytes() {
+        Ok(bytes) => println!("  ROM size:        {:02X} ({})", h.rom_size, size(bytes)),
+        Err(_) => println!("  ROM size:        {:02X} (unknown)", h.rom_size),
+    }
+    match h.ram_size_bytes() {
+        Ok(bytes) => println!("  RAM size:        {:02X} ({})", h.ram_size, size(bytes)),
+        Err(_) => println!("  RAM size:        {:02X} (unknown)", h.ram_size),
+    }
+    let cgb = if h.cgb_only() {
+        "CGB only"
+    } else if h.supports_cgb() {
+        "supported"
+    } else {
+        "no"
+    };
+    println!("  CGB:             {} ({:02X})", cgb, h.cgb_flag);
+    println!(
+        "  SGB:             {} ({:02X})",
+        if h.supports_sgb() { "supported" } else { "no" },
+        h.sgb_flag
+    );
+    println!("  Version:         {}", h.version);
+    println!(
+        "  Header checksum: {:02X} ({})",
+        h.checksum,
+        if h.checksum_matches() { "OK" } else { "bad" }
+    );
+    println!(
+        "  Global checksum: {:04X} ({})",
+        h.global_checksum,
+        if h.global_checksum_matches() {
+            "OK"
+        } else {
+            "bad"
+        }
+    );
+    println!(
+        "  Battery:         {}",
+        if h.has_battery() { "yes" } else { "no" }
+    );
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let mut bad = 0;
+    for filename in &args.filenames {
+        let header = fs::read(filename)
+            .map_err(anyhow::Error::from)
+            .and_then(|rom| CartridgeHeader::from_rom(&rom));
+        let (header, problems) = match header {
+            Ok(h) => {
+                let problems = h.problems();
+                (Some(h), problems)
+            }
+            Err(e) => (None, vec![e.to_string()]),
+        };
+        if !problems.is_empty() {
+            bad += 1;
+        }
+        if args.quiet && problems.is_empty() {
+            continue;
+        }
+
+        println!("{}", filename);
+        if let (Some(h), false) = (&header, args.quiet) {
+            print_info(h);
+        }
+        for p in &problems {
+            println!("  Problem:         {}", p);
+        }
+    }
+
+    if args.filenames.len() > 1 {
+        println!("{} of {} ROM(s) with problems", bad, args.filenames.len());
+    }
+    if args.strict && bad > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,1443 @@
+use anyhow::Result;
+
+use super::bus::bus::Bus;
+use super::savestate::{StateReader, StateWriter};
+
+/// CPU clock in T-cycles per second
+pub const CPU_CLOCK: usize = 4_194_304;
+
+/// Default output sample rate
+pub const DEFAULT_SAMPLE_RATE: usize = 44_100;
+
+/// T-cycles per frame sequencer step (512 Hz)
+const FRAME_SEQ_PERIOD: usize = CPU_CLOCK / 512;
+
+/// Maximum amount of buffered samples (one second at the
+/// default rate), further samples are dropped until the
+/// buffer is drained.
+const MAX_BUFFERED: usize = DEFAULT_SAMPLE_RATE * 2;
+
+/// Number of sound channels
+pub const CHANNELS: usize = 4;
+
+/// Time in seconds for channel levels to fall to 1/e of
+/// their peak
+const LEVEL_DECAY_TIME: f32 = 0.05;
+
+/// Output capacitor charge factors per T-cycle
+const HPF_CHARGE_DMG: f64 = 0.999958;
+const HPF_CHARGE_CGB: f64 = 0.998943;
+
+/// Pulse duty waveforms (12.5%, 25%, 50%, 75%)
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Noise channel divisors, indexed by NR43 bits 0-2
+const NOISE_DIVISORS: [usize; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// NR52 bit 7 - APU power
+const NR52_POWER: u8 = 1 << 7;
+
+/// NRx4 bit 7 - Trigger
+const NRX4_TRIGGER: u8 = 1 << 7;
+
+/// NRx4 bit 6 - Length enable
+const NRX4_LENGTH: u8 = 1 << 6;
+
+/// Bits that read as 1 for 0xFF10 - 0xFF2F
+const READ_MASK: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40 - NR44
+    0x00, 0x00, 0x70, // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Unused
+];
+
+/// Length counter, disables its channel when it expires.
+struct Length {
+    counter: u16,
+    max: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn new(max: u16) -> Self {
+        Self {
+            counter: 0,
+            max,
+            enabled: false,
+        }
+    }
+
+    /// Loads the counter from the NRx1 length bits.
+    fn load(&mut self, val: u8) {
+        self.counter = self.max - (val as u16 & (self.max - 1));
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Clocks the counter, returns true if it expired.
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.counter);
+        w.bool(self.enabled);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.counter = r.u16()?;
+        self.enabled = r.bool()?;
+        Ok(())
+    }
+}
+
+/// Volume envelope (NRx2)
+struct Envelope {
+    reg: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Self {
+            reg: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    /// The DAC is off if the initial volume is 0 and
+    /// the envelope is decreasing.
+    fn dac_enabled(&self) -> bool {
+        self.reg & 0xF8 != 0
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.reg >> 4;
+        self.timer = self.reg & 0x07;
+    }
+
+    fn clock(&mut self) {
+        let period = self.reg & 0x07;
+        if period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = period;
+            if self.reg & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if self.reg & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.reg);
+        w.u8(self.volume);
+        w.u8(self.timer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.reg = r.u8()?;
+        self.volume = r.u8()?;
+        self.timer = r.u8()?;
+        Ok(())
+    }
+}
+
+/// Pulse channel (1 and 2), channel 1 has a frequency sweep.
+struct Pulse {
+    enabled: bool,
+
+    /// NR10 - Sweep (channel 1 only)
+    sweep_reg: u8,
+
+    /// NRx1 bits 6-7 - Duty
+    duty: u8,
+
+    envelope: Envelope,
+    length: Length,
+
+    /// 11-bit frequency (NRx3, NRx4 bits 0-2)
+    freq: u16,
+
+    /// T-cycles until the next duty step
+    timer: usize,
+    duty_pos: u8,
+
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    shadow_freq: u16,
+}
+
+impl Pulse {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            sweep_reg: 0,
+            duty: 0,
+            envelope: Envelope::new(),
+            length: Length::new(64),
+            freq: 0,
+            timer: 0,
+            duty_pos: 0,
+            sweep_enabled: false,
+            sweep_timer: 0,
+            shadow_freq: 0,
+        }
+    }
+
+    fn period(&self) -> usize {
+        (2048 - self.freq as usize) * 4
+    }
+
+    fn tick(&mut self) {
+        if self.timer <= 1 {
+            self.timer = self.period();
+            self.duty_pos = (self.duty_pos + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Digital output (0-15), or None if the DAC is off.
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        let high = (DUTY[self.duty as usize] >> (7 - self.duty_pos)) & 1;
+        Some(high * self.envelope.volume)
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        self.shadow_freq = self.freq;
+        self.sweep_timer = self.sweep_period();
+        self.sweep_enabled = self.sweep_period_raw() != 0 || self.sweep_shift() != 0;
+        if self.sweep_shift() != 0 {
+            self.sweep_calc();
+        }
+    }
+
+    fn sweep_period_raw(&self) -> u8 {
+        (self.sweep_reg >> 4) & 0x07
+    }
+
+    /// Sweep timer period, a period of 0 counts as 8.
+    fn sweep_period(&self) -> u8 {
+        match self.sweep_period_raw() {
+            0 => 8,
+            p => p,
+        }
+    }
+
+    fn sweep_shift(&self) -> u8 {
+        self.sweep_reg & 0x07
+    }
+
+    /// Calculates the next sweep frequency, disabling
+    /// the channel on overflow.
+    fn sweep_calc(&mut self) -> u16 {
+        let delta = self.shadow_freq >> self.sweep_shift();
+        let freq = if self.sweep_reg & 0x08 != 0 {
+            self.shadow_freq - delta
+        } else {
+            self.shadow_freq + delta
+        };
+        if freq > 2047 {
+            self.enabled = false;
+        }
+        freq
+    }
+
+    fn clock_sweep(&mut self) {
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer > 0 {
+            return;
+        }
+
+        self.sweep_timer = self.sweep_period();
+        if self.sweep_enabled && self.sweep_period_raw() != 0 {
+            let freq = self.sweep_calc();
+            if freq <= 2047 && self.sweep_shift() != 0 {
+                self.shadow_freq = freq;
+                self.freq = freq;
+                // Overflow check again with the new frequency
+                self.sweep_calc();
+            }
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.u8(self.sweep_reg);
+        w.u8(self.duty);
+        self.envelope.save_state(w);
+        self.length.save_state(w);
+        w.u16(self.freq);
+        w.u32(self.timer as u32);
+        w.u8(self.duty_pos);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_timer);
+        w.u16(self.shadow_freq);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.enabled = r.bool()?;
+        self.sweep_reg = r.u8()?;
+        self.duty = r.u8()?;
+        self.envelope.load_state(r)?;
+        self.length.load_state(r)?;
+        self.freq = r.u16()?;
+        self.timer = r.u32()? as usize;
+        self.duty_pos = r.u8()?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_timer = r.u8()?;
+        self.shadow_freq = r.u16()?;
+        Ok(())
+    }
+}
+
+/// Wave channel (3), plays 32 4-bit samples from wave RAM.
+struct Wave {
+    enabled: bool,
+
+    /// NR30 bit 7 - DAC power
+    dac: bool,
+
+    /// NR32 bits 5-6 - Output level
+    level: u8,
+
+    length: Length,
+
+    /// 11-bit frequency (NR33, NR34 bits 0-2)
+    freq: u16,
+
+    /// T-cycles until the next sample
+    timer: usize,
+
+    /// Current sample (0-31)
+    pos: u8,
+
+    /// Wave RAM (0xFF30 - 0xFF3F)
+    ram: [u8; 16],
+}
+
+impl Wave {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            dac: false,
+            level: 0,
+            length: Length::new(256),
+            freq: 0,
+            timer: 0,
+            pos: 0,
+            ram: [0; 16],
+        }
+    }
+
+    fn period(&self) -> usize {
+        (2048 - self.freq as usize) * 2
+    }
+
+    fn tick(&mut self) {
+        if self.timer <= 1 {
+            self.timer = self.period();
+            self.pos = (self.pos + 1) % 32;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        let byte = self.ram[self.pos as usize / 2];
+        let sample = if self.pos.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        Some(match self.level {
+            0 => 0,
+            l => sample >> (l - 1),
+        })
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.length.trigger();
+        self.timer = self.period();
+        self.pos = 0;
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.dac);
+        w.u8(self.level);
+        self.length.save_state(w);
+        w.u16(self.freq);
+        w.u32(self.timer as u32);
+        w.u8(self.pos);
+        w.bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.enabled = r.bool()?;
+        self.dac = r.bool()?;
+        self.level = r.u8()?;
+        self.length.load_state(r)?;
+        self.freq = r.u16()?;
+        self.timer = r.u32()? as usize;
+        self.pos = r.u8()?;
+        r.bytes_into(&mut self.ram, "wave RAM")
+    }
+}
+
+/// Noise channel (4), driven by a linear feedback shift register.
+struct Noise {
+    enabled: bool,
+    envelope: Envelope,
+    length: Length,
+
+    /// NR43 - Clock shift, LFSR width and divisor
+    poly: u8,
+
+    /// T-cycles until the next LFSR shift
+    timer: usize,
+    lfsr: u16,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            envelope: Envelope::new(),
+            length: Length::new(64),
+            poly: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    fn period(&self) -> usize {
+        NOISE_DIVISORS[(self.poly & 0x07) as usize] << (self.poly >> 4)
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period();
+
+        let xor = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (xor << 14);
+        if self.poly & 0x08 != 0 {
+            // 7-bit mode
+            self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        Some((!self.lfsr & 1) as u8 * self.envelope.volume)
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        self.envelope.save_state(w);
+        self.length.save_state(w);
+        w.u8(self.poly);
+        w.u32(self.timer as u32);
+        w.u16(self.lfsr);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.enabled = r.bool()?;
+        self.envelope.load_state(r)?;
+        self.length.load_state(r)?;
+        self.poly = r.u8()?;
+        self.timer = r.u32()? as usize;
+        self.lfsr = r.u16()?;
+        Ok(())
+    }
+}
+
+/// Converts a digital channel output (0-15) to an analog
+/// level between -1.0 and 1.0. A disabled DAC outputs silence.
+fn dac(output: Option<u8>) -> f32 {
+    match output {
+        Some(v) => v as f32 / 7.5 - 1.0,
+        None => 0.0,
+    }
+}
+
+/// High-pass filter on the output, like the DC-blocking
+/// capacitor of the real hardware.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HighPass {
+    Off,
+    Dmg,
+    Cgb,
+}
+
+impl HighPass {
+    /// Capacitor charge factor per output sample
+    fn charge(self, sample_rate: usize) -> f32 {
+        let factor = match self {
+            Self::Off => return 1.0,
+            Self::Dmg => HPF_CHARGE_DMG,
+            Self::Cgb => HPF_CHARGE_CGB,
+        };
+        factor.powf(CPU_CLOCK as f64 / sample_rate.max(1) as f64) as f32
+    }
+}
+
+/// Audio Processing Unit
+/// Owns the sound registers (0xFF10 - 0xFF26) and wave RAM
+/// (0xFF30 - 0xFF3F).
+///
+/// Output is produced as interleaved stereo (left, right)
+/// samples between -1.0 and 1.0 at the configured sample rate,
+/// buffered until taken by the frontend.
+///
+/// The number of samples is exact: 'n' T-cycles after the
+/// sample rate was set, n * rate / CPU_CLOCK (rounded down)
+/// samples have been produced, however the cycles were split
+/// up (e.g. into frames). A reset does not restart the count.
+pub struct Apu {
+    ch1: Pulse,
+    ch2: Pulse,
+    ch3: Wave,
+    ch4: Noise,
+
+    /// NR50 - Master volume
+    nr50: u8,
+
+    /// NR51 - Panning
+    nr51: u8,
+
+    /// NR52 bit 7 - Power
+    power: bool,
+
+    /// T-cycles until the next frame sequencer step
+    frame_seq_timer: usize,
+
+    /// Frame sequencer step (0-7)
+    frame_seq_step: u8,
+
+    /// Output sample rate in Hz
+    sample_rate: usize,
+
+    /// Resampling accumulator, a sample is produced when
+    /// it reaches CPU_CLOCK.
+    sample_acc: usize,
+
+    /// Interleaved stereo samples
+    samples: Vec<f32>,
+
+    /// Channels left out of the mix
+    muted: [bool; CHANNELS],
+
+    /// Keep samples of the individual channels as well
+    capture_channels: bool,
+
+    /// Interleaved stereo samples per channel
+    channel_samples: [Vec<f32>; CHANNELS],
+
+    /// Decaying peak level of each channel, for level meters
+    levels: [f32; CHANNELS],
+
+    /// Factor the levels decay by per output sample
+    level_decay: f32,
+
+    high_pass: HighPass,
+
+    /// Capacitor charge factor per output sample
+    hpf_charge: f32,
+
+    /// Capacitor charge (left, right)
+    hpf_capacitor: (f32, f32),
+
+    /// CGB APU, which clears the length counters on power off
+    cgb: bool,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            ch1: Pulse::new(),
+            ch2: Pulse::new(),
+            ch3: Wave::new(),
+            ch4: Noise::new(),
+            nr50: 0,
+            nr51: 0,
+            power: false,
+            frame_seq_timer: FRAME_SEQ_PERIOD,
+            frame_seq_step: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_acc: 0,
+            samples: vec![],
+            muted: [false; CHANNELS],
+            capture_channels: false,
+            channel_samples: Default::default(),
+            levels: [0.0; CHANNELS],
+            level_decay: Self::level_decay(DEFAULT_SAMPLE_RATE),
+            high_pass: HighPass::Dmg,
+            hpf_charge: HighPass::Dmg.charge(DEFAULT_SAMPLE_RATE),
+            hpf_capacitor: (0.0, 0.0),
+            cgb: false,
+        }
+    }
+
+    /// APU of the CGB
+    pub fn new_cgb() -> Self {
+        Self {
+            high_pass: HighPass::Cgb,
+            hpf_charge: HighPass::Cgb.charge(DEFAULT_SAMPLE_RATE),
+            cgb: true,
+            ..Self::new()
+        }
+    }
+
+    /// Resets to the power-on state, keeping the output
+    /// settings and the samples not taken yet.
+    pub fn reset(&mut self) {
+        let old = std::mem::replace(
+            self,
+            if self.cgb {
+                Self::new_cgb()
+            } else {
+                Self::new()
+            },
+        );
+        self.sample_rate = old.sample_rate;
+        self.sample_acc = old.sample_acc;
+        self.samples = old.samples;
+        self.muted = old.muted;
+        self.capture_channels = old.capture_channels;
+        self.channel_samples = old.channel_samples;
+        self.level_decay = old.level_decay;
+        self.high_pass = old.high_pass;
+        self.hpf_charge = old.hpf_charge;
+    }
+
+    /// Sets the output sample rate in Hz.
+    pub fn set_sample_rate(&mut self, rate: usize) {
+        self.sample_rate = rate;
+        self.sample_acc = 0;
+        self.level_decay = Self::level_decay(rate);
+        self.hpf_charge = self.high_pass.charge(rate);
+    }
+
+    /// Sets the high-pass filter on the output, which
+    /// defaults to that of the model.
+    pub fn set_high_pass(&mut self, high_pass: HighPass) {
+        self.high_pass = high_pass;
+        self.hpf_charge = high_pass.charge(self.sample_rate);
+        self.hpf_capacitor = (0.0, 0.0);
+    }
+
+    pub fn high_pass(&self) -> HighPass {
+        self.high_pass
+    }
+
+    fn level_decay(rate: usize) -> f32 {
+        (-1.0 / (rate.max(1) as f32 * LEVEL_DECAY_TIME)).exp()
+    }
+
+    /// Recent peak amplitude of each channel (0.0 - 1.0),
+    /// before panning, volume and muting, e.g. for level
+    /// meters. Silent and disabled channels fall to 0.0.
+    pub fn channel_levels(&self) -> [f32; CHANNELS] {
+        self.levels
+    }
+
+    /// Returns and clears the samples produced since the
+    /// last call, interleaved left/right.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Mutes or unmutes a channel (0-3) in the mixed output.
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+        self.muted[channel] = muted;
+    }
+
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.muted[channel]
+    }
+
+    /// Mutes all channels except 'channel', or unmutes all
+    /// channels if None.
+    pub fn solo(&mut self, channel: Option<usize>) {
+        for (i, muted) in self.muted.iter_mut().enumerate() {
+            *muted = channel.is_some_and(|c| c != i);
+        }
+    }
+
+    /// Produces samples of the individual channels (before
+    /// muting) alongside the mixed output.
+    pub fn set_capture_channels(&mut self, capture: bool) {
+        self.capture_channels = capture;
+        if !capture {
+            self.channel_samples = Default::default();
+        }
+    }
+
+    /// Returns and clears the samples of each channel produced
+    /// since the last call, interleaved left/right. Only
+    /// produced if enabled with set_capture_channels().
+    pub fn take_channel_samples(&mut self) -> [Vec<f32>; CHANNELS] {
+        std::mem::take(&mut self.channel_samples)
+    }
+
+    /// Advances the APU by a number of T-cycles.
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            if self.power {
+                self.tick_cycle();
+            }
+
+            self.sample_acc += self.sample_rate;
+            if self.sample_acc >= CPU_CLOCK {
+                self.sample_acc -= CPU_CLOCK;
+                self.update_levels();
+                if self.samples.len() < MAX_BUFFERED {
+                    self.output_sample();
+                }
+            }
+        }
+    }
+
+    fn tick_cycle(&mut self) {
+        self.ch1.tick();
+        self.ch2.tick();
+        self.ch3.tick();
+        self.ch4.tick();
+
+        self.frame_seq_timer -= 1;
+        if self.frame_seq_timer == 0 {
+            self.frame_seq_timer = FRAME_SEQ_PERIOD;
+            self.clock_frame_seq();
+        }
+    }
+
+    /// Frame sequencer: length counters at 256 Hz, sweep
+    /// at 128 Hz and envelopes at 64 Hz.
+    fn clock_frame_seq(&mut self) {
+        let step = self.frame_seq_step;
+        if step.is_multiple_of(2) {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if step == 7 {
+            self.ch1.envelope.clock();
+            self.ch2.envelope.clock();
+            self.ch4.envelope.clock();
+        }
+        self.frame_seq_step = (step + 1) % 8;
+    }
+
+    /// Mixes the unmuted channels into the next (left, right)
+    /// sample and captures the individual channels.
+    fn output_sample(&mut self) {
+        let channels = self.channels();
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, (l, r)) in channels.into_iter().enumerate() {
+            if !self.muted[i] {
+                left += l;
+                right += r;
+            }
+        }
+        let (left, right) = self.high_pass_filter(left, right);
+        self.samples.push(left);
+        self.samples.push(right);
+
+        if self.capture_channels {
+            for (samples, (l, r)) in self.channel_samples.iter_mut().zip(channels) {
+                samples.push(l);
+                samples.push(r);
+            }
+        }
+    }
+
+    /// Passes a sample through the output capacitor.
+    fn high_pass_filter(&mut self, left: f32, right: f32) -> (f32, f32) {
+        if self.high_pass == HighPass::Off {
+            return (left, right);
+        }
+        let (cap_l, cap_r) = self.hpf_capacitor;
+        let (out_l, out_r) = (left - cap_l, right - cap_r);
+        self.hpf_capacitor = (
+            left - out_l * self.hpf_charge,
+            right - out_r * self.hpf_charge,
+        );
+        (out_l, out_r)
+    }
+
+    /// Analog output of each channel, None if its DAC is
+    /// off (or the APU is).
+    fn dac_outputs(&self) -> [Option<f32>; CHANNELS] {
+        if !self.power {
+            return [None; CHANNELS];
+        }
+        [
+            self.ch1.output(),
+            self.ch2.output(),
+            self.ch3.output(),
+            self.ch4.output(),
+        ]
+        .map(|out| out.map(|v| dac(Some(v))))
+    }
+
+    /// Updates the decaying peak levels with the current
+    /// channel outputs.
+    fn update_levels(&mut self) {
+        let decay = self.level_decay;
+        let outputs = self.dac_outputs();
+        for (level, out) in self.levels.iter_mut().zip(outputs) {
+            // -1.0 (digital 0) is silence
+            let current = out.map_or(0.0, |v| (v + 1.0) / 2.0);
+            *level = current.max(*level * decay);
+        }
+    }
+
+    /// Contribution of each channel to the (left, right)
+    /// output, after panning and master volume.
+    fn channels(&self) -> [(f32, f32); CHANNELS] {
+        if !self.power {
+            return [(0.0, 0.0); CHANNELS];
+        }
+
+        let outputs = self.dac_outputs().map(|out| out.unwrap_or(0.0));
+        let volume = |v: u8| ((v & 0x07) + 1) as f32 / 8.0;
+        let (vol_left, vol_right) = (volume(self.nr50 >> 4), volume(self.nr50));
+        let mut channels = [(0.0, 0.0); CHANNELS];
+        for (i, out) in outputs.into_iter().enumerate() {
+            if self.nr51 & (1 << (i + 4)) != 0 {
+                channels[i].0 = out / 4.0 * vol_left;
+            }
+            if self.nr51 & (1 << i) != 0 {
+                channels[i].1 = out / 4.0 * vol_right;
+            }
+        }
+        channels
+    }
+
+    /// Writes NRx4 of a channel, returns true on a trigger.
+    fn write_nrx4(val: u8, freq: &mut u16, length: &mut Length) -> bool {
+        *freq = (*freq & 0xFF) | ((val as u16 & 0x07) << 8);
+        length.enabled = val & NRX4_LENGTH != 0;
+        val & NRX4_TRIGGER != 0
+    }
+
+    /// NR52 channel status bits
+    fn status(&self) -> u8 {
+        u8::from(self.ch1.enabled)
+            | u8::from(self.ch2.enabled) << 1
+            | u8::from(self.ch3.enabled) << 2
+            | u8::from(self.ch4.enabled) << 3
+    }
+
+    /// Powering off clears all sound registers. Wave RAM is
+    /// not affected, nor are the length counters on DMG.
+    fn power_off(&mut self) {
+        let ram = self.ch3.ram;
+        let lengths = [
+            self.ch1.length.counter,
+            self.ch2.length.counter,
+            self.ch3.length.counter,
+            self.ch4.length.counter,
+        ];
+        self.ch1 = Pulse::new();
+        self.ch2 = Pulse::new();
+        self.ch3 = Wave::new();
+        self.ch3.ram = ram;
+        self.ch4 = Noise::new();
+        if !self.cgb {
+            self.ch1.length.counter = lengths[0];
+            self.ch2.length.counter = lengths[1];
+            self.ch3.length.counter = lengths[2];
+            self.ch4.length.counter = lengths[3];
+        }
+        self.nr50 = 0;
+        self.nr51 = 0;
+        self.power = false;
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Apu {
+    fn read(&self, addr: u16) -> u8 {
+        let val = match addr {
+            0xFF10 => self.ch1.sweep_reg,
+            0xFF11 => self.ch1.duty << 6,
+            0xFF12 => self.ch1.envelope.reg,
+            0xFF14 => u8::from(self.ch1.length.enabled) << 6,
+
+            0xFF16 => self.ch2.duty << 6,
+            0xFF17 => self.ch2.envelope.reg,
+            0xFF19 => u8::from(self.ch2.length.enabled) << 6,
+
+            0xFF1A => u8::from(self.ch3.dac) << 7,
+            0xFF1C => self.ch3.level << 5,
+            0xFF1E => u8::from(self.ch3.length.enabled) << 6,
+
+            0xFF21 => self.ch4.envelope.reg,
+            0xFF22 => self.ch4.poly,
+            0xFF23 => u8::from(self.ch4.length.enabled) << 6,
+
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => u8::from(self.power) << 7 | self.status(),
+
+            // Write-only (frequency, length) and unused registers
+            0xFF13 | 0xFF15 | 0xFF18 | 0xFF1B | 0xFF1D | 0xFF1F | 0xFF20 | 0xFF27..=0xFF2F => 0,
+
+            // Wave RAM
+            0xFF30..=0xFF3F => self.ch3.ram[addr as usize - 0xFF30],
+
+            _ => unreachable!(),
+        };
+
+        match addr {
+            0xFF10..=0xFF2F => val | READ_MASK[addr as usize - 0xFF10],
+            _ => val,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        // Registers are read-only while powered off, except
+        // for the length counters (NRx1) on DMG.
+        if !self.power && (0xFF10..=0xFF25).contains(&addr) {
+            if !self.cgb {
+                match addr {
+                    0xFF11 => self.ch1.length.load(val),
+                    0xFF16 => self.ch2.length.load(val),
+                    0xFF1B => self.ch3.length.load(val),
+                    0xFF20 => self.ch4.length.load(val),
+                    _ => (),
+                }
+            }
+            return;
+        }
+
+        match addr {
+            // Channel 1 - Pulse with sweep
+            0xFF10 => self.ch1.sweep_reg = val & 0x7F,
+            0xFF11 => {
+                self.ch1.duty = val >> 6;
+                self.ch1.length.load(val);
+            }
+            0xFF12 => {
+                self.ch1.envelope.reg = val;
+                if !self.ch1.envelope.dac_enabled() {
+                    self.ch1.enabled = false;
+                }
+            }
+            0xFF13 => self.ch1.freq = (self.ch1.freq & 0x700) | val as u16,
+            0xFF14 => {
+                if Self::write_nrx4(val, &mut self.ch1.freq, &mut self.ch1.length) {
+                    self.ch1.trigger();
+                }
+            }
+
+            // Channel 2 - Pulse
+            0xFF16 => {
+                self.ch2.duty = val >> 6;
+                self.ch2.length.load(val);
+            }
+            0xFF17 => {
+                self.ch2.envelope.reg = val;
+                if !self.ch2.envelope.dac_enabled() {
+                    self.ch2.enabled = false;
+                }
+            }
+            0xFF18 => self.ch2.freq = (self.ch2.freq & 0x700) | val as u16,
+            0xFF19 => {
+                if Self::write_nrx4(val, &mut self.ch2.freq, &mut self.ch2.length) {
+                    self.ch2.trigger();
+                }
+            }
+
+            // Channel 3 - Wave
+            0xFF1A => {
+                self.ch3.dac = val & 0x80 != 0;
+                if !self.ch3.dac {
+                    self.ch3.enabled = false;
+                }
+            }
+            0xFF1B => self.ch3.length.load(val),
+            0xFF1C => self.ch3.level = (val >> 5) & 0x03,
+            0xFF1D => self.ch3.freq = (self.ch3.freq & 0x700) | val as u16,
+            0xFF1E => {
+                if Self::write_nrx4(val, &mut self.ch3.freq, &mut self.ch3.length) {
+                    self.ch3.trigger();
+                }
+            }
+
+            // Channel 4 - Noise
+            0xFF20 => self.ch4.length.load(val),
+            0xFF21 => {
+                self.ch4.envelope.reg = val;
+                if !self.ch4.envelope.dac_enabled() {
+                    self.ch4.enabled = false;
+                }
+            }
+            0xFF22 => self.ch4.poly = val,
+            0xFF23 => {
+                self.ch4.length.enabled = val & NRX4_LENGTH != 0;
+                if val & NRX4_TRIGGER != 0 {
+                    self.ch4.trigger();
+                }
+            }
+
+            // NR50 - Master volume
+            0xFF24 => self.nr50 = val,
+
+            // NR51 - Panning
+            0xFF25 => self.nr51 = val,
+
+            // NR52 - Power, channel status bits are read-only
+            0xFF26 => {
+                if val & NR52_POWER == 0 {
+                    self.power_off();
+                } else if !self.power {
+                    self.power = true;
+                    self.frame_seq_timer = FRAME_SEQ_PERIOD;
+                    self.frame_seq_step = 0;
+                }
+            }
+
+            // Unused
+            0xFF15 | 0xFF1F | 0xFF27..=0xFF2F => (),
+
+            // Wave RAM
+            0xFF30..=0xFF3F => self.ch3.ram[addr as usize - 0xFF30] = val,
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"APU ");
+        self.ch1.save_state(w);
+        self.ch2.save_state(w);
+        self.ch3.save_state(w);
+        self.ch4.save_state(w);
+        w.u8(self.nr50);
+        w.u8(self.nr51);
+        w.bool(self.power);
+        w.u32(self.frame_seq_timer as u32);
+        w.u8(self.frame_seq_step);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"APU ", "APU")?;
+        self.ch1.load_state(r)?;
+        self.ch2.load_state(r)?;
+        self.ch3.load_state(r)?;
+        self.ch4.load_state(r)?;
+        self.nr50 = r.u8()?;
+        self.nr51 = r.u8()?;
+        self.power = r.bool()?;
+        self.frame_seq_timer = r.u32()? as usize;
+        self.frame_seq_step = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameboy::ppu::CYCLES_PER_FRAME;
+
+    fn apu() -> Apu {
+        let mut a = Apu::new();
+        a.write(0xFF26, 0x80);
+        a.write(0xFF24, 0x77);
+        a.write(0xFF25, 0xFF);
+        a
+    }
+
+    #[test]
+    fn read_masks() {
+        let mut a = apu();
+        for addr in 0xFF10..=0xFF25 {
+            a.write(addr, 0x00);
+        }
+        for addr in 0xFF10..=0xFF2F {
+            assert_eq!(
+                a.read(addr),
+                READ_MASK[addr as usize - 0xFF10] | if addr == 0xFF26 { 0x80 } else { 0 },
+                "{:04X}",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn power_off() {
+        let mut a = apu();
+        a.write(0xFF30, 0x12);
+        a.write(0xFF12, 0xF0);
+        a.write(0xFF14, 0x80);
+        assert_eq!(a.read(0xFF26), 0xF1);
+
+        a.write(0xFF26, 0x00);
+        assert_eq!(a.read(0xFF26), 0x70);
+        assert_eq!(a.read(0xFF12), 0x00);
+        assert_eq!(a.read(0xFF24), 0x00);
+        assert_eq!(a.read(0xFF30), 0x12);
+
+        // Writes are ignored while off
+        a.write(0xFF12, 0xF0);
+        assert_eq!(a.read(0xFF12), 0x00);
+    }
+
+    #[test]
+    fn high_pass() {
+        assert_eq!(Apu::new().high_pass(), HighPass::Dmg);
+        assert_eq!(Apu::new_cgb().high_pass(), HighPass::Cgb);
+
+        for (high_pass, factor) in [
+            (HighPass::Dmg, HPF_CHARGE_DMG),
+            (HighPass::Cgb, HPF_CHARGE_CGB),
+        ] {
+            let mut a = Apu::new();
+            a.set_high_pass(high_pass);
+            let charge = factor.powf(CPU_CLOCK as f64 / DEFAULT_SAMPLE_RATE as f64);
+            // DC decays by the charge factor every sample
+            for n in 0..1000 {
+                let (l, r) = a.high_pass_filter(1.0, -1.0);
+                let expected = charge.powi(n) as f32;
+                assert!((l - expected).abs() < 1e-3, "{:?} {}", high_pass, n);
+                assert!((r + expected).abs() < 1e-3, "{:?} {}", high_pass, n);
+            }
+            assert!(a.high_pass_filter(1.0, 1.0).0 < 0.9);
+        }
+
+        let mut a = Apu::new();
+        a.set_high_pass(HighPass::Off);
+        for _ in 0..1000 {
+            assert_eq!(a.high_pass_filter(1.0, -1.0), (1.0, -1.0));
+        }
+    }
+
+    #[test]
+    fn channel_levels() {
+        let mut a = apu();
+        assert_eq!(a.channel_levels(), [0.0; CHANNELS]);
+
+        // Channel 2 playing at full volume, channel 4 with
+        // its DAC on but not triggered
+        a.write(0xFF16, 0x80);
+        a.write(0xFF17, 0xF0);
+        a.write(0xFF19, 0x87);
+        a.write(0xFF21, 0xF0);
+        a.tick(CPU_CLOCK / 100);
+        let levels = a.channel_levels();
+        assert!(levels[1] > 0.5, "{:?}", levels);
+        assert_eq!(levels[0], 0.0);
+        assert_eq!(levels[2], 0.0);
+        assert_eq!(levels[3], 0.0);
+
+        // Levels decay once the channel stops
+        a.write(0xFF17, 0x00);
+        a.tick(CPU_CLOCK / 5);
+        assert!(a.channel_levels()[1] < 0.05);
+    }
+
+    #[test]
+    fn power_off_length_dmg() {
+        let mut a = apu();
+        a.write(0xFF11, 0x30);
+        a.write(0xFF1B, 0x00);
+        a.write(0xFF26, 0x00);
+        assert_eq!(a.ch1.length.counter, 16);
+        assert_eq!(a.ch3.length.counter, 256);
+
+        // Length can be loaded while off, duty cannot
+        a.write(0xFF11, 0xFE);
+        assert_eq!(a.ch1.length.counter, 2);
+        assert_eq!(a.read(0xFF11), 0x3F);
+        a.write(0xFF20, 0x3F);
+        assert_eq!(a.ch4.length.counter, 1);
+
+        a.write(0xFF26, 0x80);
+        assert_eq!(a.ch1.length.counter, 2);
+    }
+
+    #[test]
+    fn power_off_length_cgb() {
+        let mut a = Apu::new_cgb();
+        a.write(0xFF26, 0x80);
+        a.write(0xFF11, 0x30);
+        a.write(0xFF26, 0x00);
+        assert_eq!(a.ch1.length.counter, 0);
+
+        a.write(0xFF11, 0x3E);
+        assert_eq!(a.ch1.length.counter, 0);
+    }
+
+    #[test]
+    fn trigger_dac() {
+        let mut a = apu();
+        // DAC off, trigger does not enable
+        a.write(0xFF17, 0x00);
+        a.write(0xFF19, 0x80);
+        assert_eq!(a.read(0xFF26) & 0x02, 0);
+
+        a.write(0xFF17, 0x80);
+        a.write(0xFF19, 0x80);
+        assert_eq!(a.read(0xFF26) & 0x02, 0x02);
+
+        // Turning the DAC off disables the channel
+        a.write(0xFF17, 0x00);
+        assert_eq!(a.read(0xFF26) & 0x02, 0);
+
+        a.write(0xFF1A, 0x80);
+        a.write(0xFF1E, 0x80);
+        assert_eq!(a.read(0xFF26) & 0x04, 0x04);
+        a.write(0xFF21, 0x08);
+        a.write(0xFF23, 0x80);
+        assert_eq!(a.read(0xFF26) & 0x08, 0x08);
+    }
+
+    #[test]
+    fn length() {
+        let mut a = apu();
+        a.write(0xFF12, 0xF0);
+        a.write(0xFF11, 0x3E); // Length 2
+        a.write(0xFF14, 0xC0);
+        assert_eq!(a.read(0xFF26) & 0x01, 0x01);
+
+        // Clocked on steps 0 and 2
+        a.tick(FRAME_SEQ_PERIOD);
+        assert_eq!(a.read(0xFF26) & 0x01, 0x01);
+        a.tick(FRAME_SEQ_PERIOD * 2);
+        assert_eq!(a.read(0xFF26) & 0x01, 0);
+    }
+
+    #[test]
+    fn length_disabled() {
+        let mut a = apu();
+        a.write(0xFF12, 0xF0);
+        a.write(0xFF11, 0x3F);
+        a.write(0xFF14, 0x80);
+        a.tick(FRAME_SEQ_PERIOD * 8);
+        assert_eq!(a.read(0xFF26) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn sweep_overflow() {
+        let mut a = apu();
+        a.write(0xFF12, 0xF0);
+        a.write(0xFF13, 0xFF);
+        // Shift 1, frequency 0x7FF overflows on trigger
+        a.write(0xFF10, 0x11);
+        a.write(0xFF14, 0x87);
+        assert_eq!(a.read(0xFF26) & 0x01, 0);
+
+        // Increases until it overflows
+        a.write(0xFF13, 0x00);
+        a.write(0xFF14, 0x82);
+        assert_eq!(a.read(0xFF26) & 0x01, 0x01);
+        a.tick(FRAME_SEQ_PERIOD * 8);
+        assert_eq!(a.read(0xFF26) & 0x01, 0x01);
+        assert_eq!(a.ch1.freq, 0x480);
+        a.tick(FRAME_SEQ_PERIOD * 8);
+        assert_eq!(a.read(0xFF26) & 0x01, 0);
+    }
+
+    #[test]
+    fn envelope() {
+        let mut a = apu();
+        a.write(0xFF12, 0x21); // Volume 2, decreasing, period 1
+        a.write(0xFF14, 0x80);
+        assert_eq!(a.ch1.envelope.volume, 2);
+        a.tick(FRAME_SEQ_PERIOD * 8);
+        assert_eq!(a.ch1.envelope.volume, 1);
+        a.tick(FRAME_SEQ_PERIOD * 8 * 4);
+        assert_eq!(a.ch1.envelope.volume, 0);
+    }
+
+    #[test]
+    fn sample_count() {
+        let mut a = apu();
+        a.set_sample_rate(48000);
+        let mut total = 0;
+        for _ in 0..60 {
+            a.tick(CYCLES_PER_FRAME);
+            let n = a.take_samples().len() / 2;
+            // 803.7 samples per frame
+            assert!(n == 803 || n == 804, "{}", n);
+            total += n;
+        }
+        assert_eq!(total, 60 * CYCLES_PER_FRAME * 48000 / CPU_CLOCK);
+
+        // Not restarted by a reset
+        a.tick(CYCLES_PER_FRAME / 2);
+        a.reset();
+        a.tick(CYCLES_PER_FRAME / 2);
+        total += a.take_samples().len() / 2;
+        assert_eq!(total, 61 * CYCLES_PER_FRAME * 48000 / CPU_CLOCK);
+    }
+
+    #[test]
+    fn samples() {
+        let mut a = apu();
+        a.set_sample_rate(32768);
+        a.tick(CPU_CLOCK / 128);
+        assert_eq!(a.take_samples().len(), 2 * 256);
+        assert!(a.take_samples().is_empty());
+
+        // Square wave at full volume, left only
+        a.write(0xFF25, 0x10);
+        a.write(0xFF12, 0xF0);
+        a.write(0xFF11, 0x80);
+        a.write(0xFF14, 0x87);
+        a.tick(CPU_CLOCK / 128);
+        let s = a.take_samples();
+        assert!(s.iter().step_by(2).any(|&l| l > 0.2));
+        assert!(s.iter().step_by(2).any(|&l| l < -0.2));
+        assert!(s.iter().skip(1).step_by(2).all(|&r| r == 0.0));
+    }
+
+    #[test]
+    fn mute_and_capture() {
+        let mut a = apu();
+        // Channels are captured unfiltered
+        a.set_high_pass(HighPass::Off);
+        a.set_capture_channels(true);
+        // Square wave at full volume on both sides
+        a.write(0xFF25, 0x11);
+        a.write(0xFF12, 0xF0);
+        a.write(0xFF11, 0x80);
+        a.write(0xFF14, 0x87);
+        a.tick(CPU_CLOCK / 128);
+        let mixed = a.take_samples();
+        let channels = a.take_channel_samples();
+        assert_eq!(mixed, channels[0]);
+        assert!(channels[1..].iter().all(|c| c.len() == mixed.len()));
+        assert!(a.take_channel_samples().iter().all(|c| c.is_empty()));
+
+        a.solo(Some(1));
+        assert!(a.is_muted(0) && !a.is_muted(1));
+        a.tick(CPU_CLOCK / 128);
+        assert!(a.take_samples().iter().all(|&s| s == 0.0));
+        // Channels are captured before muting
+        assert!(a.take_channel_samples()[0].iter().any(|&s| s != 0.0));
+
+        a.solo(None);
+        a.set_capture_channels(false);
+        a.tick(CPU_CLOCK / 128);
+        assert!(a.take_samples().iter().any(|&s| s != 0.0));
+        assert!(a.take_channel_samples().iter().all(|c| c.is_empty()));
+    }
+
+    #[test]
+    fn noise_lfsr() {
+        let mut a = apu();
+        a.write(0xFF21, 0xF0);
+        a.write(0xFF22, 0x00);
+        a.write(0xFF23, 0x80);
+        a.tick(8);
+        assert_eq!(a.ch4.lfsr, 0x3FFF);
+
+        // 7-bit mode also feeds back into bit 6
+        a.write(0xFF22, 0x08);
+        a.write(0xFF23, 0x80);
+        a.tick(8);
+        assert_eq!(a.ch4.lfsr, 0x3FBF);
+    }
+
+    #[test]
+    fn save_state() {
+        let mut a = apu();
+        a.write(0xFF12, 0xF3);
+        a.write(0xFF14, 0x80);
+        a.write(0xFF30, 0xAB);
+        a.tick(12345);
+        let mut w = StateWriter::new();
+        a.save_state(&mut w).unwrap();
+        let state = w.into_inner();
+
+        let mut b = Apu::new();
+        b.load_state(&mut StateReader::new(&state)).unwrap();
+        for addr in 0xFF10..=0xFF3F {
+            assert_eq!(a.read(addr), b.read(addr), "{:04X}", addr);
+        }
+        assert_eq!(a.ch1.timer, b.ch1.timer);
+    }
+}
@@ -1,9 +1,27 @@
+use anyhow::{bail, Result};
+
+use super::super::savestate::{StateErr, StateReader, StateWriter};
+
+/// Memory as seen by the CPU, and the machine's sense of time
+///
+/// Only what the CPU itself needs goes here: memory access,
+/// timing and machine state. Features of a particular bus
+/// (e.g. the Game Boy's PPU or serial port) are accessed on
+/// that bus.
 pub trait Bus {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, val: u8);
 
+    /// Reads memory as the CPU sees it, for anything but the
+    /// data accesses of instructions (instruction fetches,
+    /// interrupt checks, debuggers). Unlike read(), this is
+    /// not seen by hooks or coverage.
+    fn peek(&self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
     fn write_slice(&mut self, from: &[u8], offset: u16) {
-        for (i, b) in from.into_iter().enumerate() {
+        for (i, b) in from.iter().enumerate() {
             self.write(offset.wrapping_add(i as u16), *b);
         }
     }
@@ -19,16 +37,117 @@ pub trait Bus {
     fn read16(&self, addr: u16) -> u16 {
         u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
     }
+
+    /// Called by the CPU before it executes the 'len' byte
+    /// instruction at 'pc'.
+    fn execute(&mut self, _pc: u16, _len: usize) {}
+
+    /// Advances peripherals on the bus by the amount
+    /// of CPU cycles that have passed.
+    fn tick(&mut self, _cycles: usize) {}
+
+    /// Called by the CPU on STOP. Returns true if the bus
+    /// switched clock speeds (CGB), after which the CPU keeps
+    /// running.
+    fn stop(&mut self) -> bool {
+        false
+    }
+
+    /// ROM bank mapped at a ROM address (0x0000 - 0x7FFF),
+    /// or 0 for addresses outside ROM.
+    fn rom_bank(&self, addr: u16) -> usize {
+        (0x4000..0x8000).contains(&addr).into()
+    }
+
+    /// Puts the bus in the state the boot ROM leaves it
+    /// in, as if it had just run.
+    fn skip_boot_rom(&mut self) {}
+
+    /// True if the bus runs in CGB mode.
+    fn cgb_mode(&self) -> bool {
+        false
+    }
+
+    /// Serializes the state of the bus and everything
+    /// attached to it.
+    fn save_state(&self, _w: &mut StateWriter) -> Result<()> {
+        bail!(StateErr::Unsupported)
+    }
+
+    /// Restores a state written by save_state(). The bus must
+    /// be configured the same way (e.g. the same cartridge).
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<()> {
+        bail!(StateErr::Unsupported)
+    }
+}
+
+/// Boxed buses, so CPU<Box<dyn Bus>> can take any bus
+impl<T: Bus + ?Sized> Bus for Box<T> {
+    fn read(&self, addr: u16) -> u8 {
+        (**self).read(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        (**self).write(addr, val)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        (**self).peek(addr)
+    }
+
+    fn write_slice(&mut self, from: &[u8], offset: u16) {
+        (**self).write_slice(from, offset)
+    }
+
+    fn write16(&mut self, addr: u16, val: u16) {
+        (**self).write16(addr, val)
+    }
+
+    fn read16(&self, addr: u16) -> u16 {
+        (**self).read16(addr)
+    }
+
+    fn execute(&mut self, pc: u16, len: usize) {
+        (**self).execute(pc, len)
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        (**self).tick(cycles)
+    }
+
+    fn stop(&mut self) -> bool {
+        (**self).stop()
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        (**self).rom_bank(addr)
+    }
+
+    fn skip_boot_rom(&mut self) {
+        (**self).skip_boot_rom()
+    }
+
+    fn cgb_mode(&self) -> bool {
+        (**self).cgb_mode()
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        (**self).save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        (**self).load_state(r)
+    }
 }
 
-pub struct BusIterator<'a> {
-    bus: &'a Box<dyn Bus>,
+pub struct BusIterator<'a, B: Bus + ?Sized = dyn Bus> {
+    bus: &'a B,
     next: u16,
     finished: bool,
 }
 
-impl<'a> BusIterator<'a> {
-    pub fn new_from(bus: &'a Box<dyn Bus>, offset: u16) -> BusIterator {
+impl<'a, B: Bus + ?Sized> BusIterator<'a, B> {
+    pub fn new_from(bus: &'a B, offset: u16) -> Self {
         BusIterator {
             bus,
             next: offset,
@@ -36,12 +155,12 @@ impl<'a> BusIterator<'a> {
         }
     }
 
-    pub fn new(bus: &'a Box<dyn Bus>) -> BusIterator {
+    pub fn new(bus: &'a B) -> Self {
         Self::new_from(bus, 0)
     }
 }
 
-impl<'a> Iterator for BusIterator<'a> {
+impl<'a, B: Bus + ?Sized> Iterator for BusIterator<'a, B> {
     type Item = u8;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -56,7 +175,7 @@ impl<'a> Iterator for BusIterator<'a> {
             self.next += 1;
         }
 
-        Some(self.bus.read(curr))
+        Some(self.bus.peek(curr))
     }
 }
 
@@ -76,7 +195,7 @@ mod tests {
     #[test]
     fn busiterator_new() {
         let b: Box<dyn Bus> = Box::new(testbus());
-        let mut i = BusIterator::new(&b);
+        let mut i = BusIterator::new(b.as_ref());
 
         for a in 0..=u16::MAX {
             assert_eq!(i.next(), Some(a as u8));
@@ -87,7 +206,7 @@ mod tests {
     #[test]
     fn busiterator_new_from() {
         let b: Box<dyn Bus> = Box::new(testbus());
-        let mut i = BusIterator::new_from(&b, 5);
+        let mut i = BusIterator::new_from(b.as_ref(), 5);
 
         for a in 5..=u16::MAX {
             assert_eq!(i.next(), Some(a as u8));
@@ -1,153 +1,462 @@
-use super::super::iomux::IOMux;
+use std::cell::RefCell;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+
+use super::super::apu::Apu;
+use super::super::cartridge::cartridge::Cartridge;
+use super::super::cartridge::header::LOGO;
+use super::super::coverage::{Coverage, EXECUTED, READ, WRITTEN};
+use super::super::dma::OamDma;
+use super::super::hdma::{Hdma, BLOCK_SIZE};
+use super::super::iomux::{IOMux, INT_JOYPAD, INT_SERIAL, INT_STAT, INT_TIMER, INT_VBLANK};
+use super::super::joypad::{Button, Joypad};
+use super::super::link::SerialLink;
+use super::super::model::Model;
+use super::super::ppu::{Frame, Ppu, Renderer};
+use super::super::profiler::{Location, Subsystem, SubsystemTimes};
+use super::super::savestate::{StateErr, StateReader, StateWriter};
+use super::super::serial::{Serial, SerialOutput};
+use super::super::timer::Timer;
 use super::bus::Bus;
 
-const CART_ROM_BANK_SIZE: usize = 16 * 1024;
+/// Size of a WRAM bank; DMG has 2 banks, CGB has 8.
+const WRAM_BANK_SIZE: usize = 0x1000;
+const HRAM_SIZE: usize = 0xFFFE - 0xFF80 + 1;
 
-type CartROMBank = [u8; CART_ROM_BANK_SIZE];
+/// KEY1 bit 0 - Speed switch armed
+const KEY1_ARMED: u8 = 1 << 0;
 
 /// Multiplexer for the Gameboy address bus
 pub struct Gameboybus {
-    boot_rom: [u8; 256],
+    /// Boot ROM, 256 bytes (DMG) or 2304 bytes (CGB, of
+    /// which 0x0100 - 0x01FF is unused)
+    boot_rom: Vec<u8>,
 
-    cart_rom: [CartROMBank; 2],
+    /// Cartridge (ROM and external RAM)
+    cart: Box<dyn Cartridge>,
 
     boot_rom_enabled: bool,
 
-    ext_ram: [u8; u16::MAX as usize + 1],
-    wram: [u8; u16::MAX as usize + 1],
-    vram: [u8; u16::MAX as usize + 1],
-    hram: [u8; u16::MAX as usize + 1],
+    /// Show the boot ROM a valid logo and header checksum
+    lenient_boot: bool,
+
+    /// CGB mode
+    cgb: bool,
+
+    wram: Vec<u8>,
+    hram: [u8; HRAM_SIZE],
+
+    /// SVBK - WRAM bank at 0xD000 (CGB)
+    svbk: u8,
+
+    /// KEY1 - Speed switch armed (CGB)
+    speed_armed: bool,
+
+    /// CPU runs at double speed (CGB)
+    double_speed: bool,
+
+    /// Odd T-cycle left over in double speed mode
+    speed_remainder: usize,
 
     io: IOMux,
+
+    /// PPU (VRAM, OAM and LCD registers)
+    ppu: Ppu,
+
+    timer: Timer,
+
+    /// APU (sound registers and wave RAM)
+    apu: Apu,
+
+    /// OAM DMA controller
+    dma: OamDma,
+
+    /// VRAM DMA controller (CGB)
+    hdma: Hdma,
+
+    joypad: Joypad,
+
+    serial: Serial,
+
+    /// IE register
+    ie: u8,
+
+    /// Time spent per subsystem, if profiling
+    timing: Option<SubsystemTimes>,
+
+    /// Cartridge ROM accessed, if keeping track of coverage
+    coverage: Option<RefCell<Coverage>>,
 }
 
 impl Gameboybus {
-    pub fn new(cart: &[u8], bootrom: Option<&[u8]>) -> Self {
+    /// DMG bus
+    pub fn new(cart: Box<dyn Cartridge>, bootrom: Option<&[u8]>) -> Self {
+        Self::with_model(cart, bootrom, Model::Dmg)
+    }
+
+    pub fn with_model(cart: Box<dyn Cartridge>, bootrom: Option<&[u8]>, model: Model) -> Self {
+        let cgb = model.is_cgb();
         let mut bus = Gameboybus {
-            cart_rom: [[0; CART_ROM_BANK_SIZE]; 2],
-            boot_rom: [0; 256],
+            cart,
+            boot_rom: vec![],
             boot_rom_enabled: false,
+            lenient_boot: false,
+            cgb,
+
+            wram: vec![0; WRAM_BANK_SIZE * if cgb { 8 } else { 2 }],
+            hram: [0; HRAM_SIZE],
+            svbk: 0,
+            speed_armed: false,
+            double_speed: false,
+            speed_remainder: 0,
 
-            ext_ram: [0; u16::MAX as usize + 1],
-            wram: [0; u16::MAX as usize + 1],
-            hram: [0; u16::MAX as usize + 1],
-            vram: [0; u16::MAX as usize + 1],
+            io: IOMux::new(),
+            ppu: if cgb { Ppu::new_cgb() } else { Ppu::new() },
+            timer: Timer::new(),
+            apu: if cgb { Apu::new_cgb() } else { Apu::new() },
+            dma: OamDma::new(),
+            hdma: Hdma::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
 
-            io: IOMux {},
+            ie: 0,
+            timing: None,
+            coverage: None,
         };
 
         if let Some(br) = bootrom {
-            bus.boot_rom.copy_from_slice(br);
+            bus.boot_rom = br.to_vec();
             bus.boot_rom_enabled = true;
         }
 
-        // TODO support cartridges with different
-        // amount of banks etc.
-        assert_eq!(cart.len(), 32 * 1024);
-        bus.cart_rom[0].copy_from_slice(&cart[0..(16 * 1024)]);
-        bus.cart_rom[1].copy_from_slice(&cart[(16 * 1024)..]);
-
         bus
     }
-}
 
-impl Bus for Gameboybus {
-    fn read(&self, addr: u16) -> u8 {
+    pub fn get_ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    /// Soft reset: puts everything back in the power-on
+    /// state, clearing WRAM and VRAM. Of the cartridge, only
+    /// the memory bank controller is reset; its RAM and clock
+    /// are kept. The boot ROM runs again, if there is one.
+    /// Settings like the renderer, audio output and serial
+    /// connections are kept, as are the buttons held.
+    pub fn reset(&mut self) {
+        self.boot_rom_enabled = self.has_boot_rom();
+        self.cart.reset();
+        self.wram.fill(0);
+        self.hram = [0; HRAM_SIZE];
+        self.svbk = 0;
+        self.speed_armed = false;
+        self.double_speed = false;
+        self.speed_remainder = 0;
+
+        self.io = IOMux::new();
+        self.ppu.reset();
+        self.timer = Timer::new();
+        self.apu.reset();
+        self.dma = OamDma::new();
+        self.hdma = Hdma::new();
+        self.serial.reset();
+        self.ie = 0;
+    }
+
+    pub fn has_boot_rom(&self) -> bool {
+        !self.boot_rom.is_empty()
+    }
+
+    /// Lets the boot ROM pass cartridges without the Nintendo
+    /// logo or with a bad header checksum (e.g. homebrew),
+    /// instead of locking up: while it is mapped, it reads
+    /// the logo and the correct checksum from the header.
+    /// The boot ROM then shows the Nintendo logo.
+    pub fn set_lenient_boot(&mut self, lenient: bool) {
+        self.lenient_boot = lenient;
+    }
+
+    /// The cartridge, e.g. to inspect or force its banking.
+    pub fn cartridge(&mut self) -> &mut dyn Cartridge {
+        self.cart.as_mut()
+    }
+
+    /// Enables or disables keeping track of the time spent
+    /// in each subsystem, for the profiler.
+    pub fn set_subsystem_timing(&mut self, enable: bool) {
+        self.timing = enable.then(SubsystemTimes::default);
+    }
+
+    /// Returns and clears the time spent in each subsystem
+    /// since the last call, if timing is enabled.
+    pub fn take_subsystem_times(&mut self) -> Option<SubsystemTimes> {
+        self.timing.as_mut().map(std::mem::take)
+    }
+
+    /// Enables or disables keeping track of the cartridge
+    /// ROM bytes executed, read and written.
+    pub fn set_coverage(&mut self, enable: bool) {
+        self.coverage = enable.then(|| RefCell::new(Coverage::new()));
+    }
+
+    /// Stops keeping track of coverage and returns the
+    /// coverage so far, if enabled.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take().map(RefCell::into_inner)
+    }
+
+    /// Returns and clears the data sent over the serial
+    /// port since the last call.
+    pub fn take_serial_output(&mut self) -> String {
+        self.serial.take_output()
+    }
+
+    /// Sends serial port output to 'output' instead of
+    /// buffering it for take_serial_output().
+    pub fn set_serial_output(&mut self, output: Box<dyn SerialOutput>) {
+        self.serial.set_output(output);
+    }
+
+    /// Connects the serial port to a link partner.
+    pub fn set_serial_link(&mut self, link: Box<dyn SerialLink>) {
+        self.serial.set_link(link);
+    }
+
+    /// Returns and clears the audio samples produced since
+    /// the last call (interleaved stereo).
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    /// Sets the audio output sample rate in Hz.
+    pub fn set_audio_sample_rate(&mut self, rate: usize) {
+        self.apu.set_sample_rate(rate);
+    }
+
+    /// Audio Processing Unit (channel muting and capture)
+    pub fn apu(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /// Picture Processing Unit (VRAM and OAM viewers)
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn frame(&self) -> &Frame {
+        self.ppu.get_framebuffer()
+    }
+
+    /// Returns true once after a full frame was drawn.
+    pub fn frame_ready(&mut self) -> bool {
+        self.ppu.frame_ready()
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.ppu.set_renderer(renderer);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.joypad.set_button(button, pressed);
+    }
+
+    /// Applies buttons set with set_button() only at the start
+    /// of V-blank, so that the program sees input change at
+    /// the same point in a frame, no matter when the frontend
+    /// set it. With the LCD off, input is applied right away.
+    pub fn set_input_latch(&mut self, latched: bool) {
+        self.joypad.set_latched(latched);
+    }
+
+    /// Charges the time since 'clock' to a subsystem and
+    /// restarts the clock, if profiling.
+    fn lap(&mut self, clock: &mut Option<Instant>, subsystem: Subsystem) {
+        if let (Some(start), Some(times)) = (clock, &mut self.timing) {
+            let now = Instant::now();
+            times[subsystem as usize] += now - *start;
+            *start = now;
+        }
+    }
+
+    /// Adds 'flags' to the coverage of the cartridge ROM at
+    /// 'addr', if keeping track of coverage.
+    fn cover(&self, addr: u16, flags: u8) {
+        if let Some(coverage) = &self.coverage {
+            let loc = Location {
+                bank: self.cart.rom_bank(addr),
+                addr,
+            };
+            coverage.borrow_mut().mark(loc, flags);
+        }
+    }
+
+    /// True if the boot ROM is mapped at 'addr'.
+    fn in_boot_rom(&self, addr: usize) -> bool {
+        // The CGB boot ROM leaves the cartridge header visible
+        self.boot_rom_enabled && addr < self.boot_rom.len() && !(0x0100..0x0200).contains(&addr)
+    }
+
+    /// True if the boot ROM is shown a valid header.
+    fn patch_header(&self) -> bool {
+        self.lenient_boot && self.boot_rom_enabled
+    }
+
+    /// Header checksum the boot ROM expects for the
+    /// cartridge.
+    fn header_checksum(&self) -> u8 {
+        (0x0134..=0x014C).fold(0_u8, |acc, addr| {
+            acc.wrapping_sub(self.cart.read(addr)).wrapping_sub(1)
+        })
+    }
+
+    /// Offset into WRAM for 0xC000 - 0xDFFF, through the
+    /// bank selected in SVBK (bank 0 selects bank 1).
+    fn wram_addr(&self, addr: usize) -> usize {
+        match addr {
+            0xC000..=0xCFFF => addr - 0xC000,
+            _ => (self.svbk as usize).max(1) * WRAM_BANK_SIZE + (addr - 0xD000),
+        }
+    }
+
+    /// Copies the VRAM DMA blocks that are due.
+    fn run_hdma(&mut self) {
+        while let Some((src, dst)) = self.hdma.next_block() {
+            for i in 0..BLOCK_SIZE {
+                let val = self.read_mem(src.wrapping_add(i));
+                self.ppu.write(dst + i, val);
+            }
+        }
+    }
+
+    /// Reads memory, counting cartridge ROM as covered.
+    fn read_mem(&self, addr: u16) -> u8 {
+        if addr < 0x8000 && !self.in_boot_rom(addr.into()) {
+            self.cover(addr, READ);
+        }
+        self.peek_mem(addr)
+    }
+
+    /// Memory map as seen without OAM DMA bus conflicts
+    fn peek_mem(&self, addr: u16) -> u8 {
         let addr = addr as usize;
 
         match addr {
             // Boot ROM (or cartridge after disable)
-            0x0000..=0x00FF => {
-                if self.boot_rom_enabled {
-                    self.boot_rom[addr]
-                } else {
-                    self.cart_rom[0][addr]
-                }
-            }
+            _ if self.in_boot_rom(addr) => self.boot_rom[addr],
 
-            // Cartridge bank 0
-            0x0000..=0x3FFF => self.cart_rom[0][addr],
+            // Logo and header checksum, for lenient boot
+            0x0104..=0x0133 if self.patch_header() => LOGO[addr - 0x0104],
+            0x014D if self.patch_header() => self.header_checksum(),
 
-            // Cartridge bank 1
-            // TODO bank switching
-            0x4000..=0x7FFF => self.cart_rom[1][addr],
+            // Cartridge ROM (bank 0 and switchable bank)
+            0x0000..=0x7FFF => self.cart.read(addr as u16),
 
             // Video RAM
-            0x8000..=0x9FFF => self.vram[addr],
+            0x8000..=0x9FFF => self.ppu.read(addr as u16),
 
-            // External RAM
-            // TODO bank switching
-            0xA000..=0xBFFF => self.ext_ram[addr],
+            // External (cartridge) RAM
+            0xA000..=0xBFFF => self.cart.read(addr as u16),
 
             // Working RAM (fixed portion)
-            0xC000..=0xCFFF => self.wram[addr],
-
             // Working RAM (switchable on CGB)
-            // TODO bank switching
-            0xD000..=0xDFFF => self.wram[addr],
+            0xC000..=0xDFFF => self.wram[self.wram_addr(addr)],
 
-            // Echo RAM
-            0xE000..=0xFDFF => panic!("Read from Echo RAM"),
+            // Echo RAM (mirror of 0xC000 - 0xDDFF)
+            0xE000..=0xFDFF => self.wram[self.wram_addr(addr - 0x2000)],
 
             // Sprite Attribute Table (OAM)
-            0xFE00..=0xFE9F => todo!(),
+            0xFE00..=0xFE9F => self.ppu.read(addr as u16),
 
             // Unusable segment
-            0xFEA0..=0xFEFF => panic!("Read from unusable segment"),
+            0xFEA0..=0xFEFF => 0xFF,
 
             // Boot ROM disable
-            0xFF50 => {
-                if self.boot_rom_enabled {
-                    0
-                } else {
-                    1
-                }
+            // Write-only, reads as 0xFF.
+            0xFF50 => 0xFF,
+
+            // P1/JOYP - Joypad
+            0xFF00 => self.joypad.read(addr as u16),
+
+            // Serial port
+            0xFF01..=0xFF02 => self.serial.read(addr as u16),
+
+            // Timer
+            0xFF04..=0xFF07 => self.timer.read(addr as u16),
+
+            // Sound registers and wave RAM
+            0xFF10..=0xFF3F => self.apu.read(addr as u16),
+
+            // LCD registers (except 0xFF46, OAM DMA)
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read(addr as u16),
+
+            // OAM DMA
+            0xFF46 => self.dma.read(addr as u16),
+
+            // KEY1 - Speed switch (CGB)
+            // Bits 1-6 are unused and read as 1.
+            0xFF4D if self.cgb => {
+                0x7E | if self.double_speed { 0x80 } else { 0 }
+                    | if self.speed_armed { KEY1_ARMED } else { 0 }
             }
 
+            // VBK, BCPS/BCPD, OCPS/OCPD (CGB)
+            0xFF4F | 0xFF68..=0xFF6B if self.cgb => self.ppu.read(addr as u16),
+
+            // VRAM DMA (CGB)
+            0xFF51..=0xFF55 if self.cgb => self.hdma.read(addr as u16),
+
+            // SVBK - WRAM bank (CGB)
+            // Bits 3-7 are unused and read as 1.
+            0xFF70 if self.cgb => 0xF8 | self.svbk,
+
             // I/O registers
-            0xFF00..=0xFF7F => self.io.read(addr as u16),
+            0xFF03..=0xFF7F => self.io.read(addr as u16),
 
             // High RAM
-            0xFF80..=0xFFFE => self.hram[addr],
+            0xFF80..=0xFFFE => self.hram[addr - 0xFF80],
 
             // Interrupt Enable (IE) register
-            0xFFFF => todo!(),
+            // All 8 bits are readable/writable.
+            0xFFFF => self.ie,
 
             _ => unreachable!(),
         }
     }
 
-    fn write(&mut self, addr: u16, val: u8) {
+    fn write_mem(&mut self, addr: u16, val: u8) {
         let addr = addr as usize;
 
         match addr {
-            // Cartridge bank 0
-            0x0000..=0x3FFF |
-            // Cartridge bank 1
-            0x4000..=0x7FFF => println!("Write to read-only address {:04X}", addr),
+            // Cartridge (memory bank controller)
+            0x0000..=0x7FFF => {
+                self.cover(addr as u16, WRITTEN);
+                self.cart.write(addr as u16, val)
+            }
 
             // Video RAM
-            0x8000..=0x9FFF => self.vram[addr] = val,
+            0x8000..=0x9FFF => self.ppu.write(addr as u16, val),
 
-            // External RAM
-            // TODO bank switching
-            0xA000..=0xBFFF => self.ext_ram[addr] = val,
+            // External (cartridge) RAM
+            0xA000..=0xBFFF => self.cart.write(addr as u16, val),
 
             // Working RAM (fixed portion)
-            0xC000..=0xCFFF => self.wram[addr] = val,
-
             // Working RAM (switchable on CGB)
-            // TODO bank switching
-            0xD000..=0xDFFF => self.wram[addr] = val,
+            0xC000..=0xDFFF => {
+                let offset = self.wram_addr(addr);
+                self.wram[offset] = val;
+            }
 
-            // Echo RAM
-            0xE000..=0xFDFF => panic!("Write to Echo RAM"),
+            // Echo RAM (mirror of 0xC000 - 0xDDFF)
+            0xE000..=0xFDFF => {
+                let offset = self.wram_addr(addr - 0x2000);
+                self.wram[offset] = val;
+            }
 
             // Sprite Attribute Table (OAM)
-            0xFE00..=0xFE9F => todo!(),
+            0xFE00..=0xFE9F => self.ppu.write(addr as u16, val),
 
             // Unusable segment
-            0xFEA0..=0xFEFF => panic!("Write to unusable segment"),
+            0xFEA0..=0xFEFF => (),
 
             // Boot ROM disable
             0xFF50 => if val > 0 && self.boot_rom_enabled {
@@ -155,48 +464,517 @@ impl Bus for Gameboybus {
                 self.boot_rom_enabled = false;
             },
 
+            // P1/JOYP - Joypad
+            0xFF00 => self.joypad.write(addr as u16, val),
+
+            // Serial port
+            0xFF01..=0xFF02 => self.serial.write(addr as u16, val),
+
+            // Timer
+            0xFF04..=0xFF07 => self.timer.write(addr as u16, val),
+
+            // Sound registers and wave RAM
+            0xFF10..=0xFF3F => self.apu.write(addr as u16, val),
+
+            // LCD registers (except 0xFF46, OAM DMA)
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.write(addr as u16, val),
+
+            // OAM DMA
+            0xFF46 => self.dma.write(addr as u16, val),
+
+            // KEY1 - Speed switch (CGB), takes effect on STOP
+            0xFF4D if self.cgb => self.speed_armed = val & KEY1_ARMED != 0,
+
+            // VBK, BCPS/BCPD, OCPS/OCPD (CGB)
+            0xFF4F | 0xFF68..=0xFF6B if self.cgb => self.ppu.write(addr as u16, val),
+
+            // VRAM DMA (CGB)
+            0xFF51..=0xFF55 if self.cgb => {
+                self.hdma.write(addr as u16, val);
+                self.run_hdma();
+            }
+
+            // SVBK - WRAM bank (CGB)
+            0xFF70 if self.cgb => self.svbk = val & 0x07,
+
             // I/O registers
-            0xFF00..=0xFF7F => self.io.write(addr as u16, val),
+            0xFF03..=0xFF7F => self.io.write(addr as u16, val),
 
             // High RAM
-            0xFF80..=0xFFFE => self.hram[addr] = val,
+            0xFF80..=0xFFFE => self.hram[addr - 0xFF80] = val,
 
             // Interrupt Enable (IE) register
-            0xFFFF => todo!(),
+            0xFFFF => self.ie = val,
 
             _ => unreachable!(),
         }
     }
 }
 
+impl Bus for Gameboybus {
+    fn read(&self, addr: u16) -> u8 {
+        // During OAM DMA, the CPU can only reach I/O and HRAM.
+        if self.dma.is_active() && addr < 0xFF00 {
+            return 0xFF;
+        }
+        // VRAM and OAM are locked while the PPU uses them.
+        if !self.ppu.cpu_accessible(addr) {
+            return 0xFF;
+        }
+        self.read_mem(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        if self.dma.is_active() && addr < 0xFF00 {
+            return 0xFF;
+        }
+        if !self.ppu.cpu_accessible(addr) {
+            return 0xFF;
+        }
+        self.peek_mem(addr)
+    }
+
+    /// Counts the instruction as covered.
+    fn execute(&mut self, pc: u16, len: usize) {
+        if self.coverage.is_none() {
+            return;
+        }
+        for i in 0..len {
+            let addr = pc.wrapping_add(i as u16);
+            if addr < 0x8000 && !self.in_boot_rom(addr.into()) {
+                self.cover(addr, EXECUTED);
+            }
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if self.dma.is_active() && addr < 0xFF00 {
+            return;
+        }
+        if !self.ppu.cpu_accessible(addr) {
+            return;
+        }
+        self.write_mem(addr, val)
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        // In double speed mode, the PPU, APU and RTC keep
+        // running at normal speed.
+        let slow_cycles = if self.double_speed {
+            let total = cycles + self.speed_remainder;
+            self.speed_remainder = total % 2;
+            total / 2
+        } else {
+            cycles
+        };
+
+        let mut clock = self.timing.is_some().then(Instant::now);
+        self.ppu.tick(slow_cycles);
+        self.lap(&mut clock, Subsystem::Ppu);
+        self.timer.tick(cycles);
+        self.lap(&mut clock, Subsystem::Timer);
+        self.apu.tick(slow_cycles);
+        self.lap(&mut clock, Subsystem::Apu);
+        self.cart.tick(slow_cycles);
+        self.lap(&mut clock, Subsystem::Cartridge);
+        self.serial.tick(cycles);
+        self.lap(&mut clock, Subsystem::Serial);
+
+        self.dma.tick(cycles);
+        while let Some((src, offset)) = self.dma.next_transfer() {
+            let val = self.read_mem(src);
+            self.ppu.write_oam(offset, val);
+        }
+
+        if self.ppu.get_clr_hblank() {
+            self.hdma.hblank();
+            self.run_hdma();
+        }
+        self.lap(&mut clock, Subsystem::Dma);
+
+        if self.ppu.get_clr_intreq_vblank() {
+            self.joypad.latch();
+            self.io.request_interrupt(INT_VBLANK);
+        } else if !self.ppu.lcd_enabled() {
+            // No frames to wait for
+            self.joypad.latch();
+        }
+        if self.ppu.get_clr_intreq_stat() {
+            self.io.request_interrupt(INT_STAT);
+        }
+        if self.timer.get_clr_intreq() {
+            self.io.request_interrupt(INT_TIMER);
+        }
+        if self.joypad.get_clr_intreq() {
+            self.io.request_interrupt(INT_JOYPAD);
+        }
+        if self.serial.get_clr_intreq() {
+            self.io.request_interrupt(INT_SERIAL);
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x8000 && !self.in_boot_rom(addr.into()) {
+            self.cart.rom_bank(addr)
+        } else {
+            0
+        }
+    }
+
+    fn skip_boot_rom(&mut self) {
+        self.boot_rom_enabled = false;
+
+        // The boot ROM plays its chime on channel 1
+        for (addr, val) in [
+            (0xFF26, 0x80), // NR52
+            (0xFF11, 0x80), // NR11
+            (0xFF12, 0xF3), // NR12
+            (0xFF13, 0xC1), // NR13
+            (0xFF14, 0x87), // NR14
+            (0xFF24, 0x77), // NR50
+            (0xFF25, 0xF3), // NR51
+            (0xFF40, 0x91), // LCDC
+            (0xFF47, 0xFC), // BGP
+            (0xFF48, 0xFF), // OBP0
+            (0xFF49, 0xFF), // OBP1
+            (0xFF00, 0x00), // P1
+        ] {
+            self.write(addr, val);
+        }
+
+        let div = if self.cgb { 0x1EA0 } else { 0xABCC };
+        self.timer.set_divider(div);
+        self.io.write(0xFF0F, 0x00);
+        self.io.request_interrupt(INT_VBLANK);
+    }
+
+    fn cgb_mode(&self) -> bool {
+        self.cgb
+    }
+
+    /// Performs an armed CGB speed switch.
+    fn stop(&mut self) -> bool {
+        if !self.cgb || !self.speed_armed {
+            return false;
+        }
+        self.speed_armed = false;
+        self.double_speed = !self.double_speed;
+        self.speed_remainder = 0;
+        true
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"GBUS");
+        w.bool(self.cgb);
+        w.bool(self.boot_rom_enabled);
+        w.bytes(&self.boot_rom);
+        w.bytes(&self.wram);
+        w.bytes(&self.hram);
+        w.u8(self.ie);
+        w.u8(self.svbk);
+        w.bool(self.speed_armed);
+        w.bool(self.double_speed);
+        w.u8(self.speed_remainder as u8);
+        self.io.save_state(w)?;
+        self.ppu.save_state(w)?;
+        self.timer.save_state(w)?;
+        self.apu.save_state(w)?;
+        self.dma.save_state(w)?;
+        self.hdma.save_state(w)?;
+        self.joypad.save_state(w)?;
+        self.serial.save_state(w)?;
+        self.cart.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"GBUS", "bus type")?;
+        if r.bool()? != self.cgb {
+            bail!(StateErr::Mismatch("model"));
+        }
+        self.boot_rom_enabled = r.bool()?;
+        r.bytes_into(&mut self.boot_rom, "boot ROM")?;
+        r.bytes_into(&mut self.wram, "WRAM")?;
+        r.bytes_into(&mut self.hram, "HRAM")?;
+        self.ie = r.u8()?;
+        self.svbk = r.u8()?;
+        if self.svbk > 0x07 {
+            bail!(StateErr::Mismatch("WRAM bank"));
+        }
+        self.speed_armed = r.bool()?;
+        self.double_speed = r.bool()?;
+        self.speed_remainder = r.u8()? as usize;
+        self.io.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.dma.load_state(r)?;
+        self.hdma.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.cart.load_state(r)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::super::super::cartridge::cartridge;
+    use super::super::super::cartridge::romonly::RomOnly;
+    use super::super::super::cpu::cpu::CPU;
+    use super::super::super::ppu::{PpuMode, DOTS_OAM_SEARCH, DOTS_PER_LINE, DOTS_TRANSFER};
     use super::*;
 
+    fn romonly(rom: &[u8]) -> Box<dyn Cartridge> {
+        Box::new(RomOnly::new(rom, 8 * 1024))
+    }
+
     #[test]
     fn bootrom() {
         let cart = [0xAA_u8; 32 * 1024];
         let bootrom = [0xBB_u8; 256];
 
-        let b = Gameboybus::new(&cart, Some(&bootrom));
+        let b = Gameboybus::new(romonly(&cart), Some(&bootrom));
         for i in 0..=0xFF {
             assert_eq!(b.read(i), 0xBB);
         }
         assert_eq!(b.read(0x0100), 0xAA);
 
-        let b = Gameboybus::new(&cart, None);
+        let b = Gameboybus::new(romonly(&cart), None);
         for i in 0..=0xFF {
             assert_eq!(b.read(i), 0xAA);
         }
         assert_eq!(b.read(0x0100), 0xAA);
     }
 
+    #[test]
+    fn lenient_boot() {
+        // Checks the logo and header checksum like the DMG
+        // boot ROM, then unmaps itself at 0x00FE.
+        let mut bootrom = [0_u8; 256];
+        let code = [
+            0x21, 0x04, 0x01, // LD HL,$0104
+            0x11, 0x40, 0x00, // LD DE,$0040
+            0x1A, // LD A,(DE)
+            0x13, // INC DE
+            0xBE, // CP (HL)
+            0x20, 0xFE, // JR NZ,$-2 (lock up)
+            0x23, // INC HL
+            0x7D, // LD A,L
+            0xFE, 0x34, // CP $34
+            0x20, 0xF5, // JR NZ,$0006
+            0x06, 0x19, // LD B,$19
+            0x78, // LD A,B
+            0x86, // ADD (HL)
+            0x23, // INC HL
+            0x05, // DEC B
+            0x20, 0xFB, // JR NZ,$0014
+            0x86, // ADD (HL)
+            0x20, 0xFE, // JR NZ,$-2 (lock up)
+            0xC3, 0xFC, 0x00, // JP $00FC
+        ];
+        bootrom[..code.len()].copy_from_slice(&code);
+        bootrom[0x40..0x70].copy_from_slice(&LOGO);
+        bootrom[0xFC..].copy_from_slice(&[
+            0x3E, 0x01, // LD A,$01
+            0xE0, 0x50, // LDH ($50),A
+        ]);
+
+        // No logo
+        let mut cart = [0_u8; 32 * 1024];
+        cart[0x0134..0x0138].copy_from_slice(b"TEST");
+        let boot = |lenient| {
+            let mut b = Gameboybus::new(romonly(&cart), Some(&bootrom));
+            b.set_lenient_boot(lenient);
+            let mut cpu = CPU::new(b);
+            cpu.run_cycles(10000).unwrap();
+            cpu
+        };
+        assert_eq!(boot(false).regs.pc, 0x0009);
+        let cpu = boot(true);
+        assert!(cpu.regs.pc > 0x0100, "{:04X}", cpu.regs.pc);
+
+        // Only while the boot ROM is mapped
+        assert_eq!(cpu.bus.read(0x0104), 0x00);
+        assert_eq!(cpu.bus.read(0x014D), 0x00);
+    }
+
+    #[test]
+    fn skip_boot_rom() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let bootrom = [0xBB_u8; 256];
+        let mut b = Gameboybus::new(romonly(&cart), Some(&bootrom));
+        b.skip_boot_rom();
+
+        assert_eq!(b.read(0x0000), 0xAA);
+        for (addr, val) in [
+            (0xFF00, 0xCF),
+            (0xFF04, 0xAB),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE1),
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF14, 0xBF),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+            (0xFF26, 0xF1),
+            (0xFF40, 0x91),
+            (0xFF47, 0xFC),
+            (0xFF50, 0xFF),
+        ] {
+            assert_eq!(b.read(addr), val, "{:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn ppu_force_mode() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+        b.write(0xFF40, 0x91); // LCD on
+        b.ppu.force_mode(PpuMode::HBlank, 40);
+        b.write(0x8000, 0x12);
+        b.write(0xFE00, 0x34);
+
+        b.ppu.force_mode(PpuMode::Transfer, 40);
+        assert_eq!(b.read(0xFF44), 40);
+        assert_eq!(b.read(0xFF41) & 0x03, 3);
+        assert_eq!(b.read(0x8000), 0xFF);
+        assert_eq!(b.read(0xFE00), 0xFF);
+
+        b.ppu.force_mode(PpuMode::OamSearch, 40);
+        assert_eq!(b.read(0x8000), 0x12);
+        assert_eq!(b.read(0xFE00), 0xFF);
+
+        b.ppu.force_mode(PpuMode::HBlank, 40);
+        assert_eq!(b.read(0x8000), 0x12);
+        assert_eq!(b.read(0xFE00), 0x34);
+
+        // Carries on from there
+        b.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        assert_eq!(b.read(0xFF44), 41);
+        assert_eq!(b.read(0xFF41) & 0x03, 2);
+    }
+
+    #[test]
+    fn echo_ram() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        b.write(0xC000, 0x12);
+        assert_eq!(b.read(0xE000), 0x12);
+        b.write(0xDDFF, 0x34);
+        assert_eq!(b.read(0xFDFF), 0x34);
+
+        b.write(0xE100, 0x56);
+        assert_eq!(b.read(0xC100), 0x56);
+
+        // Not mirrored
+        b.write(0xDE00, 0x78);
+        assert_eq!(b.read(0xFE00), 0x00);
+    }
+
+    #[test]
+    fn unusable() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        for addr in 0xFEA0..=0xFEFF {
+            b.write(addr, 0x00);
+            assert_eq!(b.read(addr), 0xFF);
+        }
+    }
+
+    #[test]
+    fn unmapped_io() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        // Unmapped, CGB-only and write-only registers
+        for addr in [
+            0xFF03, 0xFF08, 0xFF0E, 0xFF15, 0xFF1F, 0xFF27, 0xFF2F, 0xFF4C, 0xFF4D, 0xFF4F, 0xFF50,
+            0xFF51, 0xFF55, 0xFF56, 0xFF68, 0xFF6B, 0xFF70, 0xFF7F,
+        ] {
+            b.write(addr, 0x00);
+            assert_eq!(b.read(addr), 0xFF, "{:04X}", addr);
+        }
+
+        // Unused bits read as 1
+        for (addr, val) in [
+            (0xFF02, 0x7E),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE0),
+            (0xFF10, 0x80),
+            (0xFF1A, 0x7F),
+            (0xFF1C, 0x9F),
+            (0xFF26, 0x70),
+            (0xFF41, 0x80),
+        ] {
+            b.write(addr, 0x00);
+            assert_eq!(b.read(addr) & val, val, "{:04X}", addr);
+        }
+
+        // CGB registers are there in CGB mode
+        let mut b = Gameboybus::with_model(romonly(&cart), None, Model::Cgb);
+        for (addr, val) in [(0xFF4D, 0x7E), (0xFF4F, 0xFE), (0xFF70, 0xF8)] {
+            b.write(addr, 0x00);
+            assert_eq!(b.read(addr), val, "{:04X}", addr);
+        }
+    }
+
+    #[test]
+    fn memory_map() {
+        let mut cart = [0xAA_u8; 32 * 1024];
+        cart[0x4000] = 0xBB;
+        let mut b = Gameboybus::new(romonly(&cart), None);
+        assert_eq!(b.read(0x3FFF), 0xAA);
+        assert_eq!(b.read(0x4000), 0xBB);
+
+        // RAM regions are separate
+        for addr in [
+            0x8000, 0x9FFF, 0xA000, 0xBFFF, 0xC000, 0xDFFF, 0xFE00, 0xFE9F, 0xFF80, 0xFFFE,
+        ] {
+            b.write(addr, addr as u8 ^ 0x5A);
+        }
+        for addr in [
+            0x8000, 0x9FFF, 0xA000, 0xBFFF, 0xC000, 0xDFFF, 0xFE00, 0xFE9F, 0xFF80, 0xFFFE,
+        ] {
+            assert_eq!(b.read(addr), addr as u8 ^ 0x5A, "{:04X}", addr);
+        }
+
+        // ROM is read-only
+        b.write(0x4000, 0x00);
+        assert_eq!(b.read(0x4000), 0xBB);
+    }
+
+    #[test]
+    fn reg_ie() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        b.write(0xFFFF, 0xFF);
+        assert_eq!(b.read(0xFFFF), 0xFF);
+        b.write(0xFFFF, 0xA5);
+        assert_eq!(b.read(0xFFFF), 0xA5);
+    }
+
+    #[test]
+    fn reg_if() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        b.write(0xFF0F, 0x00);
+        assert_eq!(b.read(0xFF0F), 0xE0);
+        b.write(0xFF0F, 0xFF);
+        assert_eq!(b.read(0xFF0F), 0xFF);
+        b.write(0xFF0F, 0x15);
+        assert_eq!(b.read(0xFF0F), 0xF5);
+    }
+
     #[test]
     fn bootrom_disable() {
         let cart = [0xAA_u8; 32 * 1024];
         let bootrom = [0xBB_u8; 256];
 
-        let mut b = Gameboybus::new(&cart, Some(&bootrom));
+        let mut b = Gameboybus::new(romonly(&cart), Some(&bootrom));
         for i in 0..=0xFF {
             assert_eq!(b.read(i), 0xBB);
         }
@@ -223,4 +1001,338 @@ mod tests {
         }
         assert_eq!(b.read(0x0100), 0xAA);
     }
+
+    #[test]
+    fn vblank_interrupt() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        b.write(0xFF0F, 0x00);
+        b.write(0xFF40, 0x91);
+        b.tick(456 * 143);
+        assert_eq!(b.read(0xFF0F) & 0x01, 0);
+        b.tick(456);
+        assert_eq!(b.read(0xFF44), 144);
+        assert_eq!(b.read(0xFF0F) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn timer_interrupt() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        b.write(0xFF0F, 0x00);
+        b.write(0xFF05, 0xFE);
+        b.write(0xFF07, 0x05);
+        b.tick(16 * 2);
+        assert_eq!(b.read(0xFF0F) & 0x04, 0);
+        b.tick(4);
+        assert_eq!(b.read(0xFF0F) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn serial_capture() {
+        // Prints "Passed" over serial, then loops
+        let mut cart = vec![0_u8; 32 * 1024];
+        let mut code = vec![0x21, 0x00, 0x02]; // LD HL,$0200
+        code.extend([
+            0x2A, // LD A,(HL+)
+            0xB7, // OR A
+            0x28, 0x08, // JR Z,$+8
+            0xE0, 0x01, // LDH ($01),A
+            0x3E, 0x81, // LD A,$81
+            0xE0, 0x02, // LDH ($02),A
+            0x18, 0xF3, // JR $-13
+            0x18, 0xFE, // JR $-2
+        ]);
+        cart[..code.len()].copy_from_slice(&code);
+        cart[0x200..0x206].copy_from_slice(b"Passed");
+
+        let mut cpu = CPU::new(Box::new(Gameboybus::new(romonly(&cart), None)));
+        cpu.regs.pc = 0;
+        for _ in 0..100 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.bus.take_serial_output(), "Passed");
+        assert_eq!(cpu.bus.take_serial_output(), "");
+    }
+
+    #[test]
+    fn joypad_interrupt() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        b.write(0xFF0F, 0x00);
+        b.write(0xFF00, 0x20);
+        b.set_button(Button::Up, true);
+        assert_eq!(b.read(0xFF00), 0xEB);
+        b.tick(4);
+        assert_eq!(b.read(0xFF0F) & 0x10, 0x10);
+    }
+
+    #[test]
+    fn oam_dma() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        for i in 0..0xA0 {
+            b.write(0xC100 + i, i as u8 ^ 0x5A);
+        }
+        b.write(0xFF80, 0x12);
+        b.write(0xFF46, 0xC1);
+
+        // Only I/O and HRAM are accessible
+        assert_eq!(b.read(0xC100), 0xFF);
+        assert_eq!(b.read(0x0150), 0xFF);
+        assert_eq!(b.read(0xFF80), 0x12);
+        assert_eq!(b.read(0xFF46), 0xC1);
+        b.write(0xC000, 0x34);
+        b.write(0xFF81, 0x56);
+        assert_eq!(b.read(0xFF81), 0x56);
+
+        b.tick(4 * 0x9F);
+        assert_eq!(b.read(0xFE00), 0xFF);
+        b.tick(4);
+        for i in 0..0xA0 {
+            assert_eq!(b.read(0xFE00 + i), i as u8 ^ 0x5A);
+        }
+        assert_eq!(b.read(0xC000), 0x00);
+        assert_eq!(b.read(0x0150), 0xAA);
+    }
+
+    #[test]
+    fn ppu_locks() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+        b.write(0x8000, 0x12);
+        b.write(0xFE00, 0x34);
+
+        // Mode 2 - OAM search
+        b.write(0xFF40, 0x91);
+        assert_eq!(b.read(0xFF41) & 0x03, 2);
+        assert_eq!(b.read(0x8000), 0x12);
+        assert_eq!(b.read(0xFE00), 0xFF);
+        b.write(0xFE00, 0x56);
+
+        // Mode 3 - Pixel transfer
+        b.tick(80);
+        assert_eq!(b.read(0xFF41) & 0x03, 3);
+        assert_eq!(b.read(0x8000), 0xFF);
+        assert_eq!(b.read(0xFE00), 0xFF);
+        b.write(0x8000, 0x78);
+
+        // Mode 0 - HBlank
+        b.tick(172);
+        assert_eq!(b.read(0xFF41) & 0x03, 0);
+        assert_eq!(b.read(0x8000), 0x12);
+        assert_eq!(b.read(0xFE00), 0x34);
+    }
+
+    #[test]
+    fn audio_samples() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+
+        b.set_audio_sample_rate(32768);
+        b.write(0xFF26, 0x80);
+        b.tick(4_194_304 / 128);
+        assert_eq!(b.take_audio_samples().len(), 2 * 256);
+        assert_eq!(b.read(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn save_state() {
+        let cart = [0x00_u8; 32 * 1024];
+        let mut b = Gameboybus::new(romonly(&cart), None);
+        b.write(0xC123, 0x12);
+        b.write(0xFF80, 0x34);
+        b.write(0xFFFF, 0x05);
+        b.write(0xFF07, 0x05);
+        b.write(0xFF40, 0x91);
+        b.write(0x8000, 0x56);
+        b.write(0xFF26, 0x80);
+        b.write(0xFF12, 0xF3);
+        b.write(0xFF14, 0x80);
+        b.write(0xFF30, 0x9A);
+        b.tick(1000);
+        let mut w = StateWriter::new();
+        b.save_state(&mut w).unwrap();
+        let state = w.into_inner();
+
+        let mut b2 = Gameboybus::new(romonly(&cart), None);
+        b2.load_state(&mut StateReader::new(&state)).unwrap();
+        for addr in [
+            0xC123, 0xFF80, 0xFFFF, 0xFF07, 0xFF40, 0x8000, 0xFF04, 0xFF05, 0xFF41, 0xFF44, 0xFF12,
+            0xFF26, 0xFF30,
+        ] {
+            assert_eq!(b2.read(addr), b.read(addr), "{:04X}", addr);
+        }
+
+        // Cartridge type must match
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0147] = 0x01;
+        let mut b3 = Gameboybus::new(cartridge::load(&rom).unwrap(), None);
+        assert!(b3.load_state(&mut StateReader::new(&state)).is_err());
+    }
+
+    fn cgb(cart: &[u8]) -> Gameboybus {
+        Gameboybus::with_model(romonly(cart), None, Model::Cgb)
+    }
+
+    #[test]
+    fn cgb_bootrom() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let bootrom = [0xBB_u8; 0x900];
+        let b = Gameboybus::with_model(romonly(&cart), Some(&bootrom), Model::Cgb);
+        assert_eq!(b.read(0x00FF), 0xBB);
+        // Cartridge header shows through
+        assert_eq!(b.read(0x0100), 0xAA);
+        assert_eq!(b.read(0x01FF), 0xAA);
+        assert_eq!(b.read(0x0200), 0xBB);
+        assert_eq!(b.read(0x08FF), 0xBB);
+        assert_eq!(b.read(0x0900), 0xAA);
+    }
+
+    #[test]
+    fn cgb_wram_banks() {
+        let cart = [0xAA_u8; 32 * 1024];
+        let mut b = cgb(&cart);
+        assert_eq!(b.read(0xFF70), 0xF8);
+
+        b.write(0xC000, 0x11);
+        b.write(0xD000, 0x22);
+        b.write(0xFF70, 0x02);
+        assert_eq!(b.read(0xFF70), 0xFA);
+        assert_eq!(b.read(0xC000), 0x11);
+        assert_eq!(b.read(0xD000), 0x00);
+        b.write(0xD000, 0x33);
+        assert_eq!(b.read(0xF000), 0x33);
+
+        // Bank 0 selects bank 1
+        b.write(0xFF70, 0x00);
+        assert_eq!(b.read(0xD000), 0x22);
+        b.write(0xFF70, 0x01);
+        assert_eq!(b.read(0xD000), 0x22);
+
+        // Not present on DMG
+        let mut b = Gameboybus::new(romonly(&cart), None);
+        b.write(0xD000, 0x22);
+        b.write(0xFF70, 0x02);
+        assert_eq!(b.read(0xD000), 0x22);
+    }
+
+    #[test]
+    fn cgb_speed_switch() {
+        let mut cart = [0x00_u8; 32 * 1024];
+        cart[0x0100..0x0102].copy_from_slice(&[0x10, 0x00]); // STOP
+        let mut cpu = CPU::new(Box::new(cgb(&cart)));
+        cpu.skip_boot_rom();
+        assert_eq!(cpu.regs.a, 0x11);
+        assert_eq!(cpu.bus.read(0xFF4D), 0x7E);
+
+        cpu.bus.write(0xFF4D, 0x01);
+        assert_eq!(cpu.bus.read(0xFF4D), 0x7F);
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.read(0xFF4D), 0xFE);
+
+        // The PPU runs at half the CPU speed
+        cpu.bus.write(0xFF40, 0x91);
+        let ly = cpu.bus.read(0xFF44);
+        cpu.bus.tick(456);
+        assert_eq!(cpu.bus.read(0xFF44), ly);
+        cpu.bus.tick(456);
+        assert_eq!(cpu.bus.read(0xFF44), ly + 1);
+
+        // Not on DMG
+        let mut b = Gameboybus::new(romonly(&cart), None);
+        b.write(0xFF4D, 0x01);
+        assert!(!b.stop());
+        assert_eq!(b.read(0xFF4D), 0xFF);
+    }
+
+    #[test]
+    fn cgb_hdma() {
+        let mut cart = [0x00_u8; 32 * 1024];
+        for (i, b) in cart[0x4000..0x4040].iter_mut().enumerate() {
+            *b = i as u8 + 1;
+        }
+        let mut b = cgb(&cart);
+        b.write(0xFF51, 0x40);
+        b.write(0xFF52, 0x00);
+        b.write(0xFF53, 0x01);
+        b.write(0xFF54, 0x00);
+
+        // General purpose
+        b.write(0xFF55, 0x01);
+        assert_eq!(b.read(0xFF55), 0xFF);
+        for i in 0..0x20 {
+            assert_eq!(b.read(0x8100 + i), i as u8 + 1);
+        }
+
+        // H-blank, one block per line
+        b.write(0xFF40, 0x91);
+        b.write(0xFF4F, 0x01);
+        b.write(0xFF55, 0x81);
+        assert_eq!(b.read(0xFF55), 0x01);
+        b.tick(80 + 172);
+        assert_eq!(b.read(0xFF55), 0x00);
+        b.tick(456);
+        assert_eq!(b.read(0xFF55), 0xFF);
+        for i in 0..0x20 {
+            assert_eq!(b.read(0x8120 + i), i as u8 + 0x21);
+        }
+        b.write(0xFF4F, 0x00);
+        assert_eq!(b.read(0x8120), 0x00);
+    }
+
+    #[test]
+    fn cgb_save_state() {
+        let cart = [0x00_u8; 32 * 1024];
+        let mut b = cgb(&cart);
+        b.write(0xFF70, 0x03);
+        b.write(0xD000, 0x12);
+        let mut w = StateWriter::new();
+        b.save_state(&mut w).unwrap();
+        let state = w.into_inner();
+
+        let mut b2 = cgb(&cart);
+        b2.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(b2.read(0xFF70), 0xFB);
+        assert_eq!(b2.read(0xD000), 0x12);
+
+        // Model must match
+        let mut b3 = Gameboybus::new(romonly(&cart), None);
+        assert!(b3.load_state(&mut StateReader::new(&state)).is_err());
+    }
+
+    #[test]
+    fn coverage() {
+        let mut rom = vec![0; 64 * 1024];
+        rom[0x0147] = 0x01;
+        let bootrom = [0_u8; 256];
+        let mut b = Gameboybus::new(cartridge::load(&rom).unwrap(), Some(&bootrom));
+        b.read(0x4000);
+        assert!(b.take_coverage().is_none());
+
+        b.set_coverage(true);
+        b.execute(0x0000, 1); // Boot ROM
+        b.read(0x0000); // Boot ROM
+        b.read(0x0100);
+        b.write(0x2000, 0x05); // Bank 5, wraps to 1
+        b.execute(0x4000, 2);
+        b.read(0x4002);
+        b.peek(0x4003);
+        b.read(0xC000);
+
+        let c = b.take_coverage().unwrap();
+        assert!(b.take_coverage().is_none());
+        let at = |bank, addr| c.get(Location { bank, addr });
+        assert_eq!(at(0, 0x0000), 0);
+        assert_eq!(at(0, 0x0100), READ);
+        assert_eq!(at(0, 0x2000), WRITTEN);
+        assert_eq!(at(1, 0x4000), EXECUTED);
+        assert_eq!(at(1, 0x4001), EXECUTED);
+        assert_eq!(at(1, 0x4002), READ);
+        assert_eq!(c.count(EXECUTED | READ | WRITTEN), 5);
+    }
 }
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use super::super::savestate::{StateReader, StateWriter};
+use super::bus::Bus;
+
+/// Read hook: receives the address and the value read and
+/// returns the value the reader sees.
+type ReadHook = Box<dyn FnMut(u16, u8) -> u8>;
+
+/// Write hook: receives the address and the value written
+/// and returns the value to write, or None to drop the write.
+type WriteHook = Box<dyn FnMut(u16, u8) -> Option<u8>>;
+
+/// Execute hook: receives the address of the instruction
+/// about to be executed.
+type ExecHook = Box<dyn FnMut(u16)>;
+
+enum HookKind {
+    Read(ReadHook),
+    Patch(ReadHook),
+    Write(WriteHook),
+    Execute(ExecHook),
+}
+
+struct Hook {
+    id: usize,
+    range: RangeInclusive<u16>,
+    kind: HookKind,
+}
+
+/// Registry of memory access hooks, shared between a
+/// HookBus and whoever adds and removes hooks at runtime.
+///
+/// Hooks run while the registry is borrowed, so they cannot
+/// add or remove hooks. Accesses made from within a hook
+/// (e.g. through another HookBus sharing the registry)
+/// bypass the hooks.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+
+    /// ID for the next hook
+    next_id: usize,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, range: RangeInclusive<u16>, kind: HookKind) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.hooks.push(Hook { id, range, kind });
+        id
+    }
+
+    /// Adds a hook for reads in 'range', returning an ID
+    /// for remove().
+    pub fn add_read_hook(
+        &mut self,
+        range: RangeInclusive<u16>,
+        hook: impl FnMut(u16, u8) -> u8 + 'static,
+    ) -> usize {
+        self.add(range, HookKind::Read(Box::new(hook)))
+    }
+
+    /// Adds a hook patching memory in 'range' as anyone sees
+    /// it, including instruction fetches and peeks (e.g. a
+    /// Game Genie). Returns an ID for remove().
+    pub fn add_patch_hook(
+        &mut self,
+        range: RangeInclusive<u16>,
+        hook: impl FnMut(u16, u8) -> u8 + 'static,
+    ) -> usize {
+        self.add(range, HookKind::Patch(Box::new(hook)))
+    }
+
+    /// Adds a hook for writes in 'range', returning an ID
+    /// for remove().
+    pub fn add_write_hook(
+        &mut self,
+        range: RangeInclusive<u16>,
+        hook: impl FnMut(u16, u8) -> Option<u8> + 'static,
+    ) -> usize {
+        self.add(range, HookKind::Write(Box::new(hook)))
+    }
+
+    /// Adds a hook for instructions executed in 'range',
+    /// returning an ID for remove().
+    pub fn add_exec_hook(
+        &mut self,
+        range: RangeInclusive<u16>,
+        hook: impl FnMut(u16) + 'static,
+    ) -> usize {
+        self.add(range, HookKind::Execute(Box::new(hook)))
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        self.hooks.retain(|h| h.id != id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    fn read(&mut self, addr: u16, mut val: u8) -> u8 {
+        for h in self.hooks.iter_mut().filter(|h| h.range.contains(&addr)) {
+            if let HookKind::Read(f) | HookKind::Patch(f) = &mut h.kind {
+                val = f(addr, val);
+            }
+        }
+        val
+    }
+
+    fn peek(&mut self, addr: u16, mut val: u8) -> u8 {
+        for h in self.hooks.iter_mut().filter(|h| h.range.contains(&addr)) {
+            if let HookKind::Patch(f) = &mut h.kind {
+                val = f(addr, val);
+            }
+        }
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Option<u8> {
+        let mut val = Some(val);
+        for h in self.hooks.iter_mut().filter(|h| h.range.contains(&addr)) {
+            if let (HookKind::Write(f), Some(v)) = (&mut h.kind, val) {
+                val = f(addr, v);
+            }
+        }
+        val
+    }
+
+    fn execute(&mut self, pc: u16) {
+        for h in self.hooks.iter_mut().filter(|h| h.range.contains(&pc)) {
+            if let HookKind::Execute(f) = &mut h.kind {
+                f(pc);
+            }
+        }
+    }
+}
+
+/// Bus wrapper calling hooks on memory accesses and executed
+/// instructions (for cheat search, coverage, profiling etc.).
+///
+/// Only buses that are wrapped pay for hooks. Read hooks only
+/// see the accesses of instructions: instruction fetches and
+/// other peeks (see Bus::peek()) are not reads, use execute
+/// or patch hooks for those.
+pub struct HookBus<B: Bus = Box<dyn Bus>> {
+    inner: B,
+    hooks: Rc<RefCell<Hooks>>,
+}
+
+impl<B: Bus> HookBus<B> {
+    pub fn new(inner: B, hooks: Rc<RefCell<Hooks>>) -> Self {
+        Self { inner, hooks }
+    }
+
+    /// The wrapped bus; accesses through it bypass the hooks.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Bus> Bus for HookBus<B> {
+    fn read(&self, addr: u16) -> u8 {
+        let val = self.inner.read(addr);
+        // Already borrowed if called from within a hook
+        match self.hooks.try_borrow_mut() {
+            Ok(mut hooks) if !hooks.is_empty() => hooks.read(addr, val),
+            _ => val,
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        let val = self.inner.peek(addr);
+        match self.hooks.try_borrow_mut() {
+            Ok(mut hooks) if !hooks.is_empty() => hooks.peek(addr, val),
+            _ => val,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let val = match self.hooks.try_borrow_mut() {
+            Ok(mut hooks) if !hooks.is_empty() => hooks.write(addr, val),
+            _ => Some(val),
+        };
+        if let Some(val) = val {
+            self.inner.write(addr, val);
+        }
+    }
+
+    fn execute(&mut self, pc: u16, len: usize) {
+        if let Ok(mut hooks) = self.hooks.try_borrow_mut() {
+            if !hooks.is_empty() {
+                hooks.execute(pc);
+            }
+        }
+        self.inner.execute(pc, len);
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        self.inner.tick(cycles);
+    }
+
+    fn stop(&mut self) -> bool {
+        self.inner.stop()
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.inner.rom_bank(addr)
+    }
+
+    fn skip_boot_rom(&mut self) {
+        self.inner.skip_boot_rom();
+    }
+
+    fn cgb_mode(&self) -> bool {
+        self.inner.cgb_mode()
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        self.inner.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.inner.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::cpu::cpu::CPU;
+    use super::super::testbus::Testbus;
+    use super::*;
+
+    fn hookbus(code: &[u8]) -> (HookBus<Testbus>, Rc<RefCell<Hooks>>) {
+        let hooks = Rc::new(RefCell::new(Hooks::new()));
+        let bus = HookBus::new(Testbus::from(code), Rc::clone(&hooks));
+        (bus, hooks)
+    }
+
+    #[test]
+    fn passthrough() {
+        let (mut b, _) = hookbus(&[0x12]);
+        assert_eq!(b.read(0x0000), 0x12);
+        b.write(0xC000, 0x34);
+        assert_eq!(b.read(0xC000), 0x34);
+    }
+
+    #[test]
+    fn read_hook() {
+        let (mut b, hooks) = hookbus(&[]);
+        b.write(0xC000, 0x10);
+        b.write(0xD000, 0x10);
+        let id = hooks
+            .borrow_mut()
+            .add_read_hook(0xC000..=0xCFFF, |_, val| val + 1);
+        assert_eq!(b.read(0xC000), 0x11);
+        assert_eq!(b.read(0xD000), 0x10);
+
+        hooks.borrow_mut().remove(id);
+        assert_eq!(b.read(0xC000), 0x10);
+    }
+
+    #[test]
+    fn write_hook() {
+        let (mut b, hooks) = hookbus(&[]);
+        let seen = Rc::new(RefCell::new(vec![]));
+        let s = Rc::clone(&seen);
+        hooks
+            .borrow_mut()
+            .add_write_hook(0xC000..=0xC001, move |addr, val| {
+                s.borrow_mut().push((addr, val));
+                // Drops writes to 0xC001, doubles others
+                (addr != 0xC001).then_some(val * 2)
+            });
+
+        b.write(0xC000, 0x10);
+        b.write(0xC001, 0x10);
+        b.write(0xC002, 0x10);
+        assert_eq!(b.read(0xC000), 0x20);
+        assert_eq!(b.read(0xC001), 0x00);
+        assert_eq!(b.read(0xC002), 0x10);
+        assert_eq!(*seen.borrow(), [(0xC000, 0x10), (0xC001, 0x10)]);
+    }
+
+    #[test]
+    fn peek() {
+        let (b, hooks) = hookbus(&[0x3E, 0x12]); // LD A,$12
+        let reads = Rc::new(RefCell::new(0));
+        let r = Rc::clone(&reads);
+        hooks
+            .borrow_mut()
+            .add_read_hook(0x0000..=0xFFFF, move |_, val| {
+                *r.borrow_mut() += 1;
+                val
+            });
+        assert_eq!(b.peek(0x0001), 0x12);
+        assert_eq!(*reads.borrow(), 0);
+
+        // Neither instruction fetches nor interrupt checks
+        // are reads
+        let mut cpu = CPU::new(b);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.a, 0x12);
+        assert_eq!(*reads.borrow(), 0);
+        cpu.bus.read(0x0000);
+        assert_eq!(*reads.borrow(), 1);
+    }
+
+    #[test]
+    fn patch_hook() {
+        let (b, hooks) = hookbus(&[0x3E, 0x12]); // LD A,$12
+        hooks
+            .borrow_mut()
+            .add_patch_hook(0x0001..=0x0001, |_, val| val + 1);
+        assert_eq!(b.read(0x0001), 0x13);
+        assert_eq!(b.peek(0x0001), 0x13);
+        assert_eq!(b.inner().read(0x0001), 0x12);
+
+        let mut cpu = CPU::new(b);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.a, 0x13);
+    }
+
+    #[test]
+    fn reentrant_hooks() {
+        // Two buses sharing hooks, a hook on one reading
+        // from the other
+        let (a, hooks) = hookbus(&[0x12]);
+        let b = Rc::new(HookBus::new(Testbus::from(&[0x34]), Rc::clone(&hooks)));
+        let other = Rc::clone(&b);
+        hooks
+            .borrow_mut()
+            .add_read_hook(0x0000..=0x0000, move |_, val| val + other.read(0x0000));
+        assert_eq!(a.read(0x0000), 0x12 + 0x34);
+        assert_eq!(b.read(0x0000), 0x34 + 0x34);
+    }
+
+    #[test]
+    fn exec_hook() {
+        let (b, hooks) = hookbus(&[
+            0x00, // NOP
+            0x00, // NOP
+            0x18, 0xFC, // JR $-4
+        ]);
+        let executed = Rc::new(RefCell::new(vec![]));
+        let e = Rc::clone(&executed);
+        hooks
+            .borrow_mut()
+            .add_exec_hook(0x0001..=0x0002, move |pc| e.borrow_mut().push(pc));
+
+        let mut cpu = CPU::new(Box::new(b));
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(*executed.borrow(), [0x0001, 0x0002, 0x0001, 0x0002]);
+    }
+}
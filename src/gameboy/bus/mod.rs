@@ -1,3 +1,6 @@
+#[allow(clippy::module_inception)]
 pub mod bus;
 pub mod gbbus;
+pub mod hookbus;
+pub mod ppustub;
 pub mod testbus;
@@ -0,0 +1,164 @@
+use super::super::ppu::{
+    PpuMode, DOTS_OAM_SEARCH, DOTS_PER_LINE, DOTS_TRANSFER, LCD_HEIGHT, LINES_PER_FRAME,
+};
+use super::bus::Bus;
+use super::testbus::Testbus;
+
+/// Test double for the PPU side of the bus
+///
+/// Memory like Testbus, with LY and the STAT mode running on
+/// the PPU schedule (without drawing anything) and VRAM and
+/// OAM locked from the CPU in the modes the PPU locks them
+/// in. Tests can force a line and mode, to check CPU timing
+/// against PPU state without the full PPU.
+pub struct PpuStub {
+    mem: Testbus,
+
+    /// LY - Current scanline
+    ly: u8,
+
+    /// Dots into the current scanline
+    dots: usize,
+
+    /// Line and mode forced by the test, if any
+    forced: Option<(u8, PpuMode)>,
+}
+
+impl PpuStub {
+    pub fn new() -> Self {
+        Self {
+            mem: Testbus::new(),
+            ly: 0,
+            dots: 0,
+            forced: None,
+        }
+    }
+
+    pub fn from(data: &[u8]) -> Self {
+        let mut ret = Self::new();
+        ret.write_slice(data, 0);
+        ret
+    }
+
+    /// Stops the schedule at line 'ly' in 'mode'.
+    pub fn force(&mut self, ly: u8, mode: PpuMode) {
+        self.forced = Some((ly, mode));
+    }
+
+    /// Resumes the schedule where it was forced from.
+    pub fn release(&mut self) {
+        self.forced = None;
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.forced.map_or(self.ly, |(ly, _)| ly)
+    }
+
+    pub fn mode(&self) -> PpuMode {
+        if let Some((_, mode)) = self.forced {
+            return mode;
+        }
+        if self.ly as usize >= LCD_HEIGHT {
+            PpuMode::VBlank
+        } else if self.dots < DOTS_OAM_SEARCH {
+            PpuMode::OamSearch
+        } else if self.dots < DOTS_OAM_SEARCH + DOTS_TRANSFER {
+            PpuMode::Transfer
+        } else {
+            PpuMode::HBlank
+        }
+    }
+
+    /// True if the CPU can access 'addr' in the current mode,
+    /// like Ppu::cpu_accessible() with the LCD on.
+    fn cpu_accessible(&self, addr: u16) -> bool {
+        match addr {
+            0x8000..=0x9FFF => self.mode() != PpuMode::Transfer,
+            0xFE00..=0xFE9F => !matches!(self.mode(), PpuMode::OamSearch | PpuMode::Transfer),
+            _ => true,
+        }
+    }
+}
+
+impl Default for PpuStub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for PpuStub {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            _ if !self.cpu_accessible(addr) => 0xFF,
+            // STAT - mode in bits 0-1
+            0xFF41 => self.mem.read(addr) & !0x03 | self.mode() as u8,
+            // LY
+            0xFF44 => self.ly(),
+            _ => self.mem.read(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            _ if !self.cpu_accessible(addr) => (),
+            0xFF44 => (),
+            _ => self.mem.write(addr, val),
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        if self.forced.is_some() {
+            return;
+        }
+        self.dots += cycles;
+        while self.dots >= DOTS_PER_LINE {
+            self.dots -= DOTS_PER_LINE;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::cpu::cpu::CPU;
+    use super::*;
+
+    #[test]
+    fn schedule() {
+        let mut b = PpuStub::new();
+        assert_eq!(b.read(0xFF41) & 0x03, PpuMode::OamSearch as u8);
+        b.tick(DOTS_OAM_SEARCH);
+        assert_eq!(b.mode(), PpuMode::Transfer);
+        b.tick(DOTS_TRANSFER);
+        assert_eq!(b.mode(), PpuMode::HBlank);
+        b.tick(DOTS_PER_LINE * 144 - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        assert_eq!(b.read(0xFF44), 144);
+        assert_eq!(b.mode(), PpuMode::VBlank);
+        b.tick(DOTS_PER_LINE * 10);
+        assert_eq!(b.read(0xFF44), 0);
+
+        b.force(10, PpuMode::HBlank);
+        b.tick(DOTS_PER_LINE * 3);
+        assert_eq!(b.read(0xFF44), 10);
+        b.release();
+        assert_eq!(b.read(0xFF44), 0);
+    }
+
+    #[test]
+    fn vram_locked_in_mode_3() {
+        let mut c = CPU::new(PpuStub::from(&[
+            0xFA, 0x00, 0x80, // LD A,($8000)
+            0xFA, 0x00, 0x80, // LD A,($8000)
+        ]));
+        c.bus.force(0, PpuMode::HBlank);
+        c.bus.write(0x8000, 0x12);
+
+        c.bus.force(0, PpuMode::Transfer);
+        c.step().unwrap();
+        assert_eq!(c.regs.a, 0xFF);
+
+        c.bus.force(0, PpuMode::HBlank);
+        c.step().unwrap();
+        assert_eq!(c.regs.a, 0x12);
+    }
+}
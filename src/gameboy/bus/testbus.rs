@@ -1,13 +1,34 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use super::super::savestate::{StateReader, StateWriter};
 use super::bus::Bus;
 
+/// A single recorded bus access
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BusActivity {
+    pub addr: u16,
+    pub val: u8,
+    pub write: bool,
+}
+
+/// Shared log of bus accesses (see Testbus::enable_log())
+pub type ActivityLog = Rc<RefCell<Vec<BusActivity>>>;
+
 pub struct Testbus {
     mem: [u8; u16::MAX as usize + 1],
+
+    /// Access log, if enabled
+    log: Option<ActivityLog>,
 }
 
 impl Testbus {
     pub fn new() -> Self {
         Testbus {
             mem: [0; u16::MAX as usize + 1],
+            log: None,
         }
     }
 
@@ -16,16 +37,51 @@ impl Testbus {
         ret.write_slice(data, 0);
         ret
     }
+
+    /// Starts recording all reads and writes. Returns a
+    /// handle to the log, which remains accessible after the
+    /// bus is handed to the CPU.
+    pub fn enable_log(&mut self) -> ActivityLog {
+        let log = ActivityLog::default();
+        self.log = Some(Rc::clone(&log));
+        log
+    }
+
+    fn record(&self, addr: u16, val: u8, write: bool) {
+        if let Some(log) = &self.log {
+            log.borrow_mut().push(BusActivity { addr, val, write });
+        }
+    }
+}
+
+impl Default for Testbus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Bus for Testbus {
     fn read(&self, addr: u16) -> u8 {
-        self.mem[addr as usize]
+        let val = self.mem[addr as usize];
+        self.record(addr, val, false);
+        val
     }
 
     fn write(&mut self, addr: u16, val: u8) {
+        self.record(addr, val, true);
         self.mem[addr as usize] = val;
     }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"TBUS");
+        w.bytes(&self.mem);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"TBUS", "bus type")?;
+        r.bytes_into(&mut self.mem, "memory")
+    }
 }
 
 #[cfg(test)]
@@ -46,4 +102,29 @@ mod tests {
             assert_eq!(b.read(a), a as u8);
         }
     }
+
+    #[test]
+    fn activity_log() {
+        let mut b = Testbus::new();
+        b.write(0x1234, 0x56);
+
+        let log = b.enable_log();
+        assert_eq!(b.read(0x1234), 0x56);
+        b.write(0xC000, 0x78);
+        assert_eq!(
+            *log.borrow(),
+            [
+                BusActivity {
+                    addr: 0x1234,
+                    val: 0x56,
+                    write: false
+                },
+                BusActivity {
+                    addr: 0xC000,
+                    val: 0x78,
+                    write: true
+                },
+            ]
+        );
+    }
 }
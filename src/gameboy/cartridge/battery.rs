@@ -0,0 +1,145 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use super::cartridge::Cartridge;
+
+/// Persists battery-backed cartridge RAM to a .sav file.
+///
+/// The file holds the raw RAM contents, followed by any
+/// extra data the cartridge keeps (e.g. the MBC3 clock).
+/// flush() only writes when the contents changed, so it
+/// can be called periodically as well as on exit.
+pub struct BatterySave {
+    path: PathBuf,
+
+    /// Contents as last loaded or written
+    last: Option<Vec<u8>>,
+}
+
+impl BatterySave {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            last: None,
+        }
+    }
+
+    /// Save file path for a ROM: the ROM filename with a .sav
+    /// extension, in 'save_dir' or otherwise next to the ROM.
+    pub fn path_for_rom(rom: &Path, save_dir: Option<&Path>) -> PathBuf {
+        let sav = rom.with_extension("sav");
+        match (save_dir, sav.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => sav,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the save file into the cartridge RAM, if the
+    /// file exists. Returns true if it was loaded.
+    pub fn load(&mut self, cart: &mut dyn Cartridge) -> Result<bool> {
+        let data = match fs::read(&self.path) {
+            Ok(d) => d,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).context(format!("Reading {}", self.path.display())),
+        };
+        cart.load_battery_ram(&data)?;
+        self.last = Some(data);
+        Ok(true)
+    }
+
+    /// Writes the cartridge RAM to the save file if it changed
+    /// since it was last loaded or written. Returns true if
+    /// the file was written.
+    pub fn flush(&mut self, cart: &dyn Cartridge) -> Result<bool> {
+        let Some(data) = cart.battery_ram() else {
+            return Ok(false);
+        };
+        if self.last.as_ref() == Some(&data) {
+            return Ok(false);
+        }
+
+        fs::write(&self.path, &data).context(format!("Writing {}", self.path.display()))?;
+        self.last = Some(data);
+        Ok(true)
+    }
+}
+
+/// Loads the RAM part of save file 'data' into 'ram'. Trailing
+/// data, like an RTC footer, is ignored.
+pub fn load_ram(ram: &mut [u8], data: &[u8]) -> Result<()> {
+    if data.len() < ram.len() {
+        bail!(
+            "Save file too small ({} bytes, cartridge has {} bytes RAM)",
+            data.len(),
+            ram.len()
+        );
+    }
+    ram.copy_from_slice(&data[..ram.len()]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::bus::bus::Bus;
+    use super::super::mbc1::Mbc1;
+    use super::*;
+
+    fn cart() -> Mbc1 {
+        let mut c = Mbc1::new(&[0; 64 * 1024], 8 * 1024);
+        c.write(0x0000, 0x0A);
+        c
+    }
+
+    #[test]
+    fn path_for_rom() {
+        assert_eq!(
+            BatterySave::path_for_rom(Path::new("roms/game.gb"), None),
+            Path::new("roms/game.sav")
+        );
+        assert_eq!(
+            BatterySave::path_for_rom(Path::new("roms/game.gb"), Some(Path::new("saves"))),
+            Path::new("saves/game.sav")
+        );
+    }
+
+    #[test]
+    fn flush_load() {
+        let path = std::env::temp_dir().join(format!("gbrust-battery-{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut c = cart();
+        let mut save = BatterySave::new(&path);
+        assert!(!save.load(&mut c).unwrap());
+
+        c.write(0xA123, 0x42);
+        assert!(save.flush(&c).unwrap());
+        // Unchanged
+        assert!(!save.flush(&c).unwrap());
+        c.write(0xA124, 0x43);
+        assert!(save.flush(&c).unwrap());
+
+        let mut c = cart();
+        let mut save = BatterySave::new(&path);
+        assert!(save.load(&mut c).unwrap());
+        assert_eq!(c.read(0xA123), 0x42);
+        assert_eq!(c.read(0xA124), 0x43);
+        assert!(!save.flush(&c).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_ram_size() {
+        let mut ram = [0; 4];
+        assert!(load_ram(&mut ram, &[1, 2, 3]).is_err());
+        load_ram(&mut ram, &[1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(ram, [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{bail, Result};
+
+use super::super::bus::bus::Bus;
+use super::super::error::EmuError;
+use super::header::CartridgeHeader;
+use super::mbc1::Mbc1;
+use super::mbc3::Mbc3;
+use super::mbc5::Mbc5;
+use super::romonly::RomOnly;
+use super::rtc::RtcClock;
+
+/// Banking state of a memory bank controller
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MbcState {
+    /// ROM bank mapped at 0x4000 - 0x7FFF
+    pub rom_bank: usize,
+
+    /// RAM bank (or MBC3 RTC register) mapped at
+    /// 0xA000 - 0xBFFF
+    pub ram_bank: usize,
+
+    pub ram_enabled: bool,
+
+    /// MBC1 banking mode 1 (RAM banking and the upper ROM
+    /// bank bits in the 0x0000 - 0x3FFF area)
+    pub mode: bool,
+}
+
+/// A cartridge, mapped onto the bus at 0x0000 - 0x7FFF and
+/// 0xA000 - 0xBFFF.
+pub trait Cartridge: Bus {
+    /// Banking state, if the cartridge has a memory bank
+    /// controller.
+    fn mbc_state(&self) -> Option<MbcState> {
+        None
+    }
+
+    /// Maps a ROM bank at 0x4000 - 0x7FFF (for debuggers),
+    /// including banks the game cannot select there. Fails
+    /// if the bank is not in the ROM.
+    fn force_rom_bank(&mut self, _bank: usize) -> Result<()> {
+        bail!("Cartridge has no memory bank controller")
+    }
+
+    /// Maps a RAM bank at 0xA000 - 0xBFFF (for debuggers).
+    /// Fails if the bank is not in the RAM.
+    fn force_ram_bank(&mut self, _bank: usize) -> Result<()> {
+        bail!("Cartridge has no memory bank controller")
+    }
+
+    /// Puts the memory bank controller back in its power-on
+    /// state (RAM disabled, first ROM and RAM banks). The
+    /// contents of RAM and the clock are kept.
+    fn reset(&mut self) {}
+
+    /// Selects the time source of the real-time clock, if
+    /// there is one.
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    /// Returns the contents of battery-backed storage
+    /// (RAM and clock) for a save file, if any.
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores battery-backed storage from a save file
+    /// written from battery_ram().
+    fn load_battery_ram(&mut self, _data: &[u8]) -> Result<()> {
+        bail!("No battery-backed RAM")
+    }
+}
+
+/// Fails if 'bank' is not one of the 'size' / 'bank_size'
+/// banks of a ROM or RAM.
+pub(super) fn check_bank(bank: usize, size: usize, bank_size: usize) -> Result<()> {
+    let banks = size / bank_size;
+    if bank >= banks {
+        bail!("Bank {} out of range ({} banks)", bank, banks);
+    }
+    Ok(())
+}
+
+/// Creates the cartridge for a ROM image, based on the
+/// cartridge type in its header.
+pub fn load(rom: &[u8]) -> Result<Box<dyn Cartridge>> {
+    let header = CartridgeHeader::from_rom(rom)?;
+    let ram_size = header.ram_size_bytes()?;
+
+    Ok(match header.cart_type {
+        // ROM only
+        0x00 => Box::new(RomOnly::new(rom, 0)),
+        // ROM + RAM (+ battery)
+        0x08 | 0x09 => Box::new(RomOnly::new(rom, ram_size)),
+        // MBC1 (+ RAM + battery)
+        0x01..=0x03 => Box::new(Mbc1::new(rom, ram_size)),
+        // MBC3 + timer (+ RAM) + battery
+        0x0F | 0x10 => Box::new(Mbc3::new(rom, ram_size, true)),
+        // MBC3 (+ RAM) (+ battery)
+        0x11..=0x13 => Box::new(Mbc3::new(rom, ram_size, false)),
+        // MBC5 (+ RAM) (+ battery)
+        0x19..=0x1B => Box::new(Mbc5::new(rom, ram_size, false)),
+        // MBC5 + rumble (+ RAM) (+ battery)
+        0x1C..=0x1E => Box::new(Mbc5::new(rom, ram_size, true)),
+        _ => bail!(EmuError::Unsupported {
+            feature: format!("cartridge type {:02X}", header.cart_type)
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(cart_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 64 * 1024];
+        rom[0x0147] = cart_type;
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x02;
+        rom[0x4000] = 0x01;
+        rom[0x8000] = 0x02;
+        rom
+    }
+
+    #[test]
+    fn mbc_state() {
+        // MBC1, MBC3 and MBC5 with 128KiB ROM and 32KiB RAM
+        for t in [0x03, 0x13, 0x1B] {
+            let mut rom = vec![0; 128 * 1024];
+            for bank in 0..8 {
+                rom[bank * 0x4000] = bank as u8;
+            }
+            rom[0x0147] = t;
+            rom[0x0148] = 0x02;
+            rom[0x0149] = 0x03;
+            let mut c = load(&rom).unwrap();
+            assert_eq!(c.mbc_state().unwrap().rom_bank, 1);
+
+            c.write(0x0000, 0x0A);
+            c.write(0x2000, 0x05);
+            c.write(0x6000, 0x01);
+            c.write(0x4000, 0x02);
+            let state = c.mbc_state().unwrap();
+            assert_eq!(state.rom_bank, 5, "{:02X}", t);
+            assert_eq!(state.ram_bank, 2, "{:02X}", t);
+            assert!(state.ram_enabled);
+            assert_eq!(state.mode, t == 0x03);
+
+            c.force_rom_bank(3).unwrap();
+            assert_eq!(c.mbc_state().unwrap().rom_bank, 3);
+            assert_eq!(c.read(0x4000), 3);
+            assert!(c.force_rom_bank(8).is_err());
+
+            c.write(0xA000, 0x12);
+            c.force_ram_bank(1).unwrap();
+            assert_eq!(c.mbc_state().unwrap().ram_bank, 1);
+            assert_eq!(c.read(0xA000), 0x00);
+            c.force_ram_bank(2).unwrap();
+            assert_eq!(c.read(0xA000), 0x12);
+            assert!(c.force_ram_bank(4).is_err());
+        }
+
+        let mut c = load(&rom(0x00)).unwrap();
+        assert_eq!(c.mbc_state(), None);
+        assert!(c.force_rom_bank(1).is_err());
+    }
+
+    #[test]
+    fn load_mbc1() {
+        let mut c = load(&rom(0x03)).unwrap();
+        assert_eq!(c.read(0x4000), 0x01);
+        c.write(0x2000, 0x02);
+        assert_eq!(c.read(0x4000), 0x02);
+    }
+
+    #[test]
+    fn load_romonly() {
+        let mut c = load(&rom(0x00)).unwrap();
+        assert_eq!(c.read(0x4000), 0x01);
+        c.write(0x2000, 0x02);
+        assert_eq!(c.read(0x4000), 0x01);
+    }
+
+    #[test]
+    fn load_unsupported() {
+        let err = load(&rom(0xFC)).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<EmuError>(),
+            Some(&EmuError::Unsupported {
+                feature: "cartridge type FC".to_string()
+            })
+        );
+        assert!(load(&[0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn load_mbc3() {
+        let mut c = load(&rom(0x13)).unwrap();
+        assert_eq!(c.read(0x4000), 0x01);
+        c.write(0x2000, 0x02);
+        assert_eq!(c.read(0x4000), 0x02);
+    }
+
+    #[test]
+    fn load_mbc5() {
+        for t in [0x19, 0x1E] {
+            let mut c = load(&rom(t)).unwrap();
+            assert_eq!(c.read(0x4000), 0x01);
+            c.write(0x2000, 0x00);
+            assert_eq!(c.read(0x4000), 0x00);
+        }
+    }
+}
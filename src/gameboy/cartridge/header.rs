@@ -0,0 +1,514 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+/// Size of the cartridge header, including everything
+/// preceding it.
+pub const HEADER_END: usize = 0x0150;
+
+/// Nintendo logo bitmap, as checked by the boot ROM.
+pub const LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// How to treat a ROM with an inconsistent header
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Validation {
+    /// Refuse to load it
+    Strict,
+
+    /// Warn and load it anyway, as real hardware only
+    /// checks the logo and header checksum.
+    Lenient,
+}
+
+impl FromStr for Validation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => bail!("Unknown validation '{}' (expected strict or lenient)", s),
+        }
+    }
+}
+
+/// Cartridge header (0x0100 - 0x014F)
+pub struct CartridgeHeader {
+    /// Nintendo logo (0x0104 - 0x0133)
+    pub logo: [u8; 48],
+
+    /// Game title (0x0134 - 0x0143, up to 0x0142 on
+    /// CGB cartridges)
+    pub title: String,
+
+    /// CGB flag (0x0143)
+    pub cgb_flag: u8,
+
+    /// New licensee code (0x0144 - 0x0145), two ASCII
+    /// characters used if the old code is 0x33
+    pub new_licensee: [u8; 2],
+
+    /// SGB flag (0x0146)
+    pub sgb_flag: u8,
+
+    /// Cartridge type (0x0147)
+    pub cart_type: u8,
+
+    /// ROM size (0x0148)
+    pub rom_size: u8,
+
+    /// RAM size (0x0149)
+    pub ram_size: u8,
+
+    /// Old licensee code (0x014B)
+    pub old_licensee: u8,
+
+    /// Mask ROM version number (0x014C)
+    pub version: u8,
+
+    /// Header checksum (0x014D)
+    pub checksum: u8,
+
+    /// Global checksum (0x014E - 0x014F, big endian)
+    pub global_checksum: u16,
+
+    /// Checksum calculated over 0x0134 - 0x014C
+    calc_checksum: u8,
+
+    /// Sum of all ROM bytes but the global checksum
+    calc_global_checksum: u16,
+
+    /// Size of the ROM image
+    rom_len: usize,
+}
+
+impl CartridgeHeader {
+    /// Parse the header from a full ROM image.
+    pub fn from_rom(rom: &[u8]) -> Result<Self> {
+        if rom.len() < HEADER_END {
+            bail!("ROM too small to contain a header ({} bytes)", rom.len());
+        }
+
+        let mut logo = [0; 48];
+        logo.copy_from_slice(&rom[0x0104..=0x0133]);
+
+        // On CGB cartridges, the last byte of the title
+        // is the CGB flag.
+        let cgb_flag = rom[0x0143];
+        let title_end = if cgb_flag & 0x80 != 0 { 0x0142 } else { 0x0143 };
+        let title = rom[0x0134..=title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect();
+
+        let global_checksum = u16::from_be_bytes([rom[0x014E], rom[0x014F]]);
+        let calc_global_checksum = rom
+            .iter()
+            .fold(0_u16, |acc, &b| acc.wrapping_add(b as u16))
+            .wrapping_sub(rom[0x014E] as u16 + rom[0x014F] as u16);
+
+        Ok(Self {
+            logo,
+            title,
+            cgb_flag,
+            new_licensee: [rom[0x0144], rom[0x0145]],
+            sgb_flag: rom[0x0146],
+            cart_type: rom[0x0147],
+            rom_size: rom[0x0148],
+            ram_size: rom[0x0149],
+            old_licensee: rom[0x014B],
+            version: rom[0x014C],
+            checksum: rom[0x014D],
+            global_checksum,
+            calc_checksum: rom[0x0134..=0x014C]
+                .iter()
+                .fold(0_u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1)),
+            calc_global_checksum,
+            rom_len: rom.len(),
+        })
+    }
+
+    /// Checks the logo against the Nintendo logo
+    /// the boot ROM expects.
+    pub fn logo_matches(&self) -> bool {
+        self.logo == LOGO
+    }
+
+    /// Checks the header checksum, which the boot
+    /// ROM verifies.
+    pub fn checksum_matches(&self) -> bool {
+        self.checksum == self.calc_checksum
+    }
+
+    /// Checksum the header checksum should be
+    pub fn expected_checksum(&self) -> u8 {
+        self.calc_checksum
+    }
+
+    /// Checks the global checksum over the whole ROM, which
+    /// no hardware verifies (so homebrew often has it wrong).
+    pub fn global_checksum_matches(&self) -> bool {
+        self.global_checksum == self.calc_global_checksum
+    }
+
+    /// Checksum the global checksum should be
+    pub fn expected_global_checksum(&self) -> u16 {
+        self.calc_global_checksum
+    }
+
+    /// True if the cartridge supports CGB mode (0x80: also
+    /// runs on DMG, 0xC0: CGB only).
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    /// True if the cartridge only runs on CGB.
+    pub fn cgb_only(&self) -> bool {
+        self.cgb_flag == 0xC0
+    }
+
+    /// True if the cartridge supports SGB functions, which
+    /// also requires the old licensee code to be 0x33.
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee == 0x33
+    }
+
+    /// Licensee code (the new code if in use) and the name of
+    /// the publisher, for a selection of well-known publishers.
+    pub fn licensee(&self) -> (String, Option<&'static str>) {
+        if self.old_licensee != 0x33 {
+            let name = match self.old_licensee {
+                0x00 => Some("None"),
+                0x01 | 0x31 => Some("Nintendo"),
+                0x08 | 0x38 => Some("Capcom"),
+                0x13 | 0x69 => Some("Electronic Arts"),
+                0x18 => Some("Hudson Soft"),
+                0x34 | 0xA4 => Some("Konami"),
+                0x41 => Some("Ubi Soft"),
+                0x51 | 0xB0 => Some("Acclaim"),
+                0x52 => Some("Activision"),
+                0x67 => Some("Ocean"),
+                0x78 => Some("THQ"),
+                0xAF => Some("Namco"),
+                0xB6 => Some("HAL Laboratory"),
+                0xC3 => Some("Squaresoft"),
+                _ => None,
+            };
+            return (format!("{:02X}", self.old_licensee), name);
+        }
+
+        let code: String = self.new_licensee.iter().map(|&c| c as char).collect();
+        let name = match code.as_str() {
+            "00" => Some("None"),
+            "01" => Some("Nintendo"),
+            "08" => Some("Capcom"),
+            "13" | "69" => Some("Electronic Arts"),
+            "18" => Some("Hudson Soft"),
+            "34" | "A4" => Some("Konami"),
+            "41" => Some("Ubi Soft"),
+            "51" => Some("Acclaim"),
+            "52" => Some("Activision"),
+            "78" => Some("THQ"),
+            _ => None,
+        };
+        (code, name)
+    }
+
+    /// Name of the cartridge type (mapper and extra hardware),
+    /// None if unknown.
+    pub fn cart_type_name(&self) -> Option<&'static str> {
+        Some(match self.cart_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => return None,
+        })
+    }
+
+    /// True if the cartridge type has a battery, which
+    /// keeps the external RAM (and clock) powered.
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cart_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        )
+    }
+
+    /// ROM size in bytes, as declared by the header.
+    pub fn rom_size_bytes(&self) -> Result<usize> {
+        match self.rom_size {
+            0x00..=0x08 => Ok((32 * 1024) << self.rom_size),
+            _ => bail!("Unknown ROM size {:02X}", self.rom_size),
+        }
+    }
+
+    /// External RAM size in bytes, as declared by the header.
+    pub fn ram_size_bytes(&self) -> Result<usize> {
+        match self.ram_size {
+            0x00 => Ok(0),
+            0x01 => Ok(2 * 1024),
+            0x02 => Ok(8 * 1024),
+            0x03 => Ok(32 * 1024),
+            0x04 => Ok(128 * 1024),
+            0x05 => Ok(64 * 1024),
+            _ => bail!("Unknown RAM size {:02X}", self.ram_size),
+        }
+    }
+
+    /// Inconsistencies in the header and between the header
+    /// and the ROM image (e.g. a truncated download).
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !self.logo_matches() {
+            problems.push("Nintendo logo does not match".to_string());
+        }
+        if !self.checksum_matches() {
+            problems.push(format!(
+                "Header checksum is {:02X}, expected {:02X}",
+                self.checksum, self.calc_checksum
+            ));
+        }
+        if !self.global_checksum_matches() {
+            problems.push(format!(
+                "Global checksum is {:04X}, expected {:04X}",
+                self.global_checksum, self.calc_global_checksum
+            ));
+        }
+        if self.cart_type_name().is_none() {
+            problems.push(format!("Unknown cartridge type {:02X}", self.cart_type));
+        }
+        match self.rom_size_bytes() {
+            Ok(size) if size != self.rom_len => problems.push(format!(
+                "ROM image is {} bytes, header declares {} bytes",
+                self.rom_len, size
+            )),
+            Ok(_) => (),
+            Err(e) => problems.push(e.to_string()),
+        }
+        if let Err(e) = self.ram_size_bytes() {
+            problems.push(e.to_string());
+        }
+        problems
+    }
+
+    /// Checks the header for problems(): fails on any in
+    /// strict mode, returns them as warnings in lenient mode.
+    pub fn validate(&self, mode: Validation) -> Result<Vec<String>> {
+        let problems = self.problems();
+        if mode == Validation::Strict && !problems.is_empty() {
+            bail!("Invalid ROM: {}", problems.join("; "));
+        }
+        Ok(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0104..=0x0133].copy_from_slice(&LOGO);
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom
+    }
+
+    #[test]
+    fn parse() {
+        let h = CartridgeHeader::from_rom(&rom()).unwrap();
+        assert_eq!(h.title, "TEST");
+        assert_eq!(h.cart_type, 0);
+    }
+
+    #[test]
+    fn sizes() {
+        let mut rom = rom();
+        rom[0x0148] = 0x00;
+        rom[0x0149] = 0x00;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.rom_size_bytes().unwrap(), 32 * 1024);
+        assert_eq!(h.ram_size_bytes().unwrap(), 0);
+
+        rom[0x0148] = 0x05;
+        rom[0x0149] = 0x03;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.rom_size_bytes().unwrap(), 1024 * 1024);
+        assert_eq!(h.ram_size_bytes().unwrap(), 32 * 1024);
+
+        rom[0x0148] = 0x52;
+        rom[0x0149] = 0x06;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(h.rom_size_bytes().is_err());
+        assert!(h.ram_size_bytes().is_err());
+    }
+
+    #[test]
+    fn battery() {
+        let mut rom = rom();
+        for (t, battery) in [(0x00, false), (0x01, false), (0x03, true), (0x13, true)] {
+            rom[0x0147] = t;
+            let h = CartridgeHeader::from_rom(&rom).unwrap();
+            assert_eq!(h.has_battery(), battery, "{:02X}", t);
+        }
+    }
+
+    #[test]
+    fn cgb_flag() {
+        let mut rom = rom();
+        rom[0x0134..=0x0143].copy_from_slice(b"ABCDEFGHIJKLMNOP");
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.title, "ABCDEFGHIJKLMNOP");
+        assert!(!h.supports_cgb());
+
+        rom[0x0143] = 0x80;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.title, "ABCDEFGHIJKLMNO");
+        assert!(h.supports_cgb());
+    }
+
+    #[test]
+    fn too_small() {
+        assert!(CartridgeHeader::from_rom(&[0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn logo_matches() {
+        let h = CartridgeHeader::from_rom(&rom()).unwrap();
+        assert!(h.logo_matches());
+    }
+
+    #[test]
+    fn logo_corrupt() {
+        let mut rom = rom();
+        rom[0x0110] ^= 0x01;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(!h.logo_matches());
+    }
+
+    #[test]
+    fn checksum() {
+        let mut rom = rom();
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(!h.checksum_matches());
+
+        // Checksum of "TEST" and otherwise empty header
+        rom[0x014D] = 0xA7;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(h.checksum_matches());
+
+        rom[0x0147] = 0x01;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(!h.checksum_matches());
+    }
+
+    #[test]
+    fn global_checksum() {
+        let mut rom = rom();
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        let sum = LOGO.iter().chain(b"TEST").map(|&b| b as u16).sum::<u16>();
+        assert_eq!(h.expected_global_checksum(), sum);
+        assert!(!h.global_checksum_matches());
+
+        // Checksum bytes do not count
+        rom[0x014E..=0x014F].copy_from_slice(&sum.to_be_bytes());
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(h.global_checksum_matches());
+
+        rom[0x7FFF] = 0x01;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(!h.global_checksum_matches());
+    }
+
+    #[test]
+    fn licensee() {
+        let mut rom = rom();
+        rom[0x014B] = 0x01;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.licensee(), ("01".to_string(), Some("Nintendo")));
+
+        rom[0x014B] = 0x33;
+        rom[0x0144..=0x0145].copy_from_slice(b"52");
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.licensee(), ("52".to_string(), Some("Activision")));
+        assert!(!h.supports_sgb());
+
+        rom[0x0144..=0x0145].copy_from_slice(b"ZZ");
+        rom[0x0146] = 0x03;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.licensee(), ("ZZ".to_string(), None));
+        assert!(h.supports_sgb());
+    }
+
+    #[test]
+    fn cart_type_name() {
+        let mut rom = rom();
+        rom[0x0147] = 0x13;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.cart_type_name(), Some("MBC3+RAM+BATTERY"));
+
+        rom[0x0147] = 0x42;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(h.cart_type_name(), None);
+    }
+
+    #[test]
+    fn validate() {
+        let mut rom = rom();
+        rom[0x014D] = 0xA7;
+        let sum = LOGO.iter().chain(b"TEST").map(|&b| b as u16).sum::<u16>() + 0xA7;
+        rom[0x014E..=0x014F].copy_from_slice(&sum.to_be_bytes());
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert!(h.problems().is_empty(), "{:?}", h.problems());
+        assert!(h.validate(Validation::Strict).unwrap().is_empty());
+
+        // Truncated (the missing part is empty, so the global
+        // checksum still matches)
+        let h = CartridgeHeader::from_rom(&rom[..0x6000]).unwrap();
+        assert_eq!(
+            h.problems(),
+            ["ROM image is 24576 bytes, header declares 32768 bytes"]
+        );
+        assert_eq!(h.validate(Validation::Lenient).unwrap(), h.problems());
+        assert!(h.validate(Validation::Strict).is_err());
+    }
+
+    #[test]
+    fn validation_from_str() {
+        assert_eq!("Strict".parse::<Validation>().unwrap(), Validation::Strict);
+        assert_eq!(
+            "lenient".parse::<Validation>().unwrap(),
+            Validation::Lenient
+        );
+        assert!("loose".parse::<Validation>().is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::Result;
+
+use super::super::bus::bus::Bus;
+use super::super::savestate::{StateReader, StateWriter};
+use super::battery;
+use super::cartridge::{check_bank, Cartridge, MbcState};
+
+const ROM_BANK_SIZE: usize = 16 * 1024;
+const RAM_BANK_SIZE: usize = 8 * 1024;
+
+/// MBC1 memory bank controller, up to 2MiB ROM
+/// and 32KiB RAM.
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    /// RAM enable (0x0000 - 0x1FFF)
+    ram_enabled: bool,
+
+    /// BANK1 register, lower 5 bits of ROM bank (0x2000 - 0x3FFF)
+    bank_lo: u8,
+
+    /// BANK2 register, RAM bank or upper 2 bits of
+    /// ROM bank (0x4000 - 0x5FFF)
+    bank_hi: u8,
+
+    /// Banking mode select (0x6000 - 0x7FFF)
+    mode: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8], ram_size: usize) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank_lo: 1,
+            bank_hi: 0,
+            mode: false,
+        }
+    }
+
+    /// Reads from a ROM bank, wrapping the bank number
+    /// to the size of the ROM.
+    fn read_rom(&self, bank: usize, offset: usize) -> u8 {
+        let banks = (self.rom.len() / ROM_BANK_SIZE).max(1);
+        let addr = (bank % banks) * ROM_BANK_SIZE + offset;
+        *self.rom.get(addr).unwrap_or(&0xFF)
+    }
+
+    /// Offset into RAM for 0xA000 - 0xBFFF, if accessible.
+    fn ram_addr(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+
+        let bank = if self.mode { self.bank_hi as usize } else { 0 };
+        let addr = bank * RAM_BANK_SIZE + (addr as usize - 0xA000);
+        Some(addr % self.ram.len())
+    }
+}
+
+impl Bus for Mbc1 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.read_rom(self.rom_bank(addr), addr as usize & 0x3FFF),
+
+            // External RAM
+            0xA000..=0xBFFF => match self.ram_addr(addr) {
+                Some(a) => self.ram[a],
+                None => 0xFF,
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        let bank = match addr {
+            // ROM bank 0, or 0x20/0x40/0x60 in mode 1
+            0x0000..=0x3FFF if self.mode => self.bank_hi << 5,
+            0x0000..=0x3FFF => 0,
+
+            // Switchable ROM bank
+            0x4000..=0x7FFF => self.bank_hi << 5 | self.bank_lo,
+
+            _ => return 0,
+        };
+        bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // RAM enable
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+
+            // ROM bank (lower 5 bits)
+            // Bank 0 cannot be selected here, which also makes
+            // banks 0x20, 0x40 and 0x60 select the bank after.
+            0x2000..=0x3FFF => {
+                self.bank_lo = val & 0x1F;
+                if self.bank_lo == 0 {
+                    self.bank_lo = 1;
+                }
+            }
+
+            // RAM bank / ROM bank (upper 2 bits)
+            0x4000..=0x5FFF => self.bank_hi = val & 0x03,
+
+            // Banking mode select
+            0x6000..=0x7FFF => self.mode = val & 0x01 != 0,
+
+            // External RAM
+            0xA000..=0xBFFF => {
+                if let Some(a) = self.ram_addr(addr) {
+                    self.ram[a] = val;
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"MBC1");
+        w.bool(self.ram_enabled);
+        w.u8(self.bank_lo);
+        w.u8(self.bank_hi);
+        w.bool(self.mode);
+        w.bytes(&self.ram);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"MBC1", "cartridge type")?;
+        self.ram_enabled = r.bool()?;
+        self.bank_lo = r.u8()?;
+        self.bank_hi = r.u8()?;
+        self.mode = r.bool()?;
+        r.bytes_into(&mut self.ram, "cartridge RAM")
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn mbc_state(&self) -> Option<MbcState> {
+        Some(MbcState {
+            rom_bank: self.rom_bank(0x4000),
+            ram_bank: if self.mode { self.bank_hi as usize } else { 0 },
+            ram_enabled: self.ram_enabled,
+            mode: self.mode,
+        })
+    }
+
+    fn force_rom_bank(&mut self, bank: usize) -> Result<()> {
+        check_bank(bank, self.rom.len(), ROM_BANK_SIZE)?;
+        self.bank_lo = bank as u8 & 0x1F;
+        // BANK2 only selects ROM banks on 1MiB and up
+        if self.rom.len() > 32 * ROM_BANK_SIZE {
+            self.bank_hi = (bank >> 5) as u8;
+        }
+        Ok(())
+    }
+
+    /// Selects BANK2 in mode 1, which on 1MiB ROMs and up
+    /// also selects the upper ROM bank bits.
+    fn force_ram_bank(&mut self, bank: usize) -> Result<()> {
+        check_bank(bank, self.ram.len(), RAM_BANK_SIZE)?;
+        self.bank_hi = bank as u8;
+        self.mode = true;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.ram_enabled = false;
+        self.bank_lo = 1;
+        self.bank_hi = 0;
+        self.mode = false;
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        (!self.ram.is_empty()).then(|| self.ram.clone())
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) -> Result<()> {
+        battery::load_ram(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM where each bank is filled with its bank number
+    fn rom(banks: usize) -> Vec<u8> {
+        (0..banks).flat_map(|b| [b as u8; ROM_BANK_SIZE]).collect()
+    }
+
+    #[test]
+    fn rom_bank_switch() {
+        let mut c = Mbc1::new(&rom(8), 0);
+        assert_eq!(c.read(0x0000), 0);
+        assert_eq!(c.read(0x4000), 1);
+
+        c.write(0x2000, 0x02);
+        assert_eq!(c.read(0x4000), 2);
+        assert_eq!(c.read(0x7FFF), 2);
+        assert_eq!(c.read(0x3FFF), 0);
+
+        c.write(0x3FFF, 0x07);
+        assert_eq!(c.read(0x4000), 7);
+    }
+
+    #[test]
+    fn rom_bank_zero() {
+        let mut c = Mbc1::new(&rom(8), 0);
+        c.write(0x2000, 0x00);
+        assert_eq!(c.read(0x4000), 1);
+
+        // Only the lower 5 bits are checked
+        c.write(0x2000, 0xE0);
+        assert_eq!(c.read(0x4000), 1);
+    }
+
+    #[test]
+    fn rom_bank_upper() {
+        let mut c = Mbc1::new(&rom(128), 0);
+        c.write(0x2000, 0x05);
+        c.write(0x4000, 0x01);
+        assert_eq!(c.read(0x4000), 0x25);
+
+        // 0x20, 0x40 and 0x60 roll over to the next bank
+        for hi in 1..=3 {
+            c.write(0x4000, hi);
+            c.write(0x2000, 0x00);
+            assert_eq!(c.read(0x4000), (hi << 5) + 1);
+        }
+
+        // Bank 0 area only follows BANK2 in mode 1
+        c.write(0x4000, 0x02);
+        assert_eq!(c.read(0x0000), 0x00);
+        c.write(0x6000, 0x01);
+        assert_eq!(c.read(0x0000), 0x40);
+        assert_eq!(c.rom_bank(0x0000), 0x40);
+        assert_eq!(c.rom_bank(0x4000), 0x41);
+    }
+
+    #[test]
+    fn rom_bank_wrap() {
+        let mut c = Mbc1::new(&rom(4), 0);
+        c.write(0x2000, 0x05);
+        assert_eq!(c.read(0x4000), 1);
+        assert_eq!(c.rom_bank(0x4000), 1);
+    }
+
+    #[test]
+    fn ram_enable() {
+        let mut c = Mbc1::new(&rom(2), 8 * 1024);
+        c.write(0xA000, 0x12);
+        assert_eq!(c.read(0xA000), 0xFF);
+
+        c.write(0x0000, 0x0A);
+        c.write(0xA000, 0x12);
+        assert_eq!(c.read(0xA000), 0x12);
+
+        c.write(0x1FFF, 0x00);
+        assert_eq!(c.read(0xA000), 0xFF);
+        c.write(0x0000, 0x1A);
+        assert_eq!(c.read(0xA000), 0x12);
+    }
+
+    #[test]
+    fn ram_bank_switch() {
+        let mut c = Mbc1::new(&rom(2), 32 * 1024);
+        c.write(0x0000, 0x0A);
+        c.write(0x6000, 0x01);
+        for bank in 0..4 {
+            c.write(0x4000, bank);
+            c.write(0xA000, bank + 0x10);
+        }
+        for bank in 0..4 {
+            c.write(0x4000, bank);
+            assert_eq!(c.read(0xA000), bank + 0x10);
+        }
+
+        // Mode 0 always uses RAM bank 0
+        c.write(0x6000, 0x00);
+        assert_eq!(c.read(0xA000), 0x10);
+    }
+
+    #[test]
+    fn save_state() {
+        let mut c = Mbc1::new(&rom(8), 8 * 1024);
+        c.write(0x0000, 0x0A);
+        c.write(0x2000, 0x05);
+        c.write(0xA000, 0x42);
+        let mut w = StateWriter::new();
+        c.save_state(&mut w).unwrap();
+        let state = w.into_inner();
+
+        let mut c = Mbc1::new(&rom(8), 8 * 1024);
+        c.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(c.read(0x4000), 5);
+        assert_eq!(c.read(0xA000), 0x42);
+
+        // RAM size must match
+        let mut c = Mbc1::new(&rom(8), 32 * 1024);
+        assert!(c.load_state(&mut StateReader::new(&state)).is_err());
+    }
+
+    #[test]
+    fn reset() {
+        let mut c = Mbc1::new(&rom(128), 32 * 1024);
+        c.write(0x0000, 0x0A);
+        c.write(0xA000, 0x42);
+        c.write(0x2000, 0x05);
+        c.write(0x4000, 0x02);
+        c.write(0x6000, 0x01);
+        assert_eq!(c.read(0x0000), 0x40);
+
+        c.reset();
+        assert_eq!(c.read(0x0000), 0);
+        assert_eq!(c.read(0x4000), 1);
+        assert_eq!(c.read(0xA000), 0xFF);
+
+        // RAM is kept
+        c.write(0x0000, 0x0A);
+        assert_eq!(c.read(0xA000), 0x42);
+    }
+}
@@ -0,0 +1,394 @@
+use anyhow::{bail, Result};
+
+use super::super::bus::bus::Bus;
+use super::super::savestate::{StateErr, StateReader, StateWriter};
+use super::battery;
+use super::cartridge::{check_bank, Cartridge, MbcState};
+use super::rtc::{Rtc, RtcClock, FOOTER_SIZE};
+
+const ROM_BANK_SIZE: usize = 16 * 1024;
+const RAM_BANK_SIZE: usize = 8 * 1024;
+
+/// MBC3 memory bank controller, up to 2MiB ROM
+/// and 32KiB RAM, optionally with a real-time clock.
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    /// RAM enable (0x0000 - 0x1FFF)
+    ram_enabled: bool,
+
+    /// ROM bank, 7 bits (0x2000 - 0x3FFF)
+    rom_bank: u8,
+
+    /// RAM bank or RTC register select (0x4000 - 0x5FFF)
+    ram_bank: u8,
+
+    /// Real-time clock, if the cartridge has one
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(rom: &[u8], ram_size: usize, rtc: bool) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: rtc.then(|| Rtc::new(RtcClock::Host)),
+        }
+    }
+
+    /// The RTC register selected at 0xA000 - 0xBFFF, if any.
+    fn rtc_reg(&self) -> Option<u8> {
+        match self.ram_bank {
+            0x08..=0x0C if self.ram_enabled && self.rtc.is_some() => Some(self.ram_bank),
+            _ => None,
+        }
+    }
+
+    /// Reads from a ROM bank, wrapping the bank number
+    /// to the size of the ROM.
+    fn read_rom(&self, bank: usize, offset: usize) -> u8 {
+        let banks = (self.rom.len() / ROM_BANK_SIZE).max(1);
+        let addr = (bank % banks) * ROM_BANK_SIZE + offset;
+        *self.rom.get(addr).unwrap_or(&0xFF)
+    }
+
+    /// Offset into RAM for 0xA000 - 0xBFFF, if accessible.
+    fn ram_addr(&self, addr: u16) -> Option<usize> {
+        // Bank 0x08 - 0x0C selects the RTC registers
+        if !self.ram_enabled || self.ram.is_empty() || self.ram_bank > 0x03 {
+            return None;
+        }
+
+        let addr = self.ram_bank as usize * RAM_BANK_SIZE + (addr as usize - 0xA000);
+        Some(addr % self.ram.len())
+    }
+}
+
+impl Bus for Mbc3 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // ROM bank 0
+            0x0000..=0x3FFF => self.read_rom(0, addr as usize),
+
+            // Switchable ROM bank
+            0x4000..=0x7FFF => self.read_rom(self.rom_bank.into(), addr as usize - 0x4000),
+
+            // External RAM or RTC register
+            0xA000..=0xBFFF => match (self.ram_addr(addr), &self.rtc, self.rtc_reg()) {
+                (Some(a), _, _) => self.ram[a],
+                (None, Some(rtc), Some(reg)) => rtc.read(reg),
+                _ => 0xFF,
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x4000..=0x7FFF => self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // RAM (and RTC) enable
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+
+            // ROM bank
+            // Bank 0 cannot be selected here.
+            0x2000..=0x3FFF => {
+                self.rom_bank = val & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+
+            // RAM bank or RTC register select
+            0x4000..=0x5FFF => self.ram_bank = val & 0x0F,
+
+            // RTC latch
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+
+            // External RAM or RTC register
+            0xA000..=0xBFFF => {
+                if let Some(a) = self.ram_addr(addr) {
+                    self.ram[a] = val;
+                } else if let Some(reg) = self.rtc_reg() {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.write(reg, val);
+                    }
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"MBC3");
+        w.bool(self.ram_enabled);
+        w.u8(self.rom_bank);
+        w.u8(self.ram_bank);
+        w.bytes(&self.ram);
+        w.bool(self.rtc.is_some());
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(w);
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"MBC3", "cartridge type")?;
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u8()?;
+        self.ram_bank = r.u8()?;
+        r.bytes_into(&mut self.ram, "cartridge RAM")?;
+        if r.bool()? != self.rtc.is_some() {
+            bail!(StateErr::Mismatch("cartridge RTC"));
+        }
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_state(r)?;
+        }
+        Ok(())
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn mbc_state(&self) -> Option<MbcState> {
+        Some(MbcState {
+            rom_bank: self.rom_bank(0x4000),
+            ram_bank: self.ram_bank as usize,
+            ram_enabled: self.ram_enabled,
+            mode: false,
+        })
+    }
+
+    fn force_rom_bank(&mut self, bank: usize) -> Result<()> {
+        check_bank(bank, self.rom.len(), ROM_BANK_SIZE)?;
+        self.rom_bank = bank as u8;
+        Ok(())
+    }
+
+    fn force_ram_bank(&mut self, bank: usize) -> Result<()> {
+        check_bank(bank, self.ram.len(), RAM_BANK_SIZE)?;
+        self.ram_bank = bank as u8;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.ram_enabled = false;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
+    /// RAM, followed by the RTC footer if the cartridge
+    /// has a clock.
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        if self.ram.is_empty() && self.rtc.is_none() {
+            return None;
+        }
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend(rtc.footer());
+        }
+        Some(data)
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) -> Result<()> {
+        battery::load_ram(&mut self.ram, data)?;
+        if let Some(rtc) = &mut self.rtc {
+            let footer = &data[self.ram.len()..];
+            if footer.len() >= FOOTER_SIZE {
+                rtc.load_footer(footer);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::apu::CPU_CLOCK;
+    use super::*;
+
+    /// ROM where each bank is filled with its bank number
+    fn rom(banks: usize) -> Vec<u8> {
+        (0..banks).flat_map(|b| [b as u8; ROM_BANK_SIZE]).collect()
+    }
+
+    #[test]
+    fn rom_bank_switch() {
+        let mut c = Mbc3::new(&rom(128), 0, false);
+        assert_eq!(c.read(0x0000), 0);
+        assert_eq!(c.read(0x4000), 1);
+
+        // Full 7 bits in a single register
+        c.write(0x2000, 0x45);
+        assert_eq!(c.read(0x4000), 0x45);
+        assert_eq!(c.read(0x7FFF), 0x45);
+        assert_eq!(c.read(0x3FFF), 0);
+
+        c.write(0x3FFF, 0xFF);
+        assert_eq!(c.read(0x4000), 0x7F);
+
+        // Bank 0 selects bank 1, but 0x20 etc. do not roll over
+        c.write(0x2000, 0x00);
+        assert_eq!(c.read(0x4000), 1);
+        c.write(0x2000, 0x20);
+        assert_eq!(c.read(0x4000), 0x20);
+    }
+
+    #[test]
+    fn ram_bank_switch() {
+        let mut c = Mbc3::new(&rom(2), 32 * 1024, false);
+        c.write(0xA000, 0x12);
+        assert_eq!(c.read(0xA000), 0xFF);
+
+        c.write(0x0000, 0x0A);
+        for bank in 0..4 {
+            c.write(0x4000, bank);
+            c.write(0xBFFF, bank + 0x10);
+        }
+        for bank in 0..4 {
+            c.write(0x5FFF, bank);
+            assert_eq!(c.read(0xBFFF), bank + 0x10);
+        }
+
+        // RTC registers are not RAM
+        c.write(0x4000, 0x08);
+        assert_eq!(c.read(0xA000), 0xFF);
+
+        c.write(0x1FFF, 0x00);
+        c.write(0x4000, 0x00);
+        assert_eq!(c.read(0xBFFF), 0xFF);
+    }
+
+    #[test]
+    fn save_state() {
+        let mut c = Mbc3::new(&rom(8), 32 * 1024, true);
+        c.write(0x0000, 0x0A);
+        c.write(0x2000, 0x05);
+        c.write(0x4000, 0x02);
+        c.write(0xA000, 0x42);
+        let mut w = StateWriter::new();
+        c.save_state(&mut w).unwrap();
+        let state = w.into_inner();
+
+        let mut c = Mbc3::new(&rom(8), 32 * 1024, true);
+        c.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(c.read(0x4000), 5);
+        assert_eq!(c.read(0xA000), 0x42);
+    }
+
+    #[test]
+    fn rtc() {
+        let mut c = Mbc3::new(&rom(2), 8 * 1024, true);
+        c.set_rtc_clock(RtcClock::Cycles);
+        c.write(0x0000, 0x0A);
+        c.write(0x4000, 0x08);
+        c.write(0xA000, 30);
+        c.write(0x4000, 0x09);
+        c.write(0xA000, 2);
+
+        // RAM is separate
+        c.write(0x4000, 0x00);
+        c.write(0xA000, 0x55);
+
+        c.tick(CPU_CLOCK * 40);
+        c.write(0x6000, 0x00);
+        c.write(0x6000, 0x01);
+        c.write(0x4000, 0x08);
+        assert_eq!(c.read(0xA000), 10);
+        c.write(0x4000, 0x09);
+        assert_eq!(c.read(0xBFFF), 3);
+        c.write(0x4000, 0x00);
+        assert_eq!(c.read(0xA000), 0x55);
+
+        // Not without RAM/RTC enable
+        c.write(0x0000, 0x00);
+        c.write(0x4000, 0x08);
+        assert_eq!(c.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn no_rtc() {
+        let mut c = Mbc3::new(&rom(2), 8 * 1024, false);
+        c.write(0x0000, 0x0A);
+        c.write(0x4000, 0x08);
+        c.write(0xA000, 30);
+        c.write(0x6000, 0x00);
+        c.write(0x6000, 0x01);
+        assert_eq!(c.read(0xA000), 0xFF);
+        assert_eq!(c.battery_ram().unwrap().len(), 8 * 1024);
+    }
+
+    #[test]
+    fn battery_rtc_footer() {
+        let mut c = Mbc3::new(&rom(2), 8 * 1024, true);
+        c.set_rtc_clock(RtcClock::Cycles);
+        c.write(0x0000, 0x0A);
+        c.write(0xA000, 0x42);
+        c.write(0x4000, 0x0A);
+        c.write(0xA000, 7);
+        let data = c.battery_ram().unwrap();
+        assert_eq!(data.len(), 8 * 1024 + FOOTER_SIZE);
+
+        let mut c = Mbc3::new(&rom(2), 8 * 1024, true);
+        c.set_rtc_clock(RtcClock::Cycles);
+        c.load_battery_ram(&data).unwrap();
+        c.write(0x0000, 0x0A);
+        assert_eq!(c.read(0xA000), 0x42);
+        c.write(0x6000, 0x00);
+        c.write(0x6000, 0x01);
+        c.write(0x4000, 0x0A);
+        assert_eq!(c.read(0xA000), 7);
+
+        // Save without footer
+        c.load_battery_ram(&data[..8 * 1024]).unwrap();
+    }
+
+    #[test]
+    fn reset() {
+        let mut c = Mbc3::new(&rom(8), 32 * 1024, true);
+        c.set_rtc_clock(RtcClock::Cycles);
+        c.write(0x0000, 0x0A);
+        c.write(0xA000, 0x42);
+        c.write(0x2000, 0x05);
+        c.write(0x4000, 0x08);
+        c.write(0xA000, 7);
+
+        c.reset();
+        assert_eq!(c.read(0x4000), 1);
+        assert_eq!(c.read(0xA000), 0xFF);
+
+        // RAM and the clock are kept
+        c.write(0x0000, 0x0A);
+        assert_eq!(c.read(0xA000), 0x42);
+        c.write(0x6000, 0x00);
+        c.write(0x6000, 0x01);
+        c.write(0x4000, 0x08);
+        assert_eq!(c.read(0xA000), 7);
+    }
+}
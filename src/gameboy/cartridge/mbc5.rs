@@ -0,0 +1,294 @@
+use anyhow::Result;
+
+use super::super::bus::bus::Bus;
+use super::super::savestate::{StateReader, StateWriter};
+use super::battery;
+use super::cartridge::{check_bank, Cartridge, MbcState};
+
+const ROM_BANK_SIZE: usize = 16 * 1024;
+const RAM_BANK_SIZE: usize = 8 * 1024;
+
+/// MBC5 memory bank controller, up to 8MiB ROM
+/// and 128KiB RAM.
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    /// RAM enable (0x0000 - 0x1FFF)
+    ram_enabled: bool,
+
+    /// ROM bank, 9 bits (0x2000 - 0x2FFF: lower 8 bits,
+    /// 0x3000 - 0x3FFF: bit 8)
+    rom_bank: u16,
+
+    /// RAM bank (0x4000 - 0x5FFF)
+    ram_bank: u8,
+
+    /// Bit 3 of the RAM bank register drives the
+    /// rumble motor instead.
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: &[u8], ram_size: usize, rumble: bool) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble,
+        }
+    }
+
+    /// Reads from a ROM bank, wrapping the bank number
+    /// to the size of the ROM.
+    fn read_rom(&self, bank: usize, offset: usize) -> u8 {
+        let banks = (self.rom.len() / ROM_BANK_SIZE).max(1);
+        let addr = (bank % banks) * ROM_BANK_SIZE + offset;
+        *self.rom.get(addr).unwrap_or(&0xFF)
+    }
+
+    /// Offset into RAM for 0xA000 - 0xBFFF, if accessible.
+    fn ram_addr(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+
+        let addr = self.ram_bank as usize * RAM_BANK_SIZE + (addr as usize - 0xA000);
+        Some(addr % self.ram.len())
+    }
+}
+
+impl Bus for Mbc5 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // ROM bank 0
+            0x0000..=0x3FFF => self.read_rom(0, addr as usize),
+
+            // Switchable ROM bank
+            0x4000..=0x7FFF => self.read_rom(self.rom_bank.into(), addr as usize - 0x4000),
+
+            // External RAM
+            0xA000..=0xBFFF => match self.ram_addr(addr) {
+                Some(a) => self.ram[a],
+                None => 0xFF,
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x4000..=0x7FFF => self.rom_bank as usize % (self.rom.len() / ROM_BANK_SIZE).max(1),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // RAM enable
+            // Unlike other MBCs, only exactly 0x0A enables RAM.
+            0x0000..=0x1FFF => self.ram_enabled = val == 0x0A,
+
+            // ROM bank (lower 8 bits)
+            // Bank 0 can be selected in the switchable area.
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+
+            // ROM bank (bit 8)
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (val as u16 & 0x01) << 8,
+
+            // RAM bank
+            0x4000..=0x5FFF => {
+                self.ram_bank = if self.rumble { val & 0x07 } else { val & 0x0F };
+            }
+
+            0x6000..=0x7FFF => (),
+
+            // External RAM
+            0xA000..=0xBFFF => {
+                if let Some(a) = self.ram_addr(addr) {
+                    self.ram[a] = val;
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"MBC5");
+        w.bool(self.ram_enabled);
+        w.u16(self.rom_bank);
+        w.u8(self.ram_bank);
+        w.bytes(&self.ram);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"MBC5", "cartridge type")?;
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u16()?;
+        self.ram_bank = r.u8()?;
+        r.bytes_into(&mut self.ram, "cartridge RAM")
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn mbc_state(&self) -> Option<MbcState> {
+        Some(MbcState {
+            rom_bank: self.rom_bank(0x4000),
+            ram_bank: self.ram_bank as usize,
+            ram_enabled: self.ram_enabled,
+            mode: false,
+        })
+    }
+
+    fn force_rom_bank(&mut self, bank: usize) -> Result<()> {
+        check_bank(bank, self.rom.len(), ROM_BANK_SIZE)?;
+        self.rom_bank = bank as u16;
+        Ok(())
+    }
+
+    fn force_ram_bank(&mut self, bank: usize) -> Result<()> {
+        check_bank(bank, self.ram.len(), RAM_BANK_SIZE)?;
+        self.ram_bank = bank as u8;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.ram_enabled = false;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+    }
+
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        (!self.ram.is_empty()).then(|| self.ram.clone())
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) -> Result<()> {
+        battery::load_ram(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM where each bank is filled with the lower 8 bits
+    /// of its bank number, and the ninth bit at offset 1.
+    fn rom(banks: usize) -> Vec<u8> {
+        (0..banks)
+            .flat_map(|b| {
+                let mut bank = [b as u8; ROM_BANK_SIZE];
+                bank[1] = (b >> 8) as u8;
+                bank
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rom_bank_switch() {
+        let mut c = Mbc5::new(&rom(512), 0, false);
+        assert_eq!(c.read(0x0000), 0);
+        assert_eq!(c.read(0x4000), 1);
+
+        c.write(0x2000, 0xAB);
+        assert_eq!(c.read(0x4000), 0xAB);
+        assert_eq!(c.read(0x4001), 0);
+
+        // Ninth bit
+        c.write(0x3000, 0x01);
+        assert_eq!(c.read(0x4000), 0xAB);
+        assert_eq!(c.read(0x4001), 1);
+        c.write(0x2FFF, 0x02);
+        assert_eq!(c.read(0x4000), 0x02);
+        assert_eq!(c.read(0x4001), 1);
+        c.write(0x3FFF, 0xFE);
+        assert_eq!(c.read(0x4001), 0);
+
+        // Bank 0 is selectable
+        c.write(0x2000, 0x00);
+        assert_eq!(c.read(0x4000), 0);
+        assert_eq!(c.read(0x3FFF), 0);
+    }
+
+    #[test]
+    fn rom_bank_wrap() {
+        let mut c = Mbc5::new(&rom(4), 0, false);
+        c.write(0x2000, 0x05);
+        assert_eq!(c.read(0x4000), 1);
+        assert_eq!(c.rom_bank(0x4000), 1);
+        assert_eq!(c.rom_bank(0x3FFF), 0);
+    }
+
+    #[test]
+    fn ram_bank_switch() {
+        let mut c = Mbc5::new(&rom(2), 128 * 1024, false);
+        c.write(0x0000, 0x0A);
+        for bank in 0..16 {
+            c.write(0x4000, bank);
+            c.write(0xA000, bank + 0x10);
+        }
+        for bank in 0..16 {
+            c.write(0x5FFF, bank);
+            assert_eq!(c.read(0xA000), bank + 0x10);
+        }
+
+        // Only 0x0A enables RAM
+        c.write(0x0000, 0x1A);
+        assert_eq!(c.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn rumble() {
+        let mut c = Mbc5::new(&rom(2), 32 * 1024, true);
+        c.write(0x0000, 0x0A);
+        c.write(0x4000, 0x01);
+        c.write(0xA000, 0x12);
+
+        // Bit 3 drives the motor and does not select a bank
+        c.write(0x4000, 0x09);
+        assert_eq!(c.read(0xA000), 0x12);
+    }
+
+    #[test]
+    fn save_state() {
+        let mut c = Mbc5::new(&rom(512), 8 * 1024, false);
+        c.write(0x0000, 0x0A);
+        c.write(0x2000, 0x05);
+        c.write(0x3000, 0x01);
+        c.write(0xA000, 0x42);
+        let mut w = StateWriter::new();
+        c.save_state(&mut w).unwrap();
+        let state = w.into_inner();
+
+        let mut c = Mbc5::new(&rom(512), 8 * 1024, false);
+        c.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(c.read(0x4000), 5);
+        assert_eq!(c.read(0x4001), 1);
+        assert_eq!(c.read(0xA000), 0x42);
+    }
+
+    #[test]
+    fn reset() {
+        let mut c = Mbc5::new(&rom(512), 32 * 1024, false);
+        c.write(0x0000, 0x0A);
+        c.write(0x4000, 0x03);
+        c.write(0xA000, 0x42);
+        c.write(0x2000, 0x05);
+        c.write(0x3000, 0x01);
+
+        c.reset();
+        assert_eq!(c.read(0x4000), 1);
+        assert_eq!(c.read(0x4001), 0);
+        assert_eq!(c.read(0xA000), 0xFF);
+
+        // RAM is kept, bank 0 is selected
+        c.write(0x0000, 0x0A);
+        assert_eq!(c.read(0xA000), 0x00);
+        c.write(0x4000, 0x03);
+        assert_eq!(c.read(0xA000), 0x42);
+    }
+}
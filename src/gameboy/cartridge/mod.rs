@@ -0,0 +1,9 @@
+pub mod battery;
+#[allow(clippy::module_inception)]
+pub mod cartridge;
+pub mod header;
+pub mod mbc1;
+pub mod mbc3;
+pub mod mbc5;
+pub mod romonly;
+pub mod rtc;
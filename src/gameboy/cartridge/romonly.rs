@@ -0,0 +1,97 @@
+use anyhow::Result;
+
+use super::super::bus::bus::Bus;
+use super::super::savestate::{StateReader, StateWriter};
+use super::battery;
+use super::cartridge::Cartridge;
+
+/// Cartridge without a memory bank controller:
+/// 32KiB ROM and optionally up to 8KiB RAM.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: &[u8], ram_size: usize) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            ram: vec![0; ram_size],
+        }
+    }
+}
+
+impl Bus for RomOnly {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => *self.rom.get(addr as usize).unwrap_or(&0xFF),
+            0xA000..=0xBFFF => *self.ram.get(addr as usize - 0xA000).unwrap_or(&0xFF),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7FFF => (),
+            0xA000..=0xBFFF => {
+                if let Some(b) = self.ram.get_mut(addr as usize - 0xA000) {
+                    *b = val;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"ROM0");
+        w.bytes(&self.ram);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"ROM0", "cartridge type")?;
+        r.bytes_into(&mut self.ram, "cartridge RAM")
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn battery_ram(&self) -> Option<Vec<u8>> {
+        (!self.ram.is_empty()).then(|| self.ram.clone())
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) -> Result<()> {
+        battery::load_ram(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom() {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x7FFF] = 0xAA;
+        let mut c = RomOnly::new(&rom, 0);
+        assert_eq!(c.read(0x7FFF), 0xAA);
+        c.write(0x7FFF, 0x00);
+        assert_eq!(c.read(0x7FFF), 0xAA);
+
+        // Short ROM image
+        let c = RomOnly::new(&rom[..0x4000], 0);
+        assert_eq!(c.read(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn ram() {
+        let mut c = RomOnly::new(&[], 0);
+        c.write(0xA000, 0x12);
+        assert_eq!(c.read(0xA000), 0xFF);
+
+        let mut c = RomOnly::new(&[], 8 * 1024);
+        c.write(0xA000, 0x12);
+        c.write(0xBFFF, 0x34);
+        assert_eq!(c.read(0xA000), 0x12);
+        assert_eq!(c.read(0xBFFF), 0x34);
+    }
+}
@@ -0,0 +1,395 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use super::super::apu::CPU_CLOCK;
+use super::super::savestate::{StateReader, StateWriter};
+
+/// Size of the RTC footer in .sav files
+pub const FOOTER_SIZE: usize = 48;
+
+/// DH bit 0 - Day counter bit 8
+const DH_DAY_HIGH: u8 = 1 << 0;
+
+/// DH bit 6 - Halt
+const DH_HALT: u8 = 1 << 6;
+
+/// DH bit 7 - Day counter carry
+const DH_CARRY: u8 = 1 << 7;
+
+/// Time source driving the clock
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RtcClock {
+    /// Follows the host's wall clock, also while the
+    /// emulator is not running (through the save file).
+    Host,
+
+    /// Advances with emulated CPU cycles only, for
+    /// deterministic runs.
+    Cycles,
+}
+
+/// Clock registers (0x08 - 0x0C)
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+struct RtcRegs {
+    secs: u8,
+    mins: u8,
+    hours: u8,
+    /// Day counter, 9 bits
+    days: u16,
+    halt: bool,
+    carry: bool,
+}
+
+impl RtcRegs {
+    fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.secs,
+            0x09 => self.mins,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            0x0C => {
+                (self.days >> 8) as u8 & DH_DAY_HIGH
+                    | if self.halt { DH_HALT } else { 0 }
+                    | if self.carry { DH_CARRY } else { 0 }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u8, val: u8) {
+        match reg {
+            0x08 => self.secs = val & 0x3F,
+            0x09 => self.mins = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | val as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((val & DH_DAY_HIGH) as u16) << 8;
+                self.halt = val & DH_HALT != 0;
+                self.carry = val & DH_CARRY != 0;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn add_secs(&mut self, n: u64) {
+        let total = self.secs as u64
+            + self.mins as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400
+            + n;
+
+        self.secs = (total % 60) as u8;
+        self.mins = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days > 0x1FF {
+            self.carry = true;
+        }
+        self.days = (days % 0x200) as u16;
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// MBC3 real-time clock
+///
+/// The registers are read through a latched copy, which is
+/// updated by writing 0x00 and then 0x01 to 0x6000 - 0x7FFF.
+pub struct Rtc {
+    clock: RtcClock,
+
+    /// Running clock
+    regs: RtcRegs,
+
+    /// Latched copy, as visible to the CPU
+    latched: RtcRegs,
+
+    /// CPU cycles into the current second
+    subsec: u64,
+
+    /// Last value written to the latch register
+    latch_prev: u8,
+
+    /// Host time the clock was last brought up to date
+    /// (RtcClock::Host only)
+    last_sync: SystemTime,
+}
+
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Self {
+        Self {
+            clock,
+            regs: RtcRegs::default(),
+            latched: RtcRegs::default(),
+            subsec: 0,
+            latch_prev: 0xFF,
+            last_sync: SystemTime::now(),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        self.clock = clock;
+    }
+
+    /// Advances the clock by a number of CPU cycles.
+    fn advance(&mut self, cycles: u64) {
+        if self.regs.halt {
+            return;
+        }
+        self.subsec += cycles;
+        let secs = self.subsec / CPU_CLOCK as u64;
+        self.subsec %= CPU_CLOCK as u64;
+        if secs > 0 {
+            self.regs.add_secs(secs);
+        }
+    }
+
+    /// Brings a host-backed clock up to date.
+    fn sync(&mut self) {
+        if self.clock != RtcClock::Host {
+            return;
+        }
+        let now = SystemTime::now();
+        if let Ok(elapsed) = now.duration_since(self.last_sync) {
+            let cycles = elapsed.as_nanos() * CPU_CLOCK as u128 / 1_000_000_000;
+            self.advance(cycles as u64);
+        }
+        self.last_sync = now;
+    }
+
+    /// Advances a cycle-driven clock.
+    pub fn tick(&mut self, cycles: usize) {
+        if self.clock == RtcClock::Cycles {
+            self.advance(cycles as u64);
+        }
+    }
+
+    /// Reads a latched clock register (0x08 - 0x0C).
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    /// Writes a clock register (0x08 - 0x0C) of the
+    /// running clock.
+    pub fn write(&mut self, reg: u8, val: u8) {
+        self.sync();
+        if reg == 0x08 {
+            // Resets the prescaler
+            self.subsec = 0;
+        }
+        self.regs.write(reg, val);
+    }
+
+    /// Write to the latch register (0x6000 - 0x7FFF)
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch_prev == 0x00 && val == 0x01 {
+            self.sync();
+            self.latched = self.regs;
+        }
+        self.latch_prev = val;
+    }
+
+    /// Serializes the clock as the common 48-byte .sav footer:
+    /// the running and latched registers as 32-bit values,
+    /// followed by a 64-bit UNIX timestamp.
+    pub fn footer(&self) -> Vec<u8> {
+        let mut regs = self.regs;
+        if self.clock == RtcClock::Host && !regs.halt {
+            if let Ok(elapsed) = SystemTime::now().duration_since(self.last_sync) {
+                regs.add_secs(elapsed.as_secs());
+            }
+        }
+
+        let mut out = Vec::with_capacity(FOOTER_SIZE);
+        for r in [regs, self.latched] {
+            for reg in 0x08..=0x0C {
+                out.extend_from_slice(&(r.read(reg) as u32).to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&unix_time().to_le_bytes());
+        out
+    }
+
+    /// Restores the clock from a .sav footer. A host-backed
+    /// clock catches up with the time passed since it was saved.
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        let Some(footer) = footer.get(..FOOTER_SIZE) else {
+            return;
+        };
+        let word = |i: usize| footer[i * 4];
+
+        for (i, reg) in (0x08..=0x0C).enumerate() {
+            self.regs.write(reg, word(i));
+            self.latched.write(reg, word(i + 5));
+        }
+        self.subsec = 0;
+        self.last_sync = SystemTime::now();
+
+        if self.clock == RtcClock::Host && !self.regs.halt {
+            let saved = u64::from_le_bytes(footer[40..48].try_into().unwrap());
+            self.regs.add_secs(unix_time().saturating_sub(saved));
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for r in [self.regs, self.latched] {
+            for reg in 0x08..=0x0C {
+                w.u8(r.read(reg));
+            }
+        }
+        w.u64(self.subsec);
+        w.u8(self.latch_prev);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        for reg in 0x08..=0x0C {
+            self.regs.write(reg, r.u8()?);
+        }
+        for reg in 0x08..=0x0C {
+            self.latched.write(reg, r.u8()?);
+        }
+        self.subsec = r.u64()?;
+        self.latch_prev = r.u8()?;
+        self.last_sync = SystemTime::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    #[test]
+    fn count() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.write(0x08, 58);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.tick(CPU_CLOCK * 2 - 1);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 59);
+        assert_eq!(rtc.read(0x0B), 0xFF);
+
+        rtc.tick(1);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 0);
+        assert_eq!(rtc.read(0x09), 0);
+        assert_eq!(rtc.read(0x0A), 0);
+        assert_eq!(rtc.read(0x0B), 0x00);
+        assert_eq!(rtc.read(0x0C), 0x01);
+    }
+
+    #[test]
+    fn day_carry() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.write(0x0C, 0x01);
+        rtc.write(0x0B, 0xFF);
+        rtc.regs.add_secs(86400);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x0B), 0x00);
+        assert_eq!(rtc.read(0x0C), 0x80);
+
+        // Carry stays set until cleared
+        rtc.regs.add_secs(86400);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x0C), 0x80);
+        rtc.write(0x0C, 0x00);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x0C), 0x00);
+    }
+
+    #[test]
+    fn latch_sequence() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.write(0x08, 10);
+        assert_eq!(rtc.read(0x08), 0);
+
+        // 0x01 alone does not latch
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x02);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 10);
+
+        // Latched value does not change
+        rtc.tick(CPU_CLOCK * 5);
+        assert_eq!(rtc.read(0x08), 10);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 15);
+    }
+
+    #[test]
+    fn halt() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.write(0x0C, DH_HALT);
+        rtc.tick(CPU_CLOCK * 5);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 0);
+        assert_eq!(rtc.read(0x0C), DH_HALT);
+
+        rtc.write(0x0C, 0);
+        rtc.tick(CPU_CLOCK);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 1);
+    }
+
+    #[test]
+    fn seconds_write_resets_prescaler() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.tick(CPU_CLOCK - 1);
+        rtc.write(0x08, 0);
+        rtc.tick(1);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x08), 0);
+    }
+
+    #[test]
+    fn footer() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.write(0x08, 12);
+        rtc.write(0x09, 34);
+        latch(&mut rtc);
+        rtc.write(0x0A, 5);
+        let footer = rtc.footer();
+        assert_eq!(footer.len(), FOOTER_SIZE);
+        assert_eq!(footer[0..4], [12, 0, 0, 0]);
+        assert_eq!(footer[28..32], [0, 0, 0, 0]);
+
+        let mut rtc2 = Rtc::new(RtcClock::Cycles);
+        rtc2.load_footer(&footer);
+        assert_eq!(rtc2.regs, rtc.regs);
+        assert_eq!(rtc2.latched, rtc.latched);
+    }
+
+    #[test]
+    fn footer_host_catch_up() {
+        let mut rtc = Rtc::new(RtcClock::Host);
+        rtc.write(0x0C, 0);
+        let mut footer = rtc.footer();
+        // Saved an hour ago
+        let saved = unix_time() - 3600;
+        footer[40..48].copy_from_slice(&saved.to_le_bytes());
+
+        let mut rtc = Rtc::new(RtcClock::Host);
+        rtc.load_footer(&footer);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(0x0A), 1);
+    }
+}
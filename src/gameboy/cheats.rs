@@ -0,0 +1,303 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use super::bus::bus::Bus;
+use super::bus::hookbus::Hooks;
+
+/// A single cheat code
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Cheat {
+    /// Game Genie: patches a ROM byte as it is read, if the
+    /// original byte matches 'compare' (when given).
+    GameGenie {
+        addr: u16,
+        val: u8,
+        compare: Option<u8>,
+    },
+
+    /// GameShark: writes 'val' to a RAM byte once per frame,
+    /// like the device does during V-blank. Codes selecting a
+    /// RAM bank (types 0x8x and 0x9x) are not supported.
+    GameShark { addr: u16, val: u8 },
+}
+
+impl FromStr for Cheat {
+    type Err = Error;
+
+    /// Parses a Game Genie code ("ABC-DEF" or "ABC-DEF-GHI")
+    /// or a GameShark code ("01VVAAAA").
+    fn from_str(s: &str) -> Result<Self> {
+        let code = s.trim();
+        let digits: Vec<u8> = code
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| Error::msg(format!("Invalid cheat code '{}'", code)))?;
+
+        match (digits.len(), code.contains('-')) {
+            (6 | 9, true) => {
+                let val = digits[0] << 4 | digits[1];
+                let addr = ((digits[5] ^ 0xF) as u16) << 12
+                    | (digits[2] as u16) << 8
+                    | (digits[3] as u16) << 4
+                    | digits[4] as u16;
+                let compare = (digits.len() == 9)
+                    .then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA);
+                Ok(Cheat::GameGenie { addr, val, compare })
+            }
+            (8, false) => {
+                let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+                match byte(0) {
+                    0x00 | 0x01 => Ok(Cheat::GameShark {
+                        val: byte(2),
+                        addr: u16::from_le_bytes([byte(4), byte(6)]),
+                    }),
+                    0x80..=0x9F => {
+                        bail!("Bank selecting GameShark code '{}' is not supported", code)
+                    }
+                    _ => bail!("Unknown GameShark code type in '{}'", code),
+                }
+            }
+            _ => bail!("Unknown cheat code format '{}'", code),
+        }
+    }
+}
+
+/// Cheat engine
+///
+/// Game Genie codes patch ROM as the CPU sees it: the bus
+/// stays untouched, install() hooks them into a HookBus.
+/// GameShark codes are written to RAM by write_frame(), to
+/// be called once per frame.
+#[derive(Default)]
+pub struct Cheats {
+    /// Cheats with their IDs and enabled state
+    cheats: Vec<(usize, Cheat, bool)>,
+
+    /// ID for the next cheat
+    next_id: usize,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses and enables a cheat code. Returns an ID for
+    /// remove() and set_enabled().
+    pub fn add(&mut self, code: &str) -> Result<usize> {
+        let cheat = code.parse()?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.cheats.push((id, cheat, true));
+        Ok(id)
+    }
+
+    /// Adds all codes in a cheat file: one code per line,
+    /// optionally followed by a description. Empty lines and
+    /// lines starting with '#' are ignored.
+    pub fn add_file(&mut self, contents: &str) -> Result<Vec<usize>> {
+        let mut ids = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let code = line.split_whitespace().next().unwrap_or_default();
+            match self.add(code) {
+                Ok(id) => ids.push(id),
+                Err(e) => bail!("Line {}: {}", i + 1, e),
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        self.cheats.retain(|&(i, _, _)| i != id);
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) {
+        if let Some(c) = self.cheats.iter_mut().find(|(i, _, _)| *i == id) {
+            c.2 = enabled;
+        }
+    }
+
+    /// All cheats with their IDs and enabled state.
+    pub fn list(&self) -> &[(usize, Cheat, bool)] {
+        &self.cheats
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter().filter(|c| c.2).map(|(_, c, _)| c)
+    }
+
+    /// Applies the Game Genie codes to a byte read from ROM
+    /// (0x0000 - 0x7FFF).
+    pub fn patch(&self, addr: u16, val: u8) -> u8 {
+        self.enabled()
+            .find_map(|c| match *c {
+                Cheat::GameGenie {
+                    addr: a,
+                    val: new,
+                    compare,
+                } if a == addr && compare.is_none_or(|cmp| cmp == val) => Some(new),
+                _ => None,
+            })
+            .unwrap_or(val)
+    }
+
+    /// Writes the GameShark codes to memory.
+    pub fn write_frame<B: Bus + ?Sized>(&self, bus: &mut B) {
+        for c in self.enabled() {
+            if let Cheat::GameShark { addr, val } = *c {
+                bus.write(addr, val);
+            }
+        }
+    }
+
+    /// Hooks the Game Genie codes of 'cheats' into the buses
+    /// sharing 'hooks', so the CPU sees them, including on
+    /// instruction fetches. Returns the ID of the hook.
+    pub fn install(cheats: &Rc<RefCell<Self>>, hooks: &mut Hooks) -> usize {
+        let cheats = Rc::clone(cheats);
+        hooks.add_patch_hook(0x0000..=0x7FFF, move |addr, val| {
+            cheats.borrow().patch(addr, val)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bus::hookbus::HookBus;
+    use super::super::bus::testbus::Testbus;
+    use super::*;
+
+    #[test]
+    fn parse_game_genie() {
+        assert_eq!(
+            "00A-17B-C49".parse::<Cheat>().unwrap(),
+            Cheat::GameGenie {
+                addr: 0x4A17,
+                val: 0x00,
+                compare: Some(0xC8)
+            }
+        );
+        assert_eq!(
+            "3EA-17F".parse::<Cheat>().unwrap(),
+            Cheat::GameGenie {
+                addr: 0x0A17,
+                val: 0x3E,
+                compare: None
+            }
+        );
+    }
+
+    #[test]
+    fn parse_gameshark() {
+        assert_eq!(
+            "010238CD".parse::<Cheat>().unwrap(),
+            Cheat::GameShark {
+                addr: 0xCD38,
+                val: 0x02
+            }
+        );
+        assert!("810238CD".parse::<Cheat>().is_err());
+        assert!("F10238CD".parse::<Cheat>().is_err());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for code in ["", "00A-17B-C4", "0102038CD", "0102G8CD", "00A17BC49"] {
+            assert!(code.parse::<Cheat>().is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn patch() {
+        let mut c = Cheats::new();
+        let id = c.add("00A-17B-C49").unwrap();
+        c.add("3EA-17F").unwrap();
+        c.add("010238CD").unwrap();
+
+        assert_eq!(c.patch(0x4A17, 0xC8), 0x00);
+        // Compare mismatch (e.g. another bank)
+        assert_eq!(c.patch(0x4A17, 0xC9), 0xC9);
+        assert_eq!(c.patch(0x0A17, 0x12), 0x3E);
+        assert_eq!(c.patch(0x0A18, 0x12), 0x12);
+        // Not a Game Genie code
+        assert_eq!(c.patch(0xCD38, 0x12), 0x12);
+
+        c.set_enabled(id, false);
+        assert_eq!(c.patch(0x4A17, 0xC8), 0xC8);
+        c.set_enabled(id, true);
+        assert_eq!(c.patch(0x4A17, 0xC8), 0x00);
+        c.remove(id);
+        assert_eq!(c.patch(0x4A17, 0xC8), 0xC8);
+        assert_eq!(c.list().len(), 2);
+    }
+
+    #[test]
+    fn install() {
+        let cheats = Rc::new(RefCell::new(Cheats::new()));
+        let hooks = Rc::new(RefCell::new(Hooks::new()));
+        Cheats::install(&cheats, &mut hooks.borrow_mut());
+        let mut rom = [0xAA_u8; 0x4A18];
+        rom[0x4A17] = 0xC8;
+        let mut b = HookBus::new(Testbus::from(&rom), hooks);
+        cheats.borrow_mut().add("00A-17B-C49").unwrap();
+        cheats.borrow_mut().add("010238CD").unwrap();
+
+        // Game Genie codes also patch instruction fetches
+        assert_eq!(b.read(0x4A17), 0x00);
+        assert_eq!(b.peek(0x4A17), 0x00);
+        assert_eq!(b.read(0x4A16), 0xAA);
+
+        // GameShark codes are not read patches
+        b.write(0xCD38, 0x10);
+        assert_eq!(b.read(0xCD38), 0x10);
+        cheats.borrow_mut().clear();
+        assert_eq!(b.read(0x4A17), 0xC8);
+    }
+
+    #[test]
+    fn write_frame() {
+        let mut c = Cheats::new();
+        c.add("010238CD").unwrap();
+        let id = c.add("01FF00C0").unwrap();
+        c.add("3EA-17F").unwrap();
+        c.set_enabled(id, false);
+
+        let mut b = Testbus::new();
+        b.write(0xCD38, 0x10);
+        c.write_frame(&mut b);
+        assert_eq!(b.read(0xCD38), 0x02);
+        assert_eq!(b.read(0xC000), 0x00);
+        assert_eq!(b.read(0x0A17), 0x00);
+    }
+
+    #[test]
+    fn cheat_file() {
+        let mut c = Cheats::new();
+        let ids = c
+            .add_file("# Infinite lives\n010238CD Lives\n\n  00A-17B-C49\n")
+            .unwrap();
+        assert_eq!(ids, [0, 1]);
+        assert_eq!(c.list()[0].1, "010238CD".parse().unwrap());
+        assert_eq!(c.patch(0x4A17, 0xC8), 0x00);
+
+        let err = c.add_file("010238CD\nbogus\n").unwrap_err();
+        assert!(err.to_string().starts_with("Line 2:"));
+    }
+}
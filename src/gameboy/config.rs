@@ -0,0 +1,686 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::apu::{CHANNELS, DEFAULT_SAMPLE_RATE};
+use super::cpu::cpu::TraceFormat;
+use super::joypad::Button;
+use super::model::Model;
+use super::ppu::Renderer;
+use super::video::{Palette, PostProcess};
+
+// TODO: parse with serde and the toml crate instead (see TODO.md)
+/// Value in a configuration file
+///
+/// Configuration files are a subset of TOML: tables, bare
+/// keys, and single-line strings, integers, floats, booleans
+/// and arrays.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "a string",
+            Self::Integer(_) => "an integer",
+            Self::Float(_) => "a float",
+            Self::Boolean(_) => "a boolean",
+            Self::Array(_) => "an array",
+        }
+    }
+
+    fn string(&self) -> Result<&str> {
+        match self {
+            Self::String(s) => Ok(s),
+            _ => bail!("Expected a string, found {}", self.type_name()),
+        }
+    }
+
+    fn integer(&self) -> Result<i64> {
+        match self {
+            Self::Integer(i) => Ok(*i),
+            _ => bail!("Expected an integer, found {}", self.type_name()),
+        }
+    }
+
+    fn usize(&self) -> Result<usize> {
+        Ok(usize::try_from(self.integer()?)?)
+    }
+
+    fn float(&self) -> Result<f32> {
+        match self {
+            Self::Float(f) => Ok(*f as f32),
+            Self::Integer(i) => Ok(*i as f32),
+            _ => bail!("Expected a number, found {}", self.type_name()),
+        }
+    }
+
+    fn boolean(&self) -> Result<bool> {
+        match self {
+            Self::Boolean(b) => Ok(*b),
+            _ => bail!("Expected a boolean, found {}", self.type_name()),
+        }
+    }
+
+    fn array(&self) -> Result<&[Value]> {
+        match self {
+            Self::Array(a) => Ok(a),
+            _ => bail!("Expected an array, found {}", self.type_name()),
+        }
+    }
+}
+
+/// Parser for a single value, see Value.
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self
+            .s
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// Fails unless only whitespace and a comment are left.
+    fn end(&mut self) -> Result<()> {
+        self.skip_ws();
+        match self.s.get(self.pos) {
+            None | Some(b'#') => Ok(()),
+            Some(_) => bail!("Unexpected '{}'", self.rest()),
+        }
+    }
+
+    fn rest(&self) -> String {
+        String::from_utf8_lossy(&self.s[self.pos..]).into_owned()
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_ws();
+        match self.s.get(self.pos) {
+            Some(b'"') => self.string(),
+            Some(b'\'') => {
+                let start = self.pos + 1;
+                let len = self.s[start..]
+                    .iter()
+                    .position(|&c| c == b'\'')
+                    .ok_or_else(|| anyhow!("Unterminated string"))?;
+                self.pos = start + len + 1;
+                Ok(Value::String(
+                    String::from_utf8_lossy(&self.s[start..start + len]).into_owned(),
+                ))
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                loop {
+                    self.skip_ws();
+                    if self.s.get(self.pos) == Some(&b']') {
+                        self.pos += 1;
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_ws();
+                    match self.s.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => (),
+                        _ => bail!("Expected ',' or ']' in array"),
+                    }
+                }
+            }
+            Some(_) => {
+                let start = self.pos;
+                while self
+                    .s
+                    .get(self.pos)
+                    .is_some_and(|c| !c.is_ascii_whitespace() && !b",]#".contains(c))
+                {
+                    self.pos += 1;
+                }
+                let word = std::str::from_utf8(&self.s[start..self.pos])?.replace('_', "");
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    w if w.starts_with("0x") => {
+                        Ok(Value::Integer(i64::from_str_radix(&w[2..], 16)?))
+                    }
+                    w => {
+                        if let Ok(i) = w.parse() {
+                            Ok(Value::Integer(i))
+                        } else if let Ok(f) = w.parse() {
+                            Ok(Value::Float(f))
+                        } else {
+                            bail!("Invalid value '{}'", w)
+                        }
+                    }
+                }
+            }
+            None => bail!("Missing value"),
+        }
+    }
+
+    fn string(&mut self) -> Result<Value> {
+        self.pos += 1;
+        let mut out = vec![];
+        loop {
+            let Some(&c) = self.s.get(self.pos) else {
+                bail!("Unterminated string");
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&e) = self.s.get(self.pos) else {
+                        bail!("Unterminated string");
+                    };
+                    self.pos += 1;
+                    out.push(match e {
+                        b'"' => b'"',
+                        b'\\' => b'\\',
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        _ => bail!("Unknown escape '\\{}'", e as char),
+                    });
+                }
+                _ => out.push(c),
+            }
+        }
+        Ok(Value::String(String::from_utf8(out)?))
+    }
+}
+
+/// Parses a configuration file into (table, key, value)
+/// entries. Keys before the first table have an empty table.
+fn parse(s: &str) -> Result<Vec<(String, String, Value)>> {
+    let is_key = |k: &str| {
+        !k.is_empty()
+            && k.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+
+    let mut entries: Vec<(String, String, Value)> = vec![];
+    let mut table = String::new();
+    for (i, line) in s.lines().enumerate() {
+        let result = (|| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return Ok(());
+            }
+            if let Some(rest) = line.strip_prefix('[') {
+                let Some((name, after)) = rest.split_once(']') else {
+                    bail!("Unterminated table header");
+                };
+                let name = name.trim();
+                if !is_key(name) {
+                    bail!("Invalid table name '{}'", name);
+                }
+                Parser {
+                    s: after.as_bytes(),
+                    pos: 0,
+                }
+                .end()?;
+                table = name.to_string();
+                return Ok(());
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("Expected 'key = value'");
+            };
+            let key = key.trim();
+            if !is_key(key) {
+                bail!("Invalid key '{}'", key);
+            }
+            if entries.iter().any(|(t, k, _)| *t == table && k == key) {
+                bail!("Duplicate key '{}'", key);
+            }
+            let mut p = Parser {
+                s: value.as_bytes(),
+                pos: 0,
+            };
+            let value = p.value()?;
+            p.end()?;
+            entries.push((table.clone(), key.to_string(), value));
+            Ok(())
+        })();
+        result.with_context(|| format!("Line {}", i + 1))?;
+    }
+    Ok(entries)
+}
+
+/// Video settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoConfig {
+    /// Integer scale of the output
+    pub scale: usize,
+
+    /// DMG palette
+    pub palette: Palette,
+
+    /// Scanline darkening (0.0 - 1.0)
+    pub scanlines: f32,
+
+    /// LCD ghosting (0.0 - 1.0)
+    pub ghosting: f32,
+
+    /// PPU renderer
+    pub renderer: Renderer,
+}
+
+impl VideoConfig {
+    /// Configures post-processing with these settings.
+    pub fn apply(&self, video: &mut PostProcess) {
+        video.set_scale(self.scale);
+        video.set_palette(self.palette);
+        video.set_scanlines(self.scanlines);
+        video.set_ghosting(self.ghosting);
+    }
+}
+
+/// Audio settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioConfig {
+    pub enabled: bool,
+
+    /// Output sample rate in Hz
+    pub sample_rate: usize,
+
+    /// Muted channels (1-4)
+    pub mute: Vec<usize>,
+}
+
+/// Debugging settings
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DebugConfig {
+    /// Print serial port output to stdout
+    pub serial: bool,
+
+    /// Print a trace log in this format
+    pub trace: Option<TraceFormat>,
+
+    /// Cheat file to load
+    pub cheats: Option<PathBuf>,
+}
+
+/// Emulator configuration
+///
+/// Loaded from a TOML file, every setting is optional:
+///
+/// ```toml
+/// rom = "game.gb"
+/// bootrom = "dmg_boot.bin"
+/// save_dir = "saves"
+/// model = "cgb"
+/// latch_input = true         # apply input at the start of each frame
+///
+/// [video]
+/// scale = 3
+/// palette = "green"          # grey, green, pocket or "rrggbb,rrggbb,rrggbb,rrggbb"
+/// scanlines = 0.3
+/// ghosting = 0.5
+/// renderer = "fifo"          # scanline or fifo
+///
+/// [audio]
+/// enabled = true
+/// sample_rate = 48000
+/// mute = [4]
+///
+/// [keys]
+/// a = "x"
+/// start = "return"
+///
+/// [debug]
+/// serial = true
+/// trace = "doctor"
+/// cheats = "game.cht"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub rom: Option<PathBuf>,
+    pub bootrom: Option<PathBuf>,
+
+    /// Directory for battery saves, defaults to the
+    /// directory of the ROM
+    pub save_dir: Option<PathBuf>,
+
+    /// Hardware model, detected from the cartridge by default
+    pub model: Option<Model>,
+
+    /// Apply input only at the start of each frame, instead
+    /// of right away
+    pub latch_input: bool,
+
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+
+    /// Key bindings: the frontend key name for each button
+    pub keys: Vec<(Button, String)>,
+
+    pub debug: DebugConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rom: None,
+            bootrom: None,
+            save_dir: None,
+            model: None,
+            latch_input: false,
+            video: VideoConfig {
+                scale: 3,
+                palette: Palette::GREY,
+                scanlines: 0.0,
+                ghosting: 0.0,
+                renderer: Renderer::Scanline,
+            },
+            audio: AudioConfig {
+                enabled: true,
+                sample_rate: DEFAULT_SAMPLE_RATE,
+                mute: vec![],
+            },
+            keys: vec![
+                (Button::Right, "right".to_string()),
+                (Button::Left, "left".to_string()),
+                (Button::Up, "up".to_string()),
+                (Button::Down, "down".to_string()),
+                (Button::A, "x".to_string()),
+                (Button::B, "z".to_string()),
+                (Button::Select, "backspace".to_string()),
+                (Button::Start, "return".to_string()),
+            ],
+            debug: DebugConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Default location of the configuration file:
+    /// $XDG_CONFIG_HOME/gbrust/config.toml or
+    /// ~/.config/gbrust/config.toml.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("gbrust").join("config.toml"))
+    }
+
+    /// Loads a configuration file. Relative paths in it are
+    /// relative to the file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let mut config =
+            Self::parse(&contents).with_context(|| format!("Parsing {}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for p in [
+            &mut config.rom,
+            &mut config.bootrom,
+            &mut config.save_dir,
+            &mut config.debug.cheats,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *p = base.join(&p);
+        }
+        Ok(config)
+    }
+
+    /// Loads the configuration file at 'path' or, without a
+    /// path, the one at default_path() if it exists.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => Self::load(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// Parses a configuration file. Unknown settings are
+    /// errors, to catch typos.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (table, key, value) in parse(s)? {
+            config
+                .set(&table, &key, &value)
+                .with_context(|| match table.as_str() {
+                    "" => format!("Setting '{}'", key),
+                    t => format!("Setting '{}.{}'", t, key),
+                })?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: &Value) -> Result<()> {
+        let path = |v: &Value| -> Result<Option<PathBuf>> { Ok(Some(v.string()?.into())) };
+
+        match (table, key) {
+            ("", "rom") => self.rom = path(value)?,
+            ("", "bootrom") => self.bootrom = path(value)?,
+            ("", "save_dir") => self.save_dir = path(value)?,
+            ("", "model") => self.model = Some(value.string()?.parse()?),
+            ("", "latch_input") => self.latch_input = value.boolean()?,
+
+            ("video", "scale") => match value.usize()? {
+                0 => bail!("Scale must be at least 1"),
+                s => self.video.scale = s,
+            },
+            ("video", "palette") => {
+                self.video.palette = match value {
+                    Value::Array(colors) => colors
+                        .iter()
+                        .map(|c| c.string())
+                        .collect::<Result<Vec<_>>>()?
+                        .join(",")
+                        .parse()?,
+                    v => v.string()?.parse()?,
+                }
+            }
+            ("video", "scanlines") => self.video.scanlines = value.float()?.clamp(0.0, 1.0),
+            ("video", "ghosting") => self.video.ghosting = value.float()?.clamp(0.0, 1.0),
+            ("video", "renderer") => self.video.renderer = value.string()?.parse()?,
+
+            ("audio", "enabled") => self.audio.enabled = value.boolean()?,
+            ("audio", "sample_rate") => match value.usize()? {
+                0 => bail!("Sample rate must be above 0"),
+                r => self.audio.sample_rate = r,
+            },
+            ("audio", "mute") => {
+                self.audio.mute = value
+                    .array()?
+                    .iter()
+                    .map(|v| match v.usize()? {
+                        ch @ 1..=CHANNELS => Ok(ch),
+                        ch => bail!("No sound channel {}", ch),
+                    })
+                    .collect::<Result<_>>()?
+            }
+
+            ("keys", button) => {
+                let button: Button = button.parse()?;
+                let key = value.string()?.to_string();
+                for binding in self.keys.iter_mut().filter(|(b, _)| *b == button) {
+                    binding.1 = key.clone();
+                }
+            }
+
+            ("debug", "serial") => self.debug.serial = value.boolean()?,
+            ("debug", "trace") => self.debug.trace = Some(value.string()?.parse()?),
+            ("debug", "cheats") => self.debug.cheats = path(value)?,
+
+            _ => bail!("Unknown setting"),
+        }
+        Ok(())
+    }
+
+    /// Key name bound to a button.
+    pub fn key(&self, button: Button) -> &str {
+        self.keys
+            .iter()
+            .find(|(b, _)| *b == button)
+            .map_or("", |(_, k)| k)
+    }
+
+    /// Button bound to a key name (case insensitive).
+    pub fn button(&self, key: &str) -> Option<Button> {
+        self.keys
+            .iter()
+            .find(|(_, k)| k.eq_ignore_ascii_case(key))
+            .map(|(b, _)| *b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let entries = parse(
+            r#"
+            # Comment
+            s = "a \"b\" \\ c" # Trailing comment
+            l = 'C:\path'
+            i = -1_000
+            h = 0xFF
+            f = 0.5
+            b = true
+            a = [1, "two", [3.0]]
+
+            [table]
+            empty = []
+            "#,
+        )
+        .unwrap();
+        let values: Vec<_> = entries
+            .iter()
+            .map(|(t, k, v)| (t.as_str(), k.as_str(), v.clone()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("", "s", Value::String("a \"b\" \\ c".to_string())),
+                ("", "l", Value::String("C:\\path".to_string())),
+                ("", "i", Value::Integer(-1000)),
+                ("", "h", Value::Integer(0xFF)),
+                ("", "f", Value::Float(0.5)),
+                ("", "b", Value::Boolean(true)),
+                (
+                    "",
+                    "a",
+                    Value::Array(vec![
+                        Value::Integer(1),
+                        Value::String("two".to_string()),
+                        Value::Array(vec![Value::Float(3.0)])
+                    ])
+                ),
+                ("table", "empty", Value::Array(vec![])),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for (s, line) in [
+            ("a = 1\nb = \"open", 2),
+            ("a = 1\n\n[table", 3),
+            ("a b = 1", 1),
+            ("a = 1 2", 1),
+            ("a = [1 2]", 1),
+            ("a = 1\na = 2", 2),
+            ("a =", 1),
+            ("a = yes", 1),
+        ] {
+            let err = parse(s).unwrap_err();
+            assert_eq!(err.to_string(), format!("Line {}", line), "{}", s);
+        }
+    }
+
+    #[test]
+    fn config() {
+        let c = Config::parse(
+            r#"
+            rom = "game.gb"
+            model = "cgb"
+            latch_input = true
+
+            [video]
+            scale = 2
+            palette = ["e0f8d0", "88c070", "346856", "081820"]
+            ghosting = 1
+            renderer = "fifo"
+
+            [audio]
+            mute = [1, 4]
+
+            [keys]
+            start = "space"
+
+            [debug]
+            trace = "full"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(c.rom, Some(PathBuf::from("game.gb")));
+        assert_eq!(c.model, Some(Model::Cgb));
+        assert!(c.latch_input);
+        assert_eq!(c.video.scale, 2);
+        assert_eq!(c.video.palette.0[0], 0xE0F8D0FF);
+        assert_eq!(c.video.ghosting, 1.0);
+        assert_eq!(c.video.renderer, Renderer::Fifo);
+        assert_eq!(c.audio.mute, [1, 4]);
+        assert_eq!(c.audio.sample_rate, DEFAULT_SAMPLE_RATE);
+        assert_eq!(c.key(Button::Start), "space");
+        assert_eq!(c.button("SPACE"), Some(Button::Start));
+        assert_eq!(c.button("return"), None);
+        assert_eq!(c.button("x"), Some(Button::A));
+        assert_eq!(c.debug.trace, Some(TraceFormat::Full));
+        assert!(!c.debug.serial);
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn config_errors() {
+        for (s, err) in [
+            ("scale = 2", "Setting 'scale'"),
+            ("[video]\nscale = 0", "Setting 'video.scale'"),
+            ("[video]\nscale = \"2\"", "Setting 'video.scale'"),
+            ("[audio]\nmute = [5]", "Setting 'audio.mute'"),
+            ("[keys]\nturbo = \"t\"", "Setting 'keys.turbo'"),
+            ("model = \"sgb\"", "Setting 'model'"),
+        ] {
+            assert_eq!(Config::parse(s).unwrap_err().to_string(), err, "{}", s);
+        }
+    }
+
+    #[test]
+    fn load_relative_paths() {
+        let dir = env::temp_dir().join(format!("gbrust-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "rom = \"game.gb\"\nbootrom = \"/boot.bin\"\n").unwrap();
+        let c = Config::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(c.rom, Some(dir.join("game.gb")));
+        assert_eq!(c.bootrom, Some(PathBuf::from("/boot.bin")));
+    }
+}
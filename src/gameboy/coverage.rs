@@ -0,0 +1,187 @@
+use std::fmt::Write;
+
+use super::profiler::Location;
+
+/// Size of a ROM bank
+const BANK_SIZE: usize = 0x4000;
+
+/// Byte was executed as part of an instruction
+pub const EXECUTED: u8 = 1 << 0;
+
+/// Byte was read as data. Instruction fetches are not reads,
+/// but a byte can be both executed and read.
+pub const READ: u8 = 1 << 1;
+
+/// Byte address was written (i.e. a mapper register)
+pub const WRITTEN: u8 = 1 << 2;
+
+/// Map of the ROM bytes accessed during a run, one byte of
+/// flags (EXECUTED, READ, WRITTEN) per byte of ROM.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offset in the ROM image of a location
+    fn offset(loc: Location) -> usize {
+        loc.bank * BANK_SIZE + (loc.addr as usize % BANK_SIZE)
+    }
+
+    /// Adds 'flags' to the ROM byte at 'loc'.
+    pub fn mark(&mut self, loc: Location, flags: u8) {
+        let offset = Self::offset(loc);
+        if offset >= self.flags.len() {
+            self.flags.resize((offset / BANK_SIZE + 1) * BANK_SIZE, 0);
+        }
+        self.flags[offset] |= flags;
+    }
+
+    /// Flags of the ROM byte at 'loc'
+    pub fn get(&self, loc: Location) -> u8 {
+        self.flags.get(Self::offset(loc)).copied().unwrap_or(0)
+    }
+
+    /// Number of ROM bytes with any of 'flags' set
+    pub fn count(&self, flags: u8) -> usize {
+        self.flags.iter().filter(|&&f| f & flags != 0).count()
+    }
+
+    /// Number of ROM bytes read but not executed
+    pub fn data_count(&self) -> usize {
+        self.flags
+            .iter()
+            .filter(|&&f| f & (READ | EXECUTED) == READ)
+            .count()
+    }
+
+    /// Code/data log: the flags of every byte in a ROM of
+    /// 'rom_size' bytes, in ROM order.
+    pub fn cdl(&self, rom_size: usize) -> Vec<u8> {
+        let mut cdl = self.flags.clone();
+        cdl.resize(rom_size, 0);
+        cdl
+    }
+
+    /// Ranges of ROM (first and last location) of which all
+    /// bytes have any of 'flags' set, without crossing banks.
+    pub fn ranges(&self, flags: u8) -> Vec<(Location, Location)> {
+        let loc = |offset: usize| {
+            let bank = offset / BANK_SIZE;
+            let base = if bank == 0 { 0 } else { BANK_SIZE };
+            Location {
+                bank,
+                addr: (base + offset % BANK_SIZE) as u16,
+            }
+        };
+
+        let mut ranges = vec![];
+        let mut start = None;
+        for (offset, f) in self.flags.iter().enumerate() {
+            let covered = f & flags != 0;
+            match start {
+                Some(s) if !covered || offset % BANK_SIZE == 0 => {
+                    ranges.push((loc(s), loc(offset - 1)));
+                    start = covered.then_some(offset);
+                }
+                None if covered => start = Some(offset),
+                _ => (),
+            }
+        }
+        if let Some(s) = start {
+            ranges.push((loc(s), loc(self.flags.len() - 1)));
+        }
+        ranges
+    }
+
+    /// Summary and the executed ranges of ROM, as text.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "Executed: {} bytes, data: {} bytes, written: {} addresses",
+            self.count(EXECUTED),
+            self.data_count(),
+            self.count(WRITTEN)
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+
+        writeln!(out, "{:<10} {:<10} {:>6}", "Start", "End", "Bytes").unwrap();
+        for (start, end) in self.ranges(EXECUTED) {
+            writeln!(
+                out,
+                "{:<10} {:<10} {:>6}",
+                start.to_string(),
+                end.to_string(),
+                end.addr - start.addr + 1
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loc(bank: usize, addr: u16) -> Location {
+        Location { bank, addr }
+    }
+
+    #[test]
+    fn mark() {
+        let mut c = Coverage::new();
+        assert_eq!(c.get(loc(2, 0x4010)), 0);
+
+        c.mark(loc(2, 0x4010), EXECUTED);
+        c.mark(loc(2, 0x4010), READ);
+        c.mark(loc(0, 0x2000), WRITTEN);
+        assert_eq!(c.get(loc(2, 0x4010)), EXECUTED | READ);
+        assert_eq!(c.get(loc(0, 0x2000)), WRITTEN);
+        assert_eq!(c.get(loc(1, 0x4010)), 0);
+
+        assert_eq!(c.count(EXECUTED | WRITTEN), 2);
+        assert_eq!(c.data_count(), 0);
+        c.mark(loc(1, 0x7FFF), READ);
+        assert_eq!(c.data_count(), 1);
+    }
+
+    #[test]
+    fn cdl() {
+        let mut c = Coverage::new();
+        c.mark(loc(1, 0x4001), EXECUTED);
+        let cdl = c.cdl(64 * 1024);
+        assert_eq!(cdl.len(), 64 * 1024);
+        assert_eq!(cdl[0x4001], EXECUTED);
+        assert_eq!(cdl.iter().filter(|&&f| f != 0).count(), 1);
+    }
+
+    #[test]
+    fn ranges() {
+        let mut c = Coverage::new();
+        for addr in 0x0100..0x0104 {
+            c.mark(loc(0, addr), EXECUTED);
+        }
+        c.mark(loc(0, 0x0105), READ);
+        c.mark(loc(0, 0x3FFF), EXECUTED);
+        c.mark(loc(1, 0x4000), EXECUTED);
+
+        assert_eq!(
+            c.ranges(EXECUTED),
+            vec![
+                (loc(0, 0x0100), loc(0, 0x0103)),
+                (loc(0, 0x3FFF), loc(0, 0x3FFF)),
+                (loc(1, 0x4000), loc(1, 0x4000)),
+            ]
+        );
+        assert_eq!(c.ranges(READ), vec![(loc(0, 0x0105), loc(0, 0x0105))]);
+        assert!(c.report().contains("00:0100    00:0103         4"));
+    }
+}
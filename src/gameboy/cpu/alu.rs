@@ -14,6 +14,39 @@ pub fn add_8b(a: u8, b: u8) -> ALUResult<u8> {
     }
 }
 
+/// 8-bit add with carry in
+pub fn add_8b_carry(a: u8, b: u8, carry: bool) -> ALUResult<u8> {
+    let c = carry as u8;
+    let result: u16 = a as u16 + b as u16 + c as u16;
+    ALUResult {
+        result: result as u8,
+        carry: (result > u8::MAX.into()),
+        halfcarry: ((a & 0x0F) + (b & 0x0F) + c) > 0x0F,
+    }
+}
+
+/// 16-bit add
+/// Half-carry is from bit 11, carry from bit 15.
+pub fn add_16b(a: u16, b: u16) -> ALUResult<u16> {
+    let result: u32 = a as u32 + b as u32;
+    ALUResult {
+        result: result as u16,
+        carry: (result > u16::MAX.into()),
+        halfcarry: ((a & 0x0FFF) + (b & 0x0FFF)) > 0x0FFF,
+    }
+}
+
+/// 16-bit add of a signed 8-bit value (SP + r8)
+/// Flags are computed as an unsigned add over the low byte.
+pub fn add_16b_signed8(a: u16, b: i8) -> ALUResult<u16> {
+    let low = add_8b(a as u8, b as u8);
+    ALUResult {
+        result: a.wrapping_add_signed(b.into()),
+        carry: low.carry,
+        halfcarry: low.halfcarry,
+    }
+}
+
 /// 8-bit subtract
 pub fn sub_8b(a: u8, b: u8) -> ALUResult<u8> {
     let result: i16 = a as i16 - b as i16;
@@ -24,6 +57,17 @@ pub fn sub_8b(a: u8, b: u8) -> ALUResult<u8> {
     }
 }
 
+/// 8-bit subtract with carry (borrow) in
+pub fn sub_8b_carry(a: u8, b: u8, carry: bool) -> ALUResult<u8> {
+    let c = carry as u8;
+    let result: i16 = a as i16 - b as i16 - c as i16;
+    ALUResult {
+        result: result as u8,
+        carry: result < 0,
+        halfcarry: (a & 0x0F) < (b & 0x0F) + c,
+    }
+}
+
 /// Rotate left with carry
 pub fn rotleft_9b(a: u8, carry: bool) -> ALUResult<u8> {
     let mut result = (a as u16) << 1;
@@ -42,12 +86,63 @@ pub fn rotleft_9b(a: u8, carry: bool) -> ALUResult<u8> {
 pub fn rotleft_8b(a: u8) -> ALUResult<u8> {
     let result = a.rotate_left(1);
     ALUResult {
-        result: result as u8,
+        result,
         carry: a & 0x80 == 0x80,
         halfcarry: false,
     }
 }
 
+/// 9-bit rotate right (through carry)
+pub fn rotright_9b(a: u8, carry: bool) -> ALUResult<u8> {
+    let mut result = a >> 1;
+    if carry {
+        result |= 0x80;
+    }
+
+    ALUResult {
+        result,
+        carry: a & 0x01 == 0x01,
+        halfcarry: false,
+    }
+}
+
+/// 8-bit rotate right
+pub fn rotright_8b(a: u8) -> ALUResult<u8> {
+    let result = a.rotate_right(1);
+    ALUResult {
+        result,
+        carry: a & 0x01 == 0x01,
+        halfcarry: false,
+    }
+}
+
+/// 8-bit shift left
+pub fn shleft_8b(a: u8) -> ALUResult<u8> {
+    ALUResult {
+        result: a << 1,
+        carry: a & 0x80 == 0x80,
+        halfcarry: false,
+    }
+}
+
+/// 8-bit shift right (logical)
+pub fn shright_8b(a: u8) -> ALUResult<u8> {
+    ALUResult {
+        result: a >> 1,
+        carry: a & 0x01 == 0x01,
+        halfcarry: false,
+    }
+}
+
+/// 8-bit shift right (arithmetic, sign extends)
+pub fn shright_arith_8b(a: u8) -> ALUResult<u8> {
+    ALUResult {
+        result: ((a as i8) >> 1) as u8,
+        carry: a & 0x01 == 0x01,
+        halfcarry: false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -93,38 +188,156 @@ mod tests {
         assert!(r.carry);
     }
 
+    #[test]
+    fn add_8b_carry_in() {
+        let r = super::add_8b_carry(0x0E, 0x01, false);
+        assert_eq!(r.result, 0x0F);
+        assert!(!r.halfcarry);
+        assert!(!r.carry);
+
+        let r = super::add_8b_carry(0x0E, 0x01, true);
+        assert_eq!(r.result, 0x10);
+        assert!(r.halfcarry);
+        assert!(!r.carry);
+
+        let r = super::add_8b_carry(0xFF, 0x00, true);
+        assert_eq!(r.result, 0x00);
+        assert!(r.halfcarry);
+        assert!(r.carry);
+
+        let r = super::add_8b_carry(0x0F, 0x0F, true);
+        assert_eq!(r.result, 0x1F);
+        assert!(r.halfcarry);
+        assert!(!r.carry);
+    }
+
+    #[test]
+    fn add_16b() {
+        let r = super::add_16b(0x0FFF, 0x0001);
+        assert_eq!(r.result, 0x1000);
+        assert!(r.halfcarry);
+        assert!(!r.carry);
+
+        let r = super::add_16b(0xFFFF, 0x0001);
+        assert_eq!(r.result, 0x0000);
+        assert!(r.halfcarry);
+        assert!(r.carry);
+
+        let r = super::add_16b(0x00FF, 0x0001);
+        assert_eq!(r.result, 0x0100);
+        assert!(!r.halfcarry);
+        assert!(!r.carry);
+    }
+
+    #[test]
+    fn add_16b_signed8() {
+        let r = super::add_16b_signed8(0x00FF, 1);
+        assert_eq!(r.result, 0x0100);
+        assert!(r.halfcarry);
+        assert!(r.carry);
+
+        let r = super::add_16b_signed8(0x1000, -1);
+        assert_eq!(r.result, 0x0FFF);
+        assert!(!r.halfcarry);
+        assert!(!r.carry);
+
+        let r = super::add_16b_signed8(0x1001, -1);
+        assert_eq!(r.result, 0x1000);
+        assert!(r.halfcarry);
+        assert!(r.carry);
+    }
+
     #[test]
     fn rotleft_8b() {
         let r = super::rotleft_8b(0b01010101);
         assert_eq!(r.result, 0b10101010);
-        assert_eq!(r.carry, false);
+        assert!(!r.carry);
 
         let r = super::rotleft_8b(0x80);
         assert_eq!(r.result, 0x01);
-        assert_eq!(r.carry, true);
+        assert!(r.carry);
 
         let r = super::rotleft_8b(0x11);
         assert_eq!(r.result, 0x22);
-        assert_eq!(r.carry, false);
+        assert!(!r.carry);
 
         let r = super::rotleft_8b(0x85);
         assert_eq!(r.result, 0x0B);
-        assert_eq!(r.carry, true);
+        assert!(r.carry);
     }
 
     #[test]
     fn rotleft_9b() {
         let r = super::rotleft_9b(0b01010101, false);
         assert_eq!(r.result, 0b10101010);
-        assert_eq!(r.carry, false);
+        assert!(!r.carry);
 
         let r = super::rotleft_9b(0x80, false);
         assert_eq!(r.result, 0);
-        assert_eq!(r.carry, true);
+        assert!(r.carry);
 
         let r = super::rotleft_9b(0x11, false);
         assert_eq!(r.result, 0x22);
-        assert_eq!(r.carry, false);
+        assert!(!r.carry);
+    }
+
+    #[test]
+    fn rotright_8b() {
+        let r = super::rotright_8b(0x22);
+        assert_eq!(r.result, 0x11);
+        assert!(!r.carry);
+
+        let r = super::rotright_8b(0x0B);
+        assert_eq!(r.result, 0x85);
+        assert!(r.carry);
+    }
+
+    #[test]
+    fn rotright_9b() {
+        let r = super::rotright_9b(0b10101010, false);
+        assert_eq!(r.result, 0b01010101);
+        assert!(!r.carry);
+
+        let r = super::rotright_9b(0x01, false);
+        assert_eq!(r.result, 0);
+        assert!(r.carry);
+
+        let r = super::rotright_9b(0x22, true);
+        assert_eq!(r.result, 0x91);
+        assert!(!r.carry);
+    }
+
+    #[test]
+    fn shleft_8b() {
+        let r = super::shleft_8b(0x41);
+        assert_eq!(r.result, 0x82);
+        assert!(!r.carry);
+
+        let r = super::shleft_8b(0x80);
+        assert_eq!(r.result, 0x00);
+        assert!(r.carry);
+    }
+
+    #[test]
+    fn shright_8b() {
+        let r = super::shright_8b(0x82);
+        assert_eq!(r.result, 0x41);
+        assert!(!r.carry);
+
+        let r = super::shright_8b(0x01);
+        assert_eq!(r.result, 0x00);
+        assert!(r.carry);
+    }
+
+    #[test]
+    fn shright_arith_8b() {
+        let r = super::shright_arith_8b(0x80);
+        assert_eq!(r.result, 0xC0);
+        assert!(!r.carry);
+
+        let r = super::shright_arith_8b(0x41);
+        assert_eq!(r.result, 0x20);
+        assert!(r.carry);
     }
 
     #[test]
@@ -144,4 +357,34 @@ mod tests {
         assert!(r.carry);
         assert!(!r.halfcarry);
     }
+
+    #[test]
+    fn sub_8b_carry() {
+        let r = super::sub_8b_carry(0x3E, 0x3E, false);
+        assert_eq!(r.result, 0);
+        assert!(!r.carry);
+        assert!(!r.halfcarry);
+
+        let r = super::sub_8b_carry(0x3E, 0x3E, true);
+        assert_eq!(r.result, 0xFF);
+        assert!(r.carry);
+        assert!(r.halfcarry);
+
+        // Equal low nibbles, borrow from carry-in
+        let r = super::sub_8b_carry(0x10, 0x00, true);
+        assert_eq!(r.result, 0x0F);
+        assert!(!r.carry);
+        assert!(r.halfcarry);
+
+        let r = super::sub_8b_carry(0x1F, 0x0E, true);
+        assert_eq!(r.result, 0x10);
+        assert!(!r.carry);
+        assert!(!r.halfcarry);
+
+        // Carry-in pushes the operand past 0x0F
+        let r = super::sub_8b_carry(0x0F, 0x0F, true);
+        assert_eq!(r.result, 0xFF);
+        assert!(r.carry);
+        assert!(r.halfcarry);
+    }
 }
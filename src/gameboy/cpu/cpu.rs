@@ -1,16 +1,45 @@
+use std::str::FromStr;
+
 use anyhow::{bail, Result};
 
 use super::super::bus::bus::{Bus, BusIterator};
+use super::super::error::{EmuError, FaultPolicy};
+use super::super::iomux::Interrupt;
+use super::super::profiler::{Location, Profiler};
+use super::super::rewind::StepHistory;
+use super::super::savestate::{StateErr, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
 use super::alu;
-use super::instruction::{Instruction, Operand};
+use super::instruction::{DecodeErr, Instruction, Op, Operand};
 use super::regs::{Flag, Register, RegisterFile, RegisterWidth};
 
+/// Interrupt Enable register
+const ADDR_IE: u16 = 0xFFFF;
+
+/// Interrupt Flag (request) register
+const ADDR_IF: u16 = 0xFF0F;
+
+/// Mask of the implemented interrupt bits in IE/IF
+const INT_MASK: u8 = 0x1F;
+
+/// Vector of interrupt 0 (VBlank), subsequent
+/// interrupts are spaced 8 bytes apart.
+const INT_VECTOR_BASE: u16 = 0x40;
+
+/// Cycles taken to dispatch an interrupt
+const INT_DISPATCH_CYCLES: usize = 20;
+
+/// Cycles taken by a memory access (one M-cycle)
+const ACCESS_CYCLES: usize = 4;
+
+/// Joypad register, polled to leave STOP mode
+const ADDR_P1: u16 = 0xFF00;
+
+/// Divider register, reset by STOP
+const ADDR_DIV: u16 = 0xFF04;
+
 /// Return type of CPU::op_* functions
 type CPUOpResult = Result<OpOk>;
 
-/// Function signature of CPU::op_* functions
-pub type CPUOpFn = fn(&mut CPU, &Instruction) -> CPUOpResult;
-
 /// Result of a successful CPU::op_* function.
 pub struct OpOk {
     /// New program counter
@@ -24,7 +53,7 @@ impl OpOk {
     /// Normal successful op, moves PC to next
     /// instruction and always fixed cycle count.
     #[inline(always)]
-    fn ok(cpu: &CPU, instr: &Instruction) -> Self {
+    fn ok<B: Bus>(cpu: &CPU<B>, instr: &Instruction) -> Self {
         Self {
             pc: cpu.regs.pc + instr.len as u16,
             cycles: instr.def.cycles[0].into(),
@@ -33,7 +62,7 @@ impl OpOk {
 
     /// Branch op: successful op, branch not taken.
     #[inline(always)]
-    fn no_branch(cpu: &CPU, instr: &Instruction) -> Self {
+    fn no_branch<B: Bus>(cpu: &CPU<B>, instr: &Instruction) -> Self {
         Self {
             pc: cpu.regs.pc + instr.len as u16,
             cycles: instr.def.cycles[1].into(),
@@ -42,7 +71,7 @@ impl OpOk {
 
     /// Branch op: successful op, branch taken.
     #[inline(always)]
-    fn branch(_cpu: &CPU, instr: &Instruction, pc: u16) -> Self {
+    fn branch<B: Bus>(_cpu: &CPU<B>, instr: &Instruction, pc: u16) -> Self {
         Self {
             pc,
             cycles: instr.def.cycles[0].into(),
@@ -50,1324 +79,4688 @@ impl OpOk {
     }
 }
 
-/// Gameboy CPU
-pub struct CPU {
-    pub bus: Box<dyn Bus>,
-    pub regs: RegisterFile,
+/// Diagnostic event reported by the stack guard.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StackEvent {
+    /// SP crossed below the low watermark (new SP).
+    Overflow(u16),
 
-    /// Total amount of cycles
-    cycles: usize,
+    /// SP crossed above the high watermark (new SP).
+    Underflow(u16),
+
+    /// SP wrapped past 0x0000 or 0xFFFF (new SP).
+    Wrapped(u16),
 }
 
-impl CPU {
-    pub fn new(bus: Box<dyn Bus>) -> Self {
-        Self {
-            bus,
-            regs: RegisterFile::new(),
-            cycles: 0,
+/// Reason CPU::run_until() stopped.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StopReason {
+    /// PC reached a breakpoint (PC), the instruction
+    /// there was not executed yet.
+    Breakpoint(u16),
+
+    /// A watched memory address changed value.
+    Watch { addr: u16, old: u8, new: u8 },
+
+    /// An instruction accessed a watchpoint address. The
+    /// instruction completed.
+    Access { addr: u16, write: bool, val: u8 },
+
+    /// A break condition (ID) holds, the instruction at PC
+    /// was not executed yet.
+    Condition(usize),
+
+    /// A debugger step (e.g. step over) completed.
+    Stepped,
+
+    /// The cycle budget was exhausted.
+    CycleBudget,
+}
+
+/// Accesses a watchpoint triggers on
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(&self, write: bool) -> bool {
+        match self {
+            Self::Read => !write,
+            Self::Write => write,
+            Self::Access => true,
         }
     }
+}
 
-    pub fn peek_next_instr(&self) -> Result<Instruction> {
-        let mut busiter = BusIterator::new_from(&self.bus, self.regs.pc);
-        Instruction::decode(&mut busiter)
-    }
+/// What STOP does when it does not switch the CGB speed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StopBehavior {
+    /// Stop the system clock until a button is pressed
+    WaitForJoypad,
 
-    pub fn step(&mut self) -> Result<()> {
-        let instr = self.peek_next_instr()?;
-        let result = (instr.def.func)(self, &instr)?;
-        self.regs.pc = result.pc;
-        self.cycles += result.cycles;
-        Ok(())
-    }
+    /// Continue execution right away
+    Continue,
+}
 
-    pub fn get_cycles(&self) -> usize {
-        self.cycles
-    }
+/// Break condition on the CPU state
+type BreakCondition = Box<dyn Fn(&RegisterFile) -> bool>;
 
-    /// Pushes 16-bits onto the stack.
-    fn stack_push(&mut self, val: u16) {
-        self.regs.sp = self.regs.sp.wrapping_sub(2);
-        self.bus.write16(self.regs.sp, val);
-    }
+/// Optional debugging aid that watches SP on stack
+/// operations. Does not alter CPU behavior.
+struct StackGuard {
+    /// Lowest valid SP
+    low: u16,
 
-    /// Pops 16-bits from the stack.
-    fn stack_pop(&mut self) -> u16 {
-        let val = self.bus.read16(self.regs.sp);
-        self.regs.sp = self.regs.sp.wrapping_add(2);
-        val
+    /// Highest valid SP
+    high: u16,
+
+    callback: Box<dyn FnMut(StackEvent)>,
+}
+
+impl StackGuard {
+    /// Checks an SP change and reports to the callback.
+    fn check(&mut self, old_sp: u16, new_sp: u16, wrapped: bool) {
+        let valid = |sp| (self.low..=self.high).contains(&sp);
+
+        if wrapped {
+            (self.callback)(StackEvent::Wrapped(new_sp));
+        } else if valid(old_sp) && new_sp < self.low {
+            (self.callback)(StackEvent::Overflow(new_sp));
+        } else if valid(old_sp) && new_sp > self.high {
+            (self.callback)(StackEvent::Underflow(new_sp));
+        }
     }
+}
 
-    /// SET/RES generic implementation
-    fn op_set_res(&mut self, instr: &Instruction, set: bool) -> CPUOpResult {
-        // SET/RES const, _
-        let Operand::Constant(bit) = instr.def.operands[0]
-            else { bail!("Unknown first operand {:?}", instr.def.operands[0]) };
+/// Callback receiving instruction trace lines
+type TraceSink = Box<dyn FnMut(&str)>;
 
-        // This is always an 8-bit operation.
-        assert!((0..8).contains(&bit));
+/// Callback receiving faults skipped by FaultPolicy::Skip
+type FaultSink = Box<dyn FnMut(&EmuError)>;
 
-        let val = match instr.def.operands[1] {
-            // SET/RES _, reg
-            Operand::Register(reg) => self.regs.read8(reg)?,
-            _ => todo!(),
-        };
+/// Layout of instruction trace lines (see CPU::trace_line())
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// Gameboy Doctor log format, for diffing against
+    /// known-good logs.
+    Doctor,
 
-        let val = if set {
-            val | (1 << bit)
-        } else {
-            val & !(1 << bit)
-        };
+    /// PC, opcode bytes, disassembly, registers, flags
+    /// and the cycle count.
+    Full,
+}
 
-        match instr.def.operands[1] {
-            // SET/RES _, reg
-            Operand::Register(reg) => self.regs.write8(reg, val)?,
-            _ => todo!(),
-        }
+impl FromStr for TraceFormat {
+    type Err = anyhow::Error;
 
-        Ok(OpOk::ok(self, instr))
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "doctor" => Ok(Self::Doctor),
+            "full" => Ok(Self::Full),
+            _ => bail!("Unknown trace format '{}' (expected doctor or full)", s),
+        }
     }
+}
 
-    /// SET b,n - Set bit 'b' in 'n'
-    pub fn op_set(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_set_res(instr, true)
-    }
+/// Gameboy CPU
+///
+/// Generic over the bus, so a concrete bus (e.g.
+/// CPU<Gameboybus>) gets its accesses inlined. The default
+/// takes any bus behind a Box<dyn Bus>.
+pub struct CPU<B: Bus = Box<dyn Bus>> {
+    pub bus: B,
+    pub regs: RegisterFile,
 
-    /// RES b,n - Clear bit 'b' in 'n'
-    pub fn op_res(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_set_res(instr, false)
-    }
+    /// Total amount of cycles
+    cycles: usize,
 
-    pub fn op_srl(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
-    }
+    /// Cycles of the current step already ticked on the bus
+    ticked: usize,
 
-    pub fn op_swap(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
-    }
+    /// Stack guard, if enabled
+    stack_guard: Option<StackGuard>,
 
-    pub fn op_sla(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
-    }
+    /// Instruction trace callback, if enabled
+    trace_sink: Option<TraceSink>,
 
-    pub fn op_sra(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
-    }
+    /// Layout of trace lines
+    trace_format: TraceFormat,
 
-    /// BIT b,n - Test for bit 'b' in 'n'
-    pub fn op_bit(&mut self, instr: &Instruction) -> CPUOpResult {
-        // BIT const, _
-        let Operand::Constant(bit) = instr.def.operands[0]
-            else { bail!("Unknown first operand {:?}", instr.def.operands[0]) };
+    /// Breakpoint addresses for run_until()
+    breakpoints: Vec<u16>,
 
-        // This is always an 8-bit operation.
-        assert!((0..8).contains(&bit));
+    /// Watched memory addresses for run_until()
+    watches: Vec<u16>,
 
-        let val = match instr.def.operands[1] {
-            // BIT _, reg
-            Operand::Register(reg) => self.regs.read8(reg)?,
-            _ => todo!(),
-        };
+    /// Watchpoints for run_until(), checked on accesses
+    /// by instructions
+    watchpoints: Vec<(u16, WatchKind)>,
 
-        self.regs.write_flags(&[
-            (Flag::Z, (val & (1 << bit) == 0)),
-            (Flag::H, true),
-            (Flag::N, false),
-        ]);
+    /// Watchpoint hit during the current step
+    watchpoint_hit: Option<StopReason>,
 
-        Ok(OpOk::ok(self, instr))
+    /// Break conditions for run_until(), with their IDs
+    conditions: Vec<(usize, BreakCondition)>,
+
+    /// ID for the next break condition
+    next_condition: usize,
+
+    /// Interrupt Master Enable
+    ime: bool,
+
+    /// IME will be set after the next instruction (EI)
+    ime_pending: bool,
+
+    /// Halted until an interrupt is pending
+    halted: bool,
+
+    /// Stopped (STOP) until a button is pressed
+    stopped: bool,
+
+    /// Behavior of STOP
+    stop_behavior: StopBehavior,
+
+    /// What to do when an instruction faults
+    fault_policy: FaultPolicy,
+
+    /// Fault callback for FaultPolicy::Skip, if any
+    fault_sink: Option<FaultSink>,
+
+    /// HALT bug triggered: the next opcode byte is fetched
+    /// without incrementing PC.
+    halt_bug: bool,
+
+    /// Profiler, if enabled
+    profiler: Option<Profiler>,
+
+    /// Step-back history, if enabled
+    step_history: Option<StepHistory>,
+}
+
+impl<B: Bus> CPU<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus,
+            regs: RegisterFile::new(),
+            cycles: 0,
+            ticked: 0,
+            stack_guard: None,
+            trace_sink: None,
+            trace_format: TraceFormat::Doctor,
+            breakpoints: vec![],
+            watches: vec![],
+            watchpoints: vec![],
+            watchpoint_hit: None,
+            conditions: vec![],
+            next_condition: 0,
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            stopped: false,
+            stop_behavior: StopBehavior::WaitForJoypad,
+            fault_policy: FaultPolicy::Stop,
+            fault_sink: None,
+            halt_bug: false,
+            profiler: None,
+            step_history: None,
+        }
     }
 
-    /// RL - Rotate left (carry = bit 9)
-    pub fn op_rl(&mut self, instr: &Instruction) -> CPUOpResult {
-        let result = match instr.def.operands[0] {
-            Operand::Register(reg) => {
-                let result = alu::rotleft_9b(self.regs.read8(reg)?, self.regs.test_flag(Flag::C));
-                self.regs.write8(reg, result.result)?;
-                result
-            }
-            _ => todo!(),
+    /// Starts execution at the cartridge entry point with
+    /// the registers and bus in the state the DMG (or CGB)
+    /// boot ROM leaves them in.
+    pub fn skip_boot_rom(&mut self) {
+        self.regs = if self.bus.cgb_mode() {
+            RegisterFile::new_post_boot_cgb()
+        } else {
+            RegisterFile::new_post_boot()
         };
+        self.bus.skip_boot_rom();
+    }
 
-        self.regs.write_flags(&[
-            (Flag::C, result.carry),
-            (Flag::H, false),
-            (Flag::N, false),
-            (Flag::Z, (result.result == 0)),
-        ]);
+    /// Puts the CPU back in the power-on state, e.g. for a
+    /// soft reset along with the bus. Debugging settings
+    /// and the cycle count are kept.
+    pub fn reset(&mut self) {
+        self.regs = RegisterFile::new();
+        self.ticked = 0;
+        self.watchpoint_hit = None;
+        self.ime = false;
+        self.ime_pending = false;
+        self.halted = false;
+        self.stopped = false;
+        self.halt_bug = false;
+    }
 
-        Ok(OpOk::ok(self, instr))
+    /// Enables the stack guard, which calls 'callback' when
+    /// a stack operation moves SP outside of low..=high or
+    /// wraps SP around the address space.
+    pub fn set_stack_guard(
+        &mut self,
+        low: u16,
+        high: u16,
+        callback: impl FnMut(StackEvent) + 'static,
+    ) {
+        self.stack_guard = Some(StackGuard {
+            low,
+            high,
+            callback: Box::new(callback),
+        });
     }
 
-    /// RLA - Rotate Left, A register
-    pub fn op_rla(&mut self, instr: &Instruction) -> CPUOpResult {
-        let result = alu::rotleft_9b(self.regs.read8(Register::A)?, self.regs.test_flag(Flag::C));
-        self.regs.write8(Register::A, result.result)?;
+    /// Disables the stack guard.
+    pub fn clear_stack_guard(&mut self) {
+        self.stack_guard = None;
+    }
 
-        self.regs.write_flags(&[
-            (Flag::C, result.carry),
-            (Flag::H, false),
-            (Flag::N, false),
-            // This seems weird?
-            (Flag::Z, false),
-        ]);
+    /// Enables instruction tracing: 'sink' is called with
+    /// trace_line() before each instruction is executed.
+    pub fn set_trace_sink(&mut self, sink: impl FnMut(&str) + 'static) {
+        self.trace_sink = Some(Box::new(sink));
+    }
 
-        Ok(OpOk::ok(self, instr))
+    pub fn clear_trace_sink(&mut self) {
+        self.trace_sink = None;
     }
 
-    /// RLC - Rotate left (copy to carry)
-    pub fn op_rlc(&mut self, instr: &Instruction) -> CPUOpResult {
-        let result = match instr.def.operands[0] {
-            Operand::Register(reg) => {
-                let result = alu::rotleft_8b(self.regs.read8(reg)?);
-                self.regs.write8(reg, result.result)?;
-                result
-            }
-            _ => todo!(),
-        };
+    /// Selects the layout of trace lines, Gameboy Doctor
+    /// format by default.
+    pub fn set_trace_format(&mut self, format: TraceFormat) {
+        self.trace_format = format;
+    }
 
-        self.regs.write_flags(&[
-            (Flag::C, result.carry),
-            (Flag::H, false),
-            (Flag::N, false),
-            (Flag::Z, (result.result == 0)),
-        ]);
+    /// Starts profiling execution, discarding any earlier
+    /// profile.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
 
-        Ok(OpOk::ok(self, instr))
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stops profiling and returns the profile.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
     }
 
-    pub fn op_rlca(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Records every step so that it can be undone with
+    /// step_back(), up to 'depth' steps back. Every step then
+    /// takes a save state, which slows down execution a lot;
+    /// this is meant for debugging. Fails if the bus does not
+    /// support save states.
+    pub fn enable_step_back(&mut self, depth: usize) -> Result<()> {
+        self.save_state()?;
+        self.step_history = Some(StepHistory::new(depth));
+        Ok(())
     }
 
-    pub fn op_rr(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn disable_step_back(&mut self) {
+        self.step_history = None;
     }
 
-    pub fn op_rra(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Undoes the newest recorded step, restoring the machine
+    /// to the exact state before it. Changes made between
+    /// steps (e.g. by a debugger) to anything the step did not
+    /// touch are kept.
+    pub fn step_back(&mut self) -> Result<()> {
+        let current = self.save_state()?;
+        let Some(history) = &mut self.step_history else {
+            bail!("Step back is not enabled");
+        };
+        let state = history.pop(&current)?;
+        self.load_state(&state)
     }
 
-    pub fn op_rrc(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
     }
 
-    pub fn op_rrca(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|&a| a != addr);
     }
 
-    pub fn op_ei(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
     }
 
-    pub fn op_di(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Watches a memory address: run_until() stops after
+    /// a step that changed its value.
+    pub fn add_watch(&mut self, addr: u16) {
+        if !self.watches.contains(&addr) {
+            self.watches.push(addr);
+        }
     }
 
-    pub fn op_rst(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn remove_watch(&mut self, addr: u16) {
+        self.watches.retain(|&a| a != addr);
     }
 
-    /// NOP - No Operation
-    pub fn op_nop(&mut self, instr: &Instruction) -> CPUOpResult {
-        Ok(OpOk::ok(self, instr))
+    /// Adds a watchpoint: run_until() stops after an
+    /// instruction that reads and/or writes 'addr'.
+    pub fn add_watchpoint(&mut self, addr: u16, kind: WatchKind) {
+        self.remove_watchpoint(addr);
+        self.watchpoints.push((addr, kind));
     }
 
-    pub fn op_stop(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.retain(|&(a, _)| a != addr);
     }
 
-    pub fn op_halt(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn watchpoints(&self) -> &[(u16, WatchKind)] {
+        &self.watchpoints
     }
 
-    /// LD - Load Register
-    pub fn op_ld(&mut self, instr: &Instruction) -> CPUOpResult {
-        let indreg = |r: Register, regval| match r.width() {
-            RegisterWidth::SixteenBit => regval,
-            RegisterWidth::EightBit => {
-                assert_eq!(regval & !0xFF, 0);
-                0xFF00 | regval
-            }
-        };
+    /// Adds a break condition: run_until() stops before an
+    /// instruction when 'cond' holds. Returns its ID.
+    pub fn add_break_condition(&mut self, cond: impl Fn(&RegisterFile) -> bool + 'static) -> usize {
+        let id = self.next_condition;
+        self.next_condition += 1;
+        self.conditions.push((id, Box::new(cond)));
+        id
+    }
 
-        // Source operand
-        let val: u16 = match &instr.def.operands[1] {
-            // LD _, imm8
-            Operand::Immediate8 => instr.imm8(1)?.into(),
-            // LD _, (imm8)
-            Operand::ImmediateIndirect8 => self.bus.read(0xFF00_u16 | instr.imm8(1)? as u16).into(),
-            // LD _, imm16
-            Operand::Immediate16 => instr.imm16(1)?,
-            // LD _, reg
-            Operand::Register(reg) => self.regs.read(*reg),
-            // LD _, (reg)
-            Operand::RegisterIndirect(reg) => {
-                assert_eq!(reg.width(), RegisterWidth::SixteenBit);
-                self.bus.read(self.regs.read(*reg)).into()
-            }
-            _ => todo!(),
-        };
+    pub fn remove_break_condition(&mut self, id: usize) {
+        self.conditions.retain(|(i, _)| *i != id);
+    }
 
-        // Destination operand
-        match instr.def.operands[0] {
-            // LD reg, _
-            Operand::Register(dest) => self.regs.write(dest, val.try_into()?)?,
-            // LD (reg), _
-            Operand::RegisterIndirect(dest) => {
-                let addr = self.regs.read(dest);
-                self.bus.write(indreg(dest, addr), val.try_into()?)
+    /// Steps until PC hits a breakpoint, a break condition
+    /// holds, a watched address changes or is accessed, or
+    /// at least 'max_cycles' cycles have passed.
+    ///
+    /// A breakpoint or condition at the current PC does not
+    /// stop the first step, so execution can be resumed.
+    pub fn run_until(&mut self, max_cycles: usize) -> Result<StopReason, EmuError> {
+        let start = self.cycles;
+        let mut first = true;
+
+        loop {
+            if !first && self.breakpoints.contains(&self.regs.pc) {
+                return Ok(StopReason::Breakpoint(self.regs.pc));
             }
-            // LD (reg-), _
-            Operand::RegisterIndirectDec(dest) => {
-                let addr = self.regs.read_dec(dest)?;
-                self.bus.write(indreg(dest, addr), val.try_into()?)
+            if !first {
+                if let Some((id, _)) = self.conditions.iter().find(|(_, c)| c(&self.regs)) {
+                    return Ok(StopReason::Condition(*id));
+                }
             }
-            // LD (reg+), _
-            Operand::RegisterIndirectInc(dest) => {
-                let addr = self.regs.read_inc(dest)?;
-                self.bus.write(indreg(dest, addr), val.try_into()?)
+            if self.cycles - start >= max_cycles {
+                return Ok(StopReason::CycleBudget);
             }
-            // LDH (a8), _
-            Operand::ImmediateIndirect8 => {
-                let addr = 0xFF00_u16 + instr.imm8(0)? as u16;
-                self.bus.write(addr, val.try_into()?)
+            first = false;
+
+            let before: Vec<u8> = self.watches.iter().map(|&a| self.bus.peek(a)).collect();
+            self.watchpoint_hit = None;
+            self.step()?;
+            if let Some(hit) = self.watchpoint_hit.take() {
+                return Ok(hit);
+            }
+            for (&addr, &old) in self.watches.iter().zip(before.iter()) {
+                let new = self.bus.peek(addr);
+                if new != old {
+                    return Ok(StopReason::Watch { addr, old, new });
+                }
             }
-            // LDH (a16), _
-            Operand::ImmediateIndirect16 => self.bus.write(instr.imm16(0)?, val.try_into()?),
-            _ => bail!("Invalid first operand: {:?}", instr.def.operands[0]),
         }
+    }
 
-        Ok(OpOk::ok(self, instr))
+    /// Steps until at least 'cycles' cycles have passed,
+    /// without checking breakpoints, conditions or watches.
+    /// Returns the cycles actually run.
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<usize, EmuError> {
+        let start = self.cycles;
+        let end = start + cycles;
+        while self.cycles < end {
+            self.step()?;
+        }
+        Ok(self.cycles - start)
     }
 
-    pub fn op_scf(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Describes the current CPU state in the selected
+    /// trace format.
+    pub fn trace_line(&self) -> String {
+        match self.trace_format {
+            TraceFormat::Doctor => self.trace_line_doctor(),
+            TraceFormat::Full => self.trace_line_full(),
+        }
     }
 
-    pub fn op_ccf(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Describes the current CPU state in the Gameboy Doctor
+    /// log format, e.g.:
+    /// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+    fn trace_line_doctor(&self) -> String {
+        // Wraps around the end of the address space
+        let pcmem: Vec<String> = BusIterator::new_from(&self.bus, self.regs.pc)
+            .chain(BusIterator::new(&self.bus))
+            .take(4)
+            .map(|b| format!("{:02X}", b))
+            .collect();
+
+        format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+            self.regs.a,
+            self.regs.f,
+            self.regs.b,
+            self.regs.c,
+            self.regs.d,
+            self.regs.e,
+            self.regs.h,
+            self.regs.l,
+            self.regs.sp,
+            self.regs.pc,
+            pcmem.join(",")
+        )
     }
 
-    /// CP - Compare
-    pub fn op_cp(&mut self, instr: &Instruction) -> CPUOpResult {
-        let val = match instr.def.operands[0] {
-            // CP imm8
-            Operand::Immediate8 => instr.imm8(0)?,
-            // CP reg8
-            Operand::Register(reg) => {
-                assert_eq!(reg.width(), RegisterWidth::EightBit);
-                self.regs.read8(reg)?
-            }
-            // CP (reg16)
-            Operand::RegisterIndirect(reg) => {
-                assert_eq!(reg.width(), RegisterWidth::SixteenBit);
-                self.bus.read(self.regs.read16(reg)?)
-            }
-            _ => unreachable!(),
+    /// Describes the next instruction and the current CPU
+    /// state, e.g.:
+    /// 0150 [C3, 13, 02]   JP $0213         A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE CY:1234
+    fn trace_line_full(&self) -> String {
+        let pc = self.regs.pc;
+        let (raw, disasm) = match self.peek_next_instr() {
+            Ok(instr) => (format!("{:02X?}", instr.bytes()), instr.fmt_disasm(pc)),
+            Err(_) => (
+                format!("[{:02X}]", self.bus.peek(pc)),
+                "INVALID".to_string(),
+            ),
         };
+        let flag = |f, c| if self.regs.test_flag(f) { c } else { '-' };
 
-        let result = alu::sub_8b(self.regs.read8(Register::A)?, val);
-        self.regs.write_flags(&[
-            (Flag::Z, result.result == 0),
-            (Flag::H, result.halfcarry),
-            (Flag::C, result.carry),
-            (Flag::N, true),
-        ]);
-
-        Ok(OpOk::ok(self, instr))
+        format!(
+            "{:04X} {:<18} {:<16} A:{:02X} F:{}{}{}{} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} CY:{}",
+            pc,
+            raw,
+            disasm,
+            self.regs.a,
+            flag(Flag::Z, 'Z'),
+            flag(Flag::N, 'N'),
+            flag(Flag::H, 'H'),
+            flag(Flag::C, 'C'),
+            self.regs.b,
+            self.regs.c,
+            self.regs.d,
+            self.regs.e,
+            self.regs.h,
+            self.regs.l,
+            self.regs.sp,
+            self.cycles
+        )
     }
 
-    pub fn op_cpl(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    pub fn peek_next_instr(&self) -> Result<Instruction> {
+        let mut busiter = BusIterator::new_from(&self.bus, self.regs.pc);
+        if self.halt_bug {
+            // First byte is read twice
+            let mut stream = std::iter::once(self.bus.peek(self.regs.pc)).chain(busiter);
+            Instruction::decode(&mut stream, self.regs.pc)
+        } else {
+            Instruction::decode(&mut busiter, self.regs.pc)
+        }
     }
 
-    pub fn op_or(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Returns true if the CPU is halted (HALT).
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    /// XOR - Bitwise XOR
-    pub fn op_xor(&mut self, instr: &Instruction) -> CPUOpResult {
-        let a = self.regs.read8(Register::A)?;
-        let val = match instr.def.operands[0] {
-            // XOR reg
-            Operand::Register(r) => self.regs.read8(r)?,
-            _ => todo!(),
-        };
-        let result = a ^ val;
-        self.regs.write(Register::A, result.into())?;
-        self.regs.write_flags(&[(Flag::Z, result == 0)]);
-
-        Ok(OpOk::ok(self, instr))
+    /// Returns true if the CPU is stopped (STOP).
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
     }
 
-    pub fn op_and(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Selects what STOP does, waiting for the joypad by
+    /// default.
+    pub fn set_stop_behavior(&mut self, behavior: StopBehavior) {
+        self.stop_behavior = behavior;
     }
 
-    /// PUSH - Push register onto stack
-    pub fn op_push(&mut self, instr: &Instruction) -> CPUOpResult {
-        let Operand::Register(reg) = instr.def.operands[0]
-            else { unreachable!() };
-        assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+    /// Selects what happens when an instruction faults,
+    /// stopping with an error by default.
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
 
-        self.stack_push(self.regs.read16(reg)?);
-        Ok(OpOk::ok(self, instr))
+    /// Sets a callback receiving the faults skipped under
+    /// FaultPolicy::Skip, e.g. to log them.
+    pub fn set_fault_sink(&mut self, sink: impl FnMut(&EmuError) + 'static) {
+        self.fault_sink = Some(Box::new(sink));
     }
 
-    /// POP - Pop register from stack
-    pub fn op_pop(&mut self, instr: &Instruction) -> CPUOpResult {
-        let Operand::Register(reg) = instr.def.operands[0]
-            else { unreachable!() };
-        assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+    /// Interrupt Master Enable
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
 
-        let val = self.stack_pop();
-        self.regs.write(reg, val)?;
-        Ok(OpOk::ok(self, instr))
+    /// Sets IME directly, cancelling a pending EI.
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+        self.ime_pending = false;
     }
 
-    pub fn op_adc(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Interrupts requested and enabled (IE & IF), in
+    /// priority order. These are dispatched if ime() is set,
+    /// and wake the CPU from HALT either way.
+    pub fn pending_interrupts(&self) -> Vec<Interrupt> {
+        let pending = self.pending_mask();
+        Interrupt::ALL
+            .into_iter()
+            .filter(|int| pending & int.bit() != 0)
+            .collect()
     }
 
-    pub fn op_daa(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Requested and enabled interrupts (IE & IF)
+    fn pending_mask(&self) -> u8 {
+        self.bus.peek(ADDR_IE) & self.bus.peek(ADDR_IF) & INT_MASK
     }
 
-    /// ADD - Add (8-bit)
-    pub fn op_add(&mut self, instr: &Instruction) -> CPUOpResult {
-        // First operand is always A
-        let left = match instr.def.operands[0] {
-            Operand::Register(reg) => {
-                assert_eq!(reg, Register::A);
-                self.regs.read8(reg)?
+    /// Disassembles the next 'n' instructions starting at PC.
+    /// Instructions are decoded sequentially, branches are
+    /// not followed. Stops early at the end of the address space.
+    pub fn disassemble_ahead(&self, n: usize) -> Vec<(u16, String)> {
+        let mut out = Vec::with_capacity(n);
+        let mut addr = self.regs.pc;
+
+        while out.len() < n {
+            let mut busiter = BusIterator::new_from(&self.bus, addr);
+            let (text, len) = match Instruction::decode(&mut busiter, addr) {
+                Ok(instr) => (
+                    format!("{:02X?} {}", instr.bytes(), instr.fmt_disasm(addr)),
+                    instr.len,
+                ),
+                Err(e) => match e.downcast_ref::<DecodeErr>() {
+                    Some(DecodeErr::UndefinedOpcode { opcode, .. }) => {
+                        (format!("[{:02X}] INVALID", opcode), 1)
+                    }
+                    _ => break,
+                },
+            };
+            out.push((addr, text));
+
+            match addr.checked_add(len as u16) {
+                Some(next) => addr = next,
+                None => break,
             }
-            _ => unreachable!(),
-        };
+        }
 
-        let right = match instr.def.operands[1] {
-            Operand::RegisterIndirect(reg) => {
-                assert_eq!(reg.width(), RegisterWidth::SixteenBit);
-                self.bus.read(self.regs.read16(reg)?)
+        out
+    }
+
+    /// Executes one instruction (or services an interrupt, or
+    /// idles while halted) and ticks the bus accordingly.
+    ///
+    /// The bus is ticked as the instruction executes: for each
+    /// opcode fetch and memory access, before the access takes
+    /// place. Internal cycles are ticked after the instruction,
+    /// except for those that come before the stack writes of
+    /// PUSH, CALL, RST and interrupt dispatch.
+    pub fn step(&mut self) -> Result<(), EmuError> {
+        if self.step_history.is_none() {
+            return self.step_timed();
+        }
+        let before = self.save_state();
+        let result = self.step_timed();
+        if let (Ok(before), Ok(after), Some(history)) =
+            (before, self.save_state(), &mut self.step_history)
+        {
+            history.push(&before, &after);
+        }
+        result
+    }
+
+    /// Executes a step, see step().
+    fn step_timed(&mut self) -> Result<(), EmuError> {
+        if self.stopped {
+            // A pressed button (selected in P1) pulls its
+            // input line low and restarts the clock.
+            if self.bus.peek(ADDR_P1) & 0x0F == 0x0F {
+                // Nothing on the bus runs while stopped
+                self.cycles += 4;
+                self.profile_step(4);
+                return Ok(());
             }
-            Operand::Register(reg) => {
-                assert_eq!(reg.width(), RegisterWidth::EightBit);
-                self.regs.read8(reg)?
+            self.stopped = false;
+        }
+
+        let start = self.cycles;
+        self.ticked = 0;
+        let mut result = self.step_cpu();
+        if let Err(err) = &result {
+            if self.fault_policy == FaultPolicy::Skip {
+                self.skip_fault(err);
+                result = Ok(());
             }
-            _ => unreachable!(),
-        };
+        }
+        let elapsed = self.cycles - start;
+        self.bus.tick(elapsed.saturating_sub(self.ticked));
+        self.profile_step(elapsed);
+        result
+    }
 
-        let result = alu::add_8b(left, right);
-        self.regs.write8(Register::A, result.result)?;
-        self.regs.write_flags(&[
-            (Flag::Z, result.result == 0),
-            (Flag::C, result.carry),
-            (Flag::H, result.halfcarry),
-            (Flag::N, false),
-        ]);
+    /// Completes a step in the profiler, if profiling.
+    fn profile_step(&mut self, cycles: usize) {
+        let Some(profiler) = &mut self.profiler else {
+            return;
+        };
+        let pc = Location {
+            bank: self.bus.rom_bank(self.regs.pc),
+            addr: self.regs.pc,
+        };
+        if profiler.end_step(cycles, pc, self.regs.sp) {
+            profiler.end_frame();
+        }
+    }
 
-        Ok(OpOk::ok(self, instr))
+    /// Reports a fault and skips the faulting instruction,
+    /// taking the cycles to fetch it.
+    fn skip_fault(&mut self, err: &EmuError) {
+        if let Some(sink) = &mut self.fault_sink {
+            sink(err);
+        }
+        // Undefined opcodes and instructions running past the
+        // end of memory are skipped a byte at a time
+        let len = self.peek_next_instr().map_or(1, |instr| instr.len);
+        self.regs.pc = self.regs.pc.wrapping_add(len as u16);
+        self.cycles += self.ticked.max(len * ACCESS_CYCLES);
     }
 
-    /// SUB - Subtract (8-bit)
-    pub fn op_sub(&mut self, instr: &Instruction) -> CPUOpResult {
-        let val: u8 = match instr.def.operands[0] {
-            Operand::Register(reg) => {
-                assert_eq!(reg.width(), RegisterWidth::EightBit);
-                self.regs.read8(reg)?
-            }
-            _ => todo!(),
-        };
+    /// Ticks the bus in the middle of a step.
+    fn tick_bus(&mut self, cycles: usize) {
+        self.bus.tick(cycles);
+        self.ticked += cycles;
+    }
 
-        let res = alu::sub_8b(self.regs.read8(Register::A)?, val);
+    /// Takes an internal M-cycle that comes before a memory
+    /// access of the instruction.
+    fn tick_internal(&mut self) {
+        self.tick_bus(ACCESS_CYCLES);
+    }
 
-        self.regs.write8(Register::A, res.result)?;
-        self.regs.write_flags(&[
-            (Flag::Z, (res.result == 0)),
-            (Flag::N, true),
-            (Flag::C, res.carry),
-            (Flag::H, res.halfcarry),
-        ]);
+    /// Reads memory as part of an instruction, taking
+    /// one M-cycle.
+    fn read(&mut self, addr: u16) -> u8 {
+        self.tick_bus(ACCESS_CYCLES);
+        let val = self.bus.read(addr);
+        self.check_watchpoints(addr, false, val);
+        val
+    }
 
-        Ok(OpOk::ok(self, instr))
+    /// Writes memory as part of an instruction, taking
+    /// one M-cycle.
+    fn write(&mut self, addr: u16, val: u8) {
+        self.tick_bus(ACCESS_CYCLES);
+        self.bus.write(addr, val);
+        self.check_watchpoints(addr, true, val);
     }
 
-    /// DEC - Decrement (8-bit)
-    pub fn op_dec_8b(&mut self, instr: &Instruction) -> CPUOpResult {
-        match instr.def.operands[0] {
-            Operand::Register(reg) => {
-                let res = alu::sub_8b(self.regs.read8(reg)?, 1);
-                self.regs.write8(reg, res.result)?;
-                self.regs.write_flags(&[
-                    (Flag::H, res.halfcarry),
-                    (Flag::N, true),
-                    (Flag::Z, (res.result == 0)),
-                    // Carry not used
-                ]);
-            }
-            _ => todo!(),
+    /// Records the first watchpoint hit of a step.
+    fn check_watchpoints(&mut self, addr: u16, write: bool, val: u8) {
+        if self.watchpoint_hit.is_none()
+            && self
+                .watchpoints
+                .iter()
+                .any(|&(a, kind)| a == addr && kind.matches(write))
+        {
+            self.watchpoint_hit = Some(StopReason::Access { addr, write, val });
         }
-
-        Ok(OpOk::ok(self, instr))
     }
 
-    pub fn op_dec_16b(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Writes 16-bits in little endian, taking two M-cycles.
+    fn write16(&mut self, addr: u16, val: u16) {
+        let [lsb, msb] = val.to_le_bytes();
+        self.write(addr, lsb);
+        self.write(addr.wrapping_add(1), msb);
     }
 
-    /// INC - Increment (8-bit)
-    pub fn op_inc_8b(&mut self, instr: &Instruction) -> CPUOpResult {
-        match instr.def.operands[0] {
-            Operand::Register(reg) => {
-                let res = alu::add_8b(self.regs.read8(reg)?, 1);
-                self.regs.write8(reg, res.result)?;
-                self.regs.write_flags(&[
-                    (Flag::H, res.halfcarry),
-                    (Flag::N, false),
-                    (Flag::Z, (res.result == 0)),
-                    // Carry not used
-                ]);
+    fn step_cpu(&mut self) -> Result<(), EmuError> {
+        if self.halted {
+            if self.pending_mask() == 0 {
+                self.cycles += 4;
+                return Ok(());
             }
-            _ => todo!(),
+            // Wake up, regardless of IME
+            self.halted = false;
         }
 
-        Ok(OpOk::ok(self, instr))
-    }
+        if self.service_interrupts() {
+            return Ok(());
+        }
 
-    /// INC - Increment (16-bit)
-    pub fn op_inc_16b(&mut self, instr: &Instruction) -> CPUOpResult {
-        let Operand::Register(reg) = instr.def.operands[0]
-            else { unreachable!() };
+        if self.trace_sink.is_some() {
+            let line = self.trace_line();
+            if let Some(sink) = &mut self.trace_sink {
+                sink(&line);
+            }
+        }
 
-        assert_eq!(reg.width(), RegisterWidth::SixteenBit);
-        self.regs
-            .write(reg, self.regs.read16(reg)?.wrapping_add(1))?;
+        // EI takes effect after the instruction following it.
+        let enable_ime = self.ime_pending;
+
+        let pc = self.regs.pc;
+        let instr = self
+            .peek_next_instr()
+            .map_err(|e| EmuError::from_instr(e, pc))?;
+        if let Some(profiler) = &mut self.profiler {
+            let loc = Location {
+                bank: self.bus.rom_bank(pc),
+                addr: pc,
+            };
+            profiler.instruction(loc, instr.raw[0], self.regs.sp);
+        }
+        self.bus.execute(pc, instr.len);
+        if self.halt_bug {
+            // The duplicated byte did not advance PC
+            self.halt_bug = false;
+            self.regs.pc = self.regs.pc.wrapping_sub(1);
+        }
+        self.tick_bus(instr.len * ACCESS_CYCLES);
+        let result = self
+            .dispatch(&instr)
+            .map_err(|e| EmuError::from_instr(e, pc))?;
+        self.regs.pc = result.pc;
+        self.cycles += result.cycles;
 
-        Ok(OpOk::ok(self, instr))
+        // DI in the delay slot cancels the pending enable.
+        if enable_ime && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+        Ok(())
     }
 
-    /// JR _, s8 - Jump Relative (conditional/unconditional)
-    fn op_jr_cc(&mut self, instr: &Instruction, cc: bool) -> CPUOpResult {
-        if !cc {
-            return Ok(OpOk::no_branch(self, instr));
+    /// Executes a decoded instruction.
+    fn dispatch(&mut self, instr: &Instruction) -> CPUOpResult {
+        match instr.def.op {
+            Op::Adc => self.op_adc(instr),
+            Op::Add => self.op_add(instr),
+            Op::And => self.op_and(instr),
+            Op::Bit => self.op_bit(instr),
+            Op::Call => self.op_call(instr),
+            Op::CallC => self.op_call_c(instr),
+            Op::CallNc => self.op_call_nc(instr),
+            Op::CallNz => self.op_call_nz(instr),
+            Op::CallZ => self.op_call_z(instr),
+            Op::Ccf => self.op_ccf(instr),
+            Op::Cp => self.op_cp(instr),
+            Op::Cpl => self.op_cpl(instr),
+            Op::Daa => self.op_daa(instr),
+            Op::Dec16b => self.op_dec_16b(instr),
+            Op::Dec8b => self.op_dec_8b(instr),
+            Op::Di => self.op_di(instr),
+            Op::Ei => self.op_ei(instr),
+            Op::Halt => self.op_halt(instr),
+            Op::Inc16b => self.op_inc_16b(instr),
+            Op::Inc8b => self.op_inc_8b(instr),
+            Op::Invalid => self.op_invalid(instr),
+            Op::Jp => self.op_jp(instr),
+            Op::JpC => self.op_jp_c(instr),
+            Op::JpNc => self.op_jp_nc(instr),
+            Op::JpNz => self.op_jp_nz(instr),
+            Op::JpZ => self.op_jp_z(instr),
+            Op::Jr => self.op_jr(instr),
+            Op::JrC => self.op_jr_c(instr),
+            Op::JrNc => self.op_jr_nc(instr),
+            Op::JrNz => self.op_jr_nz(instr),
+            Op::JrZ => self.op_jr_z(instr),
+            Op::Ld => self.op_ld(instr),
+            Op::Nop => self.op_nop(instr),
+            Op::Or => self.op_or(instr),
+            Op::Pop => self.op_pop(instr),
+            Op::PrefixCb => self.op_prefix_cb(instr),
+            Op::Push => self.op_push(instr),
+            Op::Res => self.op_res(instr),
+            Op::Ret => self.op_ret(instr),
+            Op::RetC => self.op_ret_c(instr),
+            Op::RetNc => self.op_ret_nc(instr),
+            Op::RetNz => self.op_ret_nz(instr),
+            Op::RetZ => self.op_ret_z(instr),
+            Op::Reti => self.op_reti(instr),
+            Op::Rl => self.op_rl(instr),
+            Op::Rla => self.op_rla(instr),
+            Op::Rlc => self.op_rlc(instr),
+            Op::Rlca => self.op_rlca(instr),
+            Op::Rr => self.op_rr(instr),
+            Op::Rra => self.op_rra(instr),
+            Op::Rrc => self.op_rrc(instr),
+            Op::Rrca => self.op_rrca(instr),
+            Op::Rst => self.op_rst(instr),
+            Op::Sbc => self.op_sbc(instr),
+            Op::Scf => self.op_scf(instr),
+            Op::Set => self.op_set(instr),
+            Op::Sla => self.op_sla(instr),
+            Op::Sra => self.op_sra(instr),
+            Op::Srl => self.op_srl(instr),
+            Op::Stop => self.op_stop(instr),
+            Op::Sub => self.op_sub(instr),
+            Op::Swap => self.op_swap(instr),
+            Op::Xor => self.op_xor(instr),
+        }
+    }
+
+    /// Dispatches the highest priority pending interrupt,
+    /// if IME is set. Returns true if an interrupt was serviced.
+    pub fn service_interrupts(&mut self) -> bool {
+        if !self.ime {
+            return false;
         }
 
-        // value = address - 2.
-        let rel_addr = instr.imms8(0)? + 2;
-        let new_pc = self.regs.pc.wrapping_add_signed(rel_addr.into());
-        Ok(OpOk::branch(self, instr, new_pc))
+        let pending = self.pending_mask();
+        if pending == 0 {
+            return false;
+        }
+
+        // Lowest bit has the highest priority
+        let int = pending.trailing_zeros();
+        let intflags = self.bus.peek(ADDR_IF);
+        self.bus.write(ADDR_IF, intflags & !(1 << int));
+        self.ime = false;
+
+        if self.halt_bug {
+            // HALT bug during EI; HALT: returns to the HALT
+            self.halt_bug = false;
+            self.regs.pc = self.regs.pc.wrapping_sub(1);
+        }
+
+        // Two internal cycles, then PC is pushed and the
+        // vector is loaded in the fifth.
+        self.tick_internal();
+        self.tick_internal();
+        self.stack_push(self.regs.pc);
+        self.regs.pc = INT_VECTOR_BASE + (int as u16 * 8);
+        self.cycles += INT_DISPATCH_CYCLES;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.interrupt(int as u8);
+        }
+        true
     }
 
-    /// JR s8 - Jump Relative (unconditionally)
-    pub fn op_jr(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_jr_cc(instr, true)
+    pub fn get_cycles(&self) -> usize {
+        self.cycles
     }
 
-    /// JR NC s8 - Jump Relative (if not carry)
-    pub fn op_jr_nc(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_jr_cc(instr, !self.regs.test_flag(Flag::C))
+    /// Serializes the complete machine state (CPU and bus)
+    /// into a versioned blob.
+    ///
+    /// ROM contents are not included; the state can only be
+    /// loaded into a machine set up with the same cartridge.
+    pub fn save_state(&self) -> Result<Vec<u8>> {
+        let mut w = StateWriter::new();
+        w.tag(STATE_MAGIC);
+        w.u8(STATE_VERSION);
+
+        self.regs.save_state(&mut w);
+        w.u64(self.cycles as u64);
+        w.bool(self.ime);
+        w.bool(self.ime_pending);
+        w.bool(self.halted);
+        w.bool(self.stopped);
+        w.bool(self.halt_bug);
+
+        self.bus.save_state(&mut w)?;
+        Ok(w.into_inner())
     }
 
-    /// JR C s8 - Jump Relative (if carry)
-    pub fn op_jr_c(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_jr_cc(instr, self.regs.test_flag(Flag::C))
+    /// Restores a state created by save_state().
+    ///
+    /// If an error is returned, the machine may have been
+    /// partially restored.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(state);
+        if r.tag(STATE_MAGIC, "magic").is_err() {
+            bail!(StateErr::BadMagic);
+        }
+        let version = r.u8()?;
+        if version != STATE_VERSION {
+            bail!(StateErr::UnsupportedVersion(version));
+        }
+
+        self.regs.load_state(&mut r)?;
+        self.cycles = r.u64()? as usize;
+        self.ime = r.bool()?;
+        self.ime_pending = r.bool()?;
+        self.halted = r.bool()?;
+        self.stopped = r.bool()?;
+        self.halt_bug = r.bool()?;
+
+        self.bus.load_state(&mut r)?;
+        r.finish()
     }
 
-    /// JR NZ s8 - Jump Relative (if not zero)
-    pub fn op_jr_nz(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_jr_cc(instr, !self.regs.test_flag(Flag::Z))
+    /// Pushes 16-bits onto the stack.
+    ///
+    /// Like the hardware, SP is decremented before each write
+    /// and the high byte is written first, then the low byte.
+    fn stack_push(&mut self, val: u16) {
+        let old_sp = self.regs.sp;
+        let [lsb, msb] = val.to_le_bytes();
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write(self.regs.sp, msb);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write(self.regs.sp, lsb);
+
+        if let Some(guard) = &mut self.stack_guard {
+            guard.check(old_sp, self.regs.sp, old_sp < 2);
+        }
     }
 
-    /// JR Z s8 - Jump Relative (if zero)
-    pub fn op_jr_z(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_jr_cc(instr, self.regs.test_flag(Flag::Z))
+    /// Pops 16-bits from the stack.
+    ///
+    /// The low byte is read first, then the high byte.
+    fn stack_pop(&mut self) -> u16 {
+        let old_sp = self.regs.sp;
+        let lsb = self.read(self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        let msb = self.read(self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+
+        if let Some(guard) = &mut self.stack_guard {
+            guard.check(old_sp, self.regs.sp, old_sp > 0xFFFD);
+        }
+
+        u16::from_le_bytes([lsb, msb])
     }
 
-    pub fn op_jp(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Reads an 8-bit source operand of an ALU instruction:
+    /// imm8, reg8 or (reg16).
+    fn read_operand8(&mut self, instr: &Instruction, idx: usize) -> Result<u8> {
+        Ok(match instr.def.operands[idx] {
+            // _ imm8
+            Operand::Immediate8 => instr.imm8(idx)?,
+            // _ reg8
+            Operand::Register(reg) => {
+                assert_eq!(reg.width(), RegisterWidth::EightBit);
+                self.regs.read8(reg)?
+            }
+            // _ (reg16)
+            Operand::RegisterIndirect(reg) => {
+                assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+                self.read(self.regs.read16(reg)?)
+            }
+            _ => bail!("Invalid 8-bit operand: {:?}", instr.def.operands[idx]),
+        })
     }
 
-    pub fn op_jp_nc(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Writes back an 8-bit operand of a read-modify-write
+    /// instruction: reg8 or (reg16).
+    fn write_operand8(&mut self, instr: &Instruction, idx: usize, val: u8) -> Result<()> {
+        match instr.def.operands[idx] {
+            // _ reg8
+            Operand::Register(reg) => self.regs.write8(reg, val),
+            // _ (reg16)
+            Operand::RegisterIndirect(reg) => {
+                assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+                self.write(self.regs.read16(reg)?, val);
+                Ok(())
+            }
+            _ => bail!("Invalid 8-bit operand: {:?}", instr.def.operands[idx]),
+        }
     }
 
-    pub fn op_jp_nz(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// Generic implementation of the CB-prefixed rotates/shifts
+    fn op_cb_shift(
+        &mut self,
+        instr: &Instruction,
+        f: fn(u8, bool) -> alu::ALUResult<u8>,
+    ) -> CPUOpResult {
+        let result = f(self.read_operand8(instr, 0)?, self.regs.test_flag(Flag::C));
+        self.write_operand8(instr, 0, result.result)?;
+
+        self.regs.write_flags(&[
+            (Flag::C, result.carry),
+            (Flag::H, false),
+            (Flag::N, false),
+            (Flag::Z, (result.result == 0)),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
     }
 
-    pub fn op_jp_z(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// SET/RES generic implementation
+    fn op_set_res(&mut self, instr: &Instruction, set: bool) -> CPUOpResult {
+        // SET/RES const, _
+        let Operand::Constant(bit) = instr.def.operands[0]
+            else { bail!("Unknown first operand {:?}", instr.def.operands[0]) };
+
+        // This is always an 8-bit operation.
+        assert!((0..8).contains(&bit));
+
+        let val = self.read_operand8(instr, 1)?;
+
+        let val = if set {
+            val | (1 << bit)
+        } else {
+            val & !(1 << bit)
+        };
+
+        self.write_operand8(instr, 1, val)?;
+
+        Ok(OpOk::ok(self, instr))
     }
 
-    /// CALL cc - Call (conditional/unconditional)
-    fn op_call_cc(&mut self, instr: &Instruction, cc: bool) -> CPUOpResult {
-        if !cc {
-            return Ok(OpOk::no_branch(self, instr));
-        }
+    /// SET b,n - Set bit 'b' in 'n'
+    pub fn op_set(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_set_res(instr, true)
+    }
 
-        let next_addr = self.regs.pc.wrapping_add(instr.len as u16);
-        self.stack_push(next_addr);
+    /// RES b,n - Clear bit 'b' in 'n'
+    pub fn op_res(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_set_res(instr, false)
+    }
 
-        Ok(OpOk::branch(self, instr, instr.imm16(0)?))
+    /// SRL - Shift right (logical)
+    pub fn op_srl(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, |a, _| alu::shright_8b(a))
     }
 
-    /// CALL - Call (unconditional)
-    pub fn op_call(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_call_cc(instr, true)
+    /// SWAP - Swap nibbles
+    pub fn op_swap(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, |a, _| alu::ALUResult {
+            result: a.rotate_left(4),
+            carry: false,
+            halfcarry: false,
+        })
     }
 
-    /// CALL - Call (if carry)
-    pub fn op_call_c(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_call_cc(instr, self.regs.test_flag(Flag::C))
+    /// SLA - Shift left (arithmetic)
+    pub fn op_sla(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, |a, _| alu::shleft_8b(a))
     }
 
-    /// CALL - Call (if not carry)
-    pub fn op_call_nc(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_call_cc(instr, !self.regs.test_flag(Flag::C))
+    /// SRA - Shift right (arithmetic, bit 7 preserved)
+    pub fn op_sra(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, |a, _| alu::shright_arith_8b(a))
     }
 
-    /// CALL - Call (if not zero)
-    pub fn op_call_nz(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_call_cc(instr, !self.regs.test_flag(Flag::Z))
+    /// BIT b,n - Test for bit 'b' in 'n'
+    pub fn op_bit(&mut self, instr: &Instruction) -> CPUOpResult {
+        // BIT const, _
+        let Operand::Constant(bit) = instr.def.operands[0]
+            else { bail!("Unknown first operand {:?}", instr.def.operands[0]) };
+
+        // This is always an 8-bit operation.
+        assert!((0..8).contains(&bit));
+
+        // BIT _, reg / BIT _, (reg16)
+        let val = self.read_operand8(instr, 1)?;
+
+        self.regs.write_flags(&[
+            (Flag::Z, (val & (1 << bit) == 0)),
+            (Flag::H, true),
+            (Flag::N, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
     }
 
-    /// CALL - Call (if zero)
-    pub fn op_call_z(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_call_cc(instr, self.regs.test_flag(Flag::Z))
+    /// RL - Rotate left (carry = bit 9)
+    pub fn op_rl(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, alu::rotleft_9b)
     }
 
-    /// RET cc - Return (conditional/unconditional)
-    fn op_ret_cc(&mut self, instr: &Instruction, cc: bool) -> CPUOpResult {
-        if !cc {
-            return Ok(OpOk::no_branch(self, instr));
-        }
+    /// Generic implementation of the A register rotates.
+    /// Unlike the CB-prefixed rotates, Z is always cleared.
+    fn op_rotate_a(
+        &mut self,
+        instr: &Instruction,
+        f: fn(u8, bool) -> alu::ALUResult<u8>,
+    ) -> CPUOpResult {
+        let result = f(self.regs.read8(Register::A)?, self.regs.test_flag(Flag::C));
+        self.regs.write8(Register::A, result.result)?;
 
-        let ret_addr = self.stack_pop();
-        Ok(OpOk::branch(self, instr, ret_addr))
+        self.regs.write_flags(&[
+            (Flag::C, result.carry),
+            (Flag::H, false),
+            (Flag::N, false),
+            (Flag::Z, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
     }
 
-    /// RET - Return (unconditional)
-    pub fn op_ret(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_ret_cc(instr, true)
+    /// RLA - Rotate Left, A register
+    pub fn op_rla(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_rotate_a(instr, alu::rotleft_9b)
     }
 
-    /// RET NC - Return (if not carry)
-    pub fn op_ret_nc(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_ret_cc(instr, !self.regs.test_flag(Flag::C))
+    /// RLC - Rotate left (copy to carry)
+    pub fn op_rlc(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, |a, _| alu::rotleft_8b(a))
     }
 
-    /// RET C - Return (if zero)
-    pub fn op_ret_c(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_ret_cc(instr, self.regs.test_flag(Flag::C))
+    /// RLCA - Rotate Left (copy to carry), A register
+    pub fn op_rlca(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_rotate_a(instr, |a, _| alu::rotleft_8b(a))
     }
 
-    /// RET NZ - Return (if not zero)
-    pub fn op_ret_nz(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_ret_cc(instr, !self.regs.test_flag(Flag::Z))
+    /// RR - Rotate right (carry = bit 9)
+    pub fn op_rr(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, alu::rotright_9b)
     }
 
-    /// RET Z - Return (if zero)
-    pub fn op_ret_z(&mut self, instr: &Instruction) -> CPUOpResult {
-        self.op_ret_cc(instr, self.regs.test_flag(Flag::Z))
+    /// RRA - Rotate Right, A register
+    pub fn op_rra(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_rotate_a(instr, alu::rotright_9b)
     }
 
-    pub fn op_reti(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// RRC - Rotate right (copy to carry)
+    pub fn op_rrc(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_cb_shift(instr, |a, _| alu::rotright_8b(a))
     }
 
-    pub fn op_sbc(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// RRCA - Rotate Right (copy to carry), A register
+    pub fn op_rrca(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_rotate_a(instr, |a, _| alu::rotright_8b(a))
     }
 
-    pub fn op_prefix_cb(&mut self, _instr: &Instruction) -> CPUOpResult {
-        todo!();
+    /// EI - Enable interrupts (after the next instruction)
+    pub fn op_ei(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.ime_pending = true;
+        Ok(OpOk::ok(self, instr))
     }
 
-    pub fn op_invalid(&mut self, _instr: &Instruction) -> CPUOpResult {
-        panic!("Invalid opcode");
+    /// DI - Disable interrupts
+    pub fn op_di(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.ime = false;
+        self.ime_pending = false;
+        Ok(OpOk::ok(self, instr))
     }
-}
 
-#[cfg(test)]
+    /// RST - Call to fixed vector
+    pub fn op_rst(&mut self, instr: &Instruction) -> CPUOpResult {
+        let Operand::Constant(vec) = instr.def.operands[0]
+            else { unreachable!() };
+
+        // SP is decremented in an internal cycle first
+        let next_addr = self.regs.pc.wrapping_add(instr.len as u16);
+        self.tick_internal();
+        self.stack_push(next_addr);
+
+        Ok(OpOk::branch(self, instr, vec.into()))
+    }
+
+    /// NOP - No Operation
+    pub fn op_nop(&mut self, instr: &Instruction) -> CPUOpResult {
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// STOP - Stop CPU
+    pub fn op_stop(&mut self, instr: &Instruction) -> CPUOpResult {
+        // STOP is encoded as two bytes, but the second byte
+        // is not consumed if an interrupt is pending (IE & IF)
+        let pending = self.pending_mask() != 0;
+        let len = if pending { 1 } else { instr.len as u16 };
+
+        // With a button held (selected in P1), the clock is
+        // not stopped and DIV is not reset: STOP enters HALT,
+        // or does nothing if an interrupt is pending.
+        if self.bus.peek(ADDR_P1) & 0x0F != 0x0F {
+            self.halted = !pending;
+            return Ok(OpOk {
+                pc: self.regs.pc.wrapping_add(len),
+                cycles: instr.def.cycles[0].into(),
+            });
+        }
+
+        // STOP resets the divider
+        self.bus.write(ADDR_DIV, 0);
+
+        // On CGB, STOP performs an armed speed switch and
+        // execution continues. Otherwise the clock stops.
+        if !self.bus.stop() && self.stop_behavior == StopBehavior::WaitForJoypad {
+            self.stopped = true;
+        }
+
+        Ok(OpOk {
+            pc: self.regs.pc.wrapping_add(len),
+            cycles: instr.def.cycles[0].into(),
+        })
+    }
+
+    /// HALT - Halt until interrupt
+    pub fn op_halt(&mut self, instr: &Instruction) -> CPUOpResult {
+        if !self.ime && self.pending_mask() != 0 {
+            // HALT bug: the CPU does not halt and
+            // fails to increment PC on the next fetch.
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// LD - Load Register
+    pub fn op_ld(&mut self, instr: &Instruction) -> CPUOpResult {
+        let indreg = |r: Register, regval| match r.width() {
+            RegisterWidth::SixteenBit => regval,
+            RegisterWidth::EightBit => {
+                assert_eq!(regval & !0xFF, 0);
+                0xFF00 | regval
+            }
+        };
+
+        // Source operand
+        let (val, width): (u16, RegisterWidth) = match instr.def.operands[1] {
+            // LD _, imm8
+            Operand::Immediate8 => (instr.imm8(1)?.into(), RegisterWidth::EightBit),
+            // LDH _, (a8)
+            Operand::ImmediateIndirect8 => {
+                let addr = 0xFF00_u16 | instr.imm8(1)? as u16;
+                (self.read(addr).into(), RegisterWidth::EightBit)
+            }
+            // LD _, imm16
+            Operand::Immediate16 => (instr.imm16(1)?, RegisterWidth::SixteenBit),
+            // LD _, (a16)
+            Operand::ImmediateIndirect16 => {
+                (self.read(instr.imm16(1)?).into(), RegisterWidth::EightBit)
+            }
+            // LD _, reg
+            Operand::Register(reg) => (self.regs.read(reg), reg.width()),
+            // LD _, (reg)
+            Operand::RegisterIndirect(reg) => {
+                let addr = self.regs.read(reg);
+                (self.read(indreg(reg, addr)).into(), RegisterWidth::EightBit)
+            }
+            // LD _, (reg-)
+            Operand::RegisterIndirectDec(reg) => {
+                let addr = self.regs.read_dec(reg)?;
+                (self.read(indreg(reg, addr)).into(), RegisterWidth::EightBit)
+            }
+            // LD _, (reg+)
+            Operand::RegisterIndirectInc(reg) => {
+                let addr = self.regs.read_inc(reg)?;
+                (self.read(indreg(reg, addr)).into(), RegisterWidth::EightBit)
+            }
+            // LD _, SP+r8
+            Operand::SPRelative8 => {
+                let result = alu::add_16b_signed8(self.regs.sp, instr.imms8(1)?);
+                self.regs.write_flags(&[
+                    (Flag::Z, false),
+                    (Flag::C, result.carry),
+                    (Flag::H, result.halfcarry),
+                    (Flag::N, false),
+                ]);
+                (result.result, RegisterWidth::SixteenBit)
+            }
+            _ => bail!("Invalid second operand: {:?}", instr.def.operands[1]),
+        };
+
+        // Memory destinations take 8-bit values, except for LD (a16),SP.
+        let val8 = || -> Result<u8> {
+            if width != RegisterWidth::EightBit {
+                bail!("16-bit value written to 8-bit destination");
+            }
+            Ok(val as u8)
+        };
+
+        // Destination operand
+        match instr.def.operands[0] {
+            // LD reg, _
+            Operand::Register(dest) => {
+                if dest.width() != width {
+                    bail!("{:?} value written to {:?}", width, dest);
+                }
+                self.regs.write(dest, val)?
+            }
+            // LD (reg), _
+            Operand::RegisterIndirect(dest) => {
+                let addr = self.regs.read(dest);
+                self.write(indreg(dest, addr), val8()?)
+            }
+            // LD (reg-), _
+            Operand::RegisterIndirectDec(dest) => {
+                let addr = self.regs.read_dec(dest)?;
+                self.write(indreg(dest, addr), val8()?)
+            }
+            // LD (reg+), _
+            Operand::RegisterIndirectInc(dest) => {
+                let addr = self.regs.read_inc(dest)?;
+                self.write(indreg(dest, addr), val8()?)
+            }
+            // LDH (a8), _
+            Operand::ImmediateIndirect8 => {
+                let addr = 0xFF00_u16 + instr.imm8(0)? as u16;
+                self.write(addr, val8()?)
+            }
+            // LD (a16), _
+            Operand::ImmediateIndirect16 => match width {
+                RegisterWidth::EightBit => self.write(instr.imm16(0)?, val8()?),
+                RegisterWidth::SixteenBit => self.write16(instr.imm16(0)?, val),
+            },
+            _ => bail!("Invalid first operand: {:?}", instr.def.operands[0]),
+        }
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// SCF - Set carry flag
+    pub fn op_scf(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.regs
+            .write_flags(&[(Flag::C, true), (Flag::H, false), (Flag::N, false)]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// CCF - Complement carry flag
+    pub fn op_ccf(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.regs.write_flags(&[
+            (Flag::C, !self.regs.test_flag(Flag::C)),
+            (Flag::H, false),
+            (Flag::N, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// CP - Compare
+    pub fn op_cp(&mut self, instr: &Instruction) -> CPUOpResult {
+        let result = alu::sub_8b(self.regs.read8(Register::A)?, self.read_operand8(instr, 0)?);
+        self.regs.write_flags(&[
+            (Flag::Z, result.result == 0),
+            (Flag::H, result.halfcarry),
+            (Flag::C, result.carry),
+            (Flag::N, true),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// CPL - Complement A
+    pub fn op_cpl(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.regs
+            .write8(Register::A, !self.regs.read8(Register::A)?)?;
+        self.regs.write_flags(&[(Flag::H, true), (Flag::N, true)]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// Generic implementation of the bitwise ops on A
+    fn op_bitwise(&mut self, instr: &Instruction, f: fn(u8, u8) -> u8, h: bool) -> CPUOpResult {
+        let result = f(self.regs.read8(Register::A)?, self.read_operand8(instr, 0)?);
+        self.regs.write8(Register::A, result)?;
+        self.regs.write_flags(&[
+            (Flag::Z, result == 0),
+            (Flag::H, h),
+            (Flag::N, false),
+            (Flag::C, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// OR - Bitwise OR
+    pub fn op_or(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_bitwise(instr, |a, b| a | b, false)
+    }
+
+    /// XOR - Bitwise XOR
+    pub fn op_xor(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_bitwise(instr, |a, b| a ^ b, false)
+    }
+
+    /// AND - Bitwise AND
+    pub fn op_and(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_bitwise(instr, |a, b| a & b, true)
+    }
+
+    /// PUSH - Push register onto stack
+    pub fn op_push(&mut self, instr: &Instruction) -> CPUOpResult {
+        let Operand::Register(reg) = instr.def.operands[0]
+            else { unreachable!() };
+        assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+
+        // SP is decremented in an internal cycle first
+        self.tick_internal();
+        self.stack_push(self.regs.read16(reg)?);
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// POP - Pop register from stack
+    pub fn op_pop(&mut self, instr: &Instruction) -> CPUOpResult {
+        let Operand::Register(reg) = instr.def.operands[0]
+            else { unreachable!() };
+        assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+
+        // POP AF: RegisterFile masks the lower 4 bits of F
+        let val = self.stack_pop();
+        self.regs.write16(reg, val)?;
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// ADC - Add with carry (8-bit)
+    pub fn op_adc(&mut self, instr: &Instruction) -> CPUOpResult {
+        // First operand is always A
+        assert!(matches!(
+            instr.def.operands[0],
+            Operand::Register(Register::A)
+        ));
+
+        let result = alu::add_8b_carry(
+            self.regs.read8(Register::A)?,
+            self.read_operand8(instr, 1)?,
+            self.regs.test_flag(Flag::C),
+        );
+        self.regs.write8(Register::A, result.result)?;
+        self.regs.write_flags(&[
+            (Flag::Z, result.result == 0),
+            (Flag::C, result.carry),
+            (Flag::H, result.halfcarry),
+            (Flag::N, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// DAA - Decimal adjust A (BCD) after ADD/SUB
+    pub fn op_daa(&mut self, instr: &Instruction) -> CPUOpResult {
+        let mut a = self.regs.read8(Register::A)?;
+        let mut carry = self.regs.test_flag(Flag::C);
+        let halfcarry = self.regs.test_flag(Flag::H);
+
+        if !self.regs.test_flag(Flag::N) {
+            // After addition
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if halfcarry || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            // After subtraction, only the flags are considered
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if halfcarry {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+
+        self.regs.write8(Register::A, a)?;
+        self.regs.write_flags(&[
+            (Flag::Z, a == 0),
+            (Flag::C, carry),
+            (Flag::H, false),
+            // N not affected
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// ADD - Add
+    pub fn op_add(&mut self, instr: &Instruction) -> CPUOpResult {
+        match instr.def.operands[0] {
+            Operand::Register(Register::A) => self.op_add_8b(instr),
+            Operand::Register(Register::HL) => self.op_add_16b(instr),
+            Operand::Register(Register::SP) => self.op_add_sp(instr),
+            _ => unreachable!(),
+        }
+    }
+
+    /// ADD A,_ - Add (8-bit)
+    fn op_add_8b(&mut self, instr: &Instruction) -> CPUOpResult {
+        let result = alu::add_8b(self.regs.read8(Register::A)?, self.read_operand8(instr, 1)?);
+        self.regs.write8(Register::A, result.result)?;
+        self.regs.write_flags(&[
+            (Flag::Z, result.result == 0),
+            (Flag::C, result.carry),
+            (Flag::H, result.halfcarry),
+            (Flag::N, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// ADD HL,_ - Add (16-bit)
+    fn op_add_16b(&mut self, instr: &Instruction) -> CPUOpResult {
+        let Operand::Register(reg) = instr.def.operands[1]
+            else { unreachable!() };
+
+        let result = alu::add_16b(self.regs.read16(Register::HL)?, self.regs.read16(reg)?);
+        self.regs.write(Register::HL, result.result)?;
+        self.regs.write_flags(&[
+            // Zero flag not affected
+            (Flag::C, result.carry),
+            (Flag::H, result.halfcarry),
+            (Flag::N, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// ADD SP,r8 - Add signed immediate to SP
+    fn op_add_sp(&mut self, instr: &Instruction) -> CPUOpResult {
+        let result = alu::add_16b_signed8(self.regs.sp, instr.imms8(1)?);
+        self.regs.sp = result.result;
+        self.regs.write_flags(&[
+            (Flag::Z, false),
+            (Flag::C, result.carry),
+            (Flag::H, result.halfcarry),
+            (Flag::N, false),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// SUB - Subtract (8-bit)
+    pub fn op_sub(&mut self, instr: &Instruction) -> CPUOpResult {
+        let res = alu::sub_8b(self.regs.read8(Register::A)?, self.read_operand8(instr, 0)?);
+
+        self.regs.write8(Register::A, res.result)?;
+        self.regs.write_flags(&[
+            (Flag::Z, (res.result == 0)),
+            (Flag::N, true),
+            (Flag::C, res.carry),
+            (Flag::H, res.halfcarry),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// DEC - Decrement (8-bit)
+    pub fn op_dec_8b(&mut self, instr: &Instruction) -> CPUOpResult {
+        let res = alu::sub_8b(self.read_operand8(instr, 0)?, 1);
+        match instr.def.operands[0] {
+            // DEC reg8
+            Operand::Register(reg) => self.regs.write8(reg, res.result)?,
+            // DEC (reg16)
+            Operand::RegisterIndirect(reg) => self.write(self.regs.read16(reg)?, res.result),
+            _ => unreachable!(),
+        }
+        self.regs.write_flags(&[
+            (Flag::H, res.halfcarry),
+            (Flag::N, true),
+            (Flag::Z, (res.result == 0)),
+            // Carry not used
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// DEC - Decrement (16-bit)
+    pub fn op_dec_16b(&mut self, instr: &Instruction) -> CPUOpResult {
+        let Operand::Register(reg) = instr.def.operands[0]
+            else { unreachable!() };
+
+        assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+        self.regs
+            .write(reg, self.regs.read16(reg)?.wrapping_sub(1))?;
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// INC - Increment (8-bit)
+    pub fn op_inc_8b(&mut self, instr: &Instruction) -> CPUOpResult {
+        let res = alu::add_8b(self.read_operand8(instr, 0)?, 1);
+        match instr.def.operands[0] {
+            // INC reg8
+            Operand::Register(reg) => self.regs.write8(reg, res.result)?,
+            // INC (reg16)
+            Operand::RegisterIndirect(reg) => self.write(self.regs.read16(reg)?, res.result),
+            _ => unreachable!(),
+        }
+        self.regs.write_flags(&[
+            (Flag::H, res.halfcarry),
+            (Flag::N, false),
+            (Flag::Z, (res.result == 0)),
+            // Carry not used
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// INC - Increment (16-bit)
+    pub fn op_inc_16b(&mut self, instr: &Instruction) -> CPUOpResult {
+        let Operand::Register(reg) = instr.def.operands[0]
+            else { unreachable!() };
+
+        assert_eq!(reg.width(), RegisterWidth::SixteenBit);
+        self.regs
+            .write(reg, self.regs.read16(reg)?.wrapping_add(1))?;
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// JR _, s8 - Jump Relative (conditional/unconditional)
+    fn op_jr_cc(&mut self, instr: &Instruction, cc: bool) -> CPUOpResult {
+        // The offset is always read, whether the branch
+        // is taken or not. Not taken continues after it.
+        let rel_addr = instr.imms8(0)?;
+        if !cc {
+            return Ok(OpOk::no_branch(self, instr));
+        }
+
+        // Offset is relative to the next instruction.
+        let new_pc = self
+            .regs
+            .pc
+            .wrapping_add(instr.len as u16)
+            .wrapping_add_signed(rel_addr.into());
+        Ok(OpOk::branch(self, instr, new_pc))
+    }
+
+    /// JR s8 - Jump Relative (unconditionally)
+    pub fn op_jr(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jr_cc(instr, true)
+    }
+
+    /// JR NC s8 - Jump Relative (if not carry)
+    pub fn op_jr_nc(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jr_cc(instr, !self.regs.test_flag(Flag::C))
+    }
+
+    /// JR C s8 - Jump Relative (if carry)
+    pub fn op_jr_c(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jr_cc(instr, self.regs.test_flag(Flag::C))
+    }
+
+    /// JR NZ s8 - Jump Relative (if not zero)
+    pub fn op_jr_nz(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jr_cc(instr, !self.regs.test_flag(Flag::Z))
+    }
+
+    /// JR Z s8 - Jump Relative (if zero)
+    pub fn op_jr_z(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jr_cc(instr, self.regs.test_flag(Flag::Z))
+    }
+
+    /// JP _ - Jump (conditional/unconditional)
+    fn op_jp_cc(&mut self, instr: &Instruction, cc: bool) -> CPUOpResult {
+        if !cc {
+            return Ok(OpOk::no_branch(self, instr));
+        }
+
+        let new_pc = match instr.def.operands[0] {
+            // JP a16
+            Operand::ImmediateIndirect16 => instr.imm16(0)?,
+            // JP (HL) - jumps to HL itself, not to (HL)
+            Operand::RegisterIndirect(reg) => self.regs.read16(reg)?,
+            _ => unreachable!(),
+        };
+        Ok(OpOk::branch(self, instr, new_pc))
+    }
+
+    /// JP - Jump (unconditional)
+    pub fn op_jp(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jp_cc(instr, true)
+    }
+
+    /// JP C - Jump (if carry)
+    pub fn op_jp_c(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jp_cc(instr, self.regs.test_flag(Flag::C))
+    }
+
+    /// JP NC - Jump (if not carry)
+    pub fn op_jp_nc(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jp_cc(instr, !self.regs.test_flag(Flag::C))
+    }
+
+    /// JP NZ - Jump (if not zero)
+    pub fn op_jp_nz(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jp_cc(instr, !self.regs.test_flag(Flag::Z))
+    }
+
+    /// JP Z - Jump (if zero)
+    pub fn op_jp_z(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_jp_cc(instr, self.regs.test_flag(Flag::Z))
+    }
+
+    /// CALL cc - Call (conditional/unconditional)
+    fn op_call_cc(&mut self, instr: &Instruction, cc: bool) -> CPUOpResult {
+        if !cc {
+            return Ok(OpOk::no_branch(self, instr));
+        }
+
+        // SP is decremented in an internal cycle first
+        let next_addr = self.regs.pc.wrapping_add(instr.len as u16);
+        self.tick_internal();
+        self.stack_push(next_addr);
+
+        Ok(OpOk::branch(self, instr, instr.imm16(0)?))
+    }
+
+    /// CALL - Call (unconditional)
+    pub fn op_call(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_call_cc(instr, true)
+    }
+
+    /// CALL - Call (if carry)
+    pub fn op_call_c(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_call_cc(instr, self.regs.test_flag(Flag::C))
+    }
+
+    /// CALL - Call (if not carry)
+    pub fn op_call_nc(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_call_cc(instr, !self.regs.test_flag(Flag::C))
+    }
+
+    /// CALL - Call (if not zero)
+    pub fn op_call_nz(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_call_cc(instr, !self.regs.test_flag(Flag::Z))
+    }
+
+    /// CALL - Call (if zero)
+    pub fn op_call_z(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_call_cc(instr, self.regs.test_flag(Flag::Z))
+    }
+
+    /// RET cc - Return (conditional/unconditional)
+    fn op_ret_cc(&mut self, instr: &Instruction, cc: bool) -> CPUOpResult {
+        if !cc {
+            return Ok(OpOk::no_branch(self, instr));
+        }
+
+        let ret_addr = self.stack_pop();
+        Ok(OpOk::branch(self, instr, ret_addr))
+    }
+
+    /// RET - Return (unconditional)
+    pub fn op_ret(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_ret_cc(instr, true)
+    }
+
+    /// RET NC - Return (if not carry)
+    pub fn op_ret_nc(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_ret_cc(instr, !self.regs.test_flag(Flag::C))
+    }
+
+    /// RET C - Return (if carry)
+    pub fn op_ret_c(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_ret_cc(instr, self.regs.test_flag(Flag::C))
+    }
+
+    /// RET NZ - Return (if not zero)
+    pub fn op_ret_nz(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_ret_cc(instr, !self.regs.test_flag(Flag::Z))
+    }
+
+    /// RET Z - Return (if zero)
+    pub fn op_ret_z(&mut self, instr: &Instruction) -> CPUOpResult {
+        self.op_ret_cc(instr, self.regs.test_flag(Flag::Z))
+    }
+
+    /// RETI - Return and enable interrupts
+    pub fn op_reti(&mut self, instr: &Instruction) -> CPUOpResult {
+        // Unlike EI, IME is set immediately.
+        self.ime = true;
+
+        let ret_addr = self.stack_pop();
+        Ok(OpOk::branch(self, instr, ret_addr))
+    }
+
+    /// SBC - Subtract with carry (8-bit)
+    pub fn op_sbc(&mut self, instr: &Instruction) -> CPUOpResult {
+        // First operand is always A
+        assert!(matches!(
+            instr.def.operands[0],
+            Operand::Register(Register::A)
+        ));
+
+        let res = alu::sub_8b_carry(
+            self.regs.read8(Register::A)?,
+            self.read_operand8(instr, 1)?,
+            self.regs.test_flag(Flag::C),
+        );
+
+        self.regs.write8(Register::A, res.result)?;
+        self.regs.write_flags(&[
+            (Flag::Z, (res.result == 0)),
+            (Flag::N, true),
+            (Flag::C, res.carry),
+            (Flag::H, res.halfcarry),
+        ]);
+
+        Ok(OpOk::ok(self, instr))
+    }
+
+    /// PREFIX CB - Dispatch to the 0xCB instruction table
+    ///
+    /// Instruction::decode() normally decodes CB-prefixed
+    /// instructions as a whole, this handles a bare prefix
+    /// by decoding the full instruction at PC and executing it.
+    /// Cycles and length of the CB table include the prefix.
+    pub fn op_prefix_cb(&mut self, _instr: &Instruction) -> CPUOpResult {
+        let mut busiter = BusIterator::new_from(&self.bus, self.regs.pc);
+        let cbinstr = Instruction::decode(&mut busiter, self.regs.pc)?;
+        assert_eq!(cbinstr.len, 2);
+
+        self.dispatch(&cbinstr)
+    }
+
+    pub fn op_invalid(&mut self, instr: &Instruction) -> CPUOpResult {
+        bail!(EmuError::InvalidOpcode {
+            pc: self.regs.pc,
+            opcode: instr.raw[0],
+        })
+    }
+}
+
+#[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::super::super::bus::gbbus::Gameboybus;
     use super::super::super::bus::testbus::Testbus;
+    use super::super::super::cartridge::romonly::RomOnly;
     use super::*;
 
-    fn cpu(code: &[u8]) -> CPU {
-        let bus = Testbus::from(code);
-        CPU::new(Box::new(bus))
+    /// A single recorded bus access: (is_write, address, value)
+    type Access = (bool, u16, u8);
+
+    /// Testbus wrapper that records all accesses.
+    struct Tracebus {
+        bus: Testbus,
+        trace: Rc<RefCell<Vec<Access>>>,
+    }
+
+    impl Bus for Tracebus {
+        fn read(&self, addr: u16) -> u8 {
+            let val = self.bus.read(addr);
+            self.trace.borrow_mut().push((false, addr, val));
+            val
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.trace.borrow_mut().push((true, addr, val));
+            self.bus.write(addr, val);
+        }
+    }
+
+    fn cpu_traced(code: &[u8]) -> (CPU, Rc<RefCell<Vec<Access>>>) {
+        let trace = Rc::new(RefCell::new(vec![]));
+        let bus = Tracebus {
+            bus: Testbus::from(code),
+            trace: Rc::clone(&trace),
+        };
+        (CPU::new(Box::new(bus)), trace)
+    }
+
+    /// Filters a trace to data accesses, excluding
+    /// instruction fetches from 'code_len' bytes at 0.
+    fn data_accesses(trace: &Rc<RefCell<Vec<Access>>>, code_len: u16) -> Vec<Access> {
+        trace
+            .borrow()
+            .iter()
+            .filter(|&&(w, addr, _)| w || addr >= code_len)
+            .copied()
+            .collect()
+    }
+
+    /// A data access with the cycles ticked before it:
+    /// (cycles, is_write, address)
+    type TimedAccess = (usize, bool, u16);
+
+    /// Testbus wrapper that records the timing of accesses
+    /// outside of 'code_len' bytes at 0.
+    struct Tickbus {
+        bus: Testbus,
+        code_len: u16,
+        cycles: Rc<RefCell<usize>>,
+        trace: Rc<RefCell<Vec<TimedAccess>>>,
+    }
+
+    impl Bus for Tickbus {
+        fn read(&self, addr: u16) -> u8 {
+            if addr >= self.code_len {
+                let cycles = *self.cycles.borrow();
+                self.trace.borrow_mut().push((cycles, false, addr));
+            }
+            self.bus.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            let cycles = *self.cycles.borrow();
+            self.trace.borrow_mut().push((cycles, true, addr));
+            self.bus.write(addr, val);
+        }
+
+        fn tick(&mut self, cycles: usize) {
+            *self.cycles.borrow_mut() += cycles;
+        }
+    }
+
+    #[test]
+    fn access_timing() {
+        let code = [
+            0x7E, // LD A,(HL)
+            0x36, 0x12, // LD (HL),$12
+            0xC5, // PUSH BC
+            0xEA, 0x00, 0xD0, // LD ($D000),A
+        ];
+        let cycles = Rc::new(RefCell::new(0));
+        let trace = Rc::new(RefCell::new(vec![]));
+        let bus = Tickbus {
+            bus: Testbus::from(&code),
+            code_len: code.len() as u16,
+            cycles: Rc::clone(&cycles),
+            trace: Rc::clone(&trace),
+        };
+        let mut c = CPU::new(Box::new(bus));
+        c.regs.write(Register::HL, 0xC000).unwrap();
+        c.regs.sp = 0xE000;
+
+        // Accesses happen at the end of their own M-cycle,
+        // following the opcode fetch.
+        c.step().unwrap();
+        assert_eq!(*trace.borrow(), [(8, false, 0xC000)]);
+        assert_eq!(*cycles.borrow(), 8);
+
+        c.step().unwrap();
+        assert_eq!(trace.borrow()[1], (20, true, 0xC000));
+        assert_eq!(*cycles.borrow(), 20);
+
+        // The internal cycle of PUSH comes before the writes
+        c.step().unwrap();
+        assert_eq!(
+            trace.borrow()[2..4],
+            [(32, true, 0xDFFF), (36, true, 0xDFFE)]
+        );
+        assert_eq!(*cycles.borrow(), 36);
+
+        c.step().unwrap();
+        assert_eq!(trace.borrow()[4], (52, true, 0xD000));
+        assert_eq!(*cycles.borrow(), c.get_cycles());
+    }
+
+    /// Something that happened on the bus
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    enum BusEvent {
+        Tick(usize),
+        Read(u16),
+        Write(u16),
+    }
+
+    /// Testbus wrapper that records ticks and the accesses
+    /// outside of 'code_len' bytes at 0, in order.
+    struct Eventbus {
+        bus: Testbus,
+        code_len: u16,
+        events: Rc<RefCell<Vec<BusEvent>>>,
+    }
+
+    impl Bus for Eventbus {
+        fn read(&self, addr: u16) -> u8 {
+            if addr >= self.code_len {
+                self.events.borrow_mut().push(BusEvent::Read(addr));
+            }
+            self.bus.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.events.borrow_mut().push(BusEvent::Write(addr));
+            self.bus.write(addr, val);
+        }
+
+        fn tick(&mut self, cycles: usize) {
+            if cycles > 0 {
+                self.events.borrow_mut().push(BusEvent::Tick(cycles));
+            }
+        }
+    }
+
+    fn cpu_events(code: &[u8]) -> (CPU<Eventbus>, Rc<RefCell<Vec<BusEvent>>>) {
+        let events = Rc::new(RefCell::new(vec![]));
+        let bus = Eventbus {
+            bus: Testbus::from(code),
+            code_len: code.len() as u16,
+            events: Rc::clone(&events),
+        };
+        let mut c = CPU::new(bus);
+        c.regs.sp = 0xD000;
+        (c, events)
+    }
+
+    #[test]
+    fn internal_cycle_before_push() {
+        use BusEvent::*;
+
+        // Opcode fetch, internal cycle, then the writes
+        let pushes = [Tick(4), Write(0xCFFF), Tick(4), Write(0xCFFE)];
+        for (code, fetch) in [
+            (&[0xC5][..], 4),              // PUSH BC
+            (&[0xCD, 0x00, 0x10][..], 12), // CALL $1000
+            (&[0xC4, 0x00, 0x10][..], 12), // CALL NZ,$1000
+            (&[0xFF][..], 4),              // RST $38
+        ] {
+            let (mut c, events) = cpu_events(code);
+            c.step().unwrap();
+            let mut expected = vec![Tick(fetch), Tick(4)];
+            expected.extend(pushes);
+            assert_eq!(*events.borrow(), expected, "{:02X?}", code);
+        }
+
+        // Not taken, no internal cycle
+        let (mut c, events) = cpu_events(&[0xCC, 0x00, 0x10]); // CALL Z,$1000
+        c.step().unwrap();
+        assert_eq!(*events.borrow(), [Tick(12)]);
+    }
+
+    #[test]
+    fn step_back() {
+        let mut c = cpu(&[
+            0x3E, 0x12, // LD A,$12
+            0xEA, 0x00, 0xC0, // LD ($C000),A
+        ]);
+        assert!(c.step_back().is_err());
+        c.enable_step_back(1).unwrap();
+        assert!(c.step_back().is_err());
+
+        c.bus.write(0xC000, 0x34);
+        c.step().unwrap();
+        c.step().unwrap();
+        assert_eq!(c.bus.read(0xC000), 0x12);
+
+        // The write is undone too
+        c.step_back().unwrap();
+        assert_eq!(c.regs.pc, 2);
+        assert_eq!(c.regs.a, 0x12);
+        assert_eq!(c.get_cycles(), 8);
+        assert_eq!(c.bus.read(0xC000), 0x34);
+
+        // Only one step kept
+        assert!(c.step_back().is_err());
+        assert_eq!(c.regs.pc, 2);
+
+        c.enable_step_back(10).unwrap();
+        let state = c.save_state().unwrap();
+        c.step().unwrap();
+        c.step_back().unwrap();
+        assert_eq!(c.save_state().unwrap(), state);
+    }
+
+    fn cpu(code: &[u8]) -> CPU {
+        let bus = Testbus::from(code);
+        CPU::new(Box::new(bus))
+    }
+
+    fn cpu_run(cpu: &mut CPU) {
+        cpu.step().unwrap();
+    }
+
+    fn run(code: &[u8]) -> CPU {
+        let mut cpu = cpu(code);
+        cpu_run(&mut cpu);
+        cpu
+    }
+
+    fn run_reg(code: &[u8], reg: Register, val: u16) -> CPU {
+        let mut cpu = cpu(code);
+        cpu.regs.write(reg, val).unwrap();
+        cpu_run(&mut cpu);
+        cpu
+    }
+
+    fn run_flags(code: &[u8], flags: &[Flag]) -> CPU {
+        let mut cpu = cpu(code);
+        cpu.regs.write_flags(
+            &flags
+                .iter()
+                .map(|&f| (f, true))
+                .collect::<Vec<(Flag, bool)>>(),
+        );
+        cpu_run(&mut cpu);
+        cpu
+    }
+
+    fn run_reg_flags(code: &[u8], reg: Register, val: u16, flags: &[Flag]) -> CPU {
+        let mut cpu = cpu(code);
+        cpu.regs.write(reg, val).unwrap();
+        cpu.regs.write_flags(
+            &flags
+                .iter()
+                .map(|&f| (f, true))
+                .collect::<Vec<(Flag, bool)>>(),
+        );
+        cpu_run(&mut cpu);
+        cpu
+    }
+    #[test]
+    fn op_ld_reg_imm16() {
+        let cpu = run(&[0x31, 0x34, 0x12]); // LD SP,0x1234
+        assert_eq!(cpu.regs.sp, 0x1234);
+    }
+
+    #[test]
+    fn op_ld_reg_imm8() {
+        let cpu = run(&[0x3E, 0x12]); // LD A,0x12
+        assert_eq!(cpu.regs.a, 0x12);
+    }
+
+    #[test]
+    fn op_ld_reg_indreg16_inc() {
+        let mut c = cpu(&[0x2A]); // LD A,(HL+)
+        c.regs.write(Register::HL, 0x11FF).unwrap();
+        c.bus.write(0x11FF, 0x5A);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5A);
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x1200);
+    }
+
+    #[test]
+    fn op_ld_reg_indreg16_dec() {
+        let mut c = cpu(&[0x3A]); // LD A,(HL-)
+        c.regs.write(Register::HL, 0x1200).unwrap();
+        c.bus.write(0x1200, 0x5A);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5A);
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x11FF);
+    }
+
+    #[test]
+    fn op_ld_reg_indreg8() {
+        let mut c = cpu(&[0xF2]); // LD A,(C)
+        c.regs.c = 0x44;
+        c.bus.write(0xFF44, 0x5A);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5A);
+        assert_eq!(c.regs.pc, 1);
+    }
+
+    #[test]
+    fn op_ld_reg_indimm16() {
+        let mut c = cpu(&[0xFA, 0x55, 0xAA]); // LD A,(a16)
+        c.bus.write(0xAA55, 0x5A);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5A);
+        assert_eq!(c.cycles, 16);
+    }
+
+    #[test]
+    fn op_ld_indreg16_imm8() {
+        let mut c = cpu(&[0x36, 0x5A]); // LD (HL),d8
+        c.regs.write(Register::HL, 0x1122).unwrap();
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0x1122), 0x5A);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_ld_indimm16_sp() {
+        let c = run_reg(&[0x08, 0x55, 0xAA], Register::SP, 0x1234); // LD (a16),SP
+        assert_eq!(c.bus.read16(0xAA55), 0x1234);
+        assert_eq!(c.cycles, 20);
+    }
+
+    #[test]
+    fn op_ld_hl_sp_rel() {
+        let c = run_reg_flags(&[0xF8, 0x01], Register::SP, 0x00FF, &[Flag::Z, Flag::N]); // LD HL,SP+1
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x0100);
+        assert_eq!(c.regs.sp, 0x00FF);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+
+        let c = run_reg(&[0xF8, 0xFE], Register::SP, 0x1000); // LD HL,SP-2
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x0FFE);
+    }
+
+    #[test]
+    fn op_ld_sp_hl() {
+        let c = run_reg(&[0xF9], Register::HL, 0x1234); // LD SP,HL
+        assert_eq!(c.regs.sp, 0x1234);
+    }
+
+    #[test]
+    fn op_ldh_indimm8_reg() {
+        let mut c = cpu(&[0xE0, 0x80]); // LDH (a8),A
+        c.regs.a = 0x5A;
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0xFF80), 0x5A);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_ld_width_mismatch() {
+        use crate::gameboy::cpu::instruction::InstructionDef;
+
+        // Not a valid opcode, but must not silently truncate
+        static LD_HL_A: InstructionDef = InstructionDef {
+            mnemonic: "LD HL,A",
+            operands: [
+                Operand::Register(Register::HL),
+                Operand::Register(Register::A),
+            ],
+            len: 1,
+            cycles: [4, 4],
+            op: Op::Ld,
+        };
+        let mut c = cpu(&[]);
+        let mut instr = Instruction::decode(&mut [0x00].into_iter(), 0).unwrap();
+        instr.def = &LD_HL_A;
+        assert!(c.op_ld(&instr).is_err());
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0);
+    }
+
+    #[test]
+    fn op_xor_flags() {
+        let c = run_reg_flags(
+            &[0xEE, 0x0F],
+            Register::A,
+            0x0F,
+            &[Flag::N, Flag::H, Flag::C],
+        ); // XOR $0F
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+    }
+
+    #[test]
+    fn op_and() {
+        let c = run_reg_flags(&[0xE6, 0x0F], Register::A, 0x5A, &[Flag::N, Flag::C]); // AND $0F
+        assert_eq!(c.regs.a, 0x0A);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+
+        let mut c = cpu(&[0xA0]); // AND B
+        c.regs.a = 0xF0;
+        c.regs.b = 0x0F;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_or() {
+        let c = run_reg_flags(&[0xF6, 0x0F], Register::A, 0x50, &[Flag::H, Flag::C]); // OR $0F
+        assert_eq!(c.regs.a, 0x5F);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+
+        let mut c = cpu(&[0xB6]); // OR (HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0x00);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert_eq!(c.cycles, 8);
+    }
+
+    #[test]
+    fn op_cp_equal() {
+        let c = run_reg(&[0xFE, 0x42], Register::A, 0x42); // CP $42
+        assert_eq!(c.regs.a, 0x42);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+
+        let c = run_reg(&[0xFE, 0x43], Register::A, 0x42); // CP $43
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::C));
+    }
+
+    #[test]
+    fn op_cpl() {
+        let c = run_reg_flags(&[0x2F], Register::A, 0x5A, &[Flag::Z, Flag::C]); // CPL
+        assert_eq!(c.regs.a, 0xA5);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::H));
+    }
+
+    #[test]
+    fn op_scf() {
+        let c = run_flags(&[0x37], &[Flag::N, Flag::H, Flag::Z]); // SCF
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_ccf_ccf() {
+        for start in [false, true] {
+            let mut c = cpu(&[
+                0x3F, // CCF
+                0x3F, // CCF
+            ]);
+            c.regs
+                .write_flags(&[(Flag::C, start), (Flag::N, true), (Flag::H, true)]);
+            cpu_run(&mut c);
+            assert_eq!(c.regs.test_flag(Flag::C), !start);
+            assert!(!c.regs.test_flag(Flag::N));
+            assert!(!c.regs.test_flag(Flag::H));
+            cpu_run(&mut c);
+            assert_eq!(c.regs.test_flag(Flag::C), start);
+        }
+    }
+
+    #[test]
+    fn op_xor_reg() {
+        let mut c = cpu(&[0xA8]); // XOR B
+        c.regs.a = 0x55;
+        c.regs.b = 0xAA;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0xFF);
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let mut c = cpu(&[0xA8]); // XOR B
+        c.regs.a = 0xAA;
+        c.regs.b = 0xAA;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_ld_indreg8_reg() {
+        let mut c = cpu(&[0xE2]); // LD (C),A
+        c.regs.c = 0x11;
+        c.regs.a = 0x5A;
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0xFF11), 0x5A);
+    }
+
+    #[test]
+    fn op_ld_indreg16_reg() {
+        let mut c = cpu(&[0x70]); // LD (HL),B
+        (c.regs.h, c.regs.l) = (0x11, 0x22);
+        c.regs.b = 0x5A;
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0x1122), 0x5A);
+    }
+
+    #[test]
+    fn op_ld_indreg16_dec_reg() {
+        let mut c = cpu(&[0x32]); // LD (HL-),A
+        (c.regs.h, c.regs.l) = (0x11, 0x22);
+        c.regs.a = 0x5A;
+        cpu_run(&mut c);
+        assert_eq!((c.regs.h, c.regs.l), (0x11, 0x21));
+        assert_eq!(c.bus.read(0x1122), 0x5A);
+    }
+
+    #[test]
+    fn op_ld_indreg16_inc_reg() {
+        let mut c = cpu(&[0x22]); // LD (HL+),A
+        (c.regs.h, c.regs.l) = (0x11, 0x22);
+        c.regs.a = 0x5A;
+        cpu_run(&mut c);
+        assert_eq!((c.regs.h, c.regs.l), (0x11, 0x23));
+        assert_eq!(c.bus.read(0x1122), 0x5A);
+    }
+
+    #[test]
+    fn op_ld_indimm8_reg() {
+        let c = run_reg(&[0xE0, 0x5A], Register::A, 0x12);
+        assert_eq!(c.bus.read(0xFF5A), 0x12);
+    }
+
+    #[test]
+    fn op_ld_indimm16_reg() {
+        let c = run_reg(&[0xEA, 0x55, 0xAA], Register::A, 0x12);
+        assert_eq!(c.bus.read(0xAA55), 0x12);
+    }
+
+    #[test]
+    fn op_ld_reg_reg() {
+        let mut c = cpu(&[0x78]); // LD A,B
+        c.regs.b = 0x55;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x55);
+    }
+
+    #[test]
+    fn op_ld_reg_indreg16() {
+        let mut c = cpu(&[0x1A]); // LD A,(DE)
+        c.regs.write(Register::DE, 0x1122).unwrap();
+        c.bus.write(0x1122, 0x5A);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5A);
+    }
+
+    #[test]
+    fn op_ld_reg_indimm8() {
+        let mut c = cpu(&[0xF0, 0x22]); // LD A,(imm8)
+        c.bus.write(0xFF22, 0x5A);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5A);
+    }
+
+    #[test]
+    fn op_set_reg() {
+        let c = run(&[0xCB, 0xC7]); // SET 0,A
+        assert_eq!(c.regs.a, 0x01);
+
+        let c = run(&[0xCB, 0xFB]); // SET 7,E
+        assert_eq!(c.regs.e, 0x80);
+
+        let mut c = cpu(&[0xCB, 0xE2]); // SET 4,D
+        c.regs.d = 0x0F;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.d, 0x1F);
+    }
+
+    #[test]
+    fn op_res_reg() {
+        let mut c = cpu(&[0xCB, 0x80]); // RES 0,B
+        c.regs.b = 0xFF;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.b, 0xFE);
+
+        let c = run(&[0xCB, 0x81]); // RES 0,C
+        assert_eq!(c.regs.c, 0x00);
+    }
+
+    #[test]
+    fn op_bit_reg() {
+        let mut c = cpu(&[0xCB, 0x47]); // BIT 0,A
+        c.regs.a = 0x01;
+        cpu_run(&mut c);
+        assert!(
+            !c.regs.test_flag(Flag::Z) && c.regs.test_flag(Flag::H) && !c.regs.test_flag(Flag::N)
+        );
+
+        let c = run(&[0xCB, 0x61]); // BIT 4,C
+        assert!(
+            c.regs.test_flag(Flag::Z) && c.regs.test_flag(Flag::H) && !c.regs.test_flag(Flag::N)
+        );
+    }
+
+    #[test]
+    fn op_set_res_indreg() {
+        let mut c = cpu(&[0xCB, 0xFE]); // SET 7,(HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0x01);
+        c.regs.write_flags(&[(Flag::Z, true), (Flag::C, true)]);
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0x55AA), 0x81);
+        assert_eq!(c.regs.pc, 2);
+        assert_eq!(c.get_cycles(), 16);
+        assert!(c.regs.test_flag(Flag::Z) && c.regs.test_flag(Flag::C));
+
+        let mut c = cpu(&[0xCB, 0x86]); // RES 0,(HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0xFF);
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0x55AA), 0xFE);
+        assert_eq!(c.get_cycles(), 16);
+    }
+
+    #[test]
+    fn op_res_set_roundtrip() {
+        let mut c = cpu(&[
+            0xCB, 0x87, // RES 0,A
+            0xCB, 0xC7, // SET 0,A
+        ]);
+        c.regs.a = 0x5B;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5A);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x5B);
+        assert_eq!(c.regs.pc, 4);
+        assert_eq!(c.get_cycles(), 16);
+    }
+
+    #[test]
+    fn op_bit_flags() {
+        let mut c = cpu(&[0xCB, 0x7C]); // BIT 7,H
+        c.regs.h = 0x80;
+        c.regs
+            .write_flags(&[(Flag::Z, true), (Flag::N, true), (Flag::C, true)]);
+        cpu_run(&mut c);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert_eq!(c.get_cycles(), 8);
+    }
+
+    #[test]
+    fn op_bit_indreg() {
+        let mut c = cpu(&[0xCB, 0x46]); // BIT 0,(HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0xFE);
+        cpu_run(&mut c);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert_eq!(c.bus.read(0x55AA), 0xFE);
+        assert_eq!(c.get_cycles(), 12);
+    }
+
+    #[test]
+    fn op_prefix_cb() {
+        use crate::gameboy::cpu::instructions::INSTRUCTIONS;
+
+        // Dispatch a bare prefix through the 0xCB table
+        let mut c = cpu(&[0xCB, 0xC7]); // SET 0,A
+        let cbinstr = Instruction::decode(&mut [0xCB, 0xC7].into_iter(), 0).unwrap();
+        let bare = Instruction {
+            def: &INSTRUCTIONS[0xCB],
+            len: 1,
+            raw: [0xCB, 0, 0],
+            ..cbinstr
+        };
+        let res = c.dispatch(&bare).unwrap();
+        assert_eq!(c.regs.a, 0x01);
+        assert_eq!(res.pc, 2);
+        assert_eq!(res.cycles, 8);
+    }
+
+    #[test]
+    fn op_jr() {
+        let c = run(&[0x18, (-10_i8 - 2) as u8]); // JR -10
+        assert_eq!(c.regs.pc, -10_i16 as u16);
+
+        let c = run(&[0x18, 10 - 2]); // JR 10
+        assert_eq!(c.regs.pc, 10);
+    }
+
+    #[test]
+    fn op_jr_not_taken() {
+        let mut c = cpu(&[]);
+        c.bus.write_slice(&[0x28, 0x10], 0x1000); // JR Z 0x10
+        c.regs.pc = 0x1000;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x1002);
+        assert_eq!(c.cycles, 8);
+
+        let mut c = cpu(&[]);
+        c.bus.write_slice(&[0x28, 0x10], 0x1000); // JR Z 0x10
+        c.regs.pc = 0x1000;
+        c.regs.write_flags(&[(Flag::Z, true)]);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x1012);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_jr_nz() {
+        let c = run(&[0x20, 10 - 2]); // JR NZ 10
+        assert_eq!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 12);
+
+        let c = run_flags(
+            &[0x20, 10 - 2], // JR NZ 10
+            &[Flag::Z],
+        );
+        assert_ne!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 8);
+    }
+
+    #[test]
+    fn op_jr_z() {
+        let c = run(&[0x28, 10 - 2]); // JR Z 10
+        assert_ne!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 8);
+
+        let c = run_flags(
+            &[0x28, 10 - 2], // JR Z 10
+            &[Flag::Z],
+        );
+        assert_eq!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_jr_nc() {
+        let c = run(&[0x30, 10 - 2]); // JR NC 10
+        assert_eq!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 12);
+
+        let c = run_flags(
+            &[0x30, 10 - 2], // JR NC 10
+            &[Flag::C],
+        );
+        assert_ne!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 8);
+    }
+
+    #[test]
+    fn op_jr_c() {
+        let c = run(&[0x38, 10 - 2]); // JR C 10
+        assert_ne!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 8);
+
+        let c = run_flags(
+            &[0x38, 10 - 2], // JR C 10
+            &[Flag::C],
+        );
+        assert_eq!(c.regs.pc, 10);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_jr_nz_cycles() {
+        let taken = run(&[0x20, 0x00]); // JR NZ 0
+        let not_taken = run_flags(&[0x20, 0x00], &[Flag::Z]); // JR NZ 0
+        assert_eq!(taken.regs.pc, not_taken.regs.pc);
+        assert_ne!(taken.get_cycles(), not_taken.get_cycles());
+        assert_eq!(taken.get_cycles(), 12);
+        assert_eq!(not_taken.get_cycles(), 8);
+    }
+
+    #[test]
+    fn op_jp() {
+        let c = run(&[0xC3, 0x34, 0x12]); // JP $1234
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 16);
+
+        let c = run_reg(&[0xE9], Register::HL, 0x1234); // JP (HL)
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 4);
+    }
+
+    #[test]
+    fn op_jp_c() {
+        let c = run(&[0xDA, 0x34, 0x12]); // JP C,$1234
+        assert_eq!(c.regs.pc, 3);
+        assert_eq!(c.cycles, 12);
+
+        let c = run_flags(&[0xDA, 0x34, 0x12], &[Flag::C]); // JP C,$1234
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 16);
+    }
+
+    #[test]
+    fn op_jp_nc() {
+        let c = run(&[0xD2, 0x34, 0x12]); // JP NC,$1234
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 16);
+
+        let c = run_flags(&[0xD2, 0x34, 0x12], &[Flag::C]); // JP NC,$1234
+        assert_eq!(c.regs.pc, 3);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_jp_z() {
+        let c = run(&[0xCA, 0x34, 0x12]); // JP Z,$1234
+        assert_eq!(c.regs.pc, 3);
+        assert_eq!(c.cycles, 12);
+
+        let c = run_flags(&[0xCA, 0x34, 0x12], &[Flag::Z]); // JP Z,$1234
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 16);
+    }
+
+    #[test]
+    fn op_jp_nz() {
+        let c = run(&[0xC2, 0x34, 0x12]); // JP NZ,$1234
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 16);
+
+        let c = run_flags(&[0xC2, 0x34, 0x12], &[Flag::Z]); // JP NZ,$1234
+        assert_eq!(c.regs.pc, 3);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_inc_reg() {
+        let c = run_reg(&[0x3C], Register::A, 0x00);
+        assert_eq!(c.regs.a, 1);
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let c = run_reg(&[0x3C], Register::A, 0x0F);
+        assert_eq!(c.regs.a, 0x10);
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let c = run_reg(&[0x3C], Register::A, 0xFF);
+        assert_eq!(c.regs.a, 0);
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+    }
+
+    #[test]
+    fn op_call() {
+        let mut c = cpu(&[]);
+        c.bus.write_slice(&[0xCD, 0x34, 0x12], 0x8000);
+        c.regs.pc = 0x8000;
+        c.regs.sp = 0xFFFE;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.regs.sp, 0xFFFC);
+        assert_eq!(c.bus.read16(0xFFFC), 0x8003);
+
+        // Wrapping past 0
+        let c = run(&[0xCD, 0x34, 0x12]);
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.regs.sp, 0xFFFE);
+        assert_eq!(c.bus.read16(0xFFFE), 0x0003);
+    }
+
+    #[test]
+    fn op_call_c() {
+        let c = run(&[0xDC, 0x34, 0x12]);
+        assert_ne!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 12);
+
+        let c = run_flags(&[0xDC, 0x34, 0x12], &[Flag::C]);
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 24);
+    }
+
+    #[test]
+    fn op_call_nc() {
+        let c = run(&[0xD4, 0x34, 0x12]);
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 24);
+
+        let c = run_flags(&[0xD4, 0x34, 0x12], &[Flag::C]);
+        assert_ne!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_call_z() {
+        let c = run(&[0xCC, 0x34, 0x12]);
+        assert_ne!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 12);
+
+        let c = run_flags(&[0xCC, 0x34, 0x12], &[Flag::Z]);
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 24);
+    }
+
+    #[test]
+    fn op_call_nz() {
+        let c = run(&[0xC4, 0x34, 0x12]);
+        assert_eq!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 24);
+
+        let c = run_flags(&[0xC4, 0x34, 0x12], &[Flag::Z]);
+        assert_ne!(c.regs.pc, 0x1234);
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_push() {
+        let c = run_reg(&[0xC5], Register::BC, 0xABCD);
+        assert_ne!(c.regs.sp, 0);
+        assert_eq!(c.bus.read16(c.regs.sp), 0xABCD);
+    }
+
+    #[test]
+    fn op_push_timing() {
+        let (mut c, trace) = cpu_traced(&[0xC5]); // PUSH BC
+        c.regs.write(Register::BC, 0xABCD).unwrap();
+        c.regs.sp = 0xD000;
+        cpu_run(&mut c);
+        assert_eq!(c.cycles, 16);
+        assert_eq!(c.regs.sp, 0xCFFE);
+        assert_eq!(
+            data_accesses(&trace, 1),
+            vec![(true, 0xCFFF, 0xAB), (true, 0xCFFE, 0xCD)]
+        );
+    }
+
+    #[test]
+    fn op_pop_timing() {
+        let (mut c, trace) = cpu_traced(&[0xC1]); // POP BC
+        c.bus.write16(0xCFFE, 0xABCD);
+        c.regs.sp = 0xCFFE;
+        trace.borrow_mut().clear();
+        cpu_run(&mut c);
+        assert_eq!(c.cycles, 12);
+        assert_eq!(c.regs.sp, 0xD000);
+        assert_eq!(c.regs.read16(Register::BC).unwrap(), 0xABCD);
+        assert_eq!(
+            data_accesses(&trace, 1),
+            vec![(false, 0xCFFE, 0xCD), (false, 0xCFFF, 0xAB)]
+        );
+    }
+
+    fn stack_guard_events(c: &mut CPU, low: u16, high: u16) -> Rc<RefCell<Vec<StackEvent>>> {
+        let events = Rc::new(RefCell::new(vec![]));
+        let ev = Rc::clone(&events);
+        c.set_stack_guard(low, high, move |e| ev.borrow_mut().push(e));
+        events
+    }
+
+    #[test]
+    fn stack_guard_overflow() {
+        // Runaway recursion
+        let mut c = cpu(&[0xCD, 0x00, 0x00]); // CALL 0x0000
+        c.regs.sp = 0xD000;
+        let events = stack_guard_events(&mut c, 0xCF00, 0xD000);
+        for _ in 0..0x80 {
+            cpu_run(&mut c);
+        }
+        assert_eq!(c.regs.sp, 0xCF00);
+        assert!(events.borrow().is_empty());
+
+        for _ in 0..0x10 {
+            cpu_run(&mut c);
+        }
+        assert_eq!(*events.borrow(), vec![StackEvent::Overflow(0xCEFE)]);
+    }
+
+    #[test]
+    fn stack_guard_underflow() {
+        let mut c = cpu(&[0xC9]); // RET
+        c.regs.sp = 0xD000;
+        let events = stack_guard_events(&mut c, 0xCF00, 0xD000);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.sp, 0xD002);
+        assert_eq!(*events.borrow(), vec![StackEvent::Underflow(0xD002)]);
+    }
+
+    #[test]
+    fn stack_guard_wrap() {
+        let mut c = cpu(&[0xC5]); // PUSH BC
+        c.regs.sp = 0x0001;
+        let events = stack_guard_events(&mut c, 0x0000, 0xFFFF);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.sp, 0xFFFF);
+        assert_eq!(*events.borrow(), vec![StackEvent::Wrapped(0xFFFF)]);
+
+        let mut c = cpu(&[0xC1]); // POP BC
+        c.regs.sp = 0xFFFE;
+        let events = stack_guard_events(&mut c, 0x0000, 0xFFFF);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.sp, 0x0000);
+        assert_eq!(*events.borrow(), vec![StackEvent::Wrapped(0x0000)]);
+    }
+
+    #[test]
+    fn run_cycles() {
+        let mut c = cpu(&[0x00, 0xC3, 0x00, 0x00]); // NOP; JP $0000
+        assert_eq!(c.run_cycles(100).unwrap(), 100);
+        assert_eq!(c.get_cycles(), 100);
+        // Stops after the instruction crossing the limit
+        assert_eq!(c.run_cycles(10).unwrap(), 20);
+        assert_eq!(c.regs.pc, 0x0000);
+    }
+
+    #[test]
+    fn profiler() {
+        // CALL 0x0004; NOP; NOP; RET
+        let mut c = cpu(&[0xCD, 0x04, 0x00, 0x00, 0x00, 0xC9]);
+        c.regs.sp = 0xD000;
+        c.enable_profiler();
+        for _ in 0..4 {
+            cpu_run(&mut c);
+        }
+        assert_eq!(c.regs.pc, 0x0004);
+
+        let p = c.take_profiler().unwrap();
+        assert!(c.profiler().is_none());
+        let at = |addr| Location { bank: 0, addr };
+        assert_eq!(p.pc_stats(at(0x0000)).cycles, 24);
+        assert_eq!(p.pc_stats(at(0x0004)).count, 1);
+        assert_eq!(p.function_cycles(at(0x0004)), (20, 20));
+        assert_eq!(p.folded(), "main 28\nmain;00:0004 20\n");
+    }
+
+    #[test]
+    fn stack_guard_disabled() {
+        let mut c = cpu(&[0xC5]); // PUSH BC
+        c.regs.sp = 0x0001;
+        let events = stack_guard_events(&mut c, 0x0000, 0xFFFF);
+        c.clear_stack_guard();
+        cpu_run(&mut c);
+        assert_eq!(c.regs.sp, 0xFFFF);
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn op_pop() {
+        let mut c = cpu(&[0xC1]);
+        c.stack_push(0xABCD);
+        assert_ne!(c.regs.sp, 0);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.sp, 0);
+        assert_eq!(c.regs.read16(Register::BC).unwrap(), 0xABCD);
+    }
+
+    #[test]
+    fn op_pop_af() {
+        let mut c = cpu(&[0xF1]); // POP AF
+        c.stack_push(0x12FF);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.read16(Register::AF).unwrap(), 0x12F0);
+    }
+
+    #[test]
+    fn op_push_pop_transfer() {
+        let mut c = cpu(&[
+            0xC5, // PUSH BC
+            0xD1, // POP DE
+        ]);
+        c.regs.sp = 0xFFFE;
+        c.regs.write(Register::BC, 0xABCD).unwrap();
+        cpu_run(&mut c);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.read16(Register::DE).unwrap(), 0xABCD);
+        assert_eq!(c.regs.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn op_call_ret_roundtrip() {
+        let mut c = cpu(&[]);
+        c.bus.write_slice(&[0xCD, 0x00, 0x90], 0x8000); // CALL $9000
+        c.bus.write(0x9000, 0xC9); // RET
+        c.regs.pc = 0x8000;
+        c.regs.sp = 0xFFFE;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x9000);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x8003);
+        assert_eq!(c.regs.sp, 0xFFFE);
+        assert_eq!(c.cycles, 24 + 16);
+    }
+
+    #[test]
+    fn op_rst() {
+        let mut c = cpu(&[]);
+        c.bus.write(0x8000, 0xEF); // RST 28H
+        c.regs.pc = 0x8000;
+        c.regs.sp = 0xFFFE;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x0028);
+        assert_eq!(c.regs.sp, 0xFFFC);
+        assert_eq!(c.bus.read16(0xFFFC), 0x8001);
+        assert_eq!(c.cycles, 16);
+
+        let c = run(&[0xFF]); // RST 38H
+        assert_eq!(c.regs.pc, 0x0038);
+    }
+
+    #[test]
+    fn op_reti() {
+        let mut c = cpu(&[0xD9]); // RETI
+        c.stack_push(0xABCD);
+        assert!(!c.ime);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0xABCD);
+        assert!(c.ime);
+        assert_eq!(c.cycles, 16);
+    }
+
+    #[test]
+    fn op_rl_indreg() {
+        let mut c = cpu(&[0xCB, 0x16]); // RL (HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.regs.write_flags(&[(Flag::C, true)]);
+        c.bus.write(0x55AA, 0x80);
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0x55AA), 0x01);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert_eq!(c.cycles, 16);
+    }
+
+    #[test]
+    fn op_rr() {
+        let c = run_reg_flags(&[0xCB, 0x18], Register::B, 0x01, &[Flag::N, Flag::H]); // RR B
+        assert_eq!(c.regs.b, 0x00);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::H));
+
+        let c = run_reg_flags(&[0xCB, 0x18], Register::B, 0x00, &[Flag::C]); // RR B
+        assert_eq!(c.regs.b, 0x80);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_rrc() {
+        let c = run_reg(&[0xCB, 0x09], Register::C, 0x01); // RRC C
+        assert_eq!(c.regs.c, 0x80);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg_flags(&[0xCB, 0x09], Register::C, 0x00, &[Flag::C]); // RRC C
+        assert_eq!(c.regs.c, 0x00);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_sla() {
+        let c = run_reg_flags(&[0xCB, 0x27], Register::A, 0x81, &[Flag::C]); // SLA A
+        assert_eq!(c.regs.a, 0x02);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0xCB, 0x27], Register::A, 0x80); // SLA A
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_sra() {
+        let c = run_reg(&[0xCB, 0x2F], Register::A, 0x80); // SRA A
+        assert_eq!(c.regs.a, 0xC0);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0xCB, 0x2F], Register::A, 0x01); // SRA A
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_srl() {
+        let c = run_reg(&[0xCB, 0x3F], Register::A, 0x01); // SRL A
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::Z));
+
+        let c = run_reg_flags(&[0xCB, 0x3F], Register::A, 0x80, &[Flag::C]); // SRL A
+        assert_eq!(c.regs.a, 0x40);
+        assert!(!c.regs.test_flag(Flag::C));
+    }
+
+    #[test]
+    fn op_swap() {
+        let c = run_reg_flags(
+            &[0xCB, 0x37],
+            Register::A,
+            0xAB,
+            &[Flag::C, Flag::N, Flag::H],
+        ); // SWAP A
+        assert_eq!(c.regs.a, 0xBA);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let mut c = cpu(&[0xCB, 0x36]); // SWAP (HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0x00);
+        cpu_run(&mut c);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert_eq!(c.cycles, 16);
+    }
+
+    #[test]
+    fn op_rl_reg() {
+        let c = run_reg(&[0xCB, 0x10], Register::B, 0x80);
+        assert_eq!(c.regs.b, 0x00);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0xCB, 0x10], Register::B, 0x40);
+        assert_eq!(c.regs.b, 0x80);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0xCB, 0x10], Register::B, 0x00);
+        assert_eq!(c.regs.b, 0x00);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_rla() {
+        let c = run_reg(&[0x17], Register::A, 0x80);
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0x17], Register::A, 0x40);
+        assert_eq!(c.regs.a, 0x80);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0x17], Register::A, 0x00);
+        assert_eq!(c.regs.a, 0x00);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg_flags(&[0x17], Register::A, 0x95, &[Flag::C]);
+        assert_eq!(c.regs.a, 0x2B);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_rla_carry_in() {
+        let c = run_reg_flags(&[0x17], Register::A, 0x00, &[Flag::C, Flag::Z]); // RLA
+        assert_eq!(c.regs.a, 0x01);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_rlca() {
+        let c = run_reg_flags(&[0x07], Register::A, 0x85, &[Flag::N, Flag::H]); // RLCA
+        assert_eq!(c.regs.a, 0x0B);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        // Carry is not rotated in, Z stays clear on zero
+        let c = run_reg_flags(&[0x07], Register::A, 0x00, &[Flag::C]); // RLCA
+        assert_eq!(c.regs.a, 0x00);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_rrca() {
+        let c = run_reg(&[0x0F], Register::A, 0x0B); // RRCA
+        assert_eq!(c.regs.a, 0x85);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg_flags(&[0x0F], Register::A, 0x00, &[Flag::C]); // RRCA
+        assert_eq!(c.regs.a, 0x00);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_rra() {
+        let c = run_reg(&[0x1F], Register::A, 0x01); // RRA
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let c = run_reg_flags(&[0x1F], Register::A, 0x00, &[Flag::C]); // RRA
+        assert_eq!(c.regs.a, 0x80);
+        assert!(!c.regs.test_flag(Flag::C));
+    }
+
+    #[test]
+    fn op_rlc_reg() {
+        let c = run_reg(&[0xCB, 0x00], Register::B, 0x80);
+        assert_eq!(c.regs.b, 0x01);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0xCB, 0x00], Register::B, 0x40);
+        assert_eq!(c.regs.b, 0x80);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0xCB, 0x00], Register::B, 0x00);
+        assert_eq!(c.regs.b, 0x00);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_dec_8b() {
+        let c = run_reg(&[0x3D], Register::A, 0x00);
+        assert_eq!(c.regs.a, 0xFF);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+
+        let c = run_reg(&[0x3D], Register::A, 0x01);
+        assert_eq!(c.regs.a, 0x00);
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::Z));
+    }
+
+    #[test]
+    fn op_dec_8b_carry() {
+        let mut c = cpu(&[0x05]); // DEC B
+        c.regs.b = 0x01;
+        c.regs.write_flags(&[(Flag::C, true)]);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.b, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::N));
+        assert!(c.regs.test_flag(Flag::C));
+    }
+
+    #[test]
+    fn op_inc_8b_halfcarry() {
+        let c = run_reg_flags(&[0x0C], Register::C, 0x0F, &[Flag::C, Flag::N]); // INC C
+        assert_eq!(c.regs.c, 0x10);
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::C));
+    }
+
+    #[test]
+    fn op_inc_dec_indreg() {
+        let mut c = cpu(&[0x34]); // INC (HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0xFF);
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0x55AA), 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert_eq!(c.cycles, 12);
+
+        let mut c = cpu(&[0x35]); // DEC (HL)
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0x10);
+        cpu_run(&mut c);
+        assert_eq!(c.bus.read(0x55AA), 0x0F);
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::N));
+        assert_eq!(c.cycles, 12);
+    }
+
+    #[test]
+    fn op_inc_16b_flags() {
+        let mut c = cpu(&[0x03]); // INC BC
+        c.regs.write(Register::BC, 0xFFFF).unwrap();
+        c.regs.write_flags(&[(Flag::N, true), (Flag::C, true)]);
+        let flags = c.regs.read8(Register::F).unwrap();
+        cpu_run(&mut c);
+        assert_eq!(c.regs.read16(Register::BC).unwrap(), 0x0000);
+        assert_eq!(c.regs.read8(Register::F).unwrap(), flags);
+        assert_eq!(c.cycles, 8);
+    }
+
+    #[test]
+    fn op_dec_16b() {
+        let c = run_reg(&[0x2B], Register::HL, 0x1200); // DEC HL
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x11FF);
+        assert_eq!(c.regs.read8(Register::F).unwrap(), 0);
+        assert_eq!(c.cycles, 8);
+
+        let c = run_reg(&[0x3B], Register::SP, 0x0000); // DEC SP
+        assert_eq!(c.regs.sp, 0xFFFF);
+    }
+
+    #[test]
+    fn op_inc_16b() {
+        let c = run_reg(&[0x23], Register::HL, 0x00);
+        assert_eq!(c.regs.l, 0x01);
+        assert_eq!(c.regs.h, 0x00);
+
+        let c = run_reg(&[0x23], Register::HL, 0xFFFF);
+        assert_eq!(c.regs.l, 0x00);
+        assert_eq!(c.regs.h, 0x00);
+    }
+
+    #[test]
+    fn op_ret() {
+        let mut c = cpu(&[0xC9]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0xABCD);
+    }
+
+    #[test]
+    fn op_ret_z() {
+        let mut c = cpu(&[0xC8]);
+        c.regs.write_flags(&[(Flag::Z, true)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 20);
+
+        let mut c = cpu(&[0xC8]);
+        c.regs.write_flags(&[(Flag::Z, false)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_ne!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 8);
+    }
+
+    #[test]
+    fn op_ret_nz() {
+        let mut c = cpu(&[0xC0]);
+        c.regs.write_flags(&[(Flag::Z, true)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_ne!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 8);
+
+        let mut c = cpu(&[0xC0]);
+        c.regs.write_flags(&[(Flag::Z, false)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 20);
+    }
+
+    #[test]
+    fn op_ret_c() {
+        let mut c = cpu(&[0xD8]);
+        c.regs.write_flags(&[(Flag::C, true)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 20);
+
+        let mut c = cpu(&[0xD8]);
+        c.regs.write_flags(&[(Flag::C, false)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_ne!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 8);
+    }
+
+    #[test]
+    fn op_ret_nc() {
+        let mut c = cpu(&[0xD0]);
+        c.regs.write_flags(&[(Flag::C, true)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_ne!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 8);
+
+        let mut c = cpu(&[0xD0]);
+        c.regs.write_flags(&[(Flag::C, false)]);
+        c.stack_push(0xABCD);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0xABCD);
+        assert_eq!(c.cycles, 20);
+    }
+
+    #[test]
+    fn op_cp_imm8() {
+        let c = run_reg(&[0xFE, 0x2F], Register::A, 0x3C);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let c = run_reg(&[0xFE, 0x3C], Register::A, 0x3C);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let c = run_reg(&[0xFE, 0x40], Register::A, 0x3C);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+    }
+
+    #[test]
+    fn op_cp_reg8() {
+        let c = run_reg(&[0xB8], Register::B, 0x3C);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let c = run_reg(&[0xB8], Register::B, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+    }
+
+    #[test]
+    fn op_cp_indreg16() {
+        let mut c = cpu(&[0xBE]);
+        (c.regs.h, c.regs.l) = (0x08, 0x00);
+        c.bus.write(0x0800, 0x3C);
+        cpu_run(&mut c);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let c = run_reg(&[0xBE], Register::HL, 0x0800);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+    }
+
+    #[test]
+    fn op_sub_reg8() {
+        let mut c = cpu(&[0x90]); // SUB B
+        c.regs.write8(Register::A, 0x3E).unwrap();
+        c.regs.write8(Register::B, 0x3E).unwrap();
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let mut c = cpu(&[0x90]); // SUB B
+        c.regs.write8(Register::A, 0x3E).unwrap();
+        c.regs.write8(Register::B, 0x0F).unwrap();
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x2F);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let mut c = cpu(&[0x90]); // SUB B
+        c.regs.write8(Register::A, 0x3E).unwrap();
+        c.regs.write8(Register::B, 0x40).unwrap();
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0xFE);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+    }
+
+    #[test]
+    fn op_add_imm8() {
+        let c = run_reg(&[0xC6, 0x01], Register::A, 0x0F); // ADD A,$01
+        assert_eq!(c.regs.a, 0x10);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let c = run_reg_flags(&[0xC6, 0x01], Register::A, 0xFF, &[Flag::N]); // ADD A,$01
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
     }
 
-    fn cpu_run(cpu: &mut CPU) {
-        cpu.step().unwrap();
+    #[test]
+    fn op_add_16b() {
+        let mut c = cpu(&[0x09]); // ADD HL,BC
+        c.regs.write(Register::HL, 0x0FFF).unwrap();
+        c.regs.write(Register::BC, 0x0001).unwrap();
+        c.regs.write_flags(&[(Flag::Z, true), (Flag::N, true)]);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x1000);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let mut c = cpu(&[0x29]); // ADD HL,HL
+        c.regs.write(Register::HL, 0x8000).unwrap();
+        cpu_run(&mut c);
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x0000);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
     }
 
-    fn run(code: &[u8]) -> CPU {
-        let mut cpu = cpu(code);
-        cpu_run(&mut cpu);
-        cpu
+    #[test]
+    fn op_add_sp() {
+        let c = run_reg_flags(&[0xE8, 0x01], Register::SP, 0x00FF, &[Flag::Z]); // ADD SP,1
+        assert_eq!(c.regs.sp, 0x0100);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let c = run_reg(&[0xE8, 0xFE], Register::SP, 0x1000); // ADD SP,-2
+        assert_eq!(c.regs.sp, 0x0FFE);
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
     }
 
-    fn run_reg(code: &[u8], reg: Register, val: u16) -> CPU {
-        let mut cpu = cpu(code);
-        cpu.regs.write(reg, val).unwrap();
-        cpu_run(&mut cpu);
-        cpu
+    #[test]
+    fn op_adc() {
+        let c = run_reg(&[0xCE, 0x01], Register::A, 0x0E); // ADC A,$01
+        assert_eq!(c.regs.a, 0x0F);
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+
+        // Incoming carry flips the half-carry
+        let c = run_reg_flags(&[0xCE, 0x01], Register::A, 0x0E, &[Flag::C]); // ADC A,$01
+        assert_eq!(c.regs.a, 0x10);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let c = run_reg_flags(&[0xCE, 0x00], Register::A, 0xFF, &[Flag::C]); // ADC A,$00
+        assert_eq!(c.regs.a, 0x00);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
     }
 
-    fn run_flags(code: &[u8], flags: &[Flag]) -> CPU {
-        let mut cpu = cpu(code);
-        cpu.regs.write_flags(
-            &flags
-                .into_iter()
-                .map(|&f| (f, true))
-                .collect::<Vec<(Flag, bool)>>(),
-        );
-        cpu_run(&mut cpu);
-        cpu
+    #[test]
+    fn op_adc_chain() {
+        // 16-bit add of 0x00FF + 0x0001 through A, using ADC for the MSB
+        let mut c = cpu(&[
+            0x7D, // LD A,L
+            0x81, // ADD A,C
+            0x6F, // LD L,A
+            0x7C, // LD A,H
+            0x88, // ADC A,B
+            0x67, // LD H,A
+        ]);
+        c.regs.write(Register::HL, 0x0FFF).unwrap();
+        c.regs.write(Register::BC, 0x0001).unwrap();
+        for _ in 0..6 {
+            cpu_run(&mut c);
+        }
+        assert_eq!(c.regs.read16(Register::HL).unwrap(), 0x1000);
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
     }
 
-    fn run_reg_flags(code: &[u8], reg: Register, val: u16, flags: &[Flag]) -> CPU {
-        let mut cpu = cpu(code);
-        cpu.regs.write(reg, val).unwrap();
-        cpu.regs.write_flags(
-            &flags
-                .into_iter()
-                .map(|&f| (f, true))
-                .collect::<Vec<(Flag, bool)>>(),
-        );
-        cpu_run(&mut cpu);
-        cpu
+    #[test]
+    fn op_adc_indreg() {
+        let mut c = cpu(&[0x8E]); // ADC A,(HL)
+        c.regs.write8(Register::A, 0x01).unwrap();
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.regs.write_flags(&[(Flag::C, true)]);
+        c.bus.write(0x55AA, 0x0F);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x11);
+        assert!(c.regs.test_flag(Flag::H));
     }
+
     #[test]
-    fn op_ld_reg_imm16() {
-        let cpu = run(&[0x31, 0x34, 0x12]); // LD SP,0x1234
-        assert_eq!(cpu.regs.sp, 0x1234);
+    fn op_sub_imm8() {
+        let c = run_reg(&[0xD6, 0x01], Register::A, 0x10); // SUB $01
+        assert_eq!(c.regs.a, 0x0F);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let c = run_reg_flags(&[0xD6, 0x01], Register::A, 0x00, &[Flag::C]); // SUB $01
+        assert_eq!(c.regs.a, 0xFF);
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
     }
 
     #[test]
-    fn op_ld_reg_imm8() {
-        let cpu = run(&[0x3E, 0x12]); // LD A,0x12
-        assert_eq!(cpu.regs.a, 0x12);
+    fn op_sub_indreg() {
+        let mut c = cpu(&[0x96]); // SUB (HL)
+        c.regs.write8(Register::A, 0x3E).unwrap();
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0x3E);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::N));
     }
 
     #[test]
-    fn op_xor_reg() {
-        let mut c = cpu(&[0xA8]); // XOR B
-        c.regs.a = 0x55;
-        c.regs.b = 0xAA;
+    fn op_sbc_reg8() {
+        // Equal low nibbles, borrow from carry-in
+        let mut c = cpu(&[0x98]); // SBC A,B
+        c.regs.write8(Register::A, 0x10).unwrap();
+        c.regs.write8(Register::B, 0x00).unwrap();
+        c.regs.write_flags(&[(Flag::C, true)]);
         cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0xFF);
+        assert_eq!(c.regs.a, 0x0F);
         assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
 
-        let mut c = cpu(&[0xA8]); // XOR B
-        c.regs.a = 0xAA;
-        c.regs.b = 0xAA;
+        let mut c = cpu(&[0x98]); // SBC A,B
+        c.regs.write8(Register::A, 0x1F).unwrap();
+        c.regs.write8(Register::B, 0x0E).unwrap();
+        c.regs.write_flags(&[(Flag::C, true)]);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x10);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        let mut c = cpu(&[0x98]); // SBC A,B
+        c.regs.write8(Register::A, 0x01).unwrap();
+        c.regs.write8(Register::B, 0x00).unwrap();
+        c.regs.write_flags(&[(Flag::C, true)]);
         cpu_run(&mut c);
         assert_eq!(c.regs.a, 0x00);
         assert!(c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
     }
 
     #[test]
-    fn op_ld_indreg8_reg() {
-        let mut c = cpu(&[0xE2]); // LD (C),A
-        c.regs.c = 0x11;
-        c.regs.a = 0x5A;
-        cpu_run(&mut c);
-        assert_eq!(c.bus.read(0xFF11), 0x5A);
+    fn op_sbc_imm8() {
+        let c = run_reg_flags(&[0xDE, 0x3F], Register::A, 0x3F, &[Flag::C]); // SBC A,0x3F
+        assert_eq!(c.regs.a, 0xFF);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
     }
 
     #[test]
-    fn op_ld_indreg16_reg() {
-        let mut c = cpu(&[0x70]); // LD (HL),B
-        (c.regs.h, c.regs.l) = (0x11, 0x22);
-        c.regs.b = 0x5A;
+    fn op_add_reg() {
+        let mut c = cpu(&[0x80]); // ADD A,B
+        c.regs.write8(Register::A, 0x3A).unwrap();
+        c.regs.write8(Register::B, 0xC6).unwrap();
         cpu_run(&mut c);
-        assert_eq!(c.bus.read(0x1122), 0x5A);
+        assert_eq!(c.regs.a, 0);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let mut c = cpu(&[0x80]); // ADD A,B
+        c.regs.write8(Register::A, 0x3C).unwrap();
+        c.regs.write8(Register::B, 0xFF).unwrap();
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x3B);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
     }
 
     #[test]
-    fn op_ld_indreg16_dec_reg() {
-        let mut c = cpu(&[0x32]); // LD (HL-),A
-        (c.regs.h, c.regs.l) = (0x11, 0x22);
-        c.regs.a = 0x5A;
+    fn op_add_indreg() {
+        let mut c = cpu(&[0x86]); // ADD A,(HL)
+        c.regs.write8(Register::A, 0x3A).unwrap();
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0xC6);
         cpu_run(&mut c);
-        assert_eq!((c.regs.h, c.regs.l), (0x11, 0x21));
-        assert_eq!(c.bus.read(0x1122), 0x5A);
+        assert_eq!(c.regs.a, 0);
+        assert!(c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
+
+        let mut c = cpu(&[0x86]); // ADD A,(HL)
+        c.regs.write8(Register::A, 0x3C).unwrap();
+        c.regs.write(Register::HL, 0x55AA).unwrap();
+        c.bus.write(0x55AA, 0xFF);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x3B);
+        assert!(!c.regs.test_flag(Flag::Z));
+        assert!(c.regs.test_flag(Flag::H));
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(!c.regs.test_flag(Flag::N));
     }
 
     #[test]
-    fn op_ld_indreg16_inc_reg() {
-        let mut c = cpu(&[0x22]); // LD (HL+),A
-        (c.regs.h, c.regs.l) = (0x11, 0x22);
-        c.regs.a = 0x5A;
+    fn op_ei_di() {
+        let mut c = cpu(&[
+            0xFB, // EI
+            0x00, // NOP
+        ]);
         cpu_run(&mut c);
-        assert_eq!((c.regs.h, c.regs.l), (0x11, 0x23));
-        assert_eq!(c.bus.read(0x1122), 0x5A);
+        assert!(!c.ime);
+        cpu_run(&mut c);
+        assert!(c.ime);
+
+        let mut c = cpu(&[0xF3]); // DI
+        c.ime = true;
+        cpu_run(&mut c);
+        assert!(!c.ime);
+        assert_eq!(c.cycles, 4);
     }
 
     #[test]
-    fn op_ld_indimm8_reg() {
-        let c = run_reg(&[0xE0, 0x5A], Register::A, 0x12);
-        assert_eq!(c.bus.read(0xFF5A), 0x12);
+    fn int_ei_di_window() {
+        let mut c = cpu(&[
+            0xFB, // EI
+            0xF3, // DI
+            0x00, // NOP
+            0x00, // NOP
+        ]);
+        c.bus.write(0xFFFF, 0x01);
+        c.bus.write(0xFF0F, 0x01);
+        for _ in 0..4 {
+            cpu_run(&mut c);
+        }
+        assert!(!c.ime);
+        assert_eq!(c.regs.pc, 4);
+        assert_eq!(c.bus.read(0xFF0F), 0x01);
     }
 
     #[test]
-    fn op_ld_indimm16_reg() {
-        let c = run_reg(&[0xEA, 0x55, 0xAA], Register::A, 0x12);
-        assert_eq!(c.bus.read(0xAA55), 0x12);
+    fn int_after_ei_delay() {
+        let mut c = cpu(&[
+            0xFB, // EI
+            0x00, // NOP
+            0x00, // NOP
+        ]);
+        c.regs.sp = 0xFFF0;
+        c.bus.write(0xFFFF, 0x04);
+        c.bus.write(0xFF0F, 0x04);
+
+        // EI, then the instruction following it still executes
+        cpu_run(&mut c);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 2);
+
+        // Serviced right after
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x50);
+        assert_eq!(c.bus.read16(c.regs.sp), 2);
+        assert!(!c.ime);
+        assert_eq!(c.bus.read(0xFF0F), 0x00);
     }
 
     #[test]
-    fn op_ld_reg_reg() {
-        let mut c = cpu(&[0x78]); // LD A,B
-        c.regs.b = 0x55;
-        cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0x55);
+    fn int_dispatch() {
+        let (mut c, trace) = cpu_traced(&[]);
+        c.regs.pc = 0x1234;
+        c.regs.sp = 0xFFF0;
+        c.ime = true;
+        c.bus.write(0xFFFF, 0x1F);
+        c.bus.write(0xFF0F, 0x12);
+        trace.borrow_mut().clear();
+
+        assert!(c.service_interrupts());
+        assert_eq!(c.regs.pc, 0x48);
+        assert_eq!(c.cycles, 20);
+        assert_eq!(c.bus.read(0xFF0F), 0x10);
+
+        // PC high byte is pushed first
+        let writes: Vec<_> = trace.borrow().iter().filter(|a| a.0).copied().collect();
+        assert_eq!(
+            writes,
+            vec![
+                (true, 0xFF0F, 0x10),
+                (true, 0xFFEF, 0x12),
+                (true, 0xFFEE, 0x34)
+            ]
+        );
+
+        // IME is cleared, the next request waits
+        assert!(!c.service_interrupts());
     }
 
     #[test]
-    fn op_ld_reg_indreg16() {
-        let mut c = cpu(&[0x1A]); // LD A,(DE)
-        c.regs.write(Register::DE, 0x1122).unwrap();
-        c.bus.write(0x1122, 0x5A);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0x5A);
+    fn int_dispatch_order() {
+        use BusEvent::*;
+
+        let (mut c, events) = cpu_events(&[]);
+        c.ime = true;
+        c.bus.write(0xFFFF, 0x01);
+        c.bus.write(0xFF0F, 0x01);
+        events.borrow_mut().clear();
+
+        // Leaves out the peeks at IE and IF (reads on Testbus)
+        c.step().unwrap();
+        let events: Vec<_> = events
+            .borrow()
+            .iter()
+            .filter(|e| !matches!(e, Read(_)))
+            .copied()
+            .collect();
+        assert_eq!(
+            events,
+            [
+                Write(0xFF0F),
+                Tick(4),
+                Tick(4),
+                Tick(4),
+                Write(0xCFFF),
+                Tick(4),
+                Write(0xCFFE),
+                Tick(4),
+            ]
+        );
+    }
+
+    /// Testbus wrapper that requests the timer interrupt
+    /// once 'timer_at' cycles were ticked, and records IF
+    /// at every write to memory.
+    struct Timerbus {
+        bus: Testbus,
+        cycles: usize,
+        timer_at: usize,
+        if_at_writes: Vec<(u16, u8)>,
+    }
+
+    impl Bus for Timerbus {
+        fn read(&self, addr: u16) -> u8 {
+            self.bus.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.if_at_writes.push((addr, self.bus.read(ADDR_IF)));
+            self.bus.write(addr, val);
+        }
+
+        fn tick(&mut self, cycles: usize) {
+            let before = self.cycles;
+            self.cycles += cycles;
+            if before < self.timer_at && self.cycles >= self.timer_at {
+                let intflags = self.bus.read(ADDR_IF);
+                self.bus.write(ADDR_IF, intflags | 0x04);
+            }
+        }
     }
 
     #[test]
-    fn op_ld_reg_indimm8() {
-        let mut c = cpu(&[0xF0, 0x22]); // LD A,(imm8)
-        c.bus.write(0xFF22, 0x5A);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0x5A);
+    fn int_dispatch_sees_timer() {
+        // The timer fires in the first internal cycle of the
+        // dispatch of V-blank.
+        let mut c = CPU::new(Timerbus {
+            bus: Testbus::from(&[]),
+            cycles: 0,
+            timer_at: 4,
+            if_at_writes: vec![],
+        });
+        c.ime = true;
+        c.regs.sp = 0xD000;
+        c.bus.bus.write(0xFFFF, 0x05);
+        c.bus.bus.write(0xFF0F, 0x01);
+
+        c.step().unwrap();
+        assert_eq!(c.regs.pc, 0x40);
+
+        // V-blank is acknowledged first, and the timer request
+        // is on the bus by the time PC is pushed.
+        assert_eq!(
+            c.bus.if_at_writes,
+            [(0xFF0F, 0x01), (0xCFFF, 0x04), (0xCFFE, 0x04)]
+        );
+        assert_eq!(c.bus.read(0xFF0F), 0x04);
+
+        // And it is serviced next
+        c.ime = true;
+        c.step().unwrap();
+        assert_eq!(c.regs.pc, 0x50);
     }
 
     #[test]
-    fn op_set_reg() {
-        let c = run(&[0xCB, 0xC7]); // SET 0,A
-        assert_eq!(c.regs.a, 0x01);
+    fn int_vectors() {
+        for (int, vec) in [(0, 0x40), (1, 0x48), (2, 0x50), (3, 0x58), (4, 0x60)] {
+            let mut c = cpu(&[]);
+            c.ime = true;
+            c.regs.sp = 0xFFF0;
+            c.bus.write(0xFFFF, 0x1F);
+            c.bus.write(0xFF0F, 1 << int);
+            cpu_run(&mut c);
+            assert_eq!(c.regs.pc, vec);
+        }
 
-        let c = run(&[0xCB, 0xFB]); // SET 7,E
-        assert_eq!(c.regs.e, 0x80);
+        // Not enabled in IE
+        let mut c = cpu(&[]);
+        c.ime = true;
+        c.bus.write(0xFF0F, 0x1F);
+        assert!(!c.service_interrupts());
+    }
 
-        let mut c = cpu(&[0xCB, 0xE2]); // SET 4,D
-        c.regs.d = 0x0F;
+    #[test]
+    fn int_reti_vs_ret_ei() {
+        // RETI enables immediately: serviced before
+        // executing the returned-to instruction.
+        let mut c = cpu(&[]);
+        c.bus.write(0x8000, 0xD9); // RETI
+        c.regs.pc = 0x8000;
+        c.regs.sp = 0xFFF0;
+        c.stack_push(0x1000);
+        c.bus.write(0xFFFF, 0x01);
+        c.bus.write(0xFF0F, 0x01);
+        cpu_run(&mut c);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x40);
+        assert_eq!(c.bus.read16(c.regs.sp), 0x1000);
+
+        // EI; RET: the RET completes first
+        let mut c = cpu(&[]);
+        c.bus.write_slice(&[0xFB, 0xC9], 0x8000); // EI; RET
+        c.regs.pc = 0x8000;
+        c.regs.sp = 0xFFF0;
+        c.stack_push(0x1000);
+        c.bus.write(0xFFFF, 0x01);
+        c.bus.write(0xFF0F, 0x01);
         cpu_run(&mut c);
-        assert_eq!(c.regs.d, 0x1F);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x1000);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x40);
+        assert_eq!(c.bus.read16(c.regs.sp), 0x1000);
     }
 
     #[test]
-    fn op_res_reg() {
-        let mut c = cpu(&[0xCB, 0x80]); // RES 0,B
-        c.regs.b = 0xFF;
+    fn op_halt() {
+        let mut c = cpu(&[
+            0x76, // HALT
+            0x3C, // INC A
+        ]);
+        c.bus.write(0xFFFF, 0x01);
         cpu_run(&mut c);
-        assert_eq!(c.regs.b, 0xFE);
+        assert!(c.is_halted());
+        assert_eq!(c.regs.pc, 1);
+        assert_eq!(c.cycles, 4);
 
-        let c = run(&[0xCB, 0x81]); // RES 0,C
-        assert_eq!(c.regs.c, 0x00);
-    }
+        // Stays halted while nothing is pending
+        for _ in 0..10 {
+            cpu_run(&mut c);
+        }
+        assert!(c.is_halted());
+        assert_eq!(c.regs.pc, 1);
+        assert_eq!(c.regs.a, 0);
+        assert_eq!(c.cycles, 44);
 
-    #[test]
-    fn op_bit_reg() {
-        let mut c = cpu(&[0xCB, 0x47]); // BIT 0,A
-        c.regs.a = 0x01;
+        // Wakes on request without IME, continues after HALT
+        c.bus.write(0xFF0F, 0x01);
         cpu_run(&mut c);
-        assert!(
-            !c.regs.test_flag(Flag::Z) && c.regs.test_flag(Flag::H) && !c.regs.test_flag(Flag::N)
-        );
-
-        let c = run(&[0xCB, 0x61]); // BIT 4,C
-        assert!(
-            c.regs.test_flag(Flag::Z) && c.regs.test_flag(Flag::H) && !c.regs.test_flag(Flag::N)
-        );
+        assert!(!c.is_halted());
+        assert_eq!(c.regs.a, 1);
+        assert_eq!(c.regs.pc, 2);
     }
 
     #[test]
-    fn op_jr() {
-        let c = run(&[0x18, (-10_i8 - 2) as u8]); // JR -10
-        assert_eq!(c.regs.pc, -10_i16 as u16);
-
-        let c = run(&[0x18, 10 - 2]); // JR 10
-        assert_eq!(c.regs.pc, 10);
+    fn pending_interrupts() {
+        let mut c = cpu(&[0x00]);
+        c.ime = true;
+        assert_eq!(c.pending_interrupts(), vec![]);
+
+        c.bus.write(0xFFFF, 0x04);
+        c.bus.write(0xFF0F, 0x05);
+        assert!(c.ime());
+        assert_eq!(c.pending_interrupts(), vec![Interrupt::Timer]);
+
+        c.bus.write(0xFFFF, 0xFF);
+        c.bus.write(0xFF0F, 0xF5);
+        assert_eq!(
+            c.pending_interrupts(),
+            vec![Interrupt::VBlank, Interrupt::Timer, Interrupt::Joypad]
+        );
     }
 
     #[test]
-    fn op_jr_nz() {
-        let c = run(&[0x20, 10 - 2]); // JR NZ 10
-        assert_eq!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 12);
+    fn op_halt_wake_int() {
+        let mut c = cpu(&[
+            0x76, // HALT
+            0x00, // NOP
+        ]);
+        c.ime = true;
+        c.regs.sp = 0xFFF0;
+        c.bus.write(0xFFFF, 0x04);
+        cpu_run(&mut c);
+        assert!(c.is_halted());
 
-        let c = run_flags(
-            &[0x20, 10 - 2], // JR NZ 10
-            &[Flag::Z],
-        );
-        assert_ne!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 8);
+        c.bus.write(0xFF0F, 0x04);
+        cpu_run(&mut c);
+        assert!(!c.is_halted());
+        assert_eq!(c.regs.pc, 0x50);
+        assert_eq!(c.bus.read16(c.regs.sp), 1);
     }
 
     #[test]
-    fn op_jr_z() {
-        let c = run(&[0x28, 10 - 2]); // JR Z 10
-        assert_ne!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 8);
+    fn op_halt_bug() {
+        let mut c = cpu(&[
+            0x76, // HALT
+            0x3C, // INC A
+            0x00, // NOP
+        ]);
+        c.bus.write(0xFFFF, 0x01);
+        c.bus.write(0xFF0F, 0x01);
+        cpu_run(&mut c);
+        assert!(!c.is_halted());
+        assert_eq!(c.regs.pc, 1);
 
-        let c = run_flags(
-            &[0x28, 10 - 2], // JR Z 10
-            &[Flag::Z],
-        );
-        assert_eq!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 12);
+        // INC A is executed twice
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 1);
+        assert_eq!(c.regs.pc, 1);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 2);
+        assert_eq!(c.regs.pc, 2);
     }
 
     #[test]
-    fn op_jr_nc() {
-        let c = run(&[0x30, 10 - 2]); // JR NC 10
-        assert_eq!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 12);
+    fn op_halt_bug_imm() {
+        let mut c = cpu(&[
+            0x76, // HALT
+            0x3E, 0x14, // LD A,$14
+        ]);
+        c.bus.write(0xFFFF, 0x01);
+        c.bus.write(0xFF0F, 0x01);
+        cpu_run(&mut c);
 
-        let c = run_flags(
-            &[0x30, 10 - 2], // JR NC 10
-            &[Flag::C],
-        );
-        assert_ne!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 8);
+        // Decoded as LD A,$3E, followed by INC D ($14)
+        assert_eq!(c.peek_next_instr().unwrap().bytes(), [0x3E, 0x3E]);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x3E);
+        assert_eq!(c.regs.pc, 2);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.d, 1);
+        assert_eq!(c.regs.pc, 3);
     }
 
     #[test]
-    fn op_jr_c() {
-        let c = run(&[0x38, 10 - 2]); // JR C 10
-        assert_ne!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 8);
+    fn op_halt_bug_ei() {
+        let mut c = cpu(&[
+            0xFB, // EI
+            0x76, // HALT
+            0x00, // NOP
+        ]);
+        c.regs.sp = 0xFFF0;
+        c.bus.write(0xFFFF, 0x01);
+        c.bus.write(0xFF0F, 0x01);
+        cpu_run(&mut c);
+        cpu_run(&mut c);
+        assert!(!c.is_halted());
 
-        let c = run_flags(
-            &[0x38, 10 - 2], // JR C 10
-            &[Flag::C],
-        );
-        assert_eq!(c.regs.pc, 10);
-        assert_eq!(c.cycles, 12);
+        // Interrupt returns to the HALT itself
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 0x40);
+        assert_eq!(c.bus.read16(c.regs.sp), 1);
     }
 
     #[test]
-    fn op_inc_reg() {
-        let c = run_reg(&[0x3C], Register::A, 0x00);
-        assert_eq!(c.regs.a, 1);
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::N));
-
-        let c = run_reg(&[0x3C], Register::A, 0x0F);
+    fn op_daa_add() {
+        let mut c = cpu(&[
+            0xC6, 0x05, // ADD A,$05
+            0x27, // DAA
+        ]);
+        c.regs.a = 0x05;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x0A);
+        cpu_run(&mut c);
         assert_eq!(c.regs.a, 0x10);
-        assert!(c.regs.test_flag(Flag::H));
         assert!(!c.regs.test_flag(Flag::Z));
+        assert!(!c.regs.test_flag(Flag::H));
         assert!(!c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::C));
 
-        let c = run_reg(&[0x3C], Register::A, 0xFF);
-        assert_eq!(c.regs.a, 0);
-        assert!(c.regs.test_flag(Flag::H));
+        // 0x99 + 0x01 = 0x100 BCD
+        let mut c = cpu(&[
+            0xC6, 0x01, // ADD A,$01
+            0x27, // DAA
+        ]);
+        c.regs.a = 0x99;
+        cpu_run(&mut c);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x00);
         assert!(c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::N));
-    }
+        assert!(c.regs.test_flag(Flag::C));
 
-    #[test]
-    fn op_call() {
-        let mut c = cpu(&[]);
-        c.bus.write_slice(&[0xCD, 0x34, 0x12], 0x8000);
-        c.regs.pc = 0x8000;
-        c.regs.sp = 0xFFFE;
+        // Half carry from 0x09 + 0x09 = 0x12
+        let mut c = cpu(&[
+            0xC6, 0x09, // ADD A,$09
+            0x27, // DAA
+        ]);
+        c.regs.a = 0x09;
         cpu_run(&mut c);
-        assert_eq!(c.regs.pc, 0x1234);
-        assert_eq!(c.regs.sp, 0xFFFC);
-        assert_eq!(c.bus.read16(0xFFFC), 0x8003);
-
-        // Wrapping past 0
-        let c = run(&[0xCD, 0x34, 0x12]);
-        assert_eq!(c.regs.pc, 0x1234);
-        assert_eq!(c.regs.sp, 0xFFFE);
-        assert_eq!(c.bus.read16(0xFFFE), 0x0003);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x18);
+        assert!(!c.regs.test_flag(Flag::C));
     }
 
     #[test]
-    fn op_call_c() {
-        let c = run(&[0xDC, 0x34, 0x12]);
-        assert_ne!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 12);
+    fn op_daa_sub() {
+        // 0x10 - 0x01 = 0x09 BCD
+        let mut c = cpu(&[
+            0xD6, 0x01, // SUB $01
+            0x27, // DAA
+        ]);
+        c.regs.a = 0x10;
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x0F);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x09);
+        assert!(c.regs.test_flag(Flag::N));
+        assert!(!c.regs.test_flag(Flag::H));
+        assert!(!c.regs.test_flag(Flag::C));
 
-        let c = run_flags(&[0xDC, 0x34, 0x12], &[Flag::C]);
-        assert_eq!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 24);
+        // 0x00 - 0x01 = 0x99 BCD, borrow
+        let mut c = cpu(&[
+            0xD6, 0x01, // SUB $01
+            0x27, // DAA
+        ]);
+        cpu_run(&mut c);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 0x99);
+        assert!(c.regs.test_flag(Flag::C));
+        assert!(c.regs.test_flag(Flag::N));
+
+        // Nibbles are not re-examined: 0x0A stays with N set
+        let c = run_reg_flags(&[0x27], Register::A, 0x0A, &[Flag::N]); // DAA
+        assert_eq!(c.regs.a, 0x0A);
     }
 
     #[test]
-    fn op_call_nc() {
-        let c = run(&[0xD4, 0x34, 0x12]);
-        assert_eq!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 24);
+    fn op_stop() {
+        let mut c = cpu(&[0x10, 0x00]);
+        c.bus.write(0xFF00, 0xFF);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 2);
 
-        let c = run_flags(&[0xD4, 0x34, 0x12], &[Flag::C]);
-        assert_ne!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 12);
+        // Interrupt enabled but not requested
+        let mut c = cpu(&[0x10, 0x00]);
+        c.bus.write(0xFF00, 0xFF);
+        c.bus.write(0xFFFF, 0x01);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 2);
+
+        // Interrupt pending, second byte not consumed
+        let mut c = cpu(&[0x10, 0x00]);
+        c.bus.write(0xFF00, 0xFF);
+        c.bus.write(0xFFFF, 0x04);
+        c.bus.write(0xFF0F, 0x04);
+        cpu_run(&mut c);
+        assert_eq!(c.regs.pc, 1);
     }
 
     #[test]
-    fn op_call_z() {
-        let c = run(&[0xCC, 0x34, 0x12]);
-        assert_ne!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 12);
+    fn op_stop_joypad() {
+        let mut c = cpu(&[
+            0x10, 0x00, // STOP
+            0x3C, // INC A
+        ]);
+        c.bus.write(0xFF00, 0xFF);
+        c.bus.write(0xFF04, 0x55);
+        cpu_run(&mut c);
+        assert!(c.is_stopped());
+        assert_eq!(c.bus.read(0xFF04), 0x00);
 
-        let c = run_flags(&[0xCC, 0x34, 0x12], &[Flag::Z]);
-        assert_eq!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 24);
+        for _ in 0..10 {
+            cpu_run(&mut c);
+        }
+        assert!(c.is_stopped());
+        assert_eq!(c.regs.a, 0);
+        assert_eq!(c.cycles, 4 + 10 * 4);
+
+        // Button pressed
+        c.bus.write(0xFF00, 0xEE);
+        cpu_run(&mut c);
+        assert!(!c.is_stopped());
+        assert_eq!(c.regs.a, 1);
     }
 
     #[test]
-    fn op_call_nz() {
-        let c = run(&[0xC4, 0x34, 0x12]);
-        assert_eq!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 24);
+    fn op_stop_button_held() {
+        // No interrupt pending: a 2-byte HALT
+        let mut c = cpu(&[
+            0x10, 0x00, // STOP
+            0x3C, // INC A
+        ]);
+        c.bus.write(0xFF00, 0xEE);
+        c.bus.write(0xFF04, 0x55);
+        cpu_run(&mut c);
+        assert!(!c.is_stopped());
+        assert!(c.is_halted());
+        assert_eq!(c.regs.pc, 2);
+        assert_eq!(c.bus.read(0xFF04), 0x55);
 
-        let c = run_flags(&[0xC4, 0x34, 0x12], &[Flag::Z]);
-        assert_ne!(c.regs.pc, 0x1234);
-        assert_eq!(c.cycles, 12);
-    }
+        c.bus.write(0xFFFF, 0x04);
+        c.bus.write(0xFF0F, 0x04);
+        cpu_run(&mut c);
+        assert!(!c.is_halted());
+        assert_eq!(c.regs.a, 1);
 
-    #[test]
-    fn op_push() {
-        let c = run_reg(&[0xC5], Register::BC, 0xABCD);
-        assert_ne!(c.regs.sp, 0);
-        assert_eq!(c.bus.read16(c.regs.sp), 0xABCD);
+        // Interrupt pending: a 1-byte NOP
+        let mut c = cpu(&[0x10, 0x00]);
+        c.bus.write(0xFF00, 0xEE);
+        c.bus.write(0xFF04, 0x55);
+        c.bus.write(0xFFFF, 0x04);
+        c.bus.write(0xFF0F, 0x04);
+        cpu_run(&mut c);
+        assert!(!c.is_stopped());
+        assert!(!c.is_halted());
+        assert_eq!(c.regs.pc, 1);
+        assert_eq!(c.bus.read(0xFF04), 0x55);
     }
 
     #[test]
-    fn op_pop() {
-        let mut c = cpu(&[0xC1]);
-        c.stack_push(0xABCD);
-        assert_ne!(c.regs.sp, 0);
+    fn op_stop_continue() {
+        let mut c = cpu(&[
+            0x10, 0x00, // STOP
+            0x3C, // INC A
+        ]);
+        c.set_stop_behavior(StopBehavior::Continue);
+        c.bus.write(0xFF00, 0xFF);
         cpu_run(&mut c);
-        assert_eq!(c.regs.sp, 0);
-        assert_eq!(c.regs.read16(Register::BC).unwrap(), 0xABCD);
+        assert!(!c.is_stopped());
+        cpu_run(&mut c);
+        assert_eq!(c.regs.a, 1);
     }
 
     #[test]
-    fn op_rl_reg() {
-        let c = run_reg(&[0xCB, 0x10], Register::B, 0x80);
-        assert_eq!(c.regs.b, 0x00);
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(c.regs.test_flag(Flag::Z));
-
-        let c = run_reg(&[0xCB, 0x10], Register::B, 0x40);
-        assert_eq!(c.regs.b, 0x80);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+    fn disassemble_ahead() {
+        let mut c = cpu(&[]);
+        c.bus.write_slice(
+            &[
+                0x31, 0x34, 0x12, // LD SP,$1234
+                0xCB, 0x7C, // BIT 7,H
+                0x20, 0xFB, // JR NZ,-5
+                0xD3, // INVALID
+            ],
+            0x0100,
+        );
+        c.regs.pc = 0x0100;
+
+        let dis = c.disassemble_ahead(3);
+        assert_eq!(
+            dis,
+            vec![
+                (0x0100, "[31, 34, 12] LD SP,$1234".to_string()),
+                (0x0103, "[CB, 7C] BIT 7,H".to_string()),
+                (0x0105, "[20, FB] JR NZ,$0102".to_string()),
+            ]
+        );
 
-        let c = run_reg(&[0xCB, 0x10], Register::B, 0x00);
-        assert_eq!(c.regs.b, 0x00);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(c.regs.test_flag(Flag::Z));
+        let dis = c.disassemble_ahead(4);
+        assert_eq!(dis[3], (0x0107, "[D3] INVALID".to_string()));
     }
 
     #[test]
-    fn op_rla() {
-        let c = run_reg(&[0x17], Register::A, 0x80);
-        assert_eq!(c.regs.a, 0x00);
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+    fn disassemble_ahead_end_of_memory() {
+        let mut c = cpu(&[]);
+        c.regs.pc = 0xFFFE;
+        let dis = c.disassemble_ahead(5);
+        assert_eq!(
+            dis,
+            vec![
+                (0xFFFE, "[00] NOP".to_string()),
+                (0xFFFF, "[00] NOP".to_string())
+            ]
+        );
 
-        let c = run_reg(&[0x17], Register::A, 0x40);
-        assert_eq!(c.regs.a, 0x80);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+        // Incomplete instruction at the end
+        c.bus.write(0xFFFF, 0x01); // LD BC,d16
+        let dis = c.disassemble_ahead(5);
+        assert_eq!(dis, vec![(0xFFFE, "[00] NOP".to_string())]);
+    }
 
-        let c = run_reg(&[0x17], Register::A, 0x00);
-        assert_eq!(c.regs.a, 0x00);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+    #[test]
+    fn op_nop() {
+        run(&[0x00]);
+    }
+
+    #[test]
+    fn save_state_roundtrip() {
+        let mut c = cpu(&[
+            0x3C, // INC A
+            0x22, // LD (HL+),A
+            0xFB, // EI
+            0x18, 0xFB, // JR $-5
+        ]);
+        c.regs.write(Register::HL, 0xC000).unwrap();
+        for _ in 0..10 {
+            cpu_run(&mut c);
+        }
+        let state = c.save_state().unwrap();
+        let saved = (c.regs.a, c.regs.read16(Register::HL).unwrap(), c.regs.pc);
+        let saved_cycles = c.get_cycles();
 
-        let c = run_reg_flags(&[0x17], Register::A, 0x95, &[Flag::C]);
-        assert_eq!(c.regs.a, 0x2B);
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+        let run = |c: &mut CPU| {
+            for _ in 0..20 {
+                cpu_run(c);
+            }
+            (c.regs.a, c.regs.pc, c.get_cycles(), c.bus.read(0xC00A))
+        };
+        let first = run(&mut c);
+
+        c.load_state(&state).unwrap();
+        assert_eq!(
+            (c.regs.a, c.regs.read16(Register::HL).unwrap(), c.regs.pc),
+            saved
+        );
+        assert_eq!(c.get_cycles(), saved_cycles);
+        assert!(c.ime);
+        assert_eq!(c.bus.read(0xC00A), 0);
+        assert_eq!(run(&mut c), first);
     }
 
     #[test]
-    fn op_rlc_reg() {
-        let c = run_reg(&[0xCB, 0x00], Register::B, 0x80);
-        assert_eq!(c.regs.b, 0x01);
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+    fn load_state_errors() {
+        let mut c = cpu(&[]);
+        let state = c.save_state().unwrap();
+        let err = |c: &mut CPU, state: &[u8]| {
+            c.load_state(state)
+                .unwrap_err()
+                .downcast::<StateErr>()
+                .unwrap()
+        };
 
-        let c = run_reg(&[0xCB, 0x00], Register::B, 0x40);
-        assert_eq!(c.regs.b, 0x80);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+        assert_eq!(err(&mut c, b"NOPE"), StateErr::BadMagic);
 
-        let c = run_reg(&[0xCB, 0x00], Register::B, 0x00);
-        assert_eq!(c.regs.b, 0x00);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::N));
-        assert!(c.regs.test_flag(Flag::Z));
+        let mut bad = state.clone();
+        bad[4] = STATE_VERSION + 1;
+        assert_eq!(
+            err(&mut c, &bad),
+            StateErr::UnsupportedVersion(STATE_VERSION + 1)
+        );
+
+        assert_eq!(err(&mut c, &state[..state.len() - 1]), StateErr::Truncated);
+
+        let mut long = state.clone();
+        long.push(0);
+        assert!(c.load_state(&long).is_err());
+
+        c.load_state(&state).unwrap();
     }
 
     #[test]
-    fn op_dec_8b() {
-        let c = run_reg(&[0x3D], Register::A, 0x00);
-        assert_eq!(c.regs.a, 0xFF);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::N));
-        assert!(!c.regs.test_flag(Flag::Z));
+    fn trace_line() {
+        let mut c = cpu(&[0x00, 0xC3, 0x13, 0x02]);
+        c.regs.write(Register::AF, 0x01B0).unwrap();
+        c.regs.write(Register::BC, 0x0013).unwrap();
+        c.regs.write(Register::DE, 0x00D8).unwrap();
+        c.regs.write(Register::HL, 0x014D).unwrap();
+        c.regs.sp = 0xFFFE;
+        assert_eq!(
+            c.trace_line(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0000 PCMEM:00,C3,13,02"
+        );
 
-        let c = run_reg(&[0x3D], Register::A, 0x01);
-        assert_eq!(c.regs.a, 0x00);
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::N));
-        assert!(c.regs.test_flag(Flag::Z));
+        // PCMEM wraps around the address space
+        c.regs.pc = 0xFFFE;
+        c.bus.write(0xFFFE, 0xAB);
+        c.bus.write(0xFFFF, 0xCD);
+        assert!(c.trace_line().ends_with("PC:FFFE PCMEM:AB,CD,00,C3"));
     }
 
     #[test]
-    fn op_inc_16b() {
-        let c = run_reg(&[0x23], Register::HL, 0x00);
-        assert_eq!(c.regs.l, 0x01);
-        assert_eq!(c.regs.h, 0x00);
+    fn trace_line_full() {
+        let mut c = cpu(&[0x00, 0xC3, 0x13, 0x02]);
+        c.set_trace_format(TraceFormat::Full);
+        c.regs.write(Register::AF, 0x01B0).unwrap();
+        c.regs.write(Register::BC, 0x0013).unwrap();
+        c.regs.write(Register::DE, 0x00D8).unwrap();
+        c.regs.write(Register::HL, 0x014D).unwrap();
+        c.regs.sp = 0xFFFE;
+        c.regs.pc = 1;
+        c.cycles = 1234;
+        assert_eq!(
+            c.trace_line(),
+            "0001 [C3, 13, 02]       JP $0213         A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE CY:1234"
+        );
 
-        let c = run_reg(&[0x23], Register::HL, 0xFFFF);
-        assert_eq!(c.regs.l, 0x00);
-        assert_eq!(c.regs.h, 0x00);
+        c.regs.pc = 0;
+        c.regs.f = 0;
+        assert!(c
+            .trace_line()
+            .starts_with("0000 [00]               NOP              A:01 F:---- "));
     }
 
     #[test]
-    fn op_ret() {
-        let mut c = cpu(&[0xC9]);
-        c.stack_push(0xABCD);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.pc, 0xABCD);
+    fn trace_format_from_str() {
+        assert_eq!(
+            "doctor".parse::<TraceFormat>().unwrap(),
+            TraceFormat::Doctor
+        );
+        assert_eq!("Full".parse::<TraceFormat>().unwrap(), TraceFormat::Full);
+        assert!("bgb".parse::<TraceFormat>().is_err());
     }
 
     #[test]
-    fn op_ret_z() {
-        let mut c = cpu(&[0xC8]);
-        c.regs.write_flags(&[(Flag::Z, true)]);
-        c.stack_push(0xABCD);
+    fn trace_sink() {
+        let mut c = cpu(&[
+            0x00, // NOP
+            0x3C, // INC A
+        ]);
+        let lines = Rc::new(RefCell::new(vec![]));
+        let l = lines.clone();
+        c.set_trace_sink(move |s| l.borrow_mut().push(s.to_string()));
+
         cpu_run(&mut c);
-        assert_eq!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 20);
+        cpu_run(&mut c);
+        {
+            let lines = lines.borrow();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].starts_with("A:00 "));
+            assert!(lines[0].ends_with("PC:0000 PCMEM:00,3C,00,00"));
+            assert!(lines[1].ends_with("PC:0001 PCMEM:3C,00,00,00"));
+        }
 
-        let mut c = cpu(&[0xC8]);
-        c.regs.write_flags(&[(Flag::Z, false)]);
-        c.stack_push(0xABCD);
+        c.clear_trace_sink();
         cpu_run(&mut c);
-        assert_ne!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 8);
+        assert_eq!(lines.borrow().len(), 2);
     }
 
     #[test]
-    fn op_ret_nz() {
-        let mut c = cpu(&[0xC0]);
-        c.regs.write_flags(&[(Flag::Z, true)]);
-        c.stack_push(0xABCD);
-        cpu_run(&mut c);
-        assert_ne!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 8);
+    fn run_until_breakpoint() {
+        let mut c = cpu(&[
+            0x3C, // INC A
+            0x3C, // INC A
+            0x3C, // INC A
+            0x18, 0xFB, // JR $-5
+        ]);
+        c.add_breakpoint(0x0002);
+        assert_eq!(c.run_until(1000).unwrap(), StopReason::Breakpoint(0x0002));
+        assert_eq!(c.regs.pc, 0x0002);
+        assert_eq!(c.regs.a, 2);
 
-        let mut c = cpu(&[0xC0]);
-        c.regs.write_flags(&[(Flag::Z, false)]);
-        c.stack_push(0xABCD);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 20);
+        // Resuming from the breakpoint executes it
+        assert_eq!(c.run_until(1000).unwrap(), StopReason::Breakpoint(0x0002));
+        assert_eq!(c.regs.a, 5);
+
+        c.remove_breakpoint(0x0002);
+        assert_eq!(c.run_until(100).unwrap(), StopReason::CycleBudget);
     }
 
     #[test]
-    fn op_ret_c() {
-        let mut c = cpu(&[0xD8]);
-        c.regs.write_flags(&[(Flag::C, true)]);
-        c.stack_push(0xABCD);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 20);
+    fn run_until_cycle_budget() {
+        let mut c = cpu(&[
+            0x00, // NOP
+            0x18, 0xFD, // JR $-3
+        ]);
+        assert_eq!(c.run_until(0).unwrap(), StopReason::CycleBudget);
+        assert_eq!(c.get_cycles(), 0);
 
-        let mut c = cpu(&[0xD8]);
-        c.regs.write_flags(&[(Flag::C, false)]);
-        c.stack_push(0xABCD);
-        cpu_run(&mut c);
-        assert_ne!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 8);
+        // NOP (4) + JR (12)
+        assert_eq!(c.run_until(32).unwrap(), StopReason::CycleBudget);
+        assert_eq!(c.get_cycles(), 32);
+
+        // Budget is not split within an instruction
+        assert_eq!(c.run_until(6).unwrap(), StopReason::CycleBudget);
+        assert_eq!(c.get_cycles(), 48);
     }
 
     #[test]
-    fn op_ret_nc() {
-        let mut c = cpu(&[0xD0]);
-        c.regs.write_flags(&[(Flag::C, true)]);
-        c.stack_push(0xABCD);
-        cpu_run(&mut c);
-        assert_ne!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 8);
+    fn run_until_watch() {
+        let mut c = cpu(&[
+            0x3C, // INC A
+            0xEA, 0x00, 0xC0, // LD ($C000),A
+            0x18, 0xFA, // JR $-6
+        ]);
+        c.add_watch(0xC000);
+        assert_eq!(
+            c.run_until(1000).unwrap(),
+            StopReason::Watch {
+                addr: 0xC000,
+                old: 0,
+                new: 1
+            }
+        );
+        assert_eq!(c.regs.pc, 0x0004);
 
-        let mut c = cpu(&[0xD0]);
-        c.regs.write_flags(&[(Flag::C, false)]);
-        c.stack_push(0xABCD);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.pc, 0xABCD);
-        assert_eq!(c.cycles, 20);
+        c.remove_watch(0xC000);
+        assert_eq!(c.run_until(1000).unwrap(), StopReason::CycleBudget);
     }
 
     #[test]
-    fn op_cp_imm8() {
-        let c = run_reg(&[0xFE, 0x2F], Register::A, 0x3C);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
-
-        let c = run_reg(&[0xFE, 0x3C], Register::A, 0x3C);
-        assert!(c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
+    fn run_until_watchpoint() {
+        let mut c = cpu(&[
+            0xFA, 0x00, 0xC0, // LD A,($C000)
+            0xEA, 0x00, 0xC0, // LD ($C000),A
+            0x18, 0xF8, // JR $-8
+        ]);
+        c.add_watchpoint(0xC000, WatchKind::Write);
+        assert_eq!(
+            c.run_until(1000).unwrap(),
+            StopReason::Access {
+                addr: 0xC000,
+                write: true,
+                val: 0
+            }
+        );
+        assert_eq!(c.regs.pc, 0x0006);
+
+        c.add_watchpoint(0xC000, WatchKind::Read);
+        assert_eq!(
+            c.run_until(1000).unwrap(),
+            StopReason::Access {
+                addr: 0xC000,
+                write: false,
+                val: 0
+            }
+        );
+        assert_eq!(c.regs.pc, 0x0003);
 
-        let c = run_reg(&[0xFE, 0x40], Register::A, 0x3C);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
+        c.remove_watchpoint(0xC000);
+        assert_eq!(c.run_until(1000).unwrap(), StopReason::CycleBudget);
     }
 
     #[test]
-    fn op_cp_reg8() {
-        let c = run_reg(&[0xB8], Register::B, 0x3C);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
+    fn run_until_condition() {
+        let mut c = cpu(&[
+            0x3C, // INC A
+            0x18, 0xFD, // JR $-3
+        ]);
+        let id = c.add_break_condition(|r| r.a == 3);
+        assert_eq!(c.run_until(1000).unwrap(), StopReason::Condition(id));
+        assert_eq!(c.regs.a, 3);
+        assert_eq!(c.regs.pc, 0x0001);
 
-        let c = run_reg(&[0xB8], Register::B, 0x00);
-        assert!(c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
+        c.remove_break_condition(id);
+        assert_eq!(c.run_until(100).unwrap(), StopReason::CycleBudget);
     }
 
     #[test]
-    fn op_cp_indreg16() {
-        let mut c = cpu(&[0xBE]);
-        (c.regs.h, c.regs.l) = (0x08, 0x00);
-        c.bus.write(0x0800, 0x3C);
-        cpu_run(&mut c);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
-
-        let c = run_reg(&[0xBE], Register::HL, 0x0800);
-        assert!(c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
+    fn illegal_opcode() {
+        for op in [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ] {
+            let mut c = cpu(&[0x00, op]);
+            cpu_run(&mut c);
+            assert_eq!(
+                c.step(),
+                Err(EmuError::InvalidOpcode {
+                    pc: 0x0001,
+                    opcode: op
+                })
+            );
+            // PC stays at the offending opcode
+            assert_eq!(c.regs.pc, 0x0001);
+        }
     }
 
     #[test]
-    fn op_sub_reg8() {
-        let mut c = cpu(&[0x90]); // SUB B
-        c.regs.write8(Register::A, 0x3E).unwrap();
-        c.regs.write8(Register::B, 0x3E).unwrap();
-        cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0);
-        assert!(c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
+    fn fault_skip() {
+        let mut c = cpu(&[0xD3, 0x3C]); // (invalid); INC A
+        let faults = Rc::new(RefCell::new(vec![]));
+        let f = Rc::clone(&faults);
+        c.set_fault_policy(FaultPolicy::Skip);
+        c.set_fault_sink(move |e| f.borrow_mut().push(e.to_string()));
+        c.regs.a = 0;
 
-        let mut c = cpu(&[0x90]); // SUB B
-        c.regs.write8(Register::A, 0x3E).unwrap();
-        c.regs.write8(Register::B, 0x0F).unwrap();
         cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0x2F);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(!c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
-
-        let mut c = cpu(&[0x90]); // SUB B
-        c.regs.write8(Register::A, 0x3E).unwrap();
-        c.regs.write8(Register::B, 0x40).unwrap();
+        assert_eq!(c.regs.pc, 0x0001);
+        assert_eq!(c.get_cycles(), 4);
         cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0xFE);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(!c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(c.regs.test_flag(Flag::N));
+        assert_eq!(c.regs.a, 1);
+        assert_eq!(*faults.borrow(), vec!["Invalid opcode D3 at 0000"]);
     }
 
     #[test]
-    fn op_add_reg() {
-        let mut c = cpu(&[0x80]); // ADD A,B
-        c.regs.write8(Register::A, 0x3A).unwrap();
-        c.regs.write8(Register::B, 0xC6).unwrap();
-        cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0);
-        assert!(c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::N));
-
-        let mut c = cpu(&[0x80]); // ADD A,B
-        c.regs.write8(Register::A, 0x3C).unwrap();
-        c.regs.write8(Register::B, 0xFF).unwrap();
+    fn fault_skip_len() {
+        // Faults in decoded instructions skip all their bytes
+        let mut c = cpu(&[0x01, 0x34, 0x12]); // LD BC,$1234
+        c.skip_fault(&EmuError::InvalidOperand {
+            pc: 0,
+            reason: String::new(),
+        });
+        assert_eq!(c.regs.pc, 0x0003);
+        assert_eq!(c.get_cycles(), 12);
+
+        // Running past the end of memory
+        let mut c = cpu(&[]);
+        c.set_fault_policy(FaultPolicy::Skip);
+        c.regs.pc = 0xFFFF;
+        c.bus.write(0xFFFF, 0x01);
         cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0x3B);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::N));
+        assert_eq!(c.regs.pc, 0x0000);
+        assert_eq!(c.get_cycles(), 4);
     }
 
     #[test]
-    fn op_add_indreg() {
-        let mut c = cpu(&[0x86]); // ADD A,(HL)
-        c.regs.write8(Register::A, 0x3A).unwrap();
-        c.regs.write(Register::HL, 0x55AA).unwrap();
-        c.bus.write(0x55AA, 0xC6);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0);
-        assert!(c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::N));
+    fn op_invalid() {
+        use crate::gameboy::cpu::instruction::ImmediateVal;
+        use crate::gameboy::cpu::instructions::INSTRUCTIONS;
+
+        let mut c = cpu(&[0xD3]);
+        let instr = Instruction {
+            def: &INSTRUCTIONS[0xD3],
+            immediate: [ImmediateVal::None; 2],
+            len: 1,
+            raw: [0xD3, 0, 0],
+        };
+        let err = c.op_invalid(&instr).err().unwrap();
+        assert_eq!(
+            EmuError::from_instr(err, 0),
+            EmuError::InvalidOpcode {
+                pc: 0,
+                opcode: 0xD3
+            }
+        );
+    }
 
-        let mut c = cpu(&[0x86]); // ADD A,(HL)
-        c.regs.write8(Register::A, 0x3C).unwrap();
-        c.regs.write(Register::HL, 0x55AA).unwrap();
-        c.bus.write(0x55AA, 0xFF);
-        cpu_run(&mut c);
-        assert_eq!(c.regs.a, 0x3B);
-        assert!(!c.regs.test_flag(Flag::Z));
-        assert!(c.regs.test_flag(Flag::H));
-        assert!(c.regs.test_flag(Flag::C));
-        assert!(!c.regs.test_flag(Flag::N));
+    #[test]
+    fn bus_error() {
+        let mut c = cpu(&[]);
+        c.regs.pc = 0xFFFF;
+        c.bus.write(0xFFFF, 0x01); // LD BC,d16
+        assert_eq!(c.step(), Err(EmuError::BusFault { addr: 0xFFFF }));
     }
 
     #[test]
-    fn op_nop() {
-        run(&[0x00]);
+    fn raw_matches_synchronized() {
+        // Peripheral-free instruction mix, as in cpubench
+        let code = [
+            0x06, 0x00, // 0000: LD B,$00
+            0x3C, // 0002: INC A
+            0x80, // 0003: ADD A,B
+            0xA9, // 0004: XOR C
+            0xC5, // 0005: PUSH BC
+            0xD1, // 0006: POP DE
+            0xCD, 0x10, 0x00, // 0007: CALL $0010
+            0x05, // 000A: DEC B
+            0x20, 0xF5, // 000B: JR NZ,$0002
+            0x18, 0xF1, // 000D: JR $0000
+            0x00, // 000F: NOP
+            0xFE, 0x10, // 0010: CP $10
+            0xC9, // 0012: RET
+        ];
+        let mut raw = CPU::new(Box::new(Testbus::from(&code)));
+        let mut rom = vec![0; 32 * 1024];
+        rom[..code.len()].copy_from_slice(&code);
+        let mut synced = CPU::new(Gameboybus::new(Box::new(RomOnly::new(&rom, 0)), None));
+
+        for regs in [&mut raw.regs, &mut synced.regs] {
+            regs.sp = 0xFFFE;
+        }
+        for _ in 0..10_000 {
+            raw.step().unwrap();
+            synced.step().unwrap();
+        }
+        for reg in [Register::AF, Register::BC, Register::DE, Register::HL] {
+            assert_eq!(raw.regs.read(reg), synced.regs.read(reg), "{:?}", reg);
+        }
+        assert_eq!((raw.regs.sp, raw.regs.pc), (synced.regs.sp, synced.regs.pc));
+        assert_eq!(raw.get_cycles(), synced.get_cycles());
     }
 }
@@ -3,9 +3,8 @@ use std::fmt;
 use anyhow::{bail, Context, Result};
 use thiserror::Error;
 
-use super::cpu::CPUOpFn;
 use super::instructions::{INSTRUCTIONS, INSTRUCTIONS_CB};
-use super::regs::Register;
+use super::regs::{Register, RegisterFile};
 
 /// A single operand to an instruction
 #[derive(Debug)]
@@ -62,16 +61,121 @@ pub struct InstructionDef {
     /// For unconditional instructions, use cycles[0].
     pub cycles: [u8; 2],
 
-    /// CPU function that executes the instruction.
-    pub func: CPUOpFn,
+    /// CPU operation that executes the instruction.
+    pub op: Op,
 }
 
-#[derive(Debug, Error)]
-enum DecodeErr {
+/// Operations of the CPU, one for each CPU::op_* function
+///
+/// The instruction tables refer to these rather than to the
+/// functions themselves, so they don't depend on the type
+/// of bus the CPU is built with (see CPU::dispatch()).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Op {
+    Adc,
+    Add,
+    And,
+    Bit,
+    Call,
+    CallC,
+    CallNc,
+    CallNz,
+    CallZ,
+    Ccf,
+    Cp,
+    Cpl,
+    Daa,
+    Dec16b,
+    Dec8b,
+    Di,
+    Ei,
+    Halt,
+    Inc16b,
+    Inc8b,
+    Invalid,
+    Jp,
+    JpC,
+    JpNc,
+    JpNz,
+    JpZ,
+    Jr,
+    JrC,
+    JrNc,
+    JrNz,
+    JrZ,
+    Ld,
+    Nop,
+    Or,
+    Pop,
+    PrefixCb,
+    Push,
+    Res,
+    Ret,
+    RetC,
+    RetNc,
+    RetNz,
+    RetZ,
+    Reti,
+    Rl,
+    Rla,
+    Rlc,
+    Rlca,
+    Rr,
+    Rra,
+    Rrc,
+    Rrca,
+    Rst,
+    Sbc,
+    Scf,
+    Set,
+    Sla,
+    Sra,
+    Srl,
+    Stop,
+    Sub,
+    Swap,
+    Xor,
+}
+
+impl Op {
+    /// True for the jumps, calls and returns that depend on
+    /// a flag (e.g. JR NZ,r8).
+    pub fn is_conditional(self) -> bool {
+        matches!(
+            self,
+            Self::CallC
+                | Self::CallNc
+                | Self::CallNz
+                | Self::CallZ
+                | Self::JpC
+                | Self::JpNc
+                | Self::JpNz
+                | Self::JpZ
+                | Self::JrC
+                | Self::JrNc
+                | Self::JrNz
+                | Self::JrZ
+                | Self::RetC
+                | Self::RetNc
+                | Self::RetNz
+                | Self::RetZ
+        )
+    }
+}
+
+/// Errors that can occur while decoding an instruction
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum DecodeErr {
     #[error("End of instruction stream")]
     EndOfStream,
+
+    #[error("Undefined opcode {opcode:02X} at {addr:04X}")]
+    UndefinedOpcode { opcode: u8, addr: u16 },
 }
 
+/// Length of the longest instruction, in bytes
+pub const MAX_LEN: usize = 3;
+
 /// A decoded instruction.
 pub struct Instruction {
     /// Reference to the definition.
@@ -83,42 +187,56 @@ pub struct Instruction {
     /// Length of the full instruction.
     pub len: usize,
 
-    /// Raw instruction bytes.
-    pub raw: Vec<u8>,
+    /// Raw instruction bytes, of which the first 'len'
+    /// are used. Fixed size to keep decoding free of heap
+    /// allocations.
+    pub raw: [u8; MAX_LEN],
 }
 
 impl Instruction {
     /// Try to decode a single instruction from an
-    /// iterator.
-    pub fn decode(stream: &mut impl Iterator<Item = u8>) -> Result<Instruction> {
-        let mut raw: Vec<u8> = vec![];
-        let mut rd = || -> Result<u8> {
-            let b = stream.next().ok_or(DecodeErr::EndOfStream)?;
-            raw.push(b);
-            Ok(b)
-        };
-        let b = rd()?;
-        let cb = b == 0xCB;
+    /// iterator. 'addr' is the address of the first byte
+    /// in the stream, used for error reporting.
+    pub fn decode(stream: &mut impl Iterator<Item = u8>, addr: u16) -> Result<Instruction> {
+        let mut rd = || stream.next().ok_or(DecodeErr::EndOfStream);
+        let mut raw = [0; MAX_LEN];
+        raw[0] = rd()?;
+        let cb = raw[0] == 0xCB;
         let def: &InstructionDef = if cb {
-            &INSTRUCTIONS_CB[rd()? as usize]
+            raw[1] = rd()?;
+            &INSTRUCTIONS_CB[raw[1] as usize]
         } else {
-            &INSTRUCTIONS[b as usize]
+            &INSTRUCTIONS[raw[0] as usize]
         };
+        if def.mnemonic == "INVALID" {
+            bail!(DecodeErr::UndefinedOpcode {
+                opcode: raw[0],
+                addr
+            });
+        }
+
+        // Read the rest, including bytes without an operand
+        // (e.g. the second byte of STOP).
+        for b in raw.iter_mut().take(def.len).skip(1 + cb as usize) {
+            *b = rd()?;
+        }
 
         // Decode immediate values.
         let mut immediate: [ImmediateVal; 2] = [ImmediateVal::None; 2];
+        let mut pos = 1;
         for (i, operand) in def.operands.iter().enumerate() {
             match operand {
                 Operand::Immediate8
                 | Operand::ImmediateIndirect8
                 | Operand::Relative8
                 | Operand::SPRelative8 => {
-                    immediate[i] = ImmediateVal::Immediate8(rd()?);
+                    immediate[i] = ImmediateVal::Immediate8(raw[pos]);
+                    pos += 1;
                 }
                 Operand::Immediate16 | Operand::ImmediateIndirect16 => {
-                    let mut val: u16 = rd()? as u16;
-                    val |= (rd()? as u16) << 8;
+                    let val = u16::from_le_bytes([raw[pos], raw[pos + 1]]);
                     immediate[i] = ImmediateVal::Immediate16(val);
+                    pos += 2;
                 }
                 _ => {}
             }
@@ -127,11 +245,16 @@ impl Instruction {
         Ok(Instruction {
             def,
             immediate,
-            len: raw.len(),
+            len: def.len,
             raw,
         })
     }
 
+    /// Raw instruction bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.raw[..self.len]
+    }
+
     /// Read 8-bit immediate value.
     ///
     /// Returns an error if index is out of bounds or
@@ -172,34 +295,113 @@ impl Instruction {
             bail!("Value not 16-bit")
         }
     }
-}
 
-impl fmt::Display for Instruction {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    /// Computes the possible addresses of the next instruction
+    /// after this one, without executing it, assuming this
+    /// instruction is located at regs.pc.
+    ///
+    /// Conditional branches yield both the fall-through and
+    /// the branch target. Return addresses live on the stack,
+    /// so RET/RETI only yield the fall-through if conditional.
+    pub fn targets(&self, regs: &RegisterFile) -> Vec<u16> {
+        let next = regs.pc.wrapping_add(self.len as u16);
+
+        let target = match (self.def.op, &self.def.operands[0], &self.immediate[0]) {
+            (
+                Op::Jr | Op::JrC | Op::JrNc | Op::JrNz | Op::JrZ,
+                _,
+                &ImmediateVal::Immediate8(rel),
+            ) => Some(next.wrapping_add_signed((rel as i8).into())),
+            (Op::Jp, Operand::RegisterIndirect(reg), _) => Some(regs.read(*reg)),
+            (
+                Op::Jp
+                | Op::JpC
+                | Op::JpNc
+                | Op::JpNz
+                | Op::JpZ
+                | Op::Call
+                | Op::CallC
+                | Op::CallNc
+                | Op::CallNz
+                | Op::CallZ,
+                _,
+                &ImmediateVal::Immediate16(addr),
+            ) => Some(addr),
+            (Op::Rst, &Operand::Constant(vec), _) => Some(vec.into()),
+            (Op::Ret | Op::RetC | Op::RetNc | Op::RetNz | Op::RetZ | Op::Reti, _, _) => None,
+            _ => return vec![next],
+        };
+
+        let mut targets = vec![];
+        if self.def.op.is_conditional() {
+            targets.push(next);
+        }
+        targets.extend(target);
+        targets
+    }
+
+    /// Renders the instruction as assembly text,
+    /// with immediate values filled in (e.g. "LD SP,$1234").
+    ///
+    /// Relative operands are shown as signed offsets
+    /// (e.g. "JR NZ,$+5", "LD HL,SP-2").
+    pub fn disassemble(&self) -> String {
         let mut s = self.def.mnemonic.to_string();
 
         // Fill in immediate values.
         for (i, operand) in self.def.operands.iter().enumerate() {
+            let imm = format!("{}", self.immediate[i]);
             s = match operand {
-                Operand::Immediate8 => {
-                    s.replacen("d8", format!("{}", self.immediate[i]).as_str(), 1)
-                }
-                Operand::ImmediateIndirect8 => {
-                    s.replacen("a8", format!("{}", self.immediate[i]).as_str(), 1)
-                }
-                Operand::Immediate16 => {
-                    s.replacen("d16", format!("{}", self.immediate[i]).as_str(), 1)
-                }
-                Operand::ImmediateIndirect16 => {
-                    s.replacen("a16", format!("{}", self.immediate[i]).as_str(), 1)
-                }
+                Operand::Immediate8 => s.replacen("d8", &imm, 1),
+                Operand::ImmediateIndirect8 => s.replacen("a8", &imm, 1),
+                Operand::Immediate16 => s.replacen("d16", &imm, 1),
+                Operand::ImmediateIndirect16 => s.replacen("a16", &imm, 1),
                 Operand::Relative8 | Operand::SPRelative8 => {
-                    s.replacen("r8", format!("{}", self.immediate[i]).as_str(), 1)
+                    let rel = self.imms8(i).unwrap_or_default();
+                    if s.starts_with("JR") {
+                        s.replacen("r8", &format!("${:+}", rel), 1)
+                    } else {
+                        // Offset to SP
+                        s.replacen("+r8", "r8", 1)
+                            .replacen("r8", &format!("{:+}", rel), 1)
+                    }
                 }
                 _ => s,
             }
         }
-        write!(f, "{:02X?} {}", self.raw, s.as_str())
+        s
+    }
+
+    /// Renders the instruction as assembly text like
+    /// disassemble(), for an instruction located at 'addr':
+    /// relative jump targets are resolved to absolute
+    /// addresses (e.g. "JR NZ,$0107").
+    pub fn fmt_disasm(&self, addr: u16) -> String {
+        let text = self.disassemble();
+        if !self.def.mnemonic.starts_with("JR") {
+            return text;
+        }
+
+        let idx = self
+            .def
+            .operands
+            .iter()
+            .position(|o| matches!(o, Operand::Relative8));
+        match idx.and_then(|i| self.imms8(i).ok()) {
+            Some(rel) => {
+                let target = addr
+                    .wrapping_add(self.len as u16)
+                    .wrapping_add_signed(rel.into());
+                text.replacen(&format!("${:+}", rel), &format!("${:04X}", target), 1)
+            }
+            None => text,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X?} {}", self.bytes(), self.disassemble())
     }
 }
 
@@ -210,14 +412,180 @@ mod tests {
     #[test]
     fn instruction_decode() {
         let test = vec![0x00];
-        let i = Instruction::decode(&mut test.into_iter()).unwrap();
+        let i = Instruction::decode(&mut test.into_iter(), 0).unwrap();
         assert!(i.def.mnemonic == INSTRUCTIONS[0].mnemonic);
     }
 
     #[test]
     fn instruction_decode_cb() {
         let test = vec![0xCB, 0x00];
-        let i = Instruction::decode(&mut test.into_iter()).unwrap();
+        let i = Instruction::decode(&mut test.into_iter(), 0).unwrap();
         assert!(i.def.mnemonic == INSTRUCTIONS_CB[0].mnemonic);
     }
+
+    #[test]
+    fn instruction_decode_stop() {
+        let test = vec![0x10, 0x00, 0xFF];
+        let i = Instruction::decode(&mut test.into_iter(), 0).unwrap();
+        assert_eq!(i.len, 2);
+        assert_eq!(i.bytes(), [0x10, 0x00]);
+    }
+
+    #[test]
+    fn instruction_decode_undefined() {
+        for op in [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ] {
+            let test = vec![op];
+            let err = Instruction::decode(&mut test.into_iter(), 0x1234)
+                .err()
+                .unwrap();
+            assert_eq!(
+                err.downcast_ref::<DecodeErr>(),
+                Some(&DecodeErr::UndefinedOpcode {
+                    opcode: op,
+                    addr: 0x1234
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn instruction_decode_end_of_stream() {
+        let test = vec![0x01, 0x34]; // LD BC,d16
+        let err = Instruction::decode(&mut test.into_iter(), 0).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<DecodeErr>(),
+            Some(&DecodeErr::EndOfStream)
+        );
+    }
+
+    fn targets(code: &[u8], f: impl FnOnce(&mut RegisterFile)) -> Vec<u16> {
+        let mut regs = RegisterFile::new();
+        regs.pc = 0x0100;
+        f(&mut regs);
+        Instruction::decode(&mut code.iter().copied(), regs.pc)
+            .unwrap()
+            .targets(&regs)
+    }
+
+    #[test]
+    fn instruction_targets_sequential() {
+        assert_eq!(targets(&[0x00], |_| ()), vec![0x0101]); // NOP
+        assert_eq!(targets(&[0x01, 0x34, 0x12], |_| ()), vec![0x0103]); // LD BC,d16
+        assert_eq!(targets(&[0xCB, 0x7C], |_| ()), vec![0x0102]); // BIT 7,H
+    }
+
+    #[test]
+    fn instruction_targets_jr() {
+        assert_eq!(targets(&[0x18, 0x05], |_| ()), vec![0x0107]); // JR 5
+        assert_eq!(targets(&[0x18, 0xFC], |_| ()), vec![0x00FE]); // JR -4
+        assert_eq!(targets(&[0x20, 0x05], |_| ()), vec![0x0102, 0x0107]); // JR NZ,5
+        assert_eq!(targets(&[0x38, 0xFE], |_| ()), vec![0x0102, 0x0100]); // JR C,-2
+    }
+
+    #[test]
+    fn instruction_targets_jp_call() {
+        assert_eq!(targets(&[0xC3, 0x34, 0x12], |_| ()), vec![0x1234]); // JP a16
+        assert_eq!(
+            targets(&[0xCA, 0x34, 0x12], |_| ()), // JP Z,a16
+            vec![0x0103, 0x1234]
+        );
+        assert_eq!(targets(&[0xCD, 0x34, 0x12], |_| ()), vec![0x1234]); // CALL a16
+        assert_eq!(
+            targets(&[0xD4, 0x34, 0x12], |_| ()), // CALL NC,a16
+            vec![0x0103, 0x1234]
+        );
+        assert_eq!(
+            targets(&[0xE9], |r| r.write(Register::HL, 0xABCD).unwrap()), // JP (HL)
+            vec![0xABCD]
+        );
+    }
+
+    #[test]
+    fn instruction_targets_rst_ret() {
+        assert_eq!(targets(&[0xC7], |_| ()), vec![0x0000]); // RST 00H
+        assert_eq!(targets(&[0xEF], |_| ()), vec![0x0028]); // RST 28H
+        assert_eq!(targets(&[0xC9], |_| ()), vec![]); // RET
+        assert_eq!(targets(&[0xD9], |_| ()), vec![]); // RETI
+        assert_eq!(targets(&[0xC0], |_| ()), vec![0x0101]); // RET NZ
+    }
+
+    #[test]
+    fn op_is_conditional() {
+        // Only conditional instructions take different cycles
+        // depending on whether the condition is met.
+        for def in INSTRUCTIONS.iter().chain(INSTRUCTIONS_CB.iter()) {
+            assert_eq!(
+                def.op.is_conditional(),
+                def.cycles[0] != def.cycles[1],
+                "{}",
+                def.mnemonic
+            );
+        }
+    }
+
+    #[test]
+    fn instruction_cb_cycles() {
+        let cycles = |op: u8| {
+            let i = Instruction::decode(&mut vec![0xCB, op].into_iter(), 0).unwrap();
+            i.def.cycles
+        };
+
+        assert_eq!(cycles(0x36), [16, 16]); // SWAP (HL)
+        assert_eq!(cycles(0x30), [8, 8]); // SWAP B
+        assert_eq!(cycles(0x46), [12, 12]); // BIT 0,(HL)
+
+        // Register targets take 8 cycles, (HL) targets take
+        // 16 cycles for read-modify-write and 12 cycles
+        // for BIT, which only reads.
+        for op in 0..=u8::MAX {
+            let expected = match (op >> 6, op & 0x07) {
+                (1, 6) => 12,
+                (_, 6) => 16,
+                _ => 8,
+            };
+            assert_eq!(cycles(op), [expected; 2], "opcode CB {:02X}", op);
+        }
+    }
+
+    fn disassemble(code: &[u8]) -> String {
+        Instruction::decode(&mut code.iter().copied(), 0)
+            .unwrap()
+            .disassemble()
+    }
+
+    #[test]
+    fn instruction_disassemble() {
+        assert_eq!(disassemble(&[0x00]), "NOP");
+        assert_eq!(disassemble(&[0x31, 0x34, 0x12]), "LD SP,$1234");
+        assert_eq!(disassemble(&[0x3E, 0xAB]), "LD A,$AB");
+        assert_eq!(disassemble(&[0xE0, 0x44]), "LDH ($44),A");
+        assert_eq!(disassemble(&[0xEA, 0x00, 0xC0]), "LD ($C000),A");
+        assert_eq!(disassemble(&[0xCB, 0x7E]), "BIT 7,(HL)");
+        assert_eq!(disassemble(&[0xCB, 0x7C]), "BIT 7,H");
+    }
+
+    #[test]
+    fn instruction_fmt_disasm() {
+        let fmt = |code: &[u8], addr: u16| {
+            Instruction::decode(&mut code.iter().copied(), addr)
+                .unwrap()
+                .fmt_disasm(addr)
+        };
+        assert_eq!(fmt(&[0x20, 0x05], 0x0100), "JR NZ,$0107");
+        assert_eq!(fmt(&[0x18, 0xFE], 0x0150), "JR $0150");
+        assert_eq!(fmt(&[0x18, 0xFC], 0x0000), "JR $FFFE");
+        assert_eq!(fmt(&[0xE8, 0x02], 0x0100), "ADD SP,+2");
+        assert_eq!(fmt(&[0xC3, 0x34, 0x12], 0x0100), "JP $1234");
+    }
+
+    #[test]
+    fn instruction_disassemble_relative() {
+        assert_eq!(disassemble(&[0x20, 0x05]), "JR NZ,$+5");
+        assert_eq!(disassemble(&[0x18, 0xFC]), "JR $-4");
+        assert_eq!(disassemble(&[0xE8, 0x02]), "ADD SP,+2");
+        assert_eq!(disassemble(&[0xF8, 0xFE]), "LD HL,SP-2");
+        assert_eq!(disassemble(&[0xF8, 0x10]), "LD HL,SP+16");
+    }
 }
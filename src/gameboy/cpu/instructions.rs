@@ -1,5 +1,4 @@
-use super::cpu::CPU;
-use super::instruction::{InstructionDef, Operand};
+use super::instruction::{InstructionDef, Op, Operand};
 use super::regs::Register;
 
 /// Base instruction table, parsed from https://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
@@ -10,7 +9,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_nop,
+        op: Op::Nop,
     },
     // LD BC,d16 (3), - - - -
     InstructionDef {
@@ -18,7 +17,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::BC), Operand::Immediate16],
         len: 3,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (BC),A (1), - - - -
     InstructionDef {
@@ -29,7 +28,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // INC BC (1), - - - -
     InstructionDef {
@@ -37,7 +36,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::BC), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_inc_16b,
+        op: Op::Inc16b,
     },
     // INC B (1), Z 0 H -
     InstructionDef {
@@ -45,7 +44,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC B (1), Z 1 H -
     InstructionDef {
@@ -53,7 +52,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD B,d8 (2), - - - -
     InstructionDef {
@@ -61,7 +60,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // RLCA (1), 0 0 0 C
     InstructionDef {
@@ -69,7 +68,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_rlca,
+        op: Op::Rlca,
     },
     // LD (a16),SP (3), - - - -
     InstructionDef {
@@ -80,7 +79,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 3,
         cycles: [20, 20],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // ADD HL,BC (1), - 0 H C
     InstructionDef {
@@ -91,7 +90,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // LD A,(BC) (1), - - - -
     InstructionDef {
@@ -102,7 +101,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // DEC BC (1), - - - -
     InstructionDef {
@@ -110,7 +109,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::BC), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_dec_16b,
+        op: Op::Dec16b,
     },
     // INC C (1), Z 0 H -
     InstructionDef {
@@ -118,7 +117,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC C (1), Z 1 H -
     InstructionDef {
@@ -126,7 +125,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD C,d8 (2), - - - -
     InstructionDef {
@@ -134,7 +133,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // RRCA (1), 0 0 0 C
     InstructionDef {
@@ -142,7 +141,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_rrca,
+        op: Op::Rrca,
     },
     // STOP 0 (2), - - - -
     InstructionDef {
@@ -150,7 +149,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0), Operand::None],
         len: 2,
         cycles: [4, 4],
-        func: CPU::op_stop,
+        op: Op::Stop,
     },
     // LD DE,d16 (3), - - - -
     InstructionDef {
@@ -158,7 +157,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::DE), Operand::Immediate16],
         len: 3,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (DE),A (1), - - - -
     InstructionDef {
@@ -169,7 +168,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // INC DE (1), - - - -
     InstructionDef {
@@ -177,7 +176,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::DE), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_inc_16b,
+        op: Op::Inc16b,
     },
     // INC D (1), Z 0 H -
     InstructionDef {
@@ -185,7 +184,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC D (1), Z 1 H -
     InstructionDef {
@@ -193,7 +192,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD D,d8 (2), - - - -
     InstructionDef {
@@ -201,7 +200,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // RLA (1), 0 0 0 C
     InstructionDef {
@@ -209,7 +208,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_rla,
+        op: Op::Rla,
     },
     // JR r8 (2), - - - -
     InstructionDef {
@@ -217,7 +216,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Relative8, Operand::None],
         len: 2,
         cycles: [12, 12],
-        func: CPU::op_jr,
+        op: Op::Jr,
     },
     // ADD HL,DE (1), - 0 H C
     InstructionDef {
@@ -228,7 +227,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // LD A,(DE) (1), - - - -
     InstructionDef {
@@ -239,7 +238,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // DEC DE (1), - - - -
     InstructionDef {
@@ -247,7 +246,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::DE), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_dec_16b,
+        op: Op::Dec16b,
     },
     // INC E (1), Z 0 H -
     InstructionDef {
@@ -255,7 +254,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC E (1), Z 1 H -
     InstructionDef {
@@ -263,7 +262,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD E,d8 (2), - - - -
     InstructionDef {
@@ -271,7 +270,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // RRA (1), 0 0 0 C
     InstructionDef {
@@ -279,7 +278,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_rra,
+        op: Op::Rra,
     },
     // JR NZ,r8 (2), - - - -
     InstructionDef {
@@ -287,7 +286,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Relative8, Operand::None],
         len: 2,
         cycles: [12, 8],
-        func: CPU::op_jr_nz,
+        op: Op::JrNz,
     },
     // LD HL,d16 (3), - - - -
     InstructionDef {
@@ -295,7 +294,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::HL), Operand::Immediate16],
         len: 3,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL+),A (1), - - - -
     InstructionDef {
@@ -306,7 +305,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // INC HL (1), - - - -
     InstructionDef {
@@ -314,7 +313,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::HL), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_inc_16b,
+        op: Op::Inc16b,
     },
     // INC H (1), Z 0 H -
     InstructionDef {
@@ -322,7 +321,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC H (1), Z 1 H -
     InstructionDef {
@@ -330,7 +329,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD H,d8 (2), - - - -
     InstructionDef {
@@ -338,7 +337,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // DAA (1), Z - 0 C
     InstructionDef {
@@ -346,7 +345,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_daa,
+        op: Op::Daa,
     },
     // JR Z,r8 (2), - - - -
     InstructionDef {
@@ -354,7 +353,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Relative8, Operand::None],
         len: 2,
         cycles: [12, 8],
-        func: CPU::op_jr_z,
+        op: Op::JrZ,
     },
     // ADD HL,HL (1), - 0 H C
     InstructionDef {
@@ -365,7 +364,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // LD A,(HL+) (1), - - - -
     InstructionDef {
@@ -376,7 +375,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // DEC HL (1), - - - -
     InstructionDef {
@@ -384,7 +383,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::HL), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_dec_16b,
+        op: Op::Dec16b,
     },
     // INC L (1), Z 0 H -
     InstructionDef {
@@ -392,7 +391,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC L (1), Z 1 H -
     InstructionDef {
@@ -400,7 +399,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD L,d8 (2), - - - -
     InstructionDef {
@@ -408,7 +407,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // CPL (1), - 1 1 -
     InstructionDef {
@@ -416,7 +415,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cpl,
+        op: Op::Cpl,
     },
     // JR NC,r8 (2), - - - -
     InstructionDef {
@@ -424,7 +423,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Relative8, Operand::None],
         len: 2,
         cycles: [12, 8],
-        func: CPU::op_jr_nc,
+        op: Op::JrNc,
     },
     // LD SP,d16 (3), - - - -
     InstructionDef {
@@ -432,7 +431,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::SP), Operand::Immediate16],
         len: 3,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL-),A (1), - - - -
     InstructionDef {
@@ -443,7 +442,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // INC SP (1), - - - -
     InstructionDef {
@@ -451,7 +450,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::SP), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_inc_16b,
+        op: Op::Inc16b,
     },
     // INC (HL) (1), Z 0 H -
     InstructionDef {
@@ -459,7 +458,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [12, 12],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC (HL) (1), Z 1 H -
     InstructionDef {
@@ -467,7 +466,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [12, 12],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD (HL),d8 (2), - - - -
     InstructionDef {
@@ -475,7 +474,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::Immediate8],
         len: 2,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // SCF (1), - 0 0 1
     InstructionDef {
@@ -483,7 +482,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_scf,
+        op: Op::Scf,
     },
     // JR C,r8 (2), - - - -
     InstructionDef {
@@ -491,7 +490,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Relative8, Operand::None],
         len: 2,
         cycles: [12, 8],
-        func: CPU::op_jr_c,
+        op: Op::JrC,
     },
     // ADD HL,SP (1), - 0 H C
     InstructionDef {
@@ -502,7 +501,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // LD A,(HL-) (1), - - - -
     InstructionDef {
@@ -513,7 +512,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // DEC SP (1), - - - -
     InstructionDef {
@@ -521,7 +520,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::SP), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_dec_16b,
+        op: Op::Dec16b,
     },
     // INC A (1), Z 0 H -
     InstructionDef {
@@ -529,7 +528,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_inc_8b,
+        op: Op::Inc8b,
     },
     // DEC A (1), Z 1 H -
     InstructionDef {
@@ -537,7 +536,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_dec_8b,
+        op: Op::Dec8b,
     },
     // LD A,d8 (2), - - - -
     InstructionDef {
@@ -545,7 +544,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // CCF (1), - 0 0 C
     InstructionDef {
@@ -553,7 +552,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ccf,
+        op: Op::Ccf,
     },
     // LD B,B (1), - - - -
     InstructionDef {
@@ -564,7 +563,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD B,C (1), - - - -
     InstructionDef {
@@ -575,7 +574,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD B,D (1), - - - -
     InstructionDef {
@@ -586,7 +585,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD B,E (1), - - - -
     InstructionDef {
@@ -597,7 +596,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD B,H (1), - - - -
     InstructionDef {
@@ -608,7 +607,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD B,L (1), - - - -
     InstructionDef {
@@ -619,7 +618,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD B,(HL) (1), - - - -
     InstructionDef {
@@ -630,7 +629,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD B,A (1), - - - -
     InstructionDef {
@@ -641,7 +640,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,B (1), - - - -
     InstructionDef {
@@ -652,7 +651,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,C (1), - - - -
     InstructionDef {
@@ -663,7 +662,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,D (1), - - - -
     InstructionDef {
@@ -674,7 +673,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,E (1), - - - -
     InstructionDef {
@@ -685,7 +684,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,H (1), - - - -
     InstructionDef {
@@ -696,7 +695,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,L (1), - - - -
     InstructionDef {
@@ -707,7 +706,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,(HL) (1), - - - -
     InstructionDef {
@@ -718,7 +717,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD C,A (1), - - - -
     InstructionDef {
@@ -729,7 +728,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,B (1), - - - -
     InstructionDef {
@@ -740,7 +739,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,C (1), - - - -
     InstructionDef {
@@ -751,7 +750,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,D (1), - - - -
     InstructionDef {
@@ -762,7 +761,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,E (1), - - - -
     InstructionDef {
@@ -773,7 +772,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,H (1), - - - -
     InstructionDef {
@@ -784,7 +783,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,L (1), - - - -
     InstructionDef {
@@ -795,7 +794,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,(HL) (1), - - - -
     InstructionDef {
@@ -806,7 +805,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD D,A (1), - - - -
     InstructionDef {
@@ -817,7 +816,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,B (1), - - - -
     InstructionDef {
@@ -828,7 +827,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,C (1), - - - -
     InstructionDef {
@@ -839,7 +838,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,D (1), - - - -
     InstructionDef {
@@ -850,7 +849,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,E (1), - - - -
     InstructionDef {
@@ -861,7 +860,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,H (1), - - - -
     InstructionDef {
@@ -872,7 +871,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,L (1), - - - -
     InstructionDef {
@@ -883,7 +882,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,(HL) (1), - - - -
     InstructionDef {
@@ -894,7 +893,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD E,A (1), - - - -
     InstructionDef {
@@ -905,7 +904,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,B (1), - - - -
     InstructionDef {
@@ -916,7 +915,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,C (1), - - - -
     InstructionDef {
@@ -927,7 +926,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,D (1), - - - -
     InstructionDef {
@@ -938,7 +937,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,E (1), - - - -
     InstructionDef {
@@ -949,7 +948,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,H (1), - - - -
     InstructionDef {
@@ -960,7 +959,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,L (1), - - - -
     InstructionDef {
@@ -971,7 +970,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,(HL) (1), - - - -
     InstructionDef {
@@ -982,7 +981,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD H,A (1), - - - -
     InstructionDef {
@@ -993,7 +992,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,B (1), - - - -
     InstructionDef {
@@ -1004,7 +1003,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,C (1), - - - -
     InstructionDef {
@@ -1015,7 +1014,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,D (1), - - - -
     InstructionDef {
@@ -1026,7 +1025,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,E (1), - - - -
     InstructionDef {
@@ -1037,7 +1036,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,H (1), - - - -
     InstructionDef {
@@ -1048,7 +1047,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,L (1), - - - -
     InstructionDef {
@@ -1059,7 +1058,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,(HL) (1), - - - -
     InstructionDef {
@@ -1070,7 +1069,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD L,A (1), - - - -
     InstructionDef {
@@ -1081,7 +1080,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL),B (1), - - - -
     InstructionDef {
@@ -1092,7 +1091,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL),C (1), - - - -
     InstructionDef {
@@ -1103,7 +1102,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL),D (1), - - - -
     InstructionDef {
@@ -1114,7 +1113,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL),E (1), - - - -
     InstructionDef {
@@ -1125,7 +1124,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL),H (1), - - - -
     InstructionDef {
@@ -1136,7 +1135,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD (HL),L (1), - - - -
     InstructionDef {
@@ -1147,7 +1146,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // HALT (1), - - - -
     InstructionDef {
@@ -1155,7 +1154,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_halt,
+        op: Op::Halt,
     },
     // LD (HL),A (1), - - - -
     InstructionDef {
@@ -1166,7 +1165,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,B (1), - - - -
     InstructionDef {
@@ -1177,7 +1176,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,C (1), - - - -
     InstructionDef {
@@ -1188,7 +1187,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,D (1), - - - -
     InstructionDef {
@@ -1199,7 +1198,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,E (1), - - - -
     InstructionDef {
@@ -1210,7 +1209,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,H (1), - - - -
     InstructionDef {
@@ -1221,7 +1220,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,L (1), - - - -
     InstructionDef {
@@ -1232,7 +1231,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,(HL) (1), - - - -
     InstructionDef {
@@ -1243,7 +1242,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,A (1), - - - -
     InstructionDef {
@@ -1254,7 +1253,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // ADD A,B (1), Z 0 H C
     InstructionDef {
@@ -1265,7 +1264,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADD A,C (1), Z 0 H C
     InstructionDef {
@@ -1276,7 +1275,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADD A,D (1), Z 0 H C
     InstructionDef {
@@ -1287,7 +1286,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADD A,E (1), Z 0 H C
     InstructionDef {
@@ -1298,7 +1297,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADD A,H (1), Z 0 H C
     InstructionDef {
@@ -1309,7 +1308,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADD A,L (1), Z 0 H C
     InstructionDef {
@@ -1320,7 +1319,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADD A,(HL) (1), Z 0 H C
     InstructionDef {
@@ -1331,7 +1330,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADD A,A (1), Z 0 H C
     InstructionDef {
@@ -1342,7 +1341,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // ADC A,B (1), Z 0 H C
     InstructionDef {
@@ -1353,7 +1352,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // ADC A,C (1), Z 0 H C
     InstructionDef {
@@ -1364,7 +1363,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // ADC A,D (1), Z 0 H C
     InstructionDef {
@@ -1375,7 +1374,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // ADC A,E (1), Z 0 H C
     InstructionDef {
@@ -1386,7 +1385,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // ADC A,H (1), Z 0 H C
     InstructionDef {
@@ -1397,7 +1396,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // ADC A,L (1), Z 0 H C
     InstructionDef {
@@ -1408,7 +1407,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // ADC A,(HL) (1), Z 0 H C
     InstructionDef {
@@ -1419,7 +1418,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // ADC A,A (1), Z 0 H C
     InstructionDef {
@@ -1430,7 +1429,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // SUB B (1), Z 1 H C
     InstructionDef {
@@ -1438,7 +1437,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SUB C (1), Z 1 H C
     InstructionDef {
@@ -1446,7 +1445,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SUB D (1), Z 1 H C
     InstructionDef {
@@ -1454,7 +1453,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SUB E (1), Z 1 H C
     InstructionDef {
@@ -1462,7 +1461,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SUB H (1), Z 1 H C
     InstructionDef {
@@ -1470,7 +1469,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SUB L (1), Z 1 H C
     InstructionDef {
@@ -1478,7 +1477,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SUB (HL) (1), Z 1 H C
     InstructionDef {
@@ -1486,7 +1485,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SUB A (1), Z 1 H C
     InstructionDef {
@@ -1494,7 +1493,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // SBC A,B (1), Z 1 H C
     InstructionDef {
@@ -1505,7 +1504,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // SBC A,C (1), Z 1 H C
     InstructionDef {
@@ -1516,7 +1515,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // SBC A,D (1), Z 1 H C
     InstructionDef {
@@ -1527,7 +1526,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // SBC A,E (1), Z 1 H C
     InstructionDef {
@@ -1538,7 +1537,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // SBC A,H (1), Z 1 H C
     InstructionDef {
@@ -1549,7 +1548,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // SBC A,L (1), Z 1 H C
     InstructionDef {
@@ -1560,7 +1559,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // SBC A,(HL) (1), Z 1 H C
     InstructionDef {
@@ -1571,7 +1570,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // SBC A,A (1), Z 1 H C
     InstructionDef {
@@ -1582,7 +1581,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // AND B (1), Z 0 1 0
     InstructionDef {
@@ -1590,7 +1589,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // AND C (1), Z 0 1 0
     InstructionDef {
@@ -1598,7 +1597,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // AND D (1), Z 0 1 0
     InstructionDef {
@@ -1606,7 +1605,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // AND E (1), Z 0 1 0
     InstructionDef {
@@ -1614,7 +1613,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // AND H (1), Z 0 1 0
     InstructionDef {
@@ -1622,7 +1621,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // AND L (1), Z 0 1 0
     InstructionDef {
@@ -1630,7 +1629,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // AND (HL) (1), Z 0 1 0
     InstructionDef {
@@ -1638,7 +1637,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // AND A (1), Z 0 1 0
     InstructionDef {
@@ -1646,7 +1645,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // XOR B (1), Z 0 0 0
     InstructionDef {
@@ -1654,7 +1653,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // XOR C (1), Z 0 0 0
     InstructionDef {
@@ -1662,7 +1661,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // XOR D (1), Z 0 0 0
     InstructionDef {
@@ -1670,7 +1669,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // XOR E (1), Z 0 0 0
     InstructionDef {
@@ -1678,7 +1677,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // XOR H (1), Z 0 0 0
     InstructionDef {
@@ -1686,7 +1685,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // XOR L (1), Z 0 0 0
     InstructionDef {
@@ -1694,7 +1693,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // XOR (HL) (1), Z 0 0 0
     InstructionDef {
@@ -1702,7 +1701,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // XOR A (1), Z 0 0 0
     InstructionDef {
@@ -1710,7 +1709,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // OR B (1), Z 0 0 0
     InstructionDef {
@@ -1718,7 +1717,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // OR C (1), Z 0 0 0
     InstructionDef {
@@ -1726,7 +1725,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // OR D (1), Z 0 0 0
     InstructionDef {
@@ -1734,7 +1733,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // OR E (1), Z 0 0 0
     InstructionDef {
@@ -1742,7 +1741,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // OR H (1), Z 0 0 0
     InstructionDef {
@@ -1750,7 +1749,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // OR L (1), Z 0 0 0
     InstructionDef {
@@ -1758,7 +1757,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // OR (HL) (1), Z 0 0 0
     InstructionDef {
@@ -1766,7 +1765,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // OR A (1), Z 0 0 0
     InstructionDef {
@@ -1774,7 +1773,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // CP B (1), Z 1 H C
     InstructionDef {
@@ -1782,7 +1781,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::B), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // CP C (1), Z 1 H C
     InstructionDef {
@@ -1790,7 +1789,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::C), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // CP D (1), Z 1 H C
     InstructionDef {
@@ -1798,7 +1797,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::D), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // CP E (1), Z 1 H C
     InstructionDef {
@@ -1806,7 +1805,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::E), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // CP H (1), Z 1 H C
     InstructionDef {
@@ -1814,7 +1813,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::H), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // CP L (1), Z 1 H C
     InstructionDef {
@@ -1822,7 +1821,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::L), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // CP (HL) (1), Z 1 H C
     InstructionDef {
@@ -1830,7 +1829,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // CP A (1), Z 1 H C
     InstructionDef {
@@ -1838,7 +1837,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // RET NZ (1), - - - -
     InstructionDef {
@@ -1846,7 +1845,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [20, 8],
-        func: CPU::op_ret_nz,
+        op: Op::RetNz,
     },
     // POP BC (1), - - - -
     InstructionDef {
@@ -1854,7 +1853,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::BC), Operand::None],
         len: 1,
         cycles: [12, 12],
-        func: CPU::op_pop,
+        op: Op::Pop,
     },
     // JP NZ,a16 (3), - - - -
     InstructionDef {
@@ -1862,7 +1861,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [16, 12],
-        func: CPU::op_jp_nz,
+        op: Op::JpNz,
     },
     // JP a16 (3), - - - -
     InstructionDef {
@@ -1870,7 +1869,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [16, 16],
-        func: CPU::op_jp,
+        op: Op::Jp,
     },
     // CALL NZ,a16 (3), - - - -
     InstructionDef {
@@ -1878,7 +1877,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [24, 12],
-        func: CPU::op_call_nz,
+        op: Op::CallNz,
     },
     // PUSH BC (1), - - - -
     InstructionDef {
@@ -1886,7 +1885,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::BC), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_push,
+        op: Op::Push,
     },
     // ADD A,d8 (2), Z 0 H C
     InstructionDef {
@@ -1894,7 +1893,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // RST 00H (1), - - - -
     InstructionDef {
@@ -1902,7 +1901,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x00), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
     // RET Z (1), - - - -
     InstructionDef {
@@ -1910,7 +1909,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [20, 8],
-        func: CPU::op_ret_z,
+        op: Op::RetZ,
     },
     // RET (1), - - - -
     InstructionDef {
@@ -1918,7 +1917,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_ret,
+        op: Op::Ret,
     },
     // JP Z,a16 (3), - - - -
     InstructionDef {
@@ -1926,7 +1925,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [16, 12],
-        func: CPU::op_jp_z,
+        op: Op::JpZ,
     },
     // PREFIX CB (1), - - - -
     InstructionDef {
@@ -1934,7 +1933,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_prefix_cb,
+        op: Op::PrefixCb,
     },
     // CALL Z,a16 (3), - - - -
     InstructionDef {
@@ -1942,7 +1941,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [24, 12],
-        func: CPU::op_call_z,
+        op: Op::CallZ,
     },
     // CALL a16 (3), - - - -
     InstructionDef {
@@ -1950,7 +1949,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [24, 24],
-        func: CPU::op_call,
+        op: Op::Call,
     },
     // ADC A,d8 (2), Z 0 H C
     InstructionDef {
@@ -1958,7 +1957,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_adc,
+        op: Op::Adc,
     },
     // RST 08H (1), - - - -
     InstructionDef {
@@ -1966,7 +1965,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x08), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
     // RET NC (1), - - - -
     InstructionDef {
@@ -1974,7 +1973,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [20, 8],
-        func: CPU::op_ret_nc,
+        op: Op::RetNc,
     },
     // POP DE (1), - - - -
     InstructionDef {
@@ -1982,7 +1981,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::DE), Operand::None],
         len: 1,
         cycles: [12, 12],
-        func: CPU::op_pop,
+        op: Op::Pop,
     },
     // JP NC,a16 (3), - - - -
     InstructionDef {
@@ -1990,7 +1989,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [16, 12],
-        func: CPU::op_jp_nc,
+        op: Op::JpNc,
     },
     // INVALID
     InstructionDef {
@@ -1998,7 +1997,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // CALL NC,a16 (3), - - - -
     InstructionDef {
@@ -2006,7 +2005,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [24, 12],
-        func: CPU::op_call_nc,
+        op: Op::CallNc,
     },
     // PUSH DE (1), - - - -
     InstructionDef {
@@ -2014,7 +2013,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::DE), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_push,
+        op: Op::Push,
     },
     // SUB d8 (2), Z 1 H C
     InstructionDef {
@@ -2022,7 +2021,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Immediate8, Operand::None],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_sub,
+        op: Op::Sub,
     },
     // RST 10H (1), - - - -
     InstructionDef {
@@ -2030,7 +2029,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x10), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
     // RET C (1), - - - -
     InstructionDef {
@@ -2038,7 +2037,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [20, 8],
-        func: CPU::op_ret_c,
+        op: Op::RetC,
     },
     // RETI (1), - - - -
     InstructionDef {
@@ -2046,7 +2045,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_reti,
+        op: Op::Reti,
     },
     // JP C,a16 (3), - - - -
     InstructionDef {
@@ -2054,7 +2053,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [16, 12],
-        func: CPU::op_jp,
+        op: Op::JpC,
     },
     // INVALID
     InstructionDef {
@@ -2062,7 +2061,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // CALL C,a16 (3), - - - -
     InstructionDef {
@@ -2070,7 +2069,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::None],
         len: 3,
         cycles: [24, 12],
-        func: CPU::op_call_c,
+        op: Op::CallC,
     },
     // INVALID
     InstructionDef {
@@ -2078,7 +2077,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // SBC A,d8 (2), Z 1 H C
     InstructionDef {
@@ -2086,7 +2085,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::Immediate8],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_sbc,
+        op: Op::Sbc,
     },
     // RST 18H (1), - - - -
     InstructionDef {
@@ -2094,7 +2093,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x18), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
     // LDH (a8),A (2), - - - -
     InstructionDef {
@@ -2102,7 +2101,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect8, Operand::Register(Register::A)],
         len: 2,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // POP HL (1), - - - -
     InstructionDef {
@@ -2110,18 +2109,18 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::HL), Operand::None],
         len: 1,
         cycles: [12, 12],
-        func: CPU::op_pop,
+        op: Op::Pop,
     },
-    // LD (C),A (2), - - - -
+    // LD (C),A (1), - - - -
     InstructionDef {
         mnemonic: "LD (C),A",
         operands: [
             Operand::RegisterIndirect(Register::C),
             Operand::Register(Register::A),
         ],
-        len: 2,
+        len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // INVALID
     InstructionDef {
@@ -2129,7 +2128,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // INVALID
     InstructionDef {
@@ -2137,7 +2136,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // PUSH HL (1), - - - -
     InstructionDef {
@@ -2145,7 +2144,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::HL), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_push,
+        op: Op::Push,
     },
     // AND d8 (2), Z 0 1 0
     InstructionDef {
@@ -2153,7 +2152,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Immediate8, Operand::None],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_and,
+        op: Op::And,
     },
     // RST 20H (1), - - - -
     InstructionDef {
@@ -2161,7 +2160,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x20), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
     // ADD SP,r8 (2), 0 0 H C
     InstructionDef {
@@ -2169,7 +2168,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::SP), Operand::Relative8],
         len: 2,
         cycles: [16, 16],
-        func: CPU::op_add,
+        op: Op::Add,
     },
     // JP (HL) (1), - - - -
     InstructionDef {
@@ -2177,7 +2176,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_jp,
+        op: Op::Jp,
     },
     // LD (a16),A (3), - - - -
     InstructionDef {
@@ -2185,7 +2184,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::ImmediateIndirect16, Operand::Register(Register::A)],
         len: 3,
         cycles: [16, 16],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // INVALID
     InstructionDef {
@@ -2193,7 +2192,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // INVALID
     InstructionDef {
@@ -2201,7 +2200,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // INVALID
     InstructionDef {
@@ -2209,7 +2208,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // XOR d8 (2), Z 0 0 0
     InstructionDef {
@@ -2217,7 +2216,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Immediate8, Operand::None],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_xor,
+        op: Op::Xor,
     },
     // RST 28H (1), - - - -
     InstructionDef {
@@ -2225,7 +2224,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x28), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
     // LDH A,(a8) (2), - - - -
     InstructionDef {
@@ -2233,7 +2232,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::ImmediateIndirect8],
         len: 2,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // POP AF (1), Z N H C
     InstructionDef {
@@ -2241,18 +2240,18 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::AF), Operand::None],
         len: 1,
         cycles: [12, 12],
-        func: CPU::op_pop,
+        op: Op::Pop,
     },
-    // LD A,(C) (2), - - - -
+    // LD A,(C) (1), - - - -
     InstructionDef {
         mnemonic: "LD A,(C)",
         operands: [
             Operand::Register(Register::A),
             Operand::RegisterIndirect(Register::C),
         ],
-        len: 2,
+        len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // DI (1), - - - -
     InstructionDef {
@@ -2260,7 +2259,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_di,
+        op: Op::Di,
     },
     // INVALID
     InstructionDef {
@@ -2268,7 +2267,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // PUSH AF (1), - - - -
     InstructionDef {
@@ -2276,7 +2275,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::AF), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_push,
+        op: Op::Push,
     },
     // OR d8 (2), Z 0 0 0
     InstructionDef {
@@ -2284,7 +2283,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Immediate8, Operand::None],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_or,
+        op: Op::Or,
     },
     // RST 30H (1), - - - -
     InstructionDef {
@@ -2292,7 +2291,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x30), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
     // LD HL,SP+r8 (2), 0 0 H C
     InstructionDef {
@@ -2300,7 +2299,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::HL), Operand::SPRelative8],
         len: 2,
         cycles: [12, 12],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD SP,HL (1), - - - -
     InstructionDef {
@@ -2311,7 +2310,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         ],
         len: 1,
         cycles: [8, 8],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // LD A,(a16) (3), - - - -
     InstructionDef {
@@ -2319,7 +2318,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Register(Register::A), Operand::ImmediateIndirect16],
         len: 3,
         cycles: [16, 16],
-        func: CPU::op_ld,
+        op: Op::Ld,
     },
     // EI (1), - - - -
     InstructionDef {
@@ -2327,7 +2326,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [4, 4],
-        func: CPU::op_ei,
+        op: Op::Ei,
     },
     // INVALID
     InstructionDef {
@@ -2335,7 +2334,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // INVALID
     InstructionDef {
@@ -2343,7 +2342,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::None, Operand::None],
         len: 1,
         cycles: [0, 0],
-        func: CPU::op_invalid,
+        op: Op::Invalid,
     },
     // CP d8 (2), Z 1 H C
     InstructionDef {
@@ -2351,7 +2350,7 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Immediate8, Operand::None],
         len: 2,
         cycles: [8, 8],
-        func: CPU::op_cp,
+        op: Op::Cp,
     },
     // RST 38H (1), - - - -
     InstructionDef {
@@ -2359,2130 +2358,1638 @@ pub const INSTRUCTIONS: [InstructionDef; 256] = [
         operands: [Operand::Constant(0x38), Operand::None],
         len: 1,
         cycles: [16, 16],
-        func: CPU::op_rst,
+        op: Op::Rst,
     },
 ];
 
+/// Definition of a CB-prefixed instruction. The cycles follow
+/// from the operands: 8 on a register, 16 to read-modify-write
+/// (HL), or 12 for BIT (HL), which only reads.
+const fn cb(mnemonic: &'static str, operands: [Operand; 2], op: Op) -> InstructionDef {
+    let indirect = matches!(operands[0], Operand::RegisterIndirect(_))
+        || matches!(operands[1], Operand::RegisterIndirect(_));
+    let cycles = match (indirect, op) {
+        (false, _) => 8,
+        (true, Op::Bit) => 12,
+        (true, _) => 16,
+    };
+    InstructionDef {
+        mnemonic,
+        operands,
+        len: 2,
+        cycles: [cycles; 2],
+        op,
+    }
+}
+
 /// 0xCB prefix instruction table, parsed from https://www.pastraiser.com/cpu/gameboy/gameboy_opcodes.html
 pub const INSTRUCTIONS_CB: [InstructionDef; 256] = [
     // RLC B (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Rlc,
+    ),
     // RLC C (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Rlc,
+    ),
     // RLC D (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Rlc,
+    ),
     // RLC E (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Rlc,
+    ),
     // RLC H (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Rlc,
+    ),
     // RLC L (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Rlc,
+    ),
     // RLC (HL) (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Rlc,
+    ),
     // RLC A (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RLC A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rlc,
-    },
+    cb(
+        "RLC A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Rlc,
+    ),
     // RRC B (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Rrc,
+    ),
     // RRC C (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Rrc,
+    ),
     // RRC D (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Rrc,
+    ),
     // RRC E (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Rrc,
+    ),
     // RRC H (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Rrc,
+    ),
     // RRC L (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Rrc,
+    ),
     // RRC (HL) (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Rrc,
+    ),
     // RRC A (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RRC A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rrc,
-    },
+    cb(
+        "RRC A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Rrc,
+    ),
     // RL B (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Rl,
+    ),
     // RL C (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Rl,
+    ),
     // RL D (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Rl,
+    ),
     // RL E (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Rl,
+    ),
     // RL H (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Rl,
+    ),
     // RL L (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Rl,
+    ),
     // RL (HL) (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Rl,
+    ),
     // RL A (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RL A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rl,
-    },
+    cb(
+        "RL A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Rl,
+    ),
     // RR B (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Rr,
+    ),
     // RR C (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Rr,
+    ),
     // RR D (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Rr,
+    ),
     // RR E (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Rr,
+    ),
     // RR H (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Rr,
+    ),
     // RR L (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Rr,
+    ),
     // RR (HL) (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Rr,
+    ),
     // RR A (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "RR A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_rr,
-    },
+    cb(
+        "RR A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Rr,
+    ),
     // SLA B (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Sla,
+    ),
     // SLA C (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Sla,
+    ),
     // SLA D (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Sla,
+    ),
     // SLA E (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Sla,
+    ),
     // SLA H (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Sla,
+    ),
     // SLA L (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Sla,
+    ),
     // SLA (HL) (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Sla,
+    ),
     // SLA A (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SLA A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sla,
-    },
+    cb(
+        "SLA A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Sla,
+    ),
     // SRA B (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Sra,
+    ),
     // SRA C (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Sra,
+    ),
     // SRA D (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Sra,
+    ),
     // SRA E (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Sra,
+    ),
     // SRA H (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Sra,
+    ),
     // SRA L (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Sra,
+    ),
     // SRA (HL) (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Sra,
+    ),
     // SRA A (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SRA A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_sra,
-    },
+    cb(
+        "SRA A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Sra,
+    ),
     // SWAP B (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Swap,
+    ),
     // SWAP C (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Swap,
+    ),
     // SWAP D (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Swap,
+    ),
     // SWAP E (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Swap,
+    ),
     // SWAP H (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Swap,
+    ),
     // SWAP L (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Swap,
+    ),
     // SWAP (HL) (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Swap,
+    ),
     // SWAP A (2), Z 0 0 0
-    InstructionDef {
-        mnemonic: "SWAP A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_swap,
-    },
+    cb(
+        "SWAP A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Swap,
+    ),
     // SRL B (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL B",
-        operands: [Operand::Register(Register::B), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL B",
+        [Operand::Register(Register::B), Operand::None],
+        Op::Srl,
+    ),
     // SRL C (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL C",
-        operands: [Operand::Register(Register::C), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL C",
+        [Operand::Register(Register::C), Operand::None],
+        Op::Srl,
+    ),
     // SRL D (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL D",
-        operands: [Operand::Register(Register::D), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL D",
+        [Operand::Register(Register::D), Operand::None],
+        Op::Srl,
+    ),
     // SRL E (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL E",
-        operands: [Operand::Register(Register::E), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL E",
+        [Operand::Register(Register::E), Operand::None],
+        Op::Srl,
+    ),
     // SRL H (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL H",
-        operands: [Operand::Register(Register::H), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL H",
+        [Operand::Register(Register::H), Operand::None],
+        Op::Srl,
+    ),
     // SRL L (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL L",
-        operands: [Operand::Register(Register::L), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL L",
+        [Operand::Register(Register::L), Operand::None],
+        Op::Srl,
+    ),
     // SRL (HL) (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL (HL)",
-        operands: [Operand::RegisterIndirect(Register::HL), Operand::None],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL (HL)",
+        [Operand::RegisterIndirect(Register::HL), Operand::None],
+        Op::Srl,
+    ),
     // SRL A (2), Z 0 0 C
-    InstructionDef {
-        mnemonic: "SRL A",
-        operands: [Operand::Register(Register::A), Operand::None],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_srl,
-    },
+    cb(
+        "SRL A",
+        [Operand::Register(Register::A), Operand::None],
+        Op::Srl,
+    ),
     // BIT 0,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,B",
-        operands: [Operand::Constant(0), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 0,B",
+        [Operand::Constant(0), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 0,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,C",
-        operands: [Operand::Constant(0), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 0,C",
+        [Operand::Constant(0), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 0,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,D",
-        operands: [Operand::Constant(0), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 0,D",
+        [Operand::Constant(0), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 0,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,E",
-        operands: [Operand::Constant(0), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 0,E",
+        [Operand::Constant(0), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 0,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,H",
-        operands: [Operand::Constant(0), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 0,H",
+        [Operand::Constant(0), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 0,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,L",
-        operands: [Operand::Constant(0), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 0,L",
+        [Operand::Constant(0), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 0,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,(HL)",
-        operands: [
+    cb(
+        "BIT 0,(HL)",
+        [
             Operand::Constant(0),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 0,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 0,A",
-        operands: [Operand::Constant(0), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 0,A",
+        [Operand::Constant(0), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // BIT 1,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,B",
-        operands: [Operand::Constant(1), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 1,B",
+        [Operand::Constant(1), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 1,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,C",
-        operands: [Operand::Constant(1), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 1,C",
+        [Operand::Constant(1), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 1,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,D",
-        operands: [Operand::Constant(1), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 1,D",
+        [Operand::Constant(1), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 1,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,E",
-        operands: [Operand::Constant(1), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 1,E",
+        [Operand::Constant(1), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 1,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,H",
-        operands: [Operand::Constant(1), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 1,H",
+        [Operand::Constant(1), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 1,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,L",
-        operands: [Operand::Constant(1), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 1,L",
+        [Operand::Constant(1), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 1,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,(HL)",
-        operands: [
+    cb(
+        "BIT 1,(HL)",
+        [
             Operand::Constant(1),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 1,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 1,A",
-        operands: [Operand::Constant(1), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 1,A",
+        [Operand::Constant(1), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // BIT 2,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,B",
-        operands: [Operand::Constant(2), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 2,B",
+        [Operand::Constant(2), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 2,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,C",
-        operands: [Operand::Constant(2), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 2,C",
+        [Operand::Constant(2), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 2,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,D",
-        operands: [Operand::Constant(2), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 2,D",
+        [Operand::Constant(2), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 2,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,E",
-        operands: [Operand::Constant(2), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 2,E",
+        [Operand::Constant(2), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 2,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,H",
-        operands: [Operand::Constant(2), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 2,H",
+        [Operand::Constant(2), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 2,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,L",
-        operands: [Operand::Constant(2), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 2,L",
+        [Operand::Constant(2), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 2,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,(HL)",
-        operands: [
+    cb(
+        "BIT 2,(HL)",
+        [
             Operand::Constant(2),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 2,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 2,A",
-        operands: [Operand::Constant(2), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 2,A",
+        [Operand::Constant(2), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // BIT 3,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,B",
-        operands: [Operand::Constant(3), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 3,B",
+        [Operand::Constant(3), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 3,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,C",
-        operands: [Operand::Constant(3), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 3,C",
+        [Operand::Constant(3), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 3,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,D",
-        operands: [Operand::Constant(3), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 3,D",
+        [Operand::Constant(3), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 3,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,E",
-        operands: [Operand::Constant(3), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 3,E",
+        [Operand::Constant(3), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 3,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,H",
-        operands: [Operand::Constant(3), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 3,H",
+        [Operand::Constant(3), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 3,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,L",
-        operands: [Operand::Constant(3), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 3,L",
+        [Operand::Constant(3), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 3,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,(HL)",
-        operands: [
+    cb(
+        "BIT 3,(HL)",
+        [
             Operand::Constant(3),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 3,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 3,A",
-        operands: [Operand::Constant(3), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 3,A",
+        [Operand::Constant(3), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // BIT 4,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,B",
-        operands: [Operand::Constant(4), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 4,B",
+        [Operand::Constant(4), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 4,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,C",
-        operands: [Operand::Constant(4), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 4,C",
+        [Operand::Constant(4), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 4,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,D",
-        operands: [Operand::Constant(4), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 4,D",
+        [Operand::Constant(4), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 4,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,E",
-        operands: [Operand::Constant(4), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 4,E",
+        [Operand::Constant(4), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 4,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,H",
-        operands: [Operand::Constant(4), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 4,H",
+        [Operand::Constant(4), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 4,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,L",
-        operands: [Operand::Constant(4), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 4,L",
+        [Operand::Constant(4), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 4,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,(HL)",
-        operands: [
+    cb(
+        "BIT 4,(HL)",
+        [
             Operand::Constant(4),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 4,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 4,A",
-        operands: [Operand::Constant(4), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 4,A",
+        [Operand::Constant(4), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // BIT 5,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,B",
-        operands: [Operand::Constant(5), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 5,B",
+        [Operand::Constant(5), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 5,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,C",
-        operands: [Operand::Constant(5), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 5,C",
+        [Operand::Constant(5), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 5,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,D",
-        operands: [Operand::Constant(5), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 5,D",
+        [Operand::Constant(5), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 5,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,E",
-        operands: [Operand::Constant(5), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 5,E",
+        [Operand::Constant(5), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 5,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,H",
-        operands: [Operand::Constant(5), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 5,H",
+        [Operand::Constant(5), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 5,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,L",
-        operands: [Operand::Constant(5), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 5,L",
+        [Operand::Constant(5), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 5,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,(HL)",
-        operands: [
+    cb(
+        "BIT 5,(HL)",
+        [
             Operand::Constant(5),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 5,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 5,A",
-        operands: [Operand::Constant(5), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 5,A",
+        [Operand::Constant(5), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // BIT 6,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,B",
-        operands: [Operand::Constant(6), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 6,B",
+        [Operand::Constant(6), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 6,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,C",
-        operands: [Operand::Constant(6), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 6,C",
+        [Operand::Constant(6), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 6,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,D",
-        operands: [Operand::Constant(6), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 6,D",
+        [Operand::Constant(6), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 6,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,E",
-        operands: [Operand::Constant(6), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 6,E",
+        [Operand::Constant(6), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 6,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,H",
-        operands: [Operand::Constant(6), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 6,H",
+        [Operand::Constant(6), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 6,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,L",
-        operands: [Operand::Constant(6), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 6,L",
+        [Operand::Constant(6), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 6,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,(HL)",
-        operands: [
+    cb(
+        "BIT 6,(HL)",
+        [
             Operand::Constant(6),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 6,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 6,A",
-        operands: [Operand::Constant(6), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 6,A",
+        [Operand::Constant(6), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // BIT 7,B (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,B",
-        operands: [Operand::Constant(7), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 7,B",
+        [Operand::Constant(7), Operand::Register(Register::B)],
+        Op::Bit,
+    ),
     // BIT 7,C (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,C",
-        operands: [Operand::Constant(7), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 7,C",
+        [Operand::Constant(7), Operand::Register(Register::C)],
+        Op::Bit,
+    ),
     // BIT 7,D (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,D",
-        operands: [Operand::Constant(7), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 7,D",
+        [Operand::Constant(7), Operand::Register(Register::D)],
+        Op::Bit,
+    ),
     // BIT 7,E (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,E",
-        operands: [Operand::Constant(7), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 7,E",
+        [Operand::Constant(7), Operand::Register(Register::E)],
+        Op::Bit,
+    ),
     // BIT 7,H (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,H",
-        operands: [Operand::Constant(7), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 7,H",
+        [Operand::Constant(7), Operand::Register(Register::H)],
+        Op::Bit,
+    ),
     // BIT 7,L (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,L",
-        operands: [Operand::Constant(7), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 7,L",
+        [Operand::Constant(7), Operand::Register(Register::L)],
+        Op::Bit,
+    ),
     // BIT 7,(HL) (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,(HL)",
-        operands: [
+    cb(
+        "BIT 7,(HL)",
+        [
             Operand::Constant(7),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_bit,
-    },
+        Op::Bit,
+    ),
     // BIT 7,A (2), Z 0 1 -
-    InstructionDef {
-        mnemonic: "BIT 7,A",
-        operands: [Operand::Constant(7), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_bit,
-    },
+    cb(
+        "BIT 7,A",
+        [Operand::Constant(7), Operand::Register(Register::A)],
+        Op::Bit,
+    ),
     // RES 0,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,B",
-        operands: [Operand::Constant(0), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 0,B",
+        [Operand::Constant(0), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 0,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,C",
-        operands: [Operand::Constant(0), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 0,C",
+        [Operand::Constant(0), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 0,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,D",
-        operands: [Operand::Constant(0), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 0,D",
+        [Operand::Constant(0), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 0,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,E",
-        operands: [Operand::Constant(0), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 0,E",
+        [Operand::Constant(0), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 0,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,H",
-        operands: [Operand::Constant(0), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 0,H",
+        [Operand::Constant(0), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 0,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,L",
-        operands: [Operand::Constant(0), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 0,L",
+        [Operand::Constant(0), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 0,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,(HL)",
-        operands: [
+    cb(
+        "RES 0,(HL)",
+        [
             Operand::Constant(0),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 0,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 0,A",
-        operands: [Operand::Constant(0), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 0,A",
+        [Operand::Constant(0), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // RES 1,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,B",
-        operands: [Operand::Constant(1), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 1,B",
+        [Operand::Constant(1), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 1,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,C",
-        operands: [Operand::Constant(1), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 1,C",
+        [Operand::Constant(1), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 1,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,D",
-        operands: [Operand::Constant(1), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 1,D",
+        [Operand::Constant(1), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 1,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,E",
-        operands: [Operand::Constant(1), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 1,E",
+        [Operand::Constant(1), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 1,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,H",
-        operands: [Operand::Constant(1), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 1,H",
+        [Operand::Constant(1), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 1,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,L",
-        operands: [Operand::Constant(1), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 1,L",
+        [Operand::Constant(1), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 1,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,(HL)",
-        operands: [
+    cb(
+        "RES 1,(HL)",
+        [
             Operand::Constant(1),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 1,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 1,A",
-        operands: [Operand::Constant(1), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 1,A",
+        [Operand::Constant(1), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // RES 2,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,B",
-        operands: [Operand::Constant(2), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 2,B",
+        [Operand::Constant(2), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 2,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,C",
-        operands: [Operand::Constant(2), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 2,C",
+        [Operand::Constant(2), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 2,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,D",
-        operands: [Operand::Constant(2), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 2,D",
+        [Operand::Constant(2), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 2,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,E",
-        operands: [Operand::Constant(2), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 2,E",
+        [Operand::Constant(2), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 2,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,H",
-        operands: [Operand::Constant(2), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 2,H",
+        [Operand::Constant(2), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 2,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,L",
-        operands: [Operand::Constant(2), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 2,L",
+        [Operand::Constant(2), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 2,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,(HL)",
-        operands: [
+    cb(
+        "RES 2,(HL)",
+        [
             Operand::Constant(2),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 2,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 2,A",
-        operands: [Operand::Constant(2), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 2,A",
+        [Operand::Constant(2), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // RES 3,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,B",
-        operands: [Operand::Constant(3), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 3,B",
+        [Operand::Constant(3), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 3,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,C",
-        operands: [Operand::Constant(3), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 3,C",
+        [Operand::Constant(3), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 3,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,D",
-        operands: [Operand::Constant(3), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 3,D",
+        [Operand::Constant(3), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 3,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,E",
-        operands: [Operand::Constant(3), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 3,E",
+        [Operand::Constant(3), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 3,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,H",
-        operands: [Operand::Constant(3), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 3,H",
+        [Operand::Constant(3), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 3,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,L",
-        operands: [Operand::Constant(3), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 3,L",
+        [Operand::Constant(3), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 3,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,(HL)",
-        operands: [
+    cb(
+        "RES 3,(HL)",
+        [
             Operand::Constant(3),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 3,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 3,A",
-        operands: [Operand::Constant(3), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 3,A",
+        [Operand::Constant(3), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // RES 4,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,B",
-        operands: [Operand::Constant(4), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 4,B",
+        [Operand::Constant(4), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 4,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,C",
-        operands: [Operand::Constant(4), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 4,C",
+        [Operand::Constant(4), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 4,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,D",
-        operands: [Operand::Constant(4), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 4,D",
+        [Operand::Constant(4), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 4,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,E",
-        operands: [Operand::Constant(4), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 4,E",
+        [Operand::Constant(4), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 4,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,H",
-        operands: [Operand::Constant(4), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 4,H",
+        [Operand::Constant(4), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 4,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,L",
-        operands: [Operand::Constant(4), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 4,L",
+        [Operand::Constant(4), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 4,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,(HL)",
-        operands: [
+    cb(
+        "RES 4,(HL)",
+        [
             Operand::Constant(4),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 4,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 4,A",
-        operands: [Operand::Constant(4), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 4,A",
+        [Operand::Constant(4), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // RES 5,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,B",
-        operands: [Operand::Constant(5), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 5,B",
+        [Operand::Constant(5), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 5,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,C",
-        operands: [Operand::Constant(5), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 5,C",
+        [Operand::Constant(5), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 5,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,D",
-        operands: [Operand::Constant(5), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 5,D",
+        [Operand::Constant(5), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 5,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,E",
-        operands: [Operand::Constant(5), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 5,E",
+        [Operand::Constant(5), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 5,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,H",
-        operands: [Operand::Constant(5), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 5,H",
+        [Operand::Constant(5), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 5,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,L",
-        operands: [Operand::Constant(5), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 5,L",
+        [Operand::Constant(5), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 5,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,(HL)",
-        operands: [
+    cb(
+        "RES 5,(HL)",
+        [
             Operand::Constant(5),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 5,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 5,A",
-        operands: [Operand::Constant(5), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 5,A",
+        [Operand::Constant(5), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // RES 6,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,B",
-        operands: [Operand::Constant(6), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 6,B",
+        [Operand::Constant(6), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 6,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,C",
-        operands: [Operand::Constant(6), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 6,C",
+        [Operand::Constant(6), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 6,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,D",
-        operands: [Operand::Constant(6), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 6,D",
+        [Operand::Constant(6), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 6,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,E",
-        operands: [Operand::Constant(6), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 6,E",
+        [Operand::Constant(6), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 6,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,H",
-        operands: [Operand::Constant(6), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 6,H",
+        [Operand::Constant(6), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 6,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,L",
-        operands: [Operand::Constant(6), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 6,L",
+        [Operand::Constant(6), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 6,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,(HL)",
-        operands: [
+    cb(
+        "RES 6,(HL)",
+        [
             Operand::Constant(6),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 6,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 6,A",
-        operands: [Operand::Constant(6), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 6,A",
+        [Operand::Constant(6), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // RES 7,B (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,B",
-        operands: [Operand::Constant(7), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 7,B",
+        [Operand::Constant(7), Operand::Register(Register::B)],
+        Op::Res,
+    ),
     // RES 7,C (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,C",
-        operands: [Operand::Constant(7), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 7,C",
+        [Operand::Constant(7), Operand::Register(Register::C)],
+        Op::Res,
+    ),
     // RES 7,D (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,D",
-        operands: [Operand::Constant(7), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 7,D",
+        [Operand::Constant(7), Operand::Register(Register::D)],
+        Op::Res,
+    ),
     // RES 7,E (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,E",
-        operands: [Operand::Constant(7), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 7,E",
+        [Operand::Constant(7), Operand::Register(Register::E)],
+        Op::Res,
+    ),
     // RES 7,H (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,H",
-        operands: [Operand::Constant(7), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 7,H",
+        [Operand::Constant(7), Operand::Register(Register::H)],
+        Op::Res,
+    ),
     // RES 7,L (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,L",
-        operands: [Operand::Constant(7), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 7,L",
+        [Operand::Constant(7), Operand::Register(Register::L)],
+        Op::Res,
+    ),
     // RES 7,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,(HL)",
-        operands: [
+    cb(
+        "RES 7,(HL)",
+        [
             Operand::Constant(7),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_res,
-    },
+        Op::Res,
+    ),
     // RES 7,A (2), - - - -
-    InstructionDef {
-        mnemonic: "RES 7,A",
-        operands: [Operand::Constant(7), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_res,
-    },
+    cb(
+        "RES 7,A",
+        [Operand::Constant(7), Operand::Register(Register::A)],
+        Op::Res,
+    ),
     // SET 0,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,B",
-        operands: [Operand::Constant(0), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 0,B",
+        [Operand::Constant(0), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 0,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,C",
-        operands: [Operand::Constant(0), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 0,C",
+        [Operand::Constant(0), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 0,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,D",
-        operands: [Operand::Constant(0), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 0,D",
+        [Operand::Constant(0), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 0,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,E",
-        operands: [Operand::Constant(0), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 0,E",
+        [Operand::Constant(0), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 0,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,H",
-        operands: [Operand::Constant(0), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 0,H",
+        [Operand::Constant(0), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 0,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,L",
-        operands: [Operand::Constant(0), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 0,L",
+        [Operand::Constant(0), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 0,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,(HL)",
-        operands: [
+    cb(
+        "SET 0,(HL)",
+        [
             Operand::Constant(0),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 0,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 0,A",
-        operands: [Operand::Constant(0), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 0,A",
+        [Operand::Constant(0), Operand::Register(Register::A)],
+        Op::Set,
+    ),
     // SET 1,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,B",
-        operands: [Operand::Constant(1), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 1,B",
+        [Operand::Constant(1), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 1,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,C",
-        operands: [Operand::Constant(1), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 1,C",
+        [Operand::Constant(1), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 1,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,D",
-        operands: [Operand::Constant(1), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 1,D",
+        [Operand::Constant(1), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 1,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,E",
-        operands: [Operand::Constant(1), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 1,E",
+        [Operand::Constant(1), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 1,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,H",
-        operands: [Operand::Constant(1), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 1,H",
+        [Operand::Constant(1), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 1,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,L",
-        operands: [Operand::Constant(1), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 1,L",
+        [Operand::Constant(1), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 1,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,(HL)",
-        operands: [
+    cb(
+        "SET 1,(HL)",
+        [
             Operand::Constant(1),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 1,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 1,A",
-        operands: [Operand::Constant(1), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 1,A",
+        [Operand::Constant(1), Operand::Register(Register::A)],
+        Op::Set,
+    ),
     // SET 2,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,B",
-        operands: [Operand::Constant(2), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 2,B",
+        [Operand::Constant(2), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 2,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,C",
-        operands: [Operand::Constant(2), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 2,C",
+        [Operand::Constant(2), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 2,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,D",
-        operands: [Operand::Constant(2), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 2,D",
+        [Operand::Constant(2), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 2,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,E",
-        operands: [Operand::Constant(2), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 2,E",
+        [Operand::Constant(2), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 2,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,H",
-        operands: [Operand::Constant(2), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 2,H",
+        [Operand::Constant(2), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 2,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,L",
-        operands: [Operand::Constant(2), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 2,L",
+        [Operand::Constant(2), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 2,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,(HL)",
-        operands: [
+    cb(
+        "SET 2,(HL)",
+        [
             Operand::Constant(2),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 2,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 2,A",
-        operands: [Operand::Constant(2), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 2,A",
+        [Operand::Constant(2), Operand::Register(Register::A)],
+        Op::Set,
+    ),
     // SET 3,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,B",
-        operands: [Operand::Constant(3), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 3,B",
+        [Operand::Constant(3), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 3,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,C",
-        operands: [Operand::Constant(3), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 3,C",
+        [Operand::Constant(3), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 3,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,D",
-        operands: [Operand::Constant(3), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 3,D",
+        [Operand::Constant(3), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 3,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,E",
-        operands: [Operand::Constant(3), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 3,E",
+        [Operand::Constant(3), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 3,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,H",
-        operands: [Operand::Constant(3), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 3,H",
+        [Operand::Constant(3), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 3,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,L",
-        operands: [Operand::Constant(3), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 3,L",
+        [Operand::Constant(3), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 3,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,(HL)",
-        operands: [
+    cb(
+        "SET 3,(HL)",
+        [
             Operand::Constant(3),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 3,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 3,A",
-        operands: [Operand::Constant(3), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 3,A",
+        [Operand::Constant(3), Operand::Register(Register::A)],
+        Op::Set,
+    ),
     // SET 4,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,B",
-        operands: [Operand::Constant(4), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 4,B",
+        [Operand::Constant(4), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 4,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,C",
-        operands: [Operand::Constant(4), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 4,C",
+        [Operand::Constant(4), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 4,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,D",
-        operands: [Operand::Constant(4), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 4,D",
+        [Operand::Constant(4), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 4,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,E",
-        operands: [Operand::Constant(4), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 4,E",
+        [Operand::Constant(4), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 4,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,H",
-        operands: [Operand::Constant(4), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 4,H",
+        [Operand::Constant(4), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 4,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,L",
-        operands: [Operand::Constant(4), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 4,L",
+        [Operand::Constant(4), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 4,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,(HL)",
-        operands: [
+    cb(
+        "SET 4,(HL)",
+        [
             Operand::Constant(4),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 4,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 4,A",
-        operands: [Operand::Constant(4), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 4,A",
+        [Operand::Constant(4), Operand::Register(Register::A)],
+        Op::Set,
+    ),
     // SET 5,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,B",
-        operands: [Operand::Constant(5), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 5,B",
+        [Operand::Constant(5), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 5,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,C",
-        operands: [Operand::Constant(5), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 5,C",
+        [Operand::Constant(5), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 5,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,D",
-        operands: [Operand::Constant(5), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 5,D",
+        [Operand::Constant(5), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 5,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,E",
-        operands: [Operand::Constant(5), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 5,E",
+        [Operand::Constant(5), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 5,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,H",
-        operands: [Operand::Constant(5), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 5,H",
+        [Operand::Constant(5), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 5,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,L",
-        operands: [Operand::Constant(5), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 5,L",
+        [Operand::Constant(5), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 5,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,(HL)",
-        operands: [
+    cb(
+        "SET 5,(HL)",
+        [
             Operand::Constant(5),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 5,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 5,A",
-        operands: [Operand::Constant(5), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 5,A",
+        [Operand::Constant(5), Operand::Register(Register::A)],
+        Op::Set,
+    ),
     // SET 6,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,B",
-        operands: [Operand::Constant(6), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 6,B",
+        [Operand::Constant(6), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 6,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,C",
-        operands: [Operand::Constant(6), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 6,C",
+        [Operand::Constant(6), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 6,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,D",
-        operands: [Operand::Constant(6), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 6,D",
+        [Operand::Constant(6), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 6,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,E",
-        operands: [Operand::Constant(6), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 6,E",
+        [Operand::Constant(6), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 6,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,H",
-        operands: [Operand::Constant(6), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 6,H",
+        [Operand::Constant(6), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 6,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,L",
-        operands: [Operand::Constant(6), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 6,L",
+        [Operand::Constant(6), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 6,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,(HL)",
-        operands: [
+    cb(
+        "SET 6,(HL)",
+        [
             Operand::Constant(6),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 6,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 6,A",
-        operands: [Operand::Constant(6), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 6,A",
+        [Operand::Constant(6), Operand::Register(Register::A)],
+        Op::Set,
+    ),
     // SET 7,B (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,B",
-        operands: [Operand::Constant(7), Operand::Register(Register::B)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 7,B",
+        [Operand::Constant(7), Operand::Register(Register::B)],
+        Op::Set,
+    ),
     // SET 7,C (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,C",
-        operands: [Operand::Constant(7), Operand::Register(Register::C)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 7,C",
+        [Operand::Constant(7), Operand::Register(Register::C)],
+        Op::Set,
+    ),
     // SET 7,D (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,D",
-        operands: [Operand::Constant(7), Operand::Register(Register::D)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 7,D",
+        [Operand::Constant(7), Operand::Register(Register::D)],
+        Op::Set,
+    ),
     // SET 7,E (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,E",
-        operands: [Operand::Constant(7), Operand::Register(Register::E)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 7,E",
+        [Operand::Constant(7), Operand::Register(Register::E)],
+        Op::Set,
+    ),
     // SET 7,H (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,H",
-        operands: [Operand::Constant(7), Operand::Register(Register::H)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 7,H",
+        [Operand::Constant(7), Operand::Register(Register::H)],
+        Op::Set,
+    ),
     // SET 7,L (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,L",
-        operands: [Operand::Constant(7), Operand::Register(Register::L)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 7,L",
+        [Operand::Constant(7), Operand::Register(Register::L)],
+        Op::Set,
+    ),
     // SET 7,(HL) (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,(HL)",
-        operands: [
+    cb(
+        "SET 7,(HL)",
+        [
             Operand::Constant(7),
             Operand::RegisterIndirect(Register::HL),
         ],
-        len: 2,
-        cycles: [16, 16],
-        func: CPU::op_set,
-    },
+        Op::Set,
+    ),
     // SET 7,A (2), - - - -
-    InstructionDef {
-        mnemonic: "SET 7,A",
-        operands: [Operand::Constant(7), Operand::Register(Register::A)],
-        len: 2,
-        cycles: [8, 8],
-        func: CPU::op_set,
-    },
+    cb(
+        "SET 7,A",
+        [Operand::Constant(7), Operand::Register(Register::A)],
+        Op::Set,
+    ),
 ];
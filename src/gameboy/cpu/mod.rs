@@ -1,5 +1,6 @@
 mod alu;
+#[allow(clippy::module_inception)]
 pub mod cpu;
 pub mod instruction;
 pub mod instructions;
-mod regs;
+pub mod regs;
@@ -4,6 +4,8 @@ use anyhow::{bail, Result};
 use num_derive::ToPrimitive;
 use num_traits::ToPrimitive;
 
+use super::super::savestate::{StateReader, StateWriter};
+
 /// Datatype of a single CPU register.
 type Reg = u8;
 
@@ -112,9 +114,45 @@ impl RegisterFile {
         }
     }
 
+    /// Register values the DMG boot ROM leaves behind
+    /// when it jumps to the cartridge at 0x0100.
+    pub fn new_post_boot() -> Self {
+        Self {
+            a: 0x01,
+            f: 0xB0,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        }
+    }
+
+    /// Register values the CGB boot ROM leaves behind
+    /// when it starts a CGB cartridge.
+    pub fn new_post_boot_cgb() -> Self {
+        Self {
+            a: 0x11,
+            f: 0x80,
+            b: 0x00,
+            c: 0x00,
+            d: 0xFF,
+            e: 0x56,
+            h: 0x00,
+            l: 0x0D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        }
+    }
+
     /// Write a value to a register.
     /// Returns an error when attempting to write
     /// a 16-bit value to an 8-bit register.
+    /// The lower 4 bits of F do not exist and always
+    /// read back as zero when written through AF.
     pub fn write(&mut self, reg: Register, val: u16) -> Result<()> {
         let reg8 = || {
             if val > u8::MAX.into() {
@@ -140,7 +178,10 @@ impl RegisterFile {
             Register::L => self.l = reg8()?,
 
             // 16-bit combination registers
-            Register::AF => (self.a, self.f) = reg16()?,
+            Register::AF => {
+                (self.a, self.f) = reg16()?;
+                self.f &= 0xF0;
+            }
             Register::BC => (self.b, self.c) = reg16()?,
             Register::DE => (self.d, self.e) = reg16()?,
             Register::HL => (self.h, self.l) = reg16()?,
@@ -156,7 +197,16 @@ impl RegisterFile {
     /// Write an 8-bit value to an 8-bit register.
     #[inline(always)]
     pub fn write8(&mut self, reg: Register, val: u8) -> Result<()> {
-        self.write(reg, val.try_into()?)
+        self.write(reg, val.into())
+    }
+
+    /// Write a 16-bit value to a 16-bit register.
+    /// Returns an error if the register is not 16-bit.
+    pub fn write16(&mut self, reg: Register, val: u16) -> Result<()> {
+        match reg.width() {
+            RegisterWidth::SixteenBit => self.write(reg, val),
+            _ => bail!("Attempting 16-bit write on 8-bit register {:?}", reg),
+        }
     }
 
     /// Read an 8-bit or 16-bit register.
@@ -207,7 +257,7 @@ impl RegisterFile {
     /// Read register and increment.
     pub fn read_inc(&mut self, reg: Register) -> Result<u16> {
         match reg.width() {
-            RegisterWidth::EightBit => Ok(self.read8_inc(reg)?.try_into()?),
+            RegisterWidth::EightBit => Ok(self.read8_inc(reg)?.into()),
             RegisterWidth::SixteenBit => Ok(self.read16_inc(reg)?),
         }
     }
@@ -215,7 +265,7 @@ impl RegisterFile {
     /// Read register and decrement.
     pub fn read_dec(&mut self, reg: Register) -> Result<u16> {
         match reg.width() {
-            RegisterWidth::EightBit => Ok(self.read8_dec(reg)?.try_into()?),
+            RegisterWidth::EightBit => Ok(self.read8_dec(reg)?.into()),
             RegisterWidth::SixteenBit => Ok(self.read16_dec(reg)?),
         }
     }
@@ -223,8 +273,8 @@ impl RegisterFile {
     /// Read 8-bit register and increment.
     pub fn read8_inc(&mut self, reg: Register) -> Result<u8> {
         let val = self.read8(reg)?;
-        self.write(reg, val.wrapping_add(1).try_into()?)?;
-        Ok(val.try_into()?)
+        self.write(reg, val.wrapping_add(1).into())?;
+        Ok(val)
     }
 
     /// Read 16-bit register and increment.
@@ -237,8 +287,8 @@ impl RegisterFile {
     /// Read 8-bit register and decrement.
     pub fn read8_dec(&mut self, reg: Register) -> Result<u8> {
         let val = self.read8(reg)?;
-        self.write(reg, val.wrapping_sub(1).try_into()?)?;
-        Ok(val.try_into()?)
+        self.write(reg, val.wrapping_sub(1).into())?;
+        Ok(val)
     }
 
     /// Read 16-bit register and decrement.
@@ -267,6 +317,42 @@ impl RegisterFile {
     pub fn test_flag(&self, f: Flag) -> bool {
         self.f & (1u8 << f.to_u8().unwrap()) != 0
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.tag(b"REGS");
+        for r in [
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ] {
+            w.u8(r);
+        }
+        w.u16(self.sp);
+        w.u16(self.pc);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"REGS", "registers")?;
+        for reg in [
+            &mut self.a,
+            &mut self.f,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.h,
+            &mut self.l,
+        ] {
+            *reg = r.u8()?;
+        }
+        self.sp = r.u16()?;
+        self.pc = r.u16()?;
+        Ok(())
+    }
+}
+
+impl Default for RegisterFile {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl fmt::Display for RegisterFile {
@@ -326,14 +412,37 @@ mod tests {
         assert_eq!(r.l, 0x12);
     }
 
+    #[test]
+    fn write16() {
+        let mut r = RegisterFile::new();
+        r.write16(Register::BC, 0x1234).unwrap();
+        assert_eq!((r.b, r.c), (0x12, 0x34));
+
+        let mut r = RegisterFile::new();
+        r.write16(Register::SP, 0x1234).unwrap();
+        assert_eq!(r.sp, 0x1234);
+
+        let mut r = RegisterFile::new();
+        r.write16(Register::AF, 0x12FF).unwrap();
+        assert_eq!((r.a, r.f), (0x12, 0xF0));
+        assert_eq!(r.read16(Register::AF).unwrap(), 0x12F0);
+    }
+
+    #[test]
+    fn write16_error() {
+        let mut r = RegisterFile::new();
+        assert!(r.write16(Register::A, 0x12).is_err());
+        assert_eq!(r.a, 0);
+    }
+
     #[test]
     fn write_8bit_error() {
         let mut r = RegisterFile::new();
-        assert!(matches!(r.write(Register::A, 0xFF), Ok(_)));
+        assert!(r.write(Register::A, 0xFF).is_ok());
         assert_eq!(r.a, 0xFF);
 
         let mut r = RegisterFile::new();
-        assert!(matches!(r.write(Register::A, 0x1FF), Err(_)));
+        assert!(r.write(Register::A, 0x1FF).is_err());
         assert_eq!(r.a, 0);
     }
 
@@ -341,7 +450,7 @@ mod tests {
     fn write_comb16bit() {
         let mut r = RegisterFile::new();
         r.write(Register::AF, 0x1234).unwrap();
-        assert_eq!((r.a, r.f), (0x12, 0x34));
+        assert_eq!((r.a, r.f), (0x12, 0x30));
 
         let mut r = RegisterFile::new();
         r.write(Register::BC, 0x1234).unwrap();
@@ -405,7 +514,7 @@ mod tests {
     #[test]
     fn read8_error() {
         let r = RegisterFile::new();
-        assert!(matches!(r.read8(Register::AF), Err(_)));
+        assert!(r.read8(Register::AF).is_err());
     }
 
     #[test]
@@ -427,10 +536,30 @@ mod tests {
         assert!(matches!(r.read16(Register::HL), Ok(0x1234)));
     }
 
+    #[test]
+    fn post_boot() {
+        let r = RegisterFile::new_post_boot();
+        assert_eq!(r.read16(Register::AF).unwrap(), 0x01B0);
+        assert_eq!(r.read16(Register::BC).unwrap(), 0x0013);
+        assert_eq!(r.read16(Register::DE).unwrap(), 0x00D8);
+        assert_eq!(r.read16(Register::HL).unwrap(), 0x014D);
+        assert_eq!(r.sp, 0xFFFE);
+        assert_eq!(r.pc, 0x0100);
+    }
+
+    #[test]
+    fn post_boot_cgb() {
+        let r = RegisterFile::new_post_boot_cgb();
+        assert_eq!(r.read16(Register::AF).unwrap(), 0x1180);
+        assert_eq!(r.read16(Register::BC).unwrap(), 0x0000);
+        assert_eq!(r.read16(Register::DE).unwrap(), 0xFF56);
+        assert_eq!(r.read16(Register::HL).unwrap(), 0x000D);
+    }
+
     #[test]
     fn read16_error() {
         let r = RegisterFile::new();
-        assert!(matches!(r.read16(Register::A), Err(_)));
+        assert!(r.read16(Register::A).is_err());
     }
 
     #[test]
@@ -594,6 +723,12 @@ mod tests {
         (r.h, r.l) = (0x12, 0x34);
         assert_eq!(r.read_inc(Register::HL).unwrap(), 0x1234);
         assert_eq!((r.h, r.l), (0x12, 0x35));
+
+        // Check crossing 8-bit boundary
+        let mut r = RegisterFile::new();
+        (r.h, r.l) = (0x00, 0xFF);
+        assert_eq!(r.read_inc(Register::HL).unwrap(), 0x00FF);
+        assert_eq!((r.h, r.l), (0x01, 0x00));
     }
 
     #[test]
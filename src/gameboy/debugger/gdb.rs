@@ -0,0 +1,490 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use anyhow::{bail, Result};
+
+use super::super::bus::bus::Bus;
+use super::super::cpu::cpu::{StopReason, WatchKind, CPU};
+
+/// Cycles run between checks for an interrupt (Ctrl-C)
+/// from the debugger while continuing.
+const CONTINUE_SLICE: usize = 70224;
+
+/// Registers in the order of GDB's Z80 target (16-bit each):
+/// AF, BC, DE, HL, SP, PC, IX, IY, AF', BC', DE', HL', IR.
+/// The SM83 lacks the registers from IX on, they read as 0.
+const NUM_REGS: usize = 13;
+
+/// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// What the session loop should do after a packet
+#[derive(Debug, Eq, PartialEq)]
+enum Action {
+    /// Send a reply
+    Reply(String),
+
+    /// Resume execution until a breakpoint or interrupt
+    Continue,
+
+    /// Execute a single instruction
+    Step,
+
+    /// End the session
+    Detach,
+}
+
+/// GDB remote serial protocol stub, driving a CPU for
+/// a debugger attached over TCP.
+///
+/// The SM83 is presented as a Z80, so GDB can attach with
+/// 'set architecture z80' and 'target remote host:port'.
+pub struct GdbStub<'a, B: Bus = Box<dyn Bus>> {
+    cpu: &'a mut CPU<B>,
+}
+
+impl<'a, B: Bus> GdbStub<'a, B> {
+    pub fn new(cpu: &'a mut CPU<B>) -> Self {
+        Self { cpu }
+    }
+
+    /// Waits for a debugger to connect on 'listener' and
+    /// serves it until it detaches. 'connected' is called with
+    /// the address of the debugger once it connects.
+    pub fn listen(
+        &mut self,
+        listener: &TcpListener,
+        connected: impl FnOnce(SocketAddr),
+    ) -> Result<()> {
+        let (stream, peer) = listener.accept()?;
+        connected(peer);
+        self.serve(stream)
+    }
+
+    /// Serves a connected debugger until it detaches or
+    /// disconnects.
+    pub fn serve(&mut self, mut stream: TcpStream) -> Result<()> {
+        stream.set_nodelay(true)?;
+        while let Some(packet) = read_packet(&mut stream)? {
+            let reply = match self.handle_packet(&packet) {
+                Action::Reply(r) => r,
+                Action::Step => {
+                    let signal = match self.cpu.step() {
+                        Ok(()) => SIGTRAP,
+                        Err(_) => SIGILL,
+                    };
+                    stop_reply(signal)
+                }
+                Action::Continue => match self.resume(&mut stream)? {
+                    Some(r) => r,
+                    None => return Ok(()),
+                },
+                Action::Detach => {
+                    send_packet(&mut stream, "OK")?;
+                    return Ok(());
+                }
+            };
+            send_packet(&mut stream, &reply)?;
+        }
+        Ok(())
+    }
+
+    /// Runs until a breakpoint, watch or interrupt from the
+    /// debugger. Returns the stop reply, or None if the
+    /// debugger disconnected.
+    fn resume(&mut self, stream: &mut TcpStream) -> Result<Option<String>> {
+        stream.set_nonblocking(true)?;
+        let reply = loop {
+            match self.cpu.run_until(CONTINUE_SLICE) {
+                Ok(StopReason::Watch { addr, .. })
+                | Ok(StopReason::Access {
+                    addr, write: true, ..
+                }) => break Some(format!("T{:02x}watch:{:04x};", SIGTRAP, addr)),
+                Ok(StopReason::Access { addr, .. }) => {
+                    break Some(format!("T{:02x}rwatch:{:04x};", SIGTRAP, addr))
+                }
+                Ok(StopReason::CycleBudget) => (),
+                Ok(_) => break Some(stop_reply(SIGTRAP)),
+                Err(_) => break Some(stop_reply(SIGILL)),
+            }
+
+            let mut b = [0];
+            match stream.read(&mut b) {
+                Ok(0) => break None,
+                // Ctrl-C
+                Ok(_) if b[0] == 0x03 => break Some(stop_reply(SIGINT)),
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e.into()),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        Ok(reply)
+    }
+
+    /// Handles a single packet (without framing).
+    fn handle_packet(&mut self, packet: &str) -> Action {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        let reply = match cmd {
+            "?" => stop_reply(SIGTRAP),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < NUM_REGS => hex_u16(self.register(n)),
+                _ => error(0),
+            },
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "c" | "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.cpu.regs.pc = addr;
+                }
+                return if cmd == "c" {
+                    Action::Continue
+                } else {
+                    Action::Step
+                };
+            }
+            "D" | "k" => return Action::Detach,
+            "H" => "OK".to_string(),
+            "q" => match args {
+                "C" => "QC1".to_string(),
+                "Attached" => "1".to_string(),
+                "fThreadInfo" => "m1".to_string(),
+                "sThreadInfo" => "l".to_string(),
+                _ if args.starts_with("Supported") => "PacketSize=4000".to_string(),
+                _ => String::new(),
+            },
+            // Unsupported packets get an empty reply
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    /// Value of register 'n', in GDB's order.
+    fn register(&self, n: usize) -> u16 {
+        let r = &self.cpu.regs;
+        match n {
+            0 => u16::from_be_bytes([r.a, r.f]),
+            1 => u16::from_be_bytes([r.b, r.c]),
+            2 => u16::from_be_bytes([r.d, r.e]),
+            3 => u16::from_be_bytes([r.h, r.l]),
+            4 => r.sp,
+            5 => r.pc,
+            _ => 0,
+        }
+    }
+
+    fn set_register(&mut self, n: usize, val: u16) {
+        let r = &mut self.cpu.regs;
+        let [hi, lo] = val.to_be_bytes();
+        match n {
+            // The lower 4 bits of F do not exist
+            0 => (r.a, r.f) = (hi, lo & 0xF0),
+            1 => (r.b, r.c) = (hi, lo),
+            2 => (r.d, r.e) = (hi, lo),
+            3 => (r.h, r.l) = (hi, lo),
+            4 => r.sp = val,
+            5 => r.pc = val,
+            _ => (),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..NUM_REGS).map(|n| hex_u16(self.register(n))).collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(vals) = decode_hex(args) else {
+            return error(0);
+        };
+        for (n, v) in vals.chunks_exact(2).enumerate() {
+            self.set_register(n, u16::from_le_bytes([v[0], v[1]]));
+        }
+        "OK".to_string()
+    }
+
+    /// P n=v
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((n, val)) = args.split_once('=') else {
+            return error(0);
+        };
+        match (usize::from_str_radix(n, 16), decode_hex(val)) {
+            (Ok(n), Some(v)) if v.len() == 2 => {
+                self.set_register(n, u16::from_le_bytes([v[0], v[1]]));
+                "OK".to_string()
+            }
+            _ => error(0),
+        }
+    }
+
+    /// m addr,length
+    fn read_memory(&self, args: &str) -> String {
+        let Some((addr, len)) = parse_range(args) else {
+            return error(0);
+        };
+        (0..len)
+            .map(|i| format!("{:02x}", self.cpu.bus.peek(addr.wrapping_add(i))))
+            .collect()
+    }
+
+    /// M addr,length:data
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return error(0);
+        };
+        match (parse_range(range), decode_hex(data)) {
+            (Some((addr, len)), Some(data)) if data.len() == len as usize => {
+                for (i, &b) in data.iter().enumerate() {
+                    self.cpu.bus.write(addr.wrapping_add(i as u16), b);
+                }
+                "OK".to_string()
+            }
+            _ => error(0),
+        }
+    }
+
+    /// Z/z type,addr,kind
+    /// Software and hardware breakpoints map to CPU
+    /// breakpoints, watchpoints to CPU watchpoints.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let (Some(kind), Some(addr)) = (parts.next(), parts.next().and_then(parse_hex)) else {
+            return error(0);
+        };
+        match (kind, insert) {
+            ("0" | "1", true) => self.cpu.add_breakpoint(addr),
+            ("0" | "1", false) => self.cpu.remove_breakpoint(addr),
+            ("2", true) => self.cpu.add_watchpoint(addr, WatchKind::Write),
+            ("3", true) => self.cpu.add_watchpoint(addr, WatchKind::Read),
+            ("4", true) => self.cpu.add_watchpoint(addr, WatchKind::Access),
+            ("2" | "3" | "4", false) => self.cpu.remove_watchpoint(addr),
+            _ => return String::new(),
+        }
+        "OK".to_string()
+    }
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn error(code: u8) -> String {
+    format!("E{:02x}", code)
+}
+
+/// 16-bit value as target (little endian) byte order hex
+fn hex_u16(val: u16) -> String {
+    let [lo, hi] = val.to_le_bytes();
+    format!("{:02x}{:02x}", lo, hi)
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// addr,length
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |acc, b| acc.wrapping_add(b))
+}
+
+/// Frames a packet: $data#checksum
+fn encode_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+fn send_packet(stream: &mut impl Write, data: &str) -> Result<()> {
+    stream.write_all(encode_packet(data).as_bytes())?;
+    Ok(stream.flush()?)
+}
+
+/// Reads the next packet, acknowledging it. Packets with a
+/// bad checksum are rejected and the debugger resends them.
+/// Returns None when the connection is closed.
+fn read_packet(stream: &mut (impl Read + Write)) -> Result<Option<String>> {
+    fn next(stream: &mut impl Read) -> Result<Option<u8>> {
+        let mut b = [0];
+        match stream.read(&mut b)? {
+            0 => Ok(None),
+            _ => Ok(Some(b[0])),
+        }
+    }
+
+    loop {
+        // Skip acks and anything else up to the start
+        // of a packet.
+        loop {
+            match next(stream)? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(_) => (),
+            }
+        }
+
+        let mut data = vec![];
+        loop {
+            match next(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(b) => data.push(b),
+            }
+        }
+        let (Some(c1), Some(c2)) = (next(stream)?, next(stream)?) else {
+            return Ok(None);
+        };
+
+        let Ok(data) = String::from_utf8(data) else {
+            bail!("Non-UTF8 packet from debugger");
+        };
+        let sum = std::str::from_utf8(&[c1, c2])
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok());
+        if sum == Some(checksum(&data)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::super::super::bus::testbus::Testbus;
+    use super::*;
+
+    fn cpu(code: &[u8]) -> CPU {
+        CPU::new(Box::new(Testbus::from(code)))
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle_packet(packet) {
+            Action::Reply(r) => r,
+            a => panic!("Unexpected action {:?}", a),
+        }
+    }
+
+    /// Read/write stream over an input buffer
+    struct Loopback {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn listen() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut s = TcpStream::connect(addr).unwrap();
+            s.write_all(b"$D#44").unwrap();
+            let mut reply = String::new();
+            s.read_to_string(&mut reply).unwrap();
+            reply
+        });
+
+        let mut c = cpu(&[]);
+        let mut peer = None;
+        GdbStub::new(&mut c)
+            .listen(&listener, |p| peer = Some(p))
+            .unwrap();
+        assert!(peer.is_some());
+        assert_eq!(client.join().unwrap(), "+$OK#9a");
+    }
+
+    #[test]
+    fn framing() {
+        assert_eq!(encode_packet("OK"), "$OK#9a");
+
+        let mut s = Loopback {
+            input: Cursor::new(b"+$g#00$g#67$m0,2#fb".to_vec()),
+            output: vec![],
+        };
+        // Bad checksum is rejected
+        assert_eq!(read_packet(&mut s).unwrap().as_deref(), Some("g"));
+        assert_eq!(read_packet(&mut s).unwrap().as_deref(), Some("m0,2"));
+        assert_eq!(read_packet(&mut s).unwrap(), None);
+        assert_eq!(s.output, b"-++");
+    }
+
+    #[test]
+    fn registers() {
+        let mut c = cpu(&[]);
+        c.regs.a = 0x12;
+        c.regs.f = 0xB0;
+        c.regs.sp = 0xFFFE;
+        c.regs.pc = 0x0150;
+        let mut stub = GdbStub::new(&mut c);
+        let regs = reply(&mut stub, "g");
+        assert_eq!(regs.len(), NUM_REGS * 4);
+        assert_eq!(&regs[..24], "b012000000000000feff5001");
+        assert_eq!(reply(&mut stub, "p5"), "5001");
+
+        assert_eq!(reply(&mut stub, "P0=ff34"), "OK");
+        assert_eq!(reply(&mut stub, "P5=0002"), "OK");
+        assert_eq!(reply(&mut stub, "Pz=00"), "E00");
+        assert_eq!((c.regs.a, c.regs.f, c.regs.pc), (0x34, 0xF0, 0x0200));
+    }
+
+    #[test]
+    fn memory() {
+        let mut c = cpu(&[0xAA, 0xBB, 0xCC]);
+        let mut stub = GdbStub::new(&mut c);
+        assert_eq!(reply(&mut stub, "m1,2"), "bbcc");
+        assert_eq!(reply(&mut stub, "MC000,2:1234"), "OK");
+        assert_eq!(reply(&mut stub, "mc000,2"), "1234");
+        assert_eq!(reply(&mut stub, "MC000,2:12"), "E00");
+    }
+
+    #[test]
+    fn breakpoints() {
+        // NOPs
+        let mut c = cpu(&[]);
+        let mut stub = GdbStub::new(&mut c);
+        assert_eq!(reply(&mut stub, "Z0,3,1"), "OK");
+        assert_eq!(stub.handle_packet("c"), Action::Continue);
+        assert_eq!(stub.cpu.run_until(1000).unwrap(), StopReason::Breakpoint(3));
+        assert_eq!(reply(&mut stub, "z0,3,1"), "OK");
+        assert_eq!(reply(&mut stub, "Z9,3,1"), "");
+        assert_eq!(reply(&mut stub, "Z3,c000,1"), "OK");
+        assert_eq!(stub.cpu.watchpoints(), [(0xC000, WatchKind::Read)]);
+
+        assert_eq!(stub.handle_packet("s10"), Action::Step);
+        assert_eq!(stub.cpu.regs.pc, 0x10);
+    }
+}
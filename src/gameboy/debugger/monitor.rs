@@ -0,0 +1,439 @@
+use anyhow::{bail, Context, Result};
+
+use super::super::bus::bus::Bus;
+use super::super::cpu::cpu::{StopReason, WatchKind, CPU};
+use super::super::cpu::instruction::Op;
+use super::super::cpu::regs::RegisterFile;
+use super::super::error::EmuError;
+
+/// Bytes per line of a memory dump
+const DUMP_WIDTH: usize = 16;
+
+/// True if the next instruction calls a subroutine.
+fn next_is_call<B: Bus>(cpu: &CPU<B>) -> bool {
+    cpu.peek_next_instr()
+        .map(|i| {
+            matches!(
+                i.def.op,
+                Op::Call | Op::CallC | Op::CallNc | Op::CallNz | Op::CallZ | Op::Rst
+            )
+        })
+        .unwrap_or(false)
+}
+
+/// True if the next instruction returns from a subroutine.
+fn next_is_return<B: Bus>(cpu: &CPU<B>) -> bool {
+    cpu.peek_next_instr()
+        .map(|i| {
+            matches!(
+                i.def.op,
+                Op::Ret | Op::RetC | Op::RetNc | Op::RetNz | Op::RetZ | Op::Reti
+            )
+        })
+        .unwrap_or(false)
+}
+
+/// Executes one instruction, running through subroutine
+/// calls (CALL, RST) until they return. Stops early on
+/// breakpoints, watches or after 'max_cycles'.
+pub fn step_over<B: Bus>(cpu: &mut CPU<B>, max_cycles: usize) -> Result<StopReason, EmuError> {
+    if !next_is_call(cpu) {
+        cpu.step()?;
+        return Ok(StopReason::Stepped);
+    }
+
+    let sp = cpu.regs.sp;
+    let ret = cpu
+        .regs
+        .pc
+        .wrapping_add(cpu.peek_next_instr().map_or(1, |i| i.len) as u16);
+    let temporary = !cpu.breakpoints().contains(&ret);
+    if temporary {
+        cpu.add_breakpoint(ret);
+    }
+
+    let start = cpu.get_cycles();
+    let result = loop {
+        let budget = max_cycles.saturating_sub(cpu.get_cycles() - start);
+        match cpu.run_until(budget) {
+            // Returned to the same stack frame (not a
+            // recursive call hitting the same address)
+            Ok(StopReason::Breakpoint(pc)) if pc == ret && cpu.regs.sp >= sp => {
+                break Ok(StopReason::Stepped)
+            }
+            Ok(StopReason::Breakpoint(pc)) if pc == ret => (),
+            r => break r,
+        }
+    };
+
+    if temporary {
+        cpu.remove_breakpoint(ret);
+    }
+    result
+}
+
+/// Runs until the current subroutine returns. Stops early
+/// on breakpoints or after 'max_cycles'.
+pub fn run_to_return<B: Bus>(cpu: &mut CPU<B>, max_cycles: usize) -> Result<StopReason, EmuError> {
+    let sp = cpu.regs.sp;
+    let start = cpu.get_cycles();
+    let mut first = true;
+
+    loop {
+        if !first && cpu.breakpoints().contains(&cpu.regs.pc) {
+            return Ok(StopReason::Breakpoint(cpu.regs.pc));
+        }
+        if cpu.get_cycles() - start >= max_cycles {
+            return Ok(StopReason::CycleBudget);
+        }
+        first = false;
+
+        // A return that is taken pops above the current frame
+        let ret = next_is_return(cpu);
+        cpu.step()?;
+        if ret && cpu.regs.sp > sp {
+            return Ok(StopReason::Stepped);
+        }
+    }
+}
+
+/// Value of a register by (case insensitive) name.
+fn register(regs: &RegisterFile, name: &str) -> Option<u16> {
+    let pair = |hi: u8, lo: u8| u16::from_be_bytes([hi, lo]);
+    Some(match name.to_ascii_lowercase().as_str() {
+        "a" => regs.a.into(),
+        "f" => regs.f.into(),
+        "b" => regs.b.into(),
+        "c" => regs.c.into(),
+        "d" => regs.d.into(),
+        "e" => regs.e.into(),
+        "h" => regs.h.into(),
+        "l" => regs.l.into(),
+        "af" => pair(regs.a, regs.f),
+        "bc" => pair(regs.b, regs.c),
+        "de" => pair(regs.d, regs.e),
+        "hl" => pair(regs.h, regs.l),
+        "sp" => regs.sp,
+        "pc" => regs.pc,
+        _ => return None,
+    })
+}
+
+/// Parses a hexadecimal number, optionally prefixed
+/// with '$' or '0x'.
+fn parse_hex(s: &str) -> Result<u16> {
+    let digits = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).context(format!("Invalid number '{}'", s))
+}
+
+/// Describes why execution stopped.
+fn describe(reason: StopReason) -> Option<String> {
+    match reason {
+        StopReason::Breakpoint(pc) => Some(format!("Breakpoint at {:04X}", pc)),
+        StopReason::Watch { addr, old, new } => {
+            Some(format!("Watch {:04X}: {:02X} -> {:02X}", addr, old, new))
+        }
+        StopReason::Access { addr, write, val } => Some(format!(
+            "Watchpoint {:04X}: {} {:02X}",
+            addr,
+            if write { "write" } else { "read" },
+            val
+        )),
+        StopReason::Condition(id) => Some(format!("Condition {} holds", id)),
+        StopReason::CycleBudget => Some("Cycle budget exhausted".to_string()),
+        StopReason::Stepped => None,
+    }
+}
+
+/// Command line monitor, for interactive debugging from
+/// any frontend. Feed it lines with command().
+///
+/// Commands:
+///   s/step [n]        Step n instructions
+///   n/next            Step over subroutine calls
+///   fin/finish        Run until the subroutine returns
+///   c/continue        Run until a breakpoint or watchpoint
+///   b/break addr      Set a breakpoint
+///   bd addr           Delete a breakpoint
+///   w/watch addr [r|w|rw]  Set a watchpoint (default w)
+///   wd addr           Delete a watchpoint
+///   cond reg=value    Break when a register has a value
+///   condd id          Delete a condition
+///   r/regs            Show registers
+///   x addr [len]      Dump memory
+///   l/list [n]        Disassemble n instructions at PC
+///   q/quit            Exit the monitor
+///
+/// An empty line repeats the last command.
+pub struct Monitor {
+    /// Cycle limit for run commands
+    max_cycles: usize,
+
+    /// Last command, repeated by an empty line
+    last: String,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self {
+            max_cycles: usize::MAX,
+            last: String::new(),
+        }
+    }
+
+    /// Limits the cycles run by a single command, e.g.
+    /// to keep 'continue' from running forever.
+    pub fn set_max_cycles(&mut self, max_cycles: usize) {
+        self.max_cycles = max_cycles;
+    }
+
+    /// Current CPU state: registers and next instruction.
+    pub fn state<B: Bus>(cpu: &CPU<B>) -> String {
+        let next = match cpu.disassemble_ahead(1).first() {
+            Some((addr, text)) => format!("{:04X}: {}", addr, text),
+            None => "(end of memory)".to_string(),
+        };
+        format!("{}\n --> {}", cpu.regs, next)
+    }
+
+    /// Executes a command line. Returns the output, or None
+    /// if the monitor should exit.
+    pub fn command<B: Bus>(&mut self, cpu: &mut CPU<B>, line: &str) -> Result<Option<String>> {
+        let line = if line.trim().is_empty() {
+            self.last.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last = line.clone();
+
+        let mut args = line.split_whitespace();
+        let Some(cmd) = args.next() else {
+            return Ok(Some(String::new()));
+        };
+        let args: Vec<&str> = args.collect();
+        let arg = |i: usize| -> Result<u16> {
+            match args.get(i) {
+                Some(a) => parse_hex(a),
+                None => bail!("Missing argument for '{}'", cmd),
+            }
+        };
+
+        let reason = match cmd {
+            "s" | "step" => {
+                let n = if args.is_empty() { 1 } else { arg(0)? };
+                for _ in 0..n {
+                    cpu.step()?;
+                }
+                StopReason::Stepped
+            }
+            "n" | "next" => step_over(cpu, self.max_cycles)?,
+            "fin" | "finish" => run_to_return(cpu, self.max_cycles)?,
+            "c" | "continue" => cpu.run_until(self.max_cycles)?,
+
+            "b" | "break" => {
+                cpu.add_breakpoint(arg(0)?);
+                return Ok(Some(format!("Breakpoint at {:04X}", arg(0)?)));
+            }
+            "bd" => {
+                cpu.remove_breakpoint(arg(0)?);
+                return Ok(Some(String::new()));
+            }
+            "w" | "watch" => {
+                let kind = match args.get(1).copied() {
+                    None | Some("w") => WatchKind::Write,
+                    Some("r") => WatchKind::Read,
+                    Some("rw") => WatchKind::Access,
+                    Some(k) => bail!("Unknown watchpoint type '{}'", k),
+                };
+                cpu.add_watchpoint(arg(0)?, kind);
+                return Ok(Some(format!("Watchpoint at {:04X}", arg(0)?)));
+            }
+            "wd" => {
+                cpu.remove_watchpoint(arg(0)?);
+                return Ok(Some(String::new()));
+            }
+            "cond" => {
+                let Some((reg, val)) = args.first().and_then(|a| a.split_once('=')) else {
+                    bail!("Usage: cond reg=value");
+                };
+                if register(&cpu.regs, reg).is_none() {
+                    bail!("Unknown register '{}'", reg);
+                }
+                let (reg, val) = (reg.to_string(), parse_hex(val)?);
+                let id = cpu.add_break_condition(move |r| register(r, &reg) == Some(val));
+                return Ok(Some(format!("Condition {}", id)));
+            }
+            "condd" => {
+                cpu.remove_break_condition(arg(0)? as usize);
+                return Ok(Some(String::new()));
+            }
+
+            "r" | "regs" => return Ok(Some(Self::state(cpu))),
+            "x" => {
+                let addr = arg(0)?;
+                let len = if args.len() > 1 { arg(1)? } else { 0x40 };
+                return Ok(Some(Self::dump(cpu, addr, len as usize)));
+            }
+            "l" | "list" => {
+                let n = if args.is_empty() { 10 } else { arg(0)? };
+                let lines: Vec<String> = cpu
+                    .disassemble_ahead(n as usize)
+                    .iter()
+                    .map(|(addr, text)| format!("{:04X}: {}", addr, text))
+                    .collect();
+                return Ok(Some(lines.join("\n")));
+            }
+            "q" | "quit" => return Ok(None),
+            _ => bail!("Unknown command '{}'", cmd),
+        };
+
+        let state = Self::state(cpu);
+        Ok(Some(match describe(reason) {
+            Some(r) => format!("{}\n{}", r, state),
+            None => state,
+        }))
+    }
+
+    /// Hex dump of 'len' bytes of memory from 'addr'.
+    fn dump<B: Bus>(cpu: &CPU<B>, addr: u16, len: usize) -> String {
+        let lines: Vec<String> = (0..len)
+            .step_by(DUMP_WIDTH)
+            .map(|offset| {
+                let start = addr.wrapping_add(offset as u16);
+                let bytes: Vec<String> = (0..DUMP_WIDTH.min(len - offset))
+                    .map(|i| format!("{:02X}", cpu.bus.peek(start.wrapping_add(i as u16))))
+                    .collect();
+                format!("{:04X}: {}", start, bytes.join(" "))
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::bus::testbus::Testbus;
+    use super::*;
+
+    fn cpu(code: &[u8]) -> CPU {
+        let mut c: CPU = CPU::new(Box::new(Testbus::from(code)));
+        c.regs.sp = 0xFFFE;
+        c
+    }
+
+    /// Calls a subroutine at 0x0010 that increments A twice.
+    const CALL_CODE: [u8; 20] = [
+        0xCD, 0x10, 0x00, // CALL $0010
+        0x00, // NOP
+        0x18, 0xFE, // JR $-2
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0,    //
+        0x3C, // INC A
+        0x3C, // INC A
+        0xC9, // RET
+        0x00,
+    ];
+
+    #[test]
+    fn step_over_call() {
+        let mut c = cpu(&CALL_CODE);
+        assert_eq!(step_over(&mut c, 1000).unwrap(), StopReason::Stepped);
+        assert_eq!(c.regs.pc, 0x0003);
+        assert_eq!(c.regs.a, 2);
+        assert!(c.breakpoints().is_empty());
+
+        // Not a call
+        assert_eq!(step_over(&mut c, 1000).unwrap(), StopReason::Stepped);
+        assert_eq!(c.regs.pc, 0x0004);
+
+        // Breakpoint inside the subroutine
+        let mut c = cpu(&CALL_CODE);
+        c.add_breakpoint(0x0011);
+        assert_eq!(
+            step_over(&mut c, 1000).unwrap(),
+            StopReason::Breakpoint(0x0011)
+        );
+    }
+
+    #[test]
+    fn finish() {
+        let mut c = cpu(&CALL_CODE);
+        c.step().unwrap();
+        assert_eq!(c.regs.pc, 0x0010);
+        assert_eq!(run_to_return(&mut c, 1000).unwrap(), StopReason::Stepped);
+        assert_eq!(c.regs.pc, 0x0003);
+        assert_eq!(c.regs.sp, 0xFFFE);
+
+        // Never returns
+        assert_eq!(run_to_return(&mut c, 100).unwrap(), StopReason::CycleBudget);
+    }
+
+    #[test]
+    fn commands() {
+        let mut c = cpu(&CALL_CODE);
+        let mut m = Monitor::new();
+        m.set_max_cycles(1000);
+
+        let out = m.command(&mut c, "b 11").unwrap().unwrap();
+        assert_eq!(out, "Breakpoint at 0011");
+        let out = m.command(&mut c, "c").unwrap().unwrap();
+        assert!(out.starts_with("Breakpoint at 0011"), "{}", out);
+        assert!(out.ends_with("0011: [3C] INC A"), "{}", out);
+        m.command(&mut c, "bd $11").unwrap();
+
+        m.command(&mut c, "step").unwrap();
+        // Repeats the last command
+        m.command(&mut c, "").unwrap();
+        assert_eq!(c.regs.pc, 0x0003);
+
+        assert_eq!(
+            m.command(&mut c, "l 2").unwrap().unwrap(),
+            "0003: [00] NOP\n0004: [18, FE] JR $0004"
+        );
+        assert!(m.command(&mut c, "frobnicate").is_err());
+        assert!(m.command(&mut c, "b").is_err());
+        assert_eq!(m.command(&mut c, "q").unwrap(), None);
+    }
+
+    #[test]
+    fn conditions_watchpoints() {
+        let mut c = cpu(&CALL_CODE);
+        let mut m = Monitor::new();
+        m.set_max_cycles(1000);
+
+        assert_eq!(
+            m.command(&mut c, "cond a=2").unwrap().unwrap(),
+            "Condition 0"
+        );
+        assert!(m.command(&mut c, "cond x=2").is_err());
+        let out = m.command(&mut c, "c").unwrap().unwrap();
+        assert!(out.starts_with("Condition 0 holds"), "{}", out);
+        assert_eq!(c.regs.pc, 0x0012);
+        m.command(&mut c, "condd 0").unwrap();
+
+        let mut c = cpu(&CALL_CODE);
+        m.command(&mut c, "w fffd").unwrap();
+        let out = m.command(&mut c, "c").unwrap().unwrap();
+        assert!(out.starts_with("Watchpoint FFFD: write 00"), "{}", out);
+    }
+
+    #[test]
+    fn dump() {
+        let mut c = cpu(&[]);
+        c.bus.write_slice(&[0x12, 0x34, 0x56], 0xC000);
+        assert_eq!(m_dump(&mut c, "x c000 3"), "C000: 12 34 56");
+        assert_eq!(m_dump(&mut c, "x c000 12").lines().count(), 2);
+    }
+
+    fn m_dump(c: &mut CPU, cmd: &str) -> String {
+        Monitor::new().command(c, cmd).unwrap().unwrap()
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::Result;
+
+use super::bus::bus::Bus;
+use super::savestate::{StateReader, StateWriter};
+
+/// Bytes copied by a transfer (the size of OAM)
+const DMA_LENGTH: usize = 0xA0;
+
+/// T-cycles per byte copied
+const CYCLES_PER_BYTE: usize = 4;
+
+/// OAM DMA controller (DMA, 0xFF46)
+///
+/// Writing the DMA register starts a transfer of 160 bytes
+/// from XX00 - XX9F to OAM, one byte per M-cycle. The bus
+/// performs the actual copies, see next_transfer().
+pub struct OamDma {
+    /// DMA - Source address (high byte)
+    src: u8,
+
+    /// Bytes copied so far, if a transfer is running
+    pos: Option<usize>,
+
+    /// T-cycles not yet spent on the transfer
+    cycles: usize,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        Self {
+            src: 0,
+            pos: None,
+            cycles: 0,
+        }
+    }
+
+    /// True while a transfer is running.
+    pub fn is_active(&self) -> bool {
+        self.pos.is_some()
+    }
+
+    /// Advances the transfer by a number of T-cycles.
+    pub fn tick(&mut self, cycles: usize) {
+        if self.is_active() {
+            self.cycles += cycles;
+        }
+    }
+
+    /// Returns the next copy (source address, OAM offset)
+    /// the elapsed cycles allow for, if any.
+    pub fn next_transfer(&mut self) -> Option<(u16, usize)> {
+        let pos = self.pos?;
+        if self.cycles < CYCLES_PER_BYTE {
+            return None;
+        }
+        self.cycles -= CYCLES_PER_BYTE;
+
+        self.pos = if pos + 1 == DMA_LENGTH {
+            None
+        } else {
+            Some(pos + 1)
+        };
+        if self.pos.is_none() {
+            self.cycles = 0;
+        }
+
+        let mut src = (self.src as u16) << 8 | pos as u16;
+        if src >= 0xE000 {
+            // Sources above WRAM read from the WRAM mirror
+            src -= 0x2000;
+        }
+        Some((src, pos))
+    }
+}
+
+impl Default for OamDma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for OamDma {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // DMA - Reads back the last written value.
+            0xFF46 => self.src,
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // DMA - Starts (or restarts) a transfer
+            0xFF46 => {
+                self.src = val;
+                self.pos = Some(0);
+                self.cycles = 0;
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"DMA ");
+        w.u8(self.src);
+        w.bool(self.pos.is_some());
+        w.u8(self.pos.unwrap_or(0) as u8);
+        w.u8(self.cycles as u8);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"DMA ", "OAM DMA")?;
+        self.src = r.u8()?;
+        let active = r.bool()?;
+        let pos = r.u8()? as usize;
+        self.pos = active.then_some(pos);
+        self.cycles = r.u8()? as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readback() {
+        let mut d = OamDma::new();
+        d.write(0xFF46, 0xC3);
+        assert_eq!(d.read(0xFF46), 0xC3);
+    }
+
+    #[test]
+    fn transfer() {
+        let mut d = OamDma::new();
+        d.tick(100);
+        assert!(!d.is_active());
+        assert_eq!(d.next_transfer(), None);
+
+        d.write(0xFF46, 0xC1);
+        assert!(d.is_active());
+        assert_eq!(d.next_transfer(), None);
+        d.tick(3);
+        assert_eq!(d.next_transfer(), None);
+        d.tick(1);
+        assert_eq!(d.next_transfer(), Some((0xC100, 0)));
+        assert_eq!(d.next_transfer(), None);
+
+        d.tick(CYCLES_PER_BYTE * DMA_LENGTH);
+        for i in 1..DMA_LENGTH {
+            assert_eq!(d.next_transfer(), Some((0xC100 + i as u16, i)));
+        }
+        assert_eq!(d.next_transfer(), None);
+        assert!(!d.is_active());
+    }
+
+    #[test]
+    fn echo_source() {
+        let mut d = OamDma::new();
+        d.write(0xFF46, 0xFE);
+        d.tick(4);
+        assert_eq!(d.next_transfer(), Some((0xDE00, 0)));
+    }
+}
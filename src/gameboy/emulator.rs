@@ -0,0 +1,639 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{bail, Result};
+
+use super::bus::bus::Bus;
+use super::bus::gbbus::Gameboybus;
+use super::bus::hookbus::{HookBus, Hooks};
+use super::cartridge::cartridge;
+use super::cartridge::header::CartridgeHeader;
+use super::cheats::Cheats;
+use super::coverage::Coverage;
+use super::cpu::cpu::CPU;
+use super::joypad::Button;
+use super::link::LinkCable;
+use super::model::Model;
+use super::ppu::{frame_hash, Frame, Renderer, CYCLES_PER_FRAME, LCD_HEIGHT, LCD_WIDTH};
+use super::profiler::Profiler;
+use super::rewind::Rewind;
+use super::video::PostProcess;
+
+/// Frontend-facing emulator API
+///
+/// Everything a frontend needs, without file I/O, threads
+/// or timing: the frontend supplies the ROM, calls
+/// run_frame() at its own pace and feeds in input. This is
+/// also the boundary for bindings to other environments
+/// (e.g. a wasm-bindgen wrapper).
+pub struct Emulator {
+    cpu: CPU<HookBus<Gameboybus>>,
+
+    /// Hooks of the bus; only cheats, installed on first use
+    hooks: Rc<RefCell<Hooks>>,
+    cheats: Rc<RefCell<Cheats>>,
+
+    /// ID of the cheat hook, once installed
+    cheat_hook: Option<usize>,
+
+    /// Palette and effects applied to frames
+    video: PostProcess,
+
+    /// Last frame as RGBA bytes
+    frame: Vec<u8>,
+
+    /// Snapshots of past frames, if rewind is enabled
+    rewind: Option<Rewind>,
+}
+
+impl Emulator {
+    /// Loads a ROM on the model its header asks for, starting
+    /// in the post-boot state.
+    pub fn new(rom: &[u8]) -> Result<Self> {
+        let model = Model::detect(&CartridgeHeader::from_rom(rom)?);
+        Self::with_model(rom, None, model)
+    }
+
+    /// Loads a ROM on a specific model, running the boot ROM
+    /// if one is given.
+    pub fn with_model(rom: &[u8], bootrom: Option<&[u8]>, model: Model) -> Result<Self> {
+        let bus = Gameboybus::with_model(cartridge::load(rom)?, bootrom, model);
+        let hooks = Rc::new(RefCell::new(Hooks::new()));
+        let mut cpu = CPU::new(HookBus::new(bus, Rc::clone(&hooks)));
+        if bootrom.is_none() {
+            cpu.skip_boot_rom();
+        }
+        Ok(Self {
+            cpu,
+            hooks,
+            cheats: Rc::new(RefCell::new(Cheats::new())),
+            cheat_hook: None,
+            video: PostProcess::new(),
+            frame: vec![0; LCD_WIDTH * LCD_HEIGHT * 4],
+            rewind: None,
+        })
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU<HookBus<Gameboybus>> {
+        &mut self.cpu
+    }
+
+    /// The bus, for its features (e.g. serial port output or
+    /// the PPU). Memory accesses through it bypass cheats.
+    pub fn bus(&mut self) -> &mut Gameboybus {
+        self.cpu.bus.inner_mut()
+    }
+
+    /// Soft reset, like power cycling with the same
+    /// cartridge inserted: everything, including the banking
+    /// of the cartridge, goes back to the post-boot state (or
+    /// runs the boot ROM again). Cartridge RAM and clock are
+    /// kept, WRAM and VRAM are cleared.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.cpu.bus.inner_mut().reset();
+        if !self.cpu.bus.inner().has_boot_rom() {
+            self.cpu.skip_boot_rom();
+        }
+    }
+
+    /// Runs until the PPU completes a frame (or a frame's
+    /// worth of cycles if the LCD is off) and returns the
+    /// post-processed frame as frame_size() RGBA pixels.
+    pub fn run_frame(&mut self) -> Result<&[u8]> {
+        // Not a frame completed before (e.g. by run_cycles())
+        self.bus().frame_ready();
+        let end = self.cpu.get_cycles() + CYCLES_PER_FRAME;
+        while self.cpu.get_cycles() < end {
+            self.cpu.step()?;
+            if self.bus().frame_ready() {
+                break;
+            }
+        }
+        self.end_frame()?;
+        Ok(self.process_frame())
+    }
+
+    /// Runs until the PPU enters V-blank, i.e. until right
+    /// after the last line of a frame was drawn, and returns
+    /// the post-processed frame like run_frame(). Unlike
+    /// run_frame(), fails if the LCD stays off.
+    pub fn run_until_vblank(&mut self) -> Result<&[u8]> {
+        // Two frames, for the LCD being turned on mid-frame
+        let end = self.cpu.get_cycles() + CYCLES_PER_FRAME * 2;
+        // Not a V-blank from before (e.g. in run_cycles())
+        self.bus().frame_ready();
+        while !self.bus().frame_ready() {
+            if self.cpu.get_cycles() >= end {
+                bail!("No V-blank within two frames, LCD is off");
+            }
+            self.cpu.step()?;
+        }
+        self.end_frame()?;
+        Ok(self.process_frame())
+    }
+
+    /// Runs 'n' frames (see run_frame()) and returns each of
+    /// the post-processed frames.
+    pub fn run_frames(&mut self, n: usize) -> Result<Vec<Vec<u8>>> {
+        (0..n).map(|_| Ok(self.run_frame()?.to_vec())).collect()
+    }
+
+    /// Runs 'n' frames (see run_frame()) and returns the
+    /// frame_hash() of each, to check an animation without
+    /// keeping the frames.
+    pub fn run_frames_hashed(&mut self, n: usize) -> Result<Vec<u64>> {
+        (0..n)
+            .map(|_| {
+                self.run_frame()?;
+                Ok(self.frame_hash())
+            })
+            .collect()
+    }
+
+    /// Completes a frame, as run_frame() does: writes the
+    /// GameShark codes and records the frame for rewind, if
+    /// enabled. For running the CPU directly, frame by frame.
+    pub fn end_frame(&mut self) -> Result<()> {
+        self.cheats.borrow().write_frame(self.cpu.bus.inner_mut());
+        match &mut self.rewind {
+            Some(rewind) => rewind.record_frame(&self.cpu),
+            None => Ok(()),
+        }
+    }
+
+    /// Post-processes the PPU output into 'frame'.
+    fn process_frame(&mut self) -> &[u8] {
+        let bus = self.cpu.bus.inner();
+        let pixels = self.video.process(bus.frame(), bus.cgb_mode());
+        self.frame.resize(pixels.len() * 4, 0);
+        for (out, px) in self.frame.chunks_exact_mut(4).zip(pixels) {
+            out.copy_from_slice(&px.to_be_bytes());
+        }
+        &self.frame
+    }
+
+    /// Runs for at least 'cycles' cycles, regardless of
+    /// frames, and returns the cycles actually run. Nothing
+    /// is post-processed: frame() has the PPU output.
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<usize> {
+        Ok(self.cpu.run_cycles(cycles)?)
+    }
+
+    /// Palette and effects applied to the frames returned by
+    /// run_frame().
+    pub fn video(&mut self) -> &mut PostProcess {
+        &mut self.video
+    }
+
+    /// Width and height of the frames returned by run_frame().
+    pub fn frame_size(&self) -> (usize, usize) {
+        self.video.output_size()
+    }
+
+    /// Current frame, as the PPU drew it so far.
+    pub fn frame(&self) -> &Frame {
+        self.cpu.bus.inner().frame()
+    }
+
+    /// FNV-1a hash of frame(), before post-processing.
+    pub fn frame_hash(&self) -> u64 {
+        frame_hash(self.frame())
+    }
+
+    /// Selects the PPU renderer: Renderer::Fifo for games
+    /// with mid-line raster effects, at some speed cost.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.bus().set_renderer(renderer);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.bus().set_button(button, pressed);
+    }
+
+    /// Applies buttons only at the start of each frame (see
+    /// Gameboybus::set_input_latch()).
+    pub fn set_input_latch(&mut self, latched: bool) {
+        self.bus().set_input_latch(latched);
+    }
+
+    /// Returns and clears the audio samples produced since
+    /// the last call (interleaved stereo).
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.bus().take_audio_samples()
+    }
+
+    pub fn set_audio_sample_rate(&mut self, rate: usize) {
+        self.bus().set_audio_sample_rate(rate);
+    }
+
+    /// Mutes or unmutes a sound channel (0-3).
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.bus().apu().set_muted(channel, muted);
+    }
+
+    /// Plays only one sound channel (0-3), or all if None.
+    pub fn solo_channel(&mut self, channel: Option<usize>) {
+        self.bus().apu().solo(channel);
+    }
+
+    /// Connects the serial ports of two emulators with a
+    /// link cable. Run them in lockstep, e.g. alternating
+    /// run_frame() calls.
+    pub fn link(&mut self, other: &mut Emulator) {
+        let (a, b) = LinkCable::pair();
+        self.bus().set_serial_link(Box::new(a));
+        other.bus().set_serial_link(Box::new(b));
+    }
+
+    /// Starts profiling execution and the time spent in the
+    /// subsystems on the bus, discarding any earlier profile.
+    pub fn enable_profiler(&mut self) {
+        self.cpu.enable_profiler();
+        self.bus().set_subsystem_timing(true);
+    }
+
+    /// Stops profiling and returns the profile, with the time
+    /// spent in the subsystems on the bus.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        let mut profiler = self.cpu.take_profiler()?;
+        if let Some(times) = self.bus().take_subsystem_times() {
+            profiler.add_bus_times(times);
+        }
+        self.bus().set_subsystem_timing(false);
+        Some(profiler)
+    }
+
+    /// Starts recording frames for rewind_frames(): a
+    /// snapshot every 'interval' frames, keeping at most
+    /// 'capacity' snapshots (see Rewind). Discards anything
+    /// recorded before.
+    pub fn enable_rewind(&mut self, interval: usize, capacity: usize) {
+        self.rewind = Some(Rewind::new(interval, capacity));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// Goes back at least 'n' frames, to a snapshot recorded
+    /// since enable_rewind() (or the oldest one kept), and
+    /// returns the amount of frames actually rewound. frame()
+    /// has the restored PPU output.
+    pub fn rewind_frames(&mut self, n: usize) -> Result<usize> {
+        let Some(rewind) = &mut self.rewind else {
+            bail!("Rewind is not enabled");
+        };
+        rewind.rewind_frames(&mut self.cpu, n)
+    }
+
+    /// Starts keeping track of the cartridge ROM bytes
+    /// executed, read and written, discarding any earlier
+    /// coverage.
+    pub fn enable_coverage(&mut self) {
+        self.bus().set_coverage(true);
+    }
+
+    /// Stops keeping track of coverage and returns the
+    /// coverage map.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.bus().take_coverage()
+    }
+
+    /// Adds a Game Genie or GameShark code, returning its ID
+    /// in the cheat engine. GameShark codes are written at
+    /// the end of each frame run by run_frame() or
+    /// run_until_vblank().
+    pub fn add_cheat(&mut self, code: &str) -> Result<usize> {
+        let id = self.cheats.borrow_mut().add(code)?;
+        self.install_cheats();
+        Ok(id)
+    }
+
+    /// Adds all codes in a cheat file (see Cheats::add_file()),
+    /// returning their IDs.
+    pub fn add_cheat_file(&mut self, contents: &str) -> Result<Vec<usize>> {
+        let ids = self.cheats.borrow_mut().add_file(contents)?;
+        self.install_cheats();
+        Ok(ids)
+    }
+
+    /// Hooks the cheat engine into the bus, replacing the
+    /// hook installed before, if any.
+    fn install_cheats(&mut self) {
+        let mut hooks = self.hooks.borrow_mut();
+        if let Some(id) = self.cheat_hook.take() {
+            hooks.remove(id);
+        }
+        self.cheat_hook = Some(Cheats::install(&self.cheats, &mut hooks));
+    }
+
+    pub fn remove_cheat(&mut self, id: usize) {
+        self.cheats.borrow_mut().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::coverage::EXECUTED;
+    use super::super::cpu::regs::{Register, RegisterFile};
+    use super::super::profiler::Subsystem;
+    use super::super::video::Palette;
+    use super::*;
+
+    fn rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap(); // JR $-2
+        let frame = emu.run_frame().unwrap();
+        assert_eq!(frame.len(), LCD_WIDTH * LCD_HEIGHT * 4);
+        assert_eq!(frame[..4], [0xFF; 4]);
+        assert_eq!(emu.frame()[0], 0xFFFFFFFF);
+
+        // Frames are aligned to the PPU
+        let start = emu.cpu().get_cycles();
+        emu.run_frame().unwrap();
+        let cycles = emu.cpu().get_cycles() - start;
+        assert!(cycles.abs_diff(CYCLES_PER_FRAME) < 16, "{}", cycles);
+    }
+
+    #[test]
+    fn run_until_vblank() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap(); // JR $-2
+        for _ in 0..2 {
+            let frame = emu.run_until_vblank().unwrap();
+            assert_eq!(frame.len(), LCD_WIDTH * LCD_HEIGHT * 4);
+            assert!(frame.chunks_exact(4).all(|px| px == [0xFF; 4]));
+            assert_eq!(emu.cpu().bus.read(0xFF44), LCD_HEIGHT as u8);
+        }
+
+        // A V-blank passed in run_cycles() does not count
+        let elapsed = |emu: &mut Emulator, run: fn(&mut Emulator) -> Result<&[u8]>| {
+            emu.run_cycles(CYCLES_PER_FRAME * 5 / 4).unwrap();
+            let start = emu.cpu().get_cycles();
+            run(emu).unwrap();
+            emu.cpu().get_cycles() - start
+        };
+        for run in [Emulator::run_until_vblank, Emulator::run_frame] {
+            let cycles = elapsed(&mut emu, run);
+            assert!(cycles.abs_diff(CYCLES_PER_FRAME * 3 / 4) < 16, "{}", cycles);
+        }
+
+        // LCD off
+        let mut emu = Emulator::new(&rom(&[
+            0xAF, // XOR A
+            0xE0, 0x40, // LDH ($40),A
+            0x18, 0xFE, // JR $-2
+        ]))
+        .unwrap();
+        assert!(emu.run_until_vblank().is_err());
+    }
+
+    #[test]
+    fn run_frames() {
+        // Fills in a row of the (blank) tile 0 on every
+        // V-blank, so every frame differs.
+        let code = [
+            0x21, 0x00, 0x80, // LD HL,$8000
+            0xAF, // XOR A
+            0xE0, 0x0F, // LDH ($0F),A
+            0x3E, 0x01, // LD A,$01
+            0xE0, 0xFF, // LDH ($FF),A (IE: V-blank)
+            0x76, // HALT
+            0x3E, 0xFF, // LD A,$FF
+            0x22, // LD (HL+),A
+            0x23, // INC HL
+            0xAF, // XOR A
+            0xE0, 0x0F, // LDH ($0F),A
+            0x18, 0xF6, // JR $-10 (HALT)
+        ];
+        let frames = || -> Vec<u64> {
+            let mut emu = Emulator::new(&rom(&code)).unwrap();
+            // Clear tile 0
+            for addr in 0x8000..0x8010 {
+                emu.cpu().bus.write(addr, 0);
+            }
+            emu.run_frames_hashed(4).unwrap()
+        };
+        let hashes = frames();
+        assert_eq!(hashes.len(), 4);
+        // Deterministic, and a different screen every frame
+        assert_eq!(hashes, frames());
+        for (i, a) in hashes.iter().enumerate() {
+            assert!(hashes[i + 1..].iter().all(|b| a != b), "{:?}", hashes);
+        }
+
+        let mut emu = Emulator::new(&rom(&code)).unwrap();
+        for addr in 0x8000..0x8010 {
+            emu.cpu().bus.write(addr, 0);
+        }
+        let frames = emu.run_frames(4).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|f| f.len() == LCD_WIDTH * LCD_HEIGHT * 4));
+    }
+
+    #[test]
+    fn reset() {
+        // MBC1 + RAM + battery, 8KiB RAM
+        let mut rom = rom(&[
+            0x3E, 0x0A, // LD A,$0A
+            0xEA, 0x00, 0x00, // LD ($0000),A
+            0xEA, 0x00, 0xA0, // LD ($A000),A
+            0xEA, 0x00, 0xC0, // LD ($C000),A
+            0xEA, 0x00, 0x80, // LD ($8000),A
+            0xE0, 0x42, // LDH ($42),A
+            0x18, 0xFE, // JR $-2
+        ]);
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        let mut emu = Emulator::new(&rom).unwrap();
+        emu.run_frame().unwrap();
+        assert_eq!(emu.cpu().regs.a, 0x0A);
+        assert_eq!(emu.cpu().bus.read(0xFF42), 0x0A);
+
+        emu.reset();
+        let post_boot = RegisterFile::new_post_boot();
+        for reg in [Register::AF, Register::BC, Register::DE, Register::HL] {
+            assert_eq!(emu.cpu().regs.read(reg), post_boot.read(reg), "{:?}", reg);
+        }
+        assert_eq!(emu.cpu().regs.pc, 0x0100);
+        assert_eq!(emu.cpu().regs.sp, 0xFFFE);
+        assert!(!emu.cpu().ime());
+        let bus = &mut emu.cpu().bus;
+        assert_eq!(bus.read(0xC000), 0x00);
+        assert_eq!(bus.read(0x8000), 0x00);
+        assert_eq!(bus.read(0xFF42), 0x00);
+        assert_eq!(bus.read(0xFF40), 0x91);
+        assert_eq!(bus.read(0xFF44), 0x00);
+        // Cartridge RAM is disabled again, but kept
+        assert_eq!(bus.read(0xA000), 0xFF);
+        assert_eq!(emu.bus().cartridge().battery_ram().unwrap()[0], 0x0A);
+
+        emu.run_frame().unwrap();
+        assert_eq!(emu.cpu().bus.read(0xC000), 0x0A);
+    }
+
+    #[test]
+    fn input_latch() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap(); // JR $-2
+        emu.cpu().bus.write(0xFF00, 0x20); // Directions
+        emu.set_input_latch(true);
+        emu.run_until_vblank().unwrap();
+
+        // Set mid-frame, visible from the next frame
+        emu.run_cycles(CYCLES_PER_FRAME / 2).unwrap();
+        emu.set_button(Button::Down, true);
+        assert_eq!(emu.cpu().bus.read(0xFF00), 0xEF);
+        emu.run_cycles(CYCLES_PER_FRAME / 4).unwrap();
+        assert_eq!(emu.cpu().bus.read(0xFF00), 0xEF);
+        emu.run_until_vblank().unwrap();
+        assert_eq!(emu.cpu().bus.read(0xFF00), 0xE7);
+
+        // Right away without latching
+        emu.set_input_latch(false);
+        emu.run_cycles(CYCLES_PER_FRAME / 2).unwrap();
+        emu.set_button(Button::Down, false);
+        assert_eq!(emu.cpu().bus.read(0xFF00), 0xEF);
+    }
+
+    #[test]
+    fn rewind() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap(); // JR $-2
+        assert!(emu.rewind_frames(1).is_err());
+
+        emu.enable_rewind(1, 10);
+        let mut cycles = vec![];
+        for _ in 0..5 {
+            emu.run_frame().unwrap();
+            cycles.push(emu.cpu().get_cycles());
+        }
+        emu.run_until_vblank().unwrap();
+        assert_eq!(emu.rewind_frames(3).unwrap(), 3);
+        assert_eq!(emu.cpu().get_cycles(), cycles[2]);
+
+        // Recording continues from there
+        emu.run_frame().unwrap();
+        assert_eq!(emu.rewind_frames(1).unwrap(), 1);
+        assert_eq!(emu.cpu().get_cycles(), cycles[2]);
+
+        emu.disable_rewind();
+        assert!(emu.rewind_frames(1).is_err());
+    }
+
+    #[test]
+    fn video() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap();
+        emu.video().set_palette(Palette::GREEN);
+        emu.video().set_scale(2);
+        assert_eq!(emu.frame_size(), (LCD_WIDTH * 2, LCD_HEIGHT * 2));
+        let frame = emu.run_frame().unwrap();
+        assert_eq!(frame.len(), LCD_WIDTH * LCD_HEIGHT * 4 * 4);
+        assert_eq!(frame[..4], Palette::GREEN.0[0].to_be_bytes());
+    }
+
+    #[test]
+    fn run_frame_lcd_off() {
+        let mut emu = Emulator::new(&rom(&[
+            0xAF, // XOR A
+            0xE0, 0x40, // LDH ($40),A
+            0x18, 0xFE, // JR $-2
+        ]))
+        .unwrap();
+        emu.run_frame().unwrap();
+        emu.run_frame().unwrap();
+        let start = emu.cpu().get_cycles();
+        emu.run_frame().unwrap();
+        assert!(emu.cpu().get_cycles() - start >= CYCLES_PER_FRAME);
+    }
+
+    #[test]
+    fn add_cheat() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap();
+        let id = emu.add_cheat("010238CD").unwrap();
+        assert!(emu.add_cheat("bogus").is_err());
+        assert!(emu.add_cheat("810238CD").is_err());
+
+        // GameShark codes are written once per frame
+        assert_eq!(emu.cpu().bus.read(0xCD38), 0x00);
+        emu.run_frame().unwrap();
+        assert_eq!(emu.cpu().bus.read(0xCD38), 0x02);
+        emu.cpu().bus.write(0xCD38, 0x10);
+        assert_eq!(emu.cpu().bus.read(0xCD38), 0x10);
+        emu.run_until_vblank().unwrap();
+        assert_eq!(emu.cpu().bus.read(0xCD38), 0x02);
+        emu.remove_cheat(id);
+        emu.cpu().bus.write(0xCD38, 0x10);
+        emu.run_frame().unwrap();
+        assert_eq!(emu.cpu().bus.read(0xCD38), 0x10);
+    }
+
+    #[test]
+    fn add_cheat_hook() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap();
+        // Another hook on the bus
+        emu.hooks
+            .borrow_mut()
+            .add_read_hook(0xC000..=0xC000, |_, _| 0x55);
+
+        // Game Genie codes patch ROM only
+        emu.add_cheat("3EA-17F").unwrap(); // 0x0A17 = 0x3E
+        assert_eq!(emu.cpu().bus.read(0x0A17), 0x3E);
+        emu.add_cheat("3EA-177").unwrap(); // 0x8A17 = 0x3E
+        assert_eq!(emu.cpu().bus.read(0x8A17), 0x00);
+        assert_eq!(emu.cpu().bus.read(0x0A17), 0x3E);
+        assert_eq!(emu.cpu().bus.read(0xC000), 0x55);
+    }
+
+    #[test]
+    fn profiler_coverage() {
+        let mut emu = Emulator::new(&rom(&[0x18, 0xFE])).unwrap(); // JR $-2
+        assert!(emu.take_profiler().is_none());
+        emu.enable_profiler();
+        emu.enable_coverage();
+        emu.run_frames(2).unwrap();
+
+        let profiler = emu.take_profiler().unwrap();
+        assert!(profiler.frames() >= 1);
+        assert!(profiler.frame_time(Subsystem::Ppu) > Duration::ZERO);
+        let coverage = emu.take_coverage().unwrap();
+        assert_eq!(coverage.count(EXECUTED), 2);
+        assert!(emu.take_coverage().is_none());
+    }
+
+    #[test]
+    fn link() {
+        // Sends a byte from SB on the clock in A, then stores
+        // the byte received at $C000.
+        let transfer = |sb: u8, clock: u8| {
+            rom(&[
+                0x3E, sb, // LD A,sb
+                0xE0, 0x01, // LDH ($01),A
+                0x3E, clock, // LD A,clock
+                0xE0, 0x02, // LDH ($02),A
+                0xF0, 0x02, // LDH A,($02)
+                0x87, // ADD A (bit 7 to carry)
+                0x38, 0xFB, // JR C,$-5
+                0xF0, 0x01, // LDH A,($01)
+                0xEA, 0x00, 0xC0, // LD ($C000),A
+                0x18, 0xFE, // JR $-2
+            ])
+        };
+        let mut master = Emulator::new(&transfer(0x5A, 0x81)).unwrap();
+        let mut slave = Emulator::new(&transfer(0xA5, 0x80)).unwrap();
+        master.link(&mut slave);
+
+        for _ in 0..2 {
+            slave.run_frame().unwrap();
+            master.run_frame().unwrap();
+        }
+        assert_eq!(master.cpu().bus.read(0xC000), 0xA5);
+        assert_eq!(slave.cpu().bus.read(0xC000), 0x5A);
+    }
+}
@@ -0,0 +1,414 @@
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+
+use super::emulator::Emulator;
+use super::joypad::Button;
+use super::pacing::{FramePacer, Speed, AUDIO_LATENCY, FRAME_RATE};
+
+/// What paces the emulation thread
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Clock {
+    /// Sleeps between frames, at the speed set with
+    /// Command::SetSpeed. Audio the frontend does not pick
+    /// up in time is dropped.
+    Timer,
+
+    /// Runs a frame whenever there is room in the audio
+    /// queue (AUDIO_LATENCY worth of frames), so the audio
+    /// output consuming the samples sets the pace.
+    Audio,
+}
+
+/// Requests from the UI to the emulation thread
+pub enum Command {
+    Button(Button, bool),
+    Pause,
+    Resume,
+    SetSpeed(Speed),
+
+    /// Replied to with Event::StateSaved
+    SaveState,
+    LoadState(Vec<u8>),
+
+    /// Goes back a number of frames (see
+    /// Emulator::rewind_frames()), once rewind is enabled.
+    Rewind(usize),
+
+    /// Runs 'f' on the emulator in between frames, e.g. to
+    /// change settings there is no command for.
+    Run(Box<dyn FnOnce(&mut Emulator) + Send>),
+    Quit,
+}
+
+/// Notifications from the emulation thread to the UI
+#[derive(Debug)]
+pub enum Event {
+    StateSaved(Vec<u8>),
+
+    /// A command failed; emulation continues.
+    CommandFailed(anyhow::Error),
+
+    /// Emulation faulted and is paused, until resumed
+    /// (e.g. after loading a state).
+    Fault(anyhow::Error),
+}
+
+/// Latest frame, swapped between the threads
+#[derive(Default)]
+struct FrameSlot {
+    pixels: Vec<u8>,
+
+    /// Not picked up by the UI yet
+    fresh: bool,
+}
+
+/// Emulator running on a thread of its own
+///
+/// The emulation thread runs frames at the pace of its
+/// Clock, so a UI thread stalling (e.g. on a window resize)
+/// does not disturb emulation timing and sound. The UI
+/// sends commands, picks up the latest frame with
+/// take_frame() and receives audio and events on channels.
+///
+/// Dropping it stops the thread.
+pub struct EmulatorThread {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+
+    /// Audio samples per frame (interleaved stereo); None
+    /// once dropped to stop the thread.
+    audio: Option<Receiver<Vec<f32>>>,
+
+    frame: Arc<Mutex<FrameSlot>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EmulatorThread {
+    /// Starts an emulator created by 'make' on the thread,
+    /// as the emulator itself cannot move between threads.
+    /// Fails if 'make' does.
+    pub fn spawn<F>(make: F, clock: Clock) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Emulator> + Send + 'static,
+    {
+        let (command_tx, command_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let frames = (AUDIO_LATENCY.as_secs_f64() * FRAME_RATE).ceil() as usize;
+        let (audio_tx, audio_rx) = mpsc::sync_channel(frames);
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let frame = Arc::new(Mutex::new(FrameSlot::default()));
+
+        let slot = Arc::clone(&frame);
+        let handle = thread::spawn(move || {
+            let emu = match make() {
+                Ok(emu) => {
+                    let _ = ready_tx.send(Ok(()));
+                    emu
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            Worker {
+                emu,
+                clock,
+                pacer: FramePacer::new(),
+                paused: false,
+                pixels: vec![],
+                commands: command_rx,
+                events: event_tx,
+                audio: audio_tx,
+                frame: slot,
+            }
+            .run();
+        });
+
+        ready_rx
+            .recv()
+            .map_err(|_| anyhow!("Emulation thread panicked"))??;
+        Ok(Self {
+            commands: command_tx,
+            events: event_rx,
+            audio: Some(audio_rx),
+            frame,
+            handle: Some(handle),
+        })
+    }
+
+    /// Sends a command, to be handled before the next frame.
+    /// Fails if the thread is gone.
+    pub fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow!("Emulation thread stopped"))
+    }
+
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    /// Audio samples (interleaved stereo), one message per
+    /// frame. With Clock::Audio, emulation waits for these
+    /// to be received.
+    pub fn audio(&self) -> &Receiver<Vec<f32>> {
+        self.audio.as_ref().expect("Audio is only dropped on drop")
+    }
+
+    /// Swaps the latest frame (RGBA bytes) into 'pixels' if
+    /// there is a new one since the last call, returning true
+    /// if so. 'pixels' is reused by the emulation thread.
+    pub fn take_frame(&self, pixels: &mut Vec<u8>) -> bool {
+        let mut slot = self.frame.lock().unwrap();
+        if !slot.fresh {
+            return false;
+        }
+        slot.fresh = false;
+        std::mem::swap(&mut slot.pixels, pixels);
+        true
+    }
+}
+
+impl Drop for EmulatorThread {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Quit);
+        // Unblocks a thread waiting for room in the audio queue
+        self.audio = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// State of the emulation thread
+struct Worker {
+    emu: Emulator,
+    clock: Clock,
+    pacer: FramePacer,
+    paused: bool,
+
+    /// Frame being passed to the UI
+    pixels: Vec<u8>,
+
+    commands: Receiver<Command>,
+    events: Sender<Event>,
+    audio: SyncSender<Vec<f32>>,
+    frame: Arc<Mutex<FrameSlot>>,
+}
+
+impl Worker {
+    fn run(mut self) {
+        loop {
+            // Handle all pending commands; block while paused.
+            loop {
+                let command = if self.paused {
+                    self.commands.recv().map_err(|_| TryRecvError::Disconnected)
+                } else {
+                    self.commands.try_recv()
+                };
+                match command {
+                    Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return,
+                    Ok(command) => self.handle(command),
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+
+            match self.emu.run_frame() {
+                Ok(frame) => {
+                    if self.pacer.render_frame() {
+                        self.pixels.clear();
+                        self.pixels.extend_from_slice(frame);
+                        self.publish_frame();
+                    }
+                }
+                Err(e) => {
+                    self.paused = true;
+                    let _ = self.events.send(Event::Fault(e));
+                    continue;
+                }
+            }
+
+            let samples = self.emu.take_audio_samples();
+            match self.clock {
+                Clock::Audio => {
+                    if self.audio.send(samples).is_err() {
+                        return;
+                    }
+                }
+                Clock::Timer => {
+                    if let Err(TrySendError::Disconnected(_)) = self.audio.try_send(samples) {
+                        return;
+                    }
+                    self.pacer.wait();
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Button(button, pressed) => self.emu.set_button(button, pressed),
+            Command::Pause => self.paused = true,
+            Command::Resume => {
+                self.paused = false;
+                // Do not catch up on the time spent paused
+                self.pacer.set_speed(self.pacer.speed());
+            }
+            Command::SetSpeed(speed) => self.pacer.set_speed(speed),
+            Command::SaveState => {
+                let event = match self.emu.cpu().save_state() {
+                    Ok(state) => Event::StateSaved(state),
+                    Err(e) => Event::CommandFailed(e),
+                };
+                let _ = self.events.send(event);
+            }
+            Command::LoadState(state) => {
+                if let Err(e) = self.emu.cpu().load_state(&state) {
+                    let _ = self.events.send(Event::CommandFailed(e));
+                }
+            }
+            Command::Rewind(n) => {
+                if let Err(e) = self.emu.rewind_frames(n) {
+                    let _ = self.events.send(Event::CommandFailed(e));
+                }
+            }
+            Command::Run(f) => f(&mut self.emu),
+            Command::Quit => unreachable!(),
+        }
+    }
+
+    /// Swaps the frame in 'pixels' into the slot for the UI.
+    fn publish_frame(&mut self) {
+        let mut slot = self.frame.lock().unwrap();
+        std::mem::swap(&mut slot.pixels, &mut self.pixels);
+        slot.fresh = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::super::ppu::{LCD_HEIGHT, LCD_WIDTH};
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn spawn(code: &'static [u8], clock: Clock) -> EmulatorThread {
+        EmulatorThread::spawn(
+            move || {
+                let mut rom = vec![0; 32 * 1024];
+                rom[0x100..0x100 + code.len()].copy_from_slice(code);
+                Emulator::new(&rom)
+            },
+            clock,
+        )
+        .unwrap()
+    }
+
+    fn next_frame(t: &EmulatorThread, pixels: &mut Vec<u8>) {
+        let start = Instant::now();
+        while !t.take_frame(pixels) {
+            assert!(start.elapsed() < TIMEOUT, "No frame");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let t = spawn(&[0x18, 0xFE], Clock::Timer); // JR $-2
+        t.send(Command::SetSpeed(Speed::Unlimited)).unwrap();
+        let mut pixels = vec![];
+        next_frame(&t, &mut pixels);
+        assert_eq!(pixels.len(), LCD_WIDTH * LCD_HEIGHT * 4);
+        next_frame(&t, &mut pixels);
+        assert_eq!(pixels.len(), LCD_WIDTH * LCD_HEIGHT * 4);
+    }
+
+    #[test]
+    fn commands() {
+        let t = spawn(&[0x18, 0xFE], Clock::Timer);
+        t.send(Command::Pause).unwrap();
+        t.send(Command::Run(Box::new(|emu| emu.cpu().regs.b = 0x12)))
+            .unwrap();
+        t.send(Command::SaveState).unwrap();
+        let Ok(Event::StateSaved(state)) = t.events().recv_timeout(TIMEOUT) else {
+            panic!("No state");
+        };
+
+        t.send(Command::Run(Box::new(|emu| emu.cpu().regs.b = 0x34)))
+            .unwrap();
+        t.send(Command::LoadState(state)).unwrap();
+        t.send(Command::LoadState(vec![1, 2, 3])).unwrap();
+        let (tx, rx) = mpsc::channel();
+        t.send(Command::Run(Box::new(move |emu| {
+            tx.send(emu.cpu().regs.b).unwrap()
+        })))
+        .unwrap();
+        assert!(matches!(
+            t.events().recv_timeout(TIMEOUT),
+            Ok(Event::CommandFailed(_))
+        ));
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(0x12));
+    }
+
+    #[test]
+    fn rewind() {
+        let t = spawn(&[0x18, 0xFE], Clock::Timer);
+        t.send(Command::Rewind(1)).unwrap();
+        assert!(matches!(
+            t.events().recv_timeout(TIMEOUT),
+            Ok(Event::CommandFailed(_))
+        ));
+
+        t.send(Command::Run(Box::new(|emu| emu.enable_rewind(1, 100))))
+            .unwrap();
+        t.send(Command::SetSpeed(Speed::Unlimited)).unwrap();
+        let mut pixels = vec![];
+        for _ in 0..3 {
+            next_frame(&t, &mut pixels);
+        }
+        t.send(Command::Pause).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let cycles = move || -> Command {
+            let tx = tx.clone();
+            Command::Run(Box::new(move |emu| {
+                tx.send(emu.cpu().get_cycles()).unwrap()
+            }))
+        };
+        t.send(cycles()).unwrap();
+        t.send(Command::Rewind(1)).unwrap();
+        t.send(cycles()).unwrap();
+        let before = rx.recv_timeout(TIMEOUT).unwrap();
+        let after = rx.recv_timeout(TIMEOUT).unwrap();
+        assert!(after < before, "{} {}", after, before);
+    }
+
+    #[test]
+    fn fault() {
+        let t = spawn(&[0xD3], Clock::Timer); // Invalid opcode
+        assert!(matches!(
+            t.events().recv_timeout(TIMEOUT),
+            Ok(Event::Fault(_))
+        ));
+    }
+
+    #[test]
+    fn audio_clock() {
+        let t = spawn(&[0x18, 0xFE], Clock::Audio);
+        for _ in 0..10 {
+            let samples = t.audio().recv_timeout(TIMEOUT).unwrap();
+            assert!(!samples.is_empty());
+        }
+    }
+
+    #[test]
+    fn spawn_error() {
+        let result = EmulatorThread::spawn(|| Emulator::new(&[]), Clock::Timer);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use thiserror::Error;
+
+use super::cpu::instruction::DecodeErr;
+
+/// Faults of the emulated machine, returned by CPU::step()
+/// (and the functions running it) and by loading a
+/// cartridge the emulator cannot run.
+///
+/// The CPU state is left as it was when the fault occurred,
+/// so a frontend can show it along with the error.
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum EmuError {
+    /// Undefined opcode; real hardware locks up.
+    #[error("Invalid opcode {opcode:02X} at {pc:04X}")]
+    InvalidOpcode { pc: u16, opcode: u8 },
+
+    /// Instruction with an operand it cannot execute.
+    #[error("Invalid operand at {pc:04X}: {reason}")]
+    InvalidOperand { pc: u16, reason: String },
+
+    /// Instruction could not be fetched from the bus, as
+    /// it runs past the end of the address space.
+    #[error("Bus fault fetching instruction at {addr:04X}")]
+    BusFault { addr: u16 },
+
+    /// Hardware (e.g. a cartridge type) the emulator does
+    /// not support.
+    #[error("Unsupported {feature}")]
+    Unsupported { feature: String },
+}
+
+impl EmuError {
+    /// Converts an error from decoding or executing the
+    /// instruction at 'pc'.
+    pub(super) fn from_instr(err: anyhow::Error, pc: u16) -> Self {
+        let err = match err.downcast::<EmuError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        match err.downcast_ref::<DecodeErr>() {
+            Some(&DecodeErr::UndefinedOpcode { opcode, .. }) => {
+                EmuError::InvalidOpcode { pc, opcode }
+            }
+            Some(DecodeErr::EndOfStream) => EmuError::BusFault { addr: pc },
+            None => EmuError::InvalidOperand {
+                pc,
+                reason: err.to_string(),
+            },
+        }
+    }
+}
+
+/// What the CPU does when executing an instruction faults
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FaultPolicy {
+    /// Return the fault from CPU::step(), leaving the CPU
+    /// at the faulting instruction
+    Stop,
+
+    /// Report the fault to the fault callback (if any) and
+    /// skip the faulting instruction
+    Skip,
+}
+
+impl FromStr for FaultPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "stop" => Ok(Self::Stop),
+            "skip" => Ok(Self::Skip),
+            _ => bail!("Unknown fault policy '{}' (expected stop or skip)", s),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::Result;
+
+use super::bus::bus::Bus;
+use super::savestate::{StateReader, StateWriter};
+
+/// Bytes copied per block
+pub const BLOCK_SIZE: u16 = 0x10;
+
+/// HDMA5 bit 7 - H-blank DMA (set) or general purpose DMA
+const HDMA5_HBLANK: u8 = 1 << 7;
+
+/// CGB VRAM DMA controller (HDMA1-HDMA5, 0xFF51 - 0xFF55)
+///
+/// General purpose DMA copies all blocks at once, H-blank
+/// DMA copies one block of 16 bytes per H-blank. The bus
+/// performs the actual copies, see next_block(). The CPU
+/// is not stalled during the copies.
+pub struct Hdma {
+    /// HDMA1/HDMA2 - Source address
+    src: u16,
+
+    /// HDMA3/HDMA4 - Destination address (in VRAM)
+    dst: u16,
+
+    /// Blocks left to copy
+    blocks: u8,
+
+    /// A transfer is running
+    active: bool,
+
+    /// The running transfer is H-blank DMA
+    hblank_mode: bool,
+
+    /// An H-blank block is due
+    hblank_pending: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Self {
+            src: 0,
+            dst: 0,
+            blocks: 0,
+            active: false,
+            hblank_mode: false,
+            hblank_pending: false,
+        }
+    }
+
+    /// True while a transfer is running.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Signals the PPU entered H-blank, allowing an H-blank
+    /// transfer to copy its next block.
+    pub fn hblank(&mut self) {
+        if self.active && self.hblank_mode {
+            self.hblank_pending = true;
+        }
+    }
+
+    /// Returns the next block (source, destination) to copy,
+    /// if any.
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if !self.active || (self.hblank_mode && !std::mem::take(&mut self.hblank_pending)) {
+            return None;
+        }
+
+        let block = (self.src, 0x8000 | self.dst);
+        self.src = self.src.wrapping_add(BLOCK_SIZE);
+        self.dst = (self.dst + BLOCK_SIZE) & 0x1FF0;
+        self.blocks -= 1;
+        if self.blocks == 0 {
+            self.active = false;
+        }
+        Some(block)
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Hdma {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // HDMA1-HDMA4 are write-only
+            0xFF51..=0xFF54 => 0xFF,
+
+            // HDMA5 - Blocks left minus one, bit 7 is clear
+            // while a transfer is running. Reads 0xFF when
+            // a transfer completed.
+            0xFF55 => {
+                let left = self.blocks.wrapping_sub(1) & 0x7F;
+                if self.active {
+                    left
+                } else {
+                    HDMA5_HBLANK | left
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF51 => self.src = (self.src & 0x00FF) | (val as u16) << 8,
+            0xFF52 => self.src = (self.src & 0xFF00) | (val & 0xF0) as u16,
+            0xFF53 => self.dst = (self.dst & 0x00FF) | ((val & 0x1F) as u16) << 8,
+            0xFF54 => self.dst = (self.dst & 0x1F00) | (val & 0xF0) as u16,
+
+            // HDMA5 - Starts a transfer, or stops a running
+            // H-blank transfer if bit 7 is clear.
+            0xFF55 => {
+                if self.active && self.hblank_mode && val & HDMA5_HBLANK == 0 {
+                    self.active = false;
+                    return;
+                }
+                self.blocks = (val & 0x7F) + 1;
+                self.active = true;
+                self.hblank_mode = val & HDMA5_HBLANK != 0;
+                self.hblank_pending = false;
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"HDMA");
+        w.u16(self.src);
+        w.u16(self.dst);
+        w.u8(self.blocks);
+        w.bool(self.active);
+        w.bool(self.hblank_mode);
+        w.bool(self.hblank_pending);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"HDMA", "HDMA")?;
+        self.src = r.u16()?;
+        self.dst = r.u16()?;
+        self.blocks = r.u8()?;
+        self.active = r.bool()?;
+        self.hblank_mode = r.bool()?;
+        self.hblank_pending = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hdma(src: u16, dst: u16) -> Hdma {
+        let mut h = Hdma::new();
+        h.write(0xFF51, (src >> 8) as u8);
+        h.write(0xFF52, src as u8);
+        h.write(0xFF53, (dst >> 8) as u8);
+        h.write(0xFF54, dst as u8);
+        h
+    }
+
+    #[test]
+    fn general() {
+        let mut h = hdma(0xC123, 0x8456);
+        assert_eq!(h.read(0xFF55), 0xFF);
+        h.write(0xFF55, 0x01);
+        assert!(h.is_active());
+        assert_eq!(h.read(0xFF55), 0x01);
+        assert_eq!(h.next_block(), Some((0xC120, 0x8450)));
+        assert_eq!(h.next_block(), Some((0xC130, 0x8460)));
+        assert_eq!(h.next_block(), None);
+        assert!(!h.is_active());
+        assert_eq!(h.read(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn hblank() {
+        let mut h = hdma(0x4000, 0x9FF0);
+        h.write(0xFF55, 0x81);
+        assert_eq!(h.next_block(), None);
+
+        h.hblank();
+        assert_eq!(h.next_block(), Some((0x4000, 0x9FF0)));
+        assert_eq!(h.next_block(), None);
+        assert_eq!(h.read(0xFF55), 0x00);
+
+        // Destination wraps around within VRAM
+        h.hblank();
+        assert_eq!(h.next_block(), Some((0x4010, 0x8000)));
+        assert!(!h.is_active());
+    }
+
+    #[test]
+    fn hblank_stop() {
+        let mut h = hdma(0x4000, 0x8000);
+        h.write(0xFF55, 0x83);
+        h.hblank();
+        h.next_block();
+        h.write(0xFF55, 0x00);
+        assert!(!h.is_active());
+        assert_eq!(h.read(0xFF55), 0x82);
+        h.hblank();
+        assert_eq!(h.next_block(), None);
+    }
+}
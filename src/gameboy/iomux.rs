@@ -1,18 +1,74 @@
+use anyhow::Result;
+
 use super::bus::bus::Bus;
+use super::savestate::{StateReader, StateWriter};
+
+/// Interrupt request bits in IE/IF
+pub const INT_VBLANK: u8 = 1 << 0;
+pub const INT_STAT: u8 = 1 << 1;
+pub const INT_TIMER: u8 = 1 << 2;
+pub const INT_SERIAL: u8 = 1 << 3;
+pub const INT_JOYPAD: u8 = 1 << 4;
+
+/// Interrupt sources, in priority order
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Bit in IE/IF
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
 
 /// Multiplexer for the I/O address segment
-pub struct IOMux {}
+pub struct IOMux {
+    /// IF - Interrupt flags (bits 0-4)
+    intflags: u8,
+}
+
+impl IOMux {
+    pub fn new() -> Self {
+        Self { intflags: 0 }
+    }
+
+    /// Requests an interrupt by setting its bit(s) in IF.
+    pub fn request_interrupt(&mut self, int: u8) {
+        self.intflags |= int & 0x1F;
+    }
+}
+
+impl Default for IOMux {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Bus for IOMux {
     fn read(&self, addr: u16) -> u8 {
         let addr = addr as usize;
 
         match addr {
-            // LY - LCD Y position register
-            0xFF44 => 0x90,
+            // IF - Interrupt flags
+            // Bits 5-7 are unused and read as 1.
+            0xFF0F => 0xE0 | self.intflags,
 
-            // Remaining I/O space
-            0xFF00..=0xFF70 => 0,
+            // Unmapped I/O space reads as open bus
+            0xFF03..=0xFF7F => 0xFF,
             _ => unreachable!(),
         }
     }
@@ -21,8 +77,37 @@ impl Bus for IOMux {
         let addr = addr as usize;
 
         match addr {
-            0xFF00..=0xFF70 => {}
+            // IF - Interrupt flags
+            0xFF0F => self.intflags = val & 0x1F,
+
+            0xFF03..=0xFF7F => {}
             _ => unreachable!(),
         }
     }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"IO  ");
+        w.u8(self.intflags);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"IO  ", "I/O")?;
+        self.intflags = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_interrupt() {
+        let mut io = IOMux::new();
+        io.request_interrupt(INT_VBLANK);
+        assert_eq!(io.read(0xFF0F), 0xE1);
+        io.request_interrupt(INT_TIMER | INT_JOYPAD);
+        assert_eq!(io.read(0xFF0F), 0xF5);
+    }
 }
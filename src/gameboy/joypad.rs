@@ -0,0 +1,316 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use super::bus::bus::Bus;
+use super::savestate::{StateReader, StateWriter};
+
+/// P1 bit 4 - Select direction buttons (active low)
+const SELECT_DIRECTION: u8 = 1 << 4;
+
+/// P1 bit 5 - Select action buttons (active low)
+const SELECT_ACTION: u8 = 1 << 5;
+
+/// Joypad buttons
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Self::Right,
+        Self::Left,
+        Self::Up,
+        Self::Down,
+        Self::A,
+        Self::B,
+        Self::Select,
+        Self::Start,
+    ];
+
+    /// Bit of the button in a joypad state byte: directions
+    /// in the low nibble, actions in the high nibble.
+    pub fn mask(&self) -> u8 {
+        match self {
+            Self::Right => 1 << 0,
+            Self::Left => 1 << 1,
+            Self::Up => 1 << 2,
+            Self::Down => 1 << 3,
+            Self::A => 1 << 4,
+            Self::B => 1 << 5,
+            Self::Select => 1 << 6,
+            Self::Start => 1 << 7,
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "right" => Ok(Self::Right),
+            "left" => Ok(Self::Left),
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            "a" => Ok(Self::A),
+            "b" => Ok(Self::B),
+            "select" => Ok(Self::Select),
+            "start" => Ok(Self::Start),
+            _ => bail!(
+                "Unknown button '{}' (expected right, left, up, down, a, b, select or start)",
+                s
+            ),
+        }
+    }
+}
+
+/// Joypad, exposed through the P1/JOYP register (0xFF00)
+pub struct Joypad {
+    /// Selection bits (4-5)
+    select: u8,
+
+    /// Pressed buttons, directions in the low nibble and
+    /// actions in the high nibble (pressed = 1).
+    pressed: u8,
+
+    /// Buttons as set by the frontend, applied to 'pressed'
+    /// by latch() when latching
+    input: u8,
+
+    /// Apply input only on latch() instead of right away
+    latched: bool,
+
+    intreq: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: 0x30,
+            pressed: 0,
+            input: 0,
+            latched: false,
+            intreq: false,
+        }
+    }
+
+    /// Updates the state of a button. When latching, the
+    /// change shows up on the next latch().
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.input |= button.mask();
+        } else {
+            self.input &= !button.mask();
+        }
+        if !self.latched {
+            self.latch();
+        }
+    }
+
+    /// Selects whether input is applied right away or only
+    /// on latch().
+    pub fn set_latched(&mut self, latched: bool) {
+        self.latched = latched;
+        if !latched {
+            self.latch();
+        }
+    }
+
+    /// Applies the input set since the last latch.
+    pub fn latch(&mut self) {
+        let before = self.lines();
+        self.pressed = self.input;
+        self.update_intreq(before);
+    }
+
+    /// Returns and clears the joypad interrupt request.
+    pub fn get_clr_intreq(&mut self) -> bool {
+        std::mem::take(&mut self.intreq)
+    }
+
+    /// Input lines P10-P13 (active low), as seen
+    /// through the current selection.
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.select & SELECT_DIRECTION == 0 {
+            lines &= !self.pressed & 0x0F;
+        }
+        if self.select & SELECT_ACTION == 0 {
+            lines &= !(self.pressed >> 4) & 0x0F;
+        }
+        lines
+    }
+
+    /// Requests an interrupt if any line went from high to low.
+    fn update_intreq(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.intreq = true;
+        }
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Joypad {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // P1/JOYP - Joypad
+            // Bits 6-7 are unused and read as 1, the selection
+            // bits read back as written.
+            0xFF00 => 0xC0 | self.select | self.lines(),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // P1/JOYP - Joypad
+            0xFF00 => {
+                let before = self.lines();
+                self.select = val & 0x30;
+                self.update_intreq(before);
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"JOYP");
+        w.u8(self.select);
+        w.u8(self.pressed);
+        w.bool(self.intreq);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"JOYP", "joypad")?;
+        self.select = r.u8()?;
+        self.pressed = r.u8()?;
+        self.input = self.pressed;
+        self.intreq = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn button_from_str() {
+        assert_eq!("Start".parse::<Button>().unwrap(), Button::Start);
+        assert_eq!("a".parse::<Button>().unwrap(), Button::A);
+        assert!("turbo".parse::<Button>().is_err());
+    }
+
+    #[test]
+    fn select_readback() {
+        let mut j = Joypad::new();
+        assert_eq!(j.read(0xFF00), 0xFF);
+
+        j.write(0xFF00, 0x20);
+        assert_eq!(j.read(0xFF00), 0xEF);
+
+        j.write(0xFF00, 0x10);
+        assert_eq!(j.read(0xFF00), 0xDF);
+
+        // Only the selection bits are writable
+        j.write(0xFF00, 0xCF);
+        assert_eq!(j.read(0xFF00), 0xCF);
+    }
+
+    #[test]
+    fn direction() {
+        let mut j = Joypad::new();
+        j.write(0xFF00, 0x20); // Directions
+        j.set_button(Button::Up, true);
+        assert_eq!(j.read(0xFF00), 0xEB);
+        j.set_button(Button::Right, true);
+        assert_eq!(j.read(0xFF00), 0xEA);
+        j.set_button(Button::Up, false);
+        assert_eq!(j.read(0xFF00), 0xEE);
+
+        // Not visible with actions selected
+        j.write(0xFF00, 0x10);
+        assert_eq!(j.read(0xFF00), 0xDF);
+    }
+
+    #[test]
+    fn action() {
+        let mut j = Joypad::new();
+        j.write(0xFF00, 0x10); // Actions
+        j.set_button(Button::Start, true);
+        assert_eq!(j.read(0xFF00), 0xD7);
+        j.set_button(Button::A, true);
+        assert_eq!(j.read(0xFF00), 0xD6);
+
+        // Nothing selected
+        j.write(0xFF00, 0x30);
+        assert_eq!(j.read(0xFF00), 0xFF);
+
+        // Both selected
+        j.set_button(Button::Down, true);
+        j.write(0xFF00, 0x00);
+        assert_eq!(j.read(0xFF00), 0xC6);
+    }
+
+    #[test]
+    fn latched() {
+        let mut j = Joypad::new();
+        j.write(0xFF00, 0x20); // Directions
+        j.set_latched(true);
+        j.set_button(Button::Up, true);
+        assert_eq!(j.read(0xFF00), 0xEF);
+        assert!(!j.get_clr_intreq());
+
+        j.latch();
+        assert_eq!(j.read(0xFF00), 0xEB);
+        assert!(j.get_clr_intreq());
+
+        // Pending input is applied when latching is turned off
+        j.set_button(Button::Up, false);
+        assert_eq!(j.read(0xFF00), 0xEB);
+        j.set_latched(false);
+        assert_eq!(j.read(0xFF00), 0xEF);
+        j.set_button(Button::Down, true);
+        assert_eq!(j.read(0xFF00), 0xE7);
+    }
+
+    #[test]
+    fn intreq() {
+        let mut j = Joypad::new();
+        j.set_button(Button::A, true);
+        assert!(!j.get_clr_intreq());
+
+        // Selecting a row with a pressed button pulls it low
+        j.write(0xFF00, 0x10);
+        assert!(j.get_clr_intreq());
+
+        j.set_button(Button::B, true);
+        assert!(j.get_clr_intreq());
+        assert!(!j.get_clr_intreq());
+
+        // Releasing does not
+        j.set_button(Button::B, false);
+        assert!(!j.get_clr_intreq());
+
+        // Unselected row
+        j.set_button(Button::Left, true);
+        assert!(!j.get_clr_intreq());
+    }
+}
@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub mod tcp;
+
+/// Connection of the serial port to a link partner
+///
+/// Transfers are exchanged a byte at a time: the side
+/// providing the clock calls exchange() once all 8 bits
+/// would have been shifted out, the other side picks up
+/// the byte it received through poll().
+pub trait SerialLink {
+    /// Announces that this side waits for a transfer clocked
+    /// by the partner, sending 'sb' (None: no longer waiting).
+    fn set_external(&mut self, sb: Option<u8>);
+
+    /// Performs a transfer clocked by this side: sends 'out'
+    /// and returns the byte shifted in from the partner (0xFF
+    /// if the partner was not waiting for a transfer).
+    fn exchange(&mut self, out: u8) -> u8;
+
+    /// Returns the byte received by a transfer clocked by the
+    /// partner, once it has completed. Called regularly while
+    /// this side is not clocking a transfer itself.
+    fn poll(&mut self) -> Option<u8>;
+}
+
+/// State of one side of the cable
+#[derive(Default)]
+struct Side {
+    /// Byte to send while waiting for an external clock
+    external: Option<u8>,
+
+    /// Byte received from a transfer clocked by the partner
+    received: Option<u8>,
+}
+
+/// One end of a link cable between two emulated machines in
+/// the same process. Both are expected to run in lockstep
+/// (e.g. alternating frames), which bounds the clock skew.
+pub struct LinkCable {
+    sides: Rc<RefCell<[Side; 2]>>,
+    side: usize,
+}
+
+impl LinkCable {
+    /// Returns both ends of a new cable, one for each bus.
+    pub fn pair() -> (Self, Self) {
+        let sides = Rc::new(RefCell::new([Side::default(), Side::default()]));
+        (
+            Self {
+                sides: Rc::clone(&sides),
+                side: 0,
+            },
+            Self { sides, side: 1 },
+        )
+    }
+}
+
+impl SerialLink for LinkCable {
+    fn set_external(&mut self, sb: Option<u8>) {
+        self.sides.borrow_mut()[self.side].external = sb;
+    }
+
+    fn exchange(&mut self, out: u8) -> u8 {
+        let mut sides = self.sides.borrow_mut();
+        let partner = &mut sides[self.side ^ 1];
+        match partner.external.take() {
+            Some(sb) => {
+                partner.received = Some(out);
+                sb
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.sides.borrow_mut()[self.side].received.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cable() {
+        let (mut a, mut b) = LinkCable::pair();
+
+        // Partner not waiting
+        assert_eq!(a.exchange(0x12), 0xFF);
+        assert_eq!(b.poll(), None);
+
+        b.set_external(Some(0x34));
+        assert_eq!(a.exchange(0x12), 0x34);
+        assert_eq!(b.poll(), Some(0x12));
+        assert_eq!(b.poll(), None);
+
+        // Only one transfer per wait
+        assert_eq!(a.exchange(0x12), 0xFF);
+
+        // Either side can clock
+        a.set_external(Some(0x56));
+        a.set_external(None);
+        assert_eq!(b.exchange(0x78), 0xFF);
+        a.set_external(Some(0x56));
+        assert_eq!(b.exchange(0x78), 0x56);
+        assert_eq!(a.poll(), Some(0x78));
+    }
+}
@@ -0,0 +1,300 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use super::SerialLink;
+
+/// Handshake sent by both sides after connecting
+const MAGIC: &[u8; 4] = b"GBLK";
+const VERSION: u8 = 1;
+
+/// Messages, [kind, sequence, data]
+const MSG_CLOCK: u8 = 1;
+const MSG_REPLY: u8 = 2;
+
+/// Time to wait for the partner to answer a transfer
+/// before giving up and shifting in 0xFF
+const REPLY_TIMEOUT: Duration = Duration::from_millis(250);
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between reconnection attempts of a client
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+type Message = [u8; 3];
+
+enum Role {
+    /// Accepts a (new) partner on the listener
+    Server(TcpListener),
+
+    /// Connects (again) to the partner at this address
+    Client(SocketAddr),
+}
+
+/// Established, handshaked connection
+struct Connection {
+    stream: TcpStream,
+
+    /// Messages received by the reader thread
+    rx: Receiver<Message>,
+}
+
+/// Link cable over TCP, to a gbrust instance elsewhere
+///
+/// The side clocking a transfer sends its byte and waits for
+/// the partner's byte in reply (up to REPLY_TIMEOUT). The
+/// partner answers from its own emulation loop (poll()), so
+/// the clocking side stalls for about one network round
+/// trip per byte, which most games tolerate.
+///
+/// When the connection drops, transfers shift in 0xFF as if
+/// the cable were unplugged. A server accepts a new partner
+/// and a client reconnects every RECONNECT_INTERVAL.
+pub struct TcpLink {
+    role: Role,
+    conn: Option<Connection>,
+
+    /// Byte to send while waiting for an external clock
+    external: Option<u8>,
+
+    /// Byte received from a transfer clocked by the partner
+    received: Option<u8>,
+
+    /// Sequence number of the last transfer clocked here
+    seq: u8,
+
+    /// Time of the last connection attempt (client)
+    last_attempt: Instant,
+}
+
+impl TcpLink {
+    /// Listens on 'addr'. The partner is accepted once it
+    /// connects, while the emulation runs.
+    pub fn listen(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context(format!("Binding {}", addr))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self::new(Role::Server(listener)))
+    }
+
+    /// Connects to a partner listening on 'addr'.
+    pub fn connect(addr: &str) -> Result<Self> {
+        let Some(sockaddr) = addr.to_socket_addrs()?.next() else {
+            bail!("Cannot resolve {}", addr);
+        };
+        let stream = TcpStream::connect(sockaddr).context(format!("Connecting to {}", addr))?;
+        let mut link = Self::new(Role::Client(sockaddr));
+        link.conn = Some(handshake(stream)?);
+        Ok(link)
+    }
+
+    fn new(role: Role) -> Self {
+        Self {
+            role,
+            conn: None,
+            external: None,
+            received: None,
+            seq: 0,
+            last_attempt: Instant::now(),
+        }
+    }
+
+    /// Address the server listens on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.role {
+            Role::Server(listener) => listener.local_addr().ok(),
+            Role::Client(_) => None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// Accepts a partner (server) or reconnects (client) if
+    /// there is no connection.
+    fn reconnect(&mut self) {
+        if self.conn.is_some() {
+            return;
+        }
+        let stream = match &self.role {
+            Role::Server(listener) => match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => return,
+            },
+            Role::Client(addr) => {
+                if self.last_attempt.elapsed() < RECONNECT_INTERVAL {
+                    return;
+                }
+                self.last_attempt = Instant::now();
+                match TcpStream::connect_timeout(addr, CONNECT_TIMEOUT) {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                }
+            }
+        };
+        self.conn = handshake(stream).ok();
+    }
+
+    fn send(&mut self, msg: Message) {
+        if let Some(conn) = &mut self.conn {
+            if conn.stream.write_all(&msg).is_err() {
+                self.conn = None;
+            }
+        }
+    }
+
+    /// Answers a transfer clocked by the partner.
+    fn handle_clock(&mut self, seq: u8, data: u8) {
+        let reply = match self.external.take() {
+            Some(sb) => {
+                self.received = Some(data);
+                sb
+            }
+            None => 0xFF,
+        };
+        self.send([MSG_REPLY, seq, reply]);
+    }
+}
+
+/// Exchanges the handshake and starts the reader thread.
+fn handshake(mut stream: TcpStream) -> Result<Connection> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let mut hello = MAGIC.to_vec();
+    hello.push(VERSION);
+    stream.write_all(&hello)?;
+    let mut peer = [0; 5];
+    stream.read_exact(&mut peer)?;
+    if peer != hello[..] {
+        bail!("Partner is not a compatible link cable");
+    }
+    stream.set_read_timeout(None)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut reader = stream.try_clone()?;
+    thread::spawn(move || {
+        let mut msg = [0; 3];
+        while reader.read_exact(&mut msg).is_ok() && tx.send(msg).is_ok() {}
+    });
+    Ok(Connection { stream, rx })
+}
+
+impl SerialLink for TcpLink {
+    fn set_external(&mut self, sb: Option<u8>) {
+        self.external = sb;
+    }
+
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.reconnect();
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        self.send([MSG_CLOCK, seq, out]);
+
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            let Some(conn) = &self.conn else {
+                return 0xFF;
+            };
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match conn.rx.recv_timeout(timeout) {
+                Ok([MSG_REPLY, s, data]) if s == seq => return data,
+                // Both sides clocking at once
+                Ok([MSG_CLOCK, s, data]) => self.handle_clock(s, data),
+                // Late reply to an earlier transfer
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => return 0xFF,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.conn = None;
+                    return 0xFF;
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.reconnect();
+        while let Some(conn) = &self.conn {
+            match conn.rx.try_recv() {
+                Ok([MSG_CLOCK, s, data]) => self.handle_clock(s, data),
+                Ok(_) => (),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.conn = None,
+            }
+        }
+        self.received.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> (TcpLink, String) {
+        let link = TcpLink::listen("127.0.0.1:0").unwrap();
+        let addr = link.local_addr().unwrap().to_string();
+        (link, addr)
+    }
+
+    /// Connects a client in a thread, which waits for a
+    /// transfer sending 'sb' and returns the byte received.
+    fn client(addr: String, sb: u8) -> thread::JoinHandle<u8> {
+        thread::spawn(move || {
+            let mut link = TcpLink::connect(&addr).unwrap();
+            link.set_external(Some(sb));
+            loop {
+                if let Some(b) = link.poll() {
+                    return b;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    }
+
+    /// Clocks transfers until the partner answers.
+    fn clock(link: &mut TcpLink, out: u8) -> u8 {
+        for _ in 0..1000 {
+            link.poll();
+            let b = link.exchange(out);
+            if b != 0xFF {
+                return b;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("No answer");
+    }
+
+    #[test]
+    fn transfer() {
+        let (mut server, addr) = server();
+        assert!(!server.is_connected());
+        assert_eq!(server.exchange(0x11), 0xFF);
+
+        let c = client(addr.clone(), 0x42);
+        assert_eq!(clock(&mut server, 0x11), 0x42);
+        assert_eq!(c.join().unwrap(), 0x11);
+
+        // A new partner is accepted after a disconnect
+        let c = client(addr, 0x43);
+        assert_eq!(clock(&mut server, 0x12), 0x43);
+        assert_eq!(c.join().unwrap(), 0x12);
+    }
+
+    #[test]
+    fn bad_handshake() {
+        let (mut server, addr) = server();
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.write_all(b"HELLO").unwrap();
+        for _ in 0..100 {
+            server.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!server.is_connected());
+    }
+}
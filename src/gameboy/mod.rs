@@ -1,3 +1,31 @@
+pub mod apu;
 pub mod bus;
+pub mod cartridge;
+pub mod cheats;
+pub mod config;
+pub mod coverage;
 pub mod cpu;
+pub mod debugger;
+pub mod dma;
+pub mod emulator;
+pub mod emuthread;
+pub mod error;
+pub mod hdma;
 pub mod iomux;
+pub mod joypad;
+pub mod link;
+pub mod model;
+pub mod movie;
+pub mod pacing;
+pub mod ppu;
+pub mod profiler;
+pub mod rewind;
+pub mod savestate;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod screenshot;
+pub mod serial;
+pub mod testrunner;
+pub mod timer;
+pub mod video;
+pub mod wav;
@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use super::cartridge::header::CartridgeHeader;
+
+/// Game Boy hardware model to emulate
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Model {
+    /// Original Game Boy
+    Dmg,
+
+    /// Game Boy Color, running in CGB mode
+    Cgb,
+}
+
+impl Model {
+    /// Picks the model for a cartridge: CGB for cartridges
+    /// that support it, DMG otherwise.
+    pub fn detect(header: &CartridgeHeader) -> Self {
+        if header.supports_cgb() {
+            Self::Cgb
+        } else {
+            Self::Dmg
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        *self == Self::Cgb
+    }
+}
+
+impl FromStr for Model {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(Self::Dmg),
+            "cgb" => Ok(Self::Cgb),
+            _ => bail!("Unknown model '{}' (expected dmg or cgb)", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("dmg".parse::<Model>().unwrap(), Model::Dmg);
+        assert_eq!("CGB".parse::<Model>().unwrap(), Model::Cgb);
+        assert!("sgb".parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn detect() {
+        let mut rom = vec![0; 32 * 1024];
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(Model::detect(&h), Model::Dmg);
+
+        rom[0x0143] = 0x80;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(Model::detect(&h), Model::Cgb);
+
+        rom[0x0143] = 0xC0;
+        let h = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(Model::detect(&h), Model::Cgb);
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::{bail, Result};
+
+use super::cartridge::rtc::RtcClock;
+use super::emulator::Emulator;
+use super::joypad::Button;
+use super::savestate::{StateReader, StateWriter};
+
+/// Magic bytes at the start of a movie file
+const MOVIE_MAGIC: &[u8; 4] = b"GBMV";
+
+/// Magic bytes at the start of a movie save state
+const MOVIE_STATE_MAGIC: &[u8; 4] = b"GBMS";
+
+/// Current movie file format version
+const MOVIE_VERSION: u8 = 1;
+
+/// Joypad state of one frame, a bit per button (see
+/// Button::mask()).
+pub type Input = u8;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MovieMode {
+    /// Inputs given to next_frame() are appended
+    Recording,
+
+    /// Inputs come from the movie
+    Playing,
+
+    /// Playback reached the end of the movie; inputs given
+    /// to next_frame() are passed through
+    Finished,
+}
+
+/// Input movie: the joypad state of every frame, recorded
+/// from a save state
+///
+/// The core has no sources of randomness, so replaying the
+/// same inputs from the same state reproduces the run exactly
+/// as long as:
+///  - inputs only change between frames (through
+///    next_frame()), and frames are run with
+///    Emulator::run_frame(),
+///  - a cartridge clock runs on emulated cycles (record()
+///    and play() switch it over from the host clock),
+///  - no link cable is connected.
+pub struct Movie {
+    /// Hash of the ROM the movie was recorded on
+    rom_hash: u64,
+
+    /// Save state the movie starts from
+    start: Vec<u8>,
+
+    /// Input per frame
+    inputs: Vec<Input>,
+
+    /// Amount of times a save state was loaded while recording
+    rerecords: u32,
+
+    /// Next frame to record or play
+    frame: usize,
+
+    mode: MovieMode,
+}
+
+/// FNV-1a hash of a ROM, identifying it in a movie.
+fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xCBF29CE484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+/// Presses exactly the buttons in 'input'.
+fn apply(emu: &mut Emulator, input: Input) {
+    for button in Button::ALL {
+        emu.set_button(button, input & button.mask() != 0);
+    }
+}
+
+impl Movie {
+    /// Starts recording a movie from the current state of the
+    /// machine running 'rom'.
+    pub fn record(emu: &mut Emulator, rom: &[u8]) -> Result<Self> {
+        emu.bus().cartridge().set_rtc_clock(RtcClock::Cycles);
+        Ok(Self {
+            rom_hash: rom_hash(rom),
+            start: emu.cpu().save_state()?,
+            inputs: vec![],
+            rerecords: 0,
+            frame: 0,
+            mode: MovieMode::Recording,
+        })
+    }
+
+    /// Restores the starting state of the movie and plays it
+    /// back from the first frame.
+    pub fn play(&mut self, emu: &mut Emulator) -> Result<()> {
+        emu.bus().cartridge().set_rtc_clock(RtcClock::Cycles);
+        emu.cpu().load_state(&self.start)?;
+        self.frame = 0;
+        self.mode = MovieMode::Playing;
+        Ok(())
+    }
+
+    /// Switches from playback to recording at the current
+    /// frame, discarding the rest of the movie.
+    pub fn resume_recording(&mut self) {
+        self.inputs.truncate(self.frame);
+        self.mode = MovieMode::Recording;
+    }
+
+    /// Fails if the movie was not recorded on 'rom'.
+    pub fn check_rom(&self, rom: &[u8]) -> Result<()> {
+        if rom_hash(rom) != self.rom_hash {
+            bail!("Movie was recorded on a different ROM");
+        }
+        Ok(())
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    /// Next frame to record or play.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Length of the movie in frames.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    pub fn rerecords(&self) -> u32 {
+        self.rerecords
+    }
+
+    /// Input of a frame of the movie.
+    pub fn input(&self, frame: usize) -> Option<Input> {
+        self.inputs.get(frame).copied()
+    }
+
+    /// Call before running every frame. Records 'input' or
+    /// replaces it by the input from the movie, applies it to
+    /// the joypad and returns it.
+    pub fn next_frame(&mut self, emu: &mut Emulator, input: Input) -> Input {
+        let input = match self.mode {
+            MovieMode::Recording => {
+                self.inputs.push(input);
+                self.frame += 1;
+                input
+            }
+            MovieMode::Playing => match self.input(self.frame) {
+                Some(recorded) => {
+                    self.frame += 1;
+                    recorded
+                }
+                None => {
+                    self.mode = MovieMode::Finished;
+                    input
+                }
+            },
+            MovieMode::Finished => input,
+        };
+        apply(emu, input);
+        input
+    }
+
+    /// Creates a save state that includes the position in the
+    /// movie and the inputs up to it.
+    pub fn save_state(&self, emu: &mut Emulator) -> Result<Vec<u8>> {
+        let mut w = StateWriter::new();
+        w.tag(MOVIE_STATE_MAGIC);
+        w.u64(self.rom_hash);
+        w.bytes(&self.inputs[..self.frame]);
+        w.bytes(&emu.cpu().save_state()?);
+        Ok(w.into_inner())
+    }
+
+    /// Loads a state created by save_state().
+    ///
+    /// While recording, this is a re-record: the movie
+    /// continues from the state's frame with the inputs that
+    /// led up to it. During playback, the state must be from
+    /// this movie and playback continues from there.
+    pub fn load_state(&mut self, emu: &mut Emulator, state: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(state);
+        r.tag(MOVIE_STATE_MAGIC, "movie")?;
+        if r.u64()? != self.rom_hash {
+            bail!("Save state was made on a different ROM");
+        }
+        let inputs = r.bytes()?;
+        let cpu_state = r.bytes()?;
+        r.finish()?;
+
+        if self.mode != MovieMode::Recording && !self.inputs.starts_with(inputs) {
+            bail!("Save state is not part of this movie");
+        }
+        emu.cpu().load_state(cpu_state)?;
+        if self.mode == MovieMode::Recording {
+            self.inputs = inputs.to_vec();
+            self.rerecords += 1;
+        } else {
+            self.mode = MovieMode::Playing;
+        }
+        self.frame = inputs.len();
+        Ok(())
+    }
+
+    /// Serializes the movie for a movie file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.tag(MOVIE_MAGIC);
+        w.u8(MOVIE_VERSION);
+        w.u64(self.rom_hash);
+        w.u32(self.rerecords);
+        w.bytes(&self.start);
+        w.bytes(&self.inputs);
+        w.into_inner()
+    }
+
+    /// Loads a movie file written from to_bytes(). Call
+    /// play() to start playback.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut r = StateReader::new(data);
+        if r.tag(MOVIE_MAGIC, "movie").is_err() {
+            bail!("Not a movie file");
+        }
+        let version = r.u8()?;
+        if version != MOVIE_VERSION {
+            bail!("Unsupported movie version {}", version);
+        }
+        let movie = Self {
+            rom_hash: r.u64()?,
+            rerecords: r.u32()?,
+            start: r.bytes()?.to_vec(),
+            inputs: r.bytes()?.to_vec(),
+            frame: 0,
+            mode: MovieMode::Finished,
+        };
+        r.finish()?;
+        Ok(movie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bus::bus::Bus;
+    use super::*;
+
+    /// Adds the pressed action buttons to $C000 in a loop.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 32 * 1024];
+        let code = [
+            0x3E, 0x10, // LD A,$10
+            0xE0, 0x00, // LDH ($00),A
+            0xF0, 0x00, // LDH A,($00)
+            0x2F, // CPL
+            0xE6, 0x0F, // AND $0F
+            0x21, 0x00, 0xC0, // LD HL,$C000
+            0x86, // ADD (HL)
+            0x77, // LD (HL),A
+            0x18, 0xF4, // JR $-12
+        ];
+        rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+        rom
+    }
+
+    fn input(frame: usize) -> Input {
+        [0, Button::A.mask(), Button::B.mask() | Button::Start.mask()][frame % 3]
+    }
+
+    /// Records 'frames' frames, returning the movie and the
+    /// final state.
+    fn record(emu: &mut Emulator, frames: usize) -> (Movie, Vec<u8>) {
+        let mut movie = Movie::record(emu, &rom()).unwrap();
+        for i in 0..frames {
+            assert_eq!(movie.next_frame(emu, input(i)), input(i));
+            emu.run_frame().unwrap();
+        }
+        let state = emu.cpu().save_state().unwrap();
+        (movie, state)
+    }
+
+    #[test]
+    fn playback() {
+        let mut emu = Emulator::new(&rom()).unwrap();
+        let (movie, state) = record(&mut emu, 20);
+        assert_eq!(movie.len(), 20);
+        assert_ne!(emu.cpu().bus.read(0xC000), 0);
+
+        let mut movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        movie.check_rom(&rom()).unwrap();
+        assert!(movie.check_rom(&[0; 32 * 1024]).is_err());
+
+        let mut emu = Emulator::new(&rom()).unwrap();
+        movie.play(&mut emu).unwrap();
+        while movie.frame() < movie.len() {
+            // Live input is ignored during playback
+            movie.next_frame(&mut emu, 0xFF);
+            emu.run_frame().unwrap();
+        }
+        assert!(emu.cpu().save_state().unwrap() == state);
+
+        // Live input is passed through after the end
+        assert_eq!(movie.next_frame(&mut emu, 0x12), 0x12);
+        assert_eq!(movie.mode(), MovieMode::Finished);
+    }
+
+    #[test]
+    fn rerecord() {
+        let mut emu = Emulator::new(&rom()).unwrap();
+        let mut movie = Movie::record(&mut emu, &rom()).unwrap();
+        let mut saved = None;
+        for i in 0..20 {
+            if i == 10 {
+                saved = Some(movie.save_state(&mut emu).unwrap());
+            }
+            // A mistake after the save state
+            movie.next_frame(&mut emu, if i >= 10 { 0xFF } else { input(i) });
+            emu.run_frame().unwrap();
+        }
+
+        movie.load_state(&mut emu, &saved.unwrap()).unwrap();
+        assert_eq!((movie.frame(), movie.len(), movie.rerecords()), (10, 10, 1));
+        for i in 10..20 {
+            movie.next_frame(&mut emu, input(i));
+            emu.run_frame().unwrap();
+        }
+        let rerecorded = emu.cpu().save_state().unwrap();
+
+        // Same result as recording without mistakes
+        let mut emu = Emulator::new(&rom()).unwrap();
+        let (clean, state) = record(&mut emu, 20);
+        assert!(rerecorded == state);
+        assert_eq!(movie.inputs, clean.inputs);
+    }
+
+    #[test]
+    fn load_state_playing() {
+        let mut emu = Emulator::new(&rom()).unwrap();
+        let (mut movie, _) = record(&mut emu, 10);
+        let state = movie.save_state(&mut emu).unwrap();
+
+        movie.play(&mut emu).unwrap();
+        movie.load_state(&mut emu, &state).unwrap();
+        assert_eq!(movie.frame(), 10);
+
+        // States from another timeline are rejected
+        let mut emu = Emulator::new(&rom()).unwrap();
+        let mut other = Movie::record(&mut emu, &rom()).unwrap();
+        for _ in 0..5 {
+            other.next_frame(&mut emu, 0xFF);
+            emu.run_frame().unwrap();
+        }
+        let state = other.save_state(&mut emu).unwrap();
+        movie.play(&mut emu).unwrap();
+        assert!(movie.load_state(&mut emu, &state).is_err());
+
+        movie.resume_recording();
+        movie.load_state(&mut emu, &state).unwrap();
+        assert_eq!(movie.len(), 5);
+    }
+
+    #[test]
+    fn bad_file() {
+        assert!(Movie::from_bytes(b"GBRS").is_err());
+        let mut emu = Emulator::new(&rom()).unwrap();
+        let (movie, _) = record(&mut emu, 1);
+        let mut data = movie.to_bytes();
+        data.pop();
+        assert!(Movie::from_bytes(&data).is_err());
+    }
+}
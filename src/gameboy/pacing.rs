@@ -0,0 +1,205 @@
+use std::time::{Duration, Instant};
+
+use super::apu::CPU_CLOCK;
+use super::ppu::CYCLES_PER_FRAME;
+
+/// Frames per second of the real hardware (~59.7275)
+pub const FRAME_RATE: f64 = CPU_CLOCK as f64 / CYCLES_PER_FRAME as f64;
+
+/// Frames the pacer may fall behind before it gives up on
+/// catching up and resynchronizes to the current time.
+const MAX_LAG_FRAMES: u32 = 5;
+
+/// Frames skipped between rendered frames when running
+/// at unlimited speed
+const UNLIMITED_SKIP: usize = 10;
+
+/// Audio kept queued by audio-clocked pacing
+pub const AUDIO_LATENCY: Duration = Duration::from_millis(50);
+
+/// Emulation speed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Speed {
+    /// Real hardware speed
+    Normal,
+
+    /// A multiple of the hardware speed: fast-forward above
+    /// 1.0, slow motion below.
+    Times(f64),
+
+    /// As fast as possible
+    Unlimited,
+}
+
+/// Paces the main loop of a frontend to the frame rate of
+/// the hardware (or a multiple of it).
+///
+/// Timer-clocked frontends call wait() (or delay()) after
+/// every frame. Audio-clocked frontends instead wait for
+/// audio_delay() based on the amount of queued audio. In
+/// fast-forward, render_frame() tells which frames to draw.
+pub struct FramePacer {
+    speed: Speed,
+
+    /// Time the next frame is due, once running
+    deadline: Option<Instant>,
+
+    /// Frames since the last rendered frame
+    skipped: usize,
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        Self {
+            speed: Speed::Normal,
+            deadline: None,
+            skipped: 0,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.deadline = None;
+        self.skipped = 0;
+    }
+
+    /// Wall-clock time per emulated frame, None when running
+    /// at unlimited speed.
+    pub fn frame_time(&self) -> Option<Duration> {
+        let fps = match self.speed {
+            Speed::Normal => FRAME_RATE,
+            Speed::Times(mult) if mult > 0.0 => FRAME_RATE * mult,
+            Speed::Times(_) | Speed::Unlimited => return None,
+        };
+        Some(Duration::from_secs_f64(1.0 / fps))
+    }
+
+    /// Timer-clocked pacing: returns how long to wait after
+    /// a frame completed at 'now' before starting the next.
+    pub fn delay(&mut self, now: Instant) -> Duration {
+        let Some(frame_time) = self.frame_time() else {
+            return Duration::ZERO;
+        };
+        let deadline = match self.deadline {
+            Some(d) if now <= d + frame_time * MAX_LAG_FRAMES => d + frame_time,
+            // First frame, or too far behind to catch up
+            _ => now + frame_time,
+        };
+        self.deadline = Some(deadline);
+        deadline.saturating_duration_since(now)
+    }
+
+    /// Sleeps until the next frame is due.
+    pub fn wait(&mut self) {
+        let delay = self.delay(Instant::now());
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Audio-clocked pacing: returns how long to wait until
+    /// the queued audio has drained down to AUDIO_LATENCY.
+    pub fn audio_delay(&self, queued: Duration) -> Duration {
+        queued.saturating_sub(AUDIO_LATENCY)
+    }
+
+    /// Frame skipping: returns true if the current frame
+    /// should be drawn. When fast-forwarding at N times the
+    /// speed, one in N frames is drawn.
+    pub fn render_frame(&mut self) -> bool {
+        let interval = match self.speed {
+            Speed::Normal => 1,
+            Speed::Times(mult) => mult.ceil().max(1.0) as usize,
+            Speed::Unlimited => UNLIMITED_SKIP,
+        };
+        self.skipped += 1;
+        if self.skipped >= interval {
+            self.skipped = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rate() {
+        assert!((FRAME_RATE - 59.7275).abs() < 0.0001);
+    }
+
+    #[test]
+    fn frame_time() {
+        let mut p = FramePacer::new();
+        let normal = p.frame_time().unwrap();
+        assert_eq!(normal.as_micros(), 16742);
+
+        p.set_speed(Speed::Times(2.0));
+        assert_eq!(p.frame_time().unwrap(), normal / 2);
+        p.set_speed(Speed::Times(0.5));
+        assert_eq!(p.frame_time().unwrap().as_micros(), 16742 * 2 + 1);
+        p.set_speed(Speed::Unlimited);
+        assert_eq!(p.frame_time(), None);
+    }
+
+    #[test]
+    fn delay() {
+        let mut p = FramePacer::new();
+        let frame = p.frame_time().unwrap();
+        let start = Instant::now();
+
+        // Frames that take part of the frame time
+        assert_eq!(p.delay(start), frame);
+        let t = start + frame + Duration::from_millis(5);
+        assert_eq!(p.delay(t), frame - Duration::from_millis(5));
+
+        // A late frame is caught up on by the next ones
+        let t = start + frame * 3;
+        assert_eq!(p.delay(t), Duration::ZERO);
+        assert_eq!(p.delay(start + frame * 3), frame);
+
+        // Too far behind: resynchronize
+        let t = start + frame * 20;
+        assert_eq!(p.delay(t), frame);
+
+        p.set_speed(Speed::Unlimited);
+        assert_eq!(p.delay(t), Duration::ZERO);
+    }
+
+    #[test]
+    fn audio_delay() {
+        let p = FramePacer::new();
+        assert_eq!(p.audio_delay(Duration::from_millis(10)), Duration::ZERO);
+        assert_eq!(
+            p.audio_delay(AUDIO_LATENCY + Duration::from_millis(10)),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn render_frame() {
+        let rendered = |p: &mut FramePacer| (0..60).filter(|_| p.render_frame()).count();
+
+        let mut p = FramePacer::new();
+        assert_eq!(rendered(&mut p), 60);
+        p.set_speed(Speed::Times(3.0));
+        assert_eq!(rendered(&mut p), 20);
+        p.set_speed(Speed::Times(0.25));
+        assert_eq!(rendered(&mut p), 60);
+        p.set_speed(Speed::Unlimited);
+        assert_eq!(rendered(&mut p), 6);
+    }
+}
@@ -0,0 +1,1749 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use super::bus::bus::Bus;
+use super::savestate::{StateErr, StateReader, StateWriter};
+
+mod fifo;
+
+use fifo::Fifo;
+
+/// Display width in pixels
+pub const LCD_WIDTH: usize = 160;
+
+/// Display height in pixels
+pub const LCD_HEIGHT: usize = 144;
+
+/// RGBA pixel color
+pub type Color = u32;
+
+/// A full frame, LCD_WIDTH * LCD_HEIGHT pixels row by row
+pub type Frame = [Color; LCD_WIDTH * LCD_HEIGHT];
+
+/// FNV-1a hash of the pixels of a frame, to compare against
+/// a known-good screen.
+pub fn frame_hash(pixels: &[Color]) -> u64 {
+    pixels
+        .iter()
+        .flat_map(|p| p.to_le_bytes())
+        .fold(0xCBF29CE484222325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001B3)
+        })
+}
+
+/// Size of a single VRAM bank
+const VRAM_SIZE: usize = 0x9FFF - 0x8000 + 1;
+
+/// Offset of VRAM bank 1 (CGB)
+const VRAM_BANK1: usize = VRAM_SIZE;
+const OAM_SIZE: usize = 0xFE9F - 0xFE00 + 1;
+
+/// Dots (T-cycles) per scanline
+pub const DOTS_PER_LINE: usize = 456;
+
+/// Scanlines per frame, including V-blank
+pub const LINES_PER_FRAME: u8 = 154;
+
+/// T-cycles per frame
+pub const CYCLES_PER_FRAME: usize = DOTS_PER_LINE * LINES_PER_FRAME as usize;
+
+/// Dots spent in OAM search (mode 2)
+pub const DOTS_OAM_SEARCH: usize = 80;
+
+/// Dots spent in pixel transfer (mode 3)
+pub const DOTS_TRANSFER: usize = 172;
+
+// LCDC bits
+const LCDC_BG_ENABLE: u8 = 1 << 0;
+const LCDC_OBJ_ENABLE: u8 = 1 << 1;
+const LCDC_OBJ_SIZE: u8 = 1 << 2;
+const LCDC_BG_TILEMAP: u8 = 1 << 3;
+const LCDC_TILEDATA: u8 = 1 << 4;
+const LCDC_WINDOW_ENABLE: u8 = 1 << 5;
+const LCDC_WINDOW_TILEMAP: u8 = 1 << 6;
+const LCDC_ENABLE: u8 = 1 << 7;
+
+// OAM attribute bits; the CGB BG map attributes in VRAM
+// bank 1 share the flip, bank and priority bits.
+const ATTR_CGB_PALETTE: u8 = 0x07;
+const ATTR_BANK: u8 = 1 << 3;
+const OAM_PALETTE: u8 = 1 << 4;
+const OAM_XFLIP: u8 = 1 << 5;
+const OAM_YFLIP: u8 = 1 << 6;
+const OAM_BG_PRIORITY: u8 = 1 << 7;
+
+/// Maximum amount of sprites drawn on a single line
+const SPRITES_PER_LINE: usize = 10;
+
+// STAT bits
+const STAT_LYC_EQ: u8 = 1 << 2;
+const STAT_INT_HBLANK: u8 = 1 << 3;
+const STAT_INT_VBLANK: u8 = 1 << 4;
+const STAT_INT_OAM: u8 = 1 << 5;
+const STAT_INT_LYC: u8 = 1 << 6;
+const STAT_WRITABLE: u8 = 0x78;
+
+/// RGBA values of the 4 DMG shades (white to black)
+pub const SHADES: [Color; 4] = [0xFFFFFFFF, 0xAAAAAAFF, 0x555555FF, 0x000000FF];
+
+/// BCPS/OCPS bit 7 - Auto-increment the index on writes
+const CPS_AUTO_INC: u8 = 1 << 7;
+
+/// Tiles per VRAM bank (0x8000 - 0x97FF)
+pub const TILES_PER_BANK: usize = 384;
+
+/// Tiles per row in dump_tiles()
+const TILES_PER_ROW: usize = 16;
+
+/// Decoded RGBA image, for debug viewers
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+
+    /// Pixels row by row
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+}
+
+/// Decoded OAM entry, for debug viewers
+pub struct Sprite {
+    /// Position on the screen, the top left corner
+    /// is at (-8, -16)
+    pub x: i16,
+    pub y: i16,
+
+    pub tile: u8,
+
+    /// Raw attribute byte
+    pub attr: u8,
+
+    /// Palette: OBP0/OBP1 on DMG, 0-7 on CGB
+    pub palette: u8,
+
+    /// VRAM bank of the tile (CGB)
+    pub bank: u8,
+
+    pub xflip: bool,
+    pub yflip: bool,
+
+    /// Drawn behind background colors 1-3
+    pub behind_bg: bool,
+
+    /// Sprite as drawn (8x8 or 8x16), color 0 is transparent
+    pub image: Image,
+}
+
+/// Background/window pixel, as needed for sprite priority
+#[derive(Copy, Clone, Default)]
+struct BgPixel {
+    /// Color index (0-3)
+    color: u8,
+
+    /// CGB palette (0-7)
+    palette: u8,
+
+    /// CGB BG-to-OAM priority attribute
+    priority: bool,
+}
+
+/// PPU mode, as reported in STAT bits 0-1
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamSearch = 2,
+    Transfer = 3,
+}
+
+/// Renderer for pixel transfer (mode 3)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Renderer {
+    /// Draws a whole line at the end of a fixed-length
+    /// mode 3. Fast, but register writes during mode 3 take
+    /// effect for the entire line.
+    Scanline,
+
+    /// Dot by dot through a background fetcher and pixel
+    /// FIFOs, with the variable mode 3 length of the
+    /// hardware (SCX fine scroll, window and sprite fetches).
+    /// Needed for mid-line raster effects.
+    Fifo,
+}
+
+impl FromStr for Renderer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "scanline" => Ok(Self::Scanline),
+            "fifo" => Ok(Self::Fifo),
+            _ => bail!("Unknown renderer '{}' (expected scanline or fifo)", s),
+        }
+    }
+}
+
+/// Background tile map, as selected by LCDC bits 3 and 6
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TileMap {
+    /// 0x9800 - 0x9BFF
+    Low,
+
+    /// 0x9C00 - 0x9FFF
+    High,
+}
+
+impl TileMap {
+    pub fn addr(&self) -> u16 {
+        match self {
+            Self::Low => 0x9800,
+            Self::High => 0x9C00,
+        }
+    }
+}
+
+/// Tile data addressing, as selected by LCDC bit 4
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TileData {
+    /// Tiles 0 - 255 from 0x8000
+    Unsigned,
+
+    /// Tiles -128 - 127 around 0x9000 (0x8800 - 0x97FF)
+    Signed,
+}
+
+/// Picture Processing Unit
+/// Owns VRAM, OAM and the LCD registers (0xFF40 - 0xFF4B).
+pub struct Ppu {
+    /// CGB mode: VRAM banking, color palettes and
+    /// BG map attributes
+    cgb: bool,
+
+    /// VRAM, two banks on CGB
+    vram: [u8; VRAM_SIZE * 2],
+    oam: [u8; OAM_SIZE],
+
+    /// VBK - VRAM bank (CGB)
+    vbk: u8,
+
+    /// BCPS/OCPS - Palette index (CGB)
+    bcps: u8,
+    ocps: u8,
+
+    /// Background and object palette RAM, 8 palettes of
+    /// 4 RGB555 colors each (CGB)
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+
+    /// LCDC - LCD control
+    lcdc: u8,
+
+    /// STAT - LCD status (interrupt enable bits only)
+    stat: u8,
+
+    /// SCY/SCX - Background scroll
+    scy: u8,
+    scx: u8,
+
+    /// LY - Current scanline
+    ly: u8,
+
+    /// LYC - LY compare
+    lyc: u8,
+
+    /// BGP/OBP0/OBP1 - Palettes
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+
+    /// WY/WX - Window position
+    wy: u8,
+    wx: u8,
+
+    /// LY reached WY this frame, window may be drawn
+    window_triggered: bool,
+
+    /// Internal window line counter
+    window_line: u8,
+
+    /// Dots into the current scanline
+    dots: usize,
+
+    mode: PpuMode,
+
+    renderer: Renderer,
+
+    /// State of the FIFO renderer during mode 3
+    fifo: Fifo,
+
+    /// Current STAT interrupt line, requests are
+    /// raised on its rising edge only.
+    stat_line: bool,
+
+    framebuffer: Box<Frame>,
+    frame_ready: bool,
+
+    intreq_vblank: bool,
+    intreq_stat: bool,
+
+    /// A visible line entered H-blank (for HDMA)
+    hblank_event: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
+            cgb: false,
+            vram: [0; VRAM_SIZE * 2],
+            oam: [0; OAM_SIZE],
+            vbk: 0,
+            bcps: 0,
+            ocps: 0,
+            bg_palettes: [0; 64],
+            obj_palettes: [0; 64],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            window_triggered: false,
+            window_line: 0,
+            dots: 0,
+            mode: PpuMode::HBlank,
+            renderer: Renderer::Scanline,
+            fifo: Fifo::default(),
+            stat_line: false,
+            framebuffer: vec![SHADES[0]; LCD_WIDTH * LCD_HEIGHT]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
+            frame_ready: false,
+            intreq_vblank: false,
+            intreq_stat: false,
+            hblank_event: false,
+        }
+    }
+
+    /// PPU in CGB mode. Background palettes start out white.
+    pub fn new_cgb() -> Self {
+        Self {
+            cgb: true,
+            bg_palettes: [0xFF; 64],
+            ..Self::new()
+        }
+    }
+
+    /// Current framebuffer.
+    pub fn get_framebuffer(&self) -> &Frame {
+        &self.framebuffer
+    }
+
+    /// Resets to the power-on state (clearing VRAM and OAM),
+    /// keeping the renderer.
+    pub fn reset(&mut self) {
+        let renderer = self.renderer;
+        *self = if self.cgb {
+            Self::new_cgb()
+        } else {
+            Self::new()
+        };
+        self.renderer = renderer;
+    }
+
+    /// Returns true once after a full frame was drawn.
+    pub fn frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    /// Returns and clears the V-blank interrupt request.
+    pub fn get_clr_intreq_vblank(&mut self) -> bool {
+        std::mem::take(&mut self.intreq_vblank)
+    }
+
+    /// Returns and clears the STAT interrupt request.
+    pub fn get_clr_intreq_stat(&mut self) -> bool {
+        std::mem::take(&mut self.intreq_stat)
+    }
+
+    /// Returns true once after a visible line entered H-blank.
+    pub fn get_clr_hblank(&mut self) -> bool {
+        std::mem::take(&mut self.hblank_event)
+    }
+
+    /// Writes a byte to OAM on behalf of OAM DMA.
+    pub fn write_oam(&mut self, offset: usize, val: u8) {
+        self.oam[offset] = val;
+    }
+
+    pub fn get_mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    /// Selects the renderer, also in the middle of a frame.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        // Mode 3 in progress starts over in the FIFO
+        let in_transfer = self.mode == PpuMode::Transfer;
+        if renderer == Renderer::Fifo && self.renderer == Renderer::Scanline && in_transfer {
+            self.fifo_start();
+        }
+        self.renderer = renderer;
+    }
+
+    /// Testing hook, not part of the emulation: jumps to the
+    /// start of 'mode' on line 'ly', so tests can check VRAM
+    /// and OAM locking and STAT without running up to that
+    /// point. The PPU carries on from there when ticked.
+    pub fn force_mode(&mut self, mode: PpuMode, ly: u8) {
+        self.ly = ly;
+        self.mode = mode;
+        self.dots = match mode {
+            PpuMode::HBlank => DOTS_OAM_SEARCH + DOTS_TRANSFER,
+            PpuMode::VBlank | PpuMode::OamSearch => 0,
+            PpuMode::Transfer => DOTS_OAM_SEARCH,
+        };
+        if mode == PpuMode::Transfer && self.renderer == Renderer::Fifo {
+            self.fifo_start();
+        }
+        self.update_stat_line();
+    }
+
+    /// True if the CPU can access 'addr' in the current mode:
+    /// VRAM is locked during pixel transfer (mode 3) and OAM
+    /// during OAM search and pixel transfer (modes 2 and 3).
+    /// Everything is accessible with the LCD off.
+    pub fn cpu_accessible(&self, addr: u16) -> bool {
+        if !self.lcd_enabled() {
+            return true;
+        }
+        match addr {
+            0x8000..=0x9FFF => self.mode != PpuMode::Transfer,
+            0xFE00..=0xFE9F => !matches!(self.mode, PpuMode::OamSearch | PpuMode::Transfer),
+            _ => true,
+        }
+    }
+
+    /// Offset into VRAM for a CPU access, through the
+    /// selected bank.
+    fn vram_addr(&self, addr: u16) -> usize {
+        self.vbk as usize * VRAM_BANK1 + (addr as usize - 0x8000)
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    /// Advances the PPU by a number of dots (T-cycles).
+    pub fn tick(&mut self, cycles: usize) {
+        if !self.lcd_enabled() {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.tick_dot();
+        }
+    }
+
+    fn tick_dot(&mut self) {
+        if self.dots == 0 && self.ly < LCD_HEIGHT as u8 {
+            self.check_window_trigger();
+        }
+        self.dots += 1;
+
+        if self.ly < LCD_HEIGHT as u8 {
+            match self.renderer {
+                Renderer::Scanline => {
+                    if self.dots == DOTS_OAM_SEARCH {
+                        self.mode = PpuMode::Transfer;
+                    } else if self.dots == DOTS_OAM_SEARCH + DOTS_TRANSFER {
+                        self.draw_scanline();
+                        self.mode = PpuMode::HBlank;
+                        self.hblank_event = true;
+                    }
+                }
+                Renderer::Fifo => {
+                    if self.dots == DOTS_OAM_SEARCH {
+                        self.mode = PpuMode::Transfer;
+                        self.fifo_start();
+                    } else if self.mode == PpuMode::Transfer && self.fifo_dot() {
+                        self.mode = PpuMode::HBlank;
+                        self.hblank_event = true;
+                    }
+                }
+            }
+        }
+
+        if self.dots == DOTS_PER_LINE {
+            self.dots = 0;
+            self.ly += 1;
+
+            if self.ly == LINES_PER_FRAME {
+                self.ly = 0;
+                self.window_triggered = false;
+                self.window_line = 0;
+            }
+
+            if self.ly == LCD_HEIGHT as u8 {
+                self.mode = PpuMode::VBlank;
+                self.intreq_vblank = true;
+                self.frame_ready = true;
+            } else if self.ly < LCD_HEIGHT as u8 {
+                self.mode = PpuMode::OamSearch;
+            }
+        }
+
+        self.update_stat_line();
+    }
+
+    /// Triggers the window for the rest of the frame if LY
+    /// matches WY at the start of a line (mode 2). Changing WY
+    /// afterwards has no effect until the next frame.
+    fn check_window_trigger(&mut self) {
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+    }
+
+    /// Re-evaluates the STAT interrupt sources and requests
+    /// an interrupt on a rising edge.
+    fn update_stat_line(&mut self) {
+        let line = (self.stat & STAT_INT_LYC != 0 && self.ly == self.lyc)
+            || (self.stat & STAT_INT_HBLANK != 0 && self.mode == PpuMode::HBlank)
+            || (self.stat & STAT_INT_VBLANK != 0 && self.mode == PpuMode::VBlank)
+            || (self.stat & STAT_INT_OAM != 0 && self.mode == PpuMode::OamSearch);
+
+        if line && !self.stat_line {
+            self.intreq_stat = true;
+        }
+        self.stat_line = line;
+    }
+
+    /// Tile data addressing selected by LCDC bit 4: true for
+    /// unsigned indices from 0x8000.
+    fn unsigned_tiledata(&self) -> bool {
+        self.lcdc & LCDC_TILEDATA != 0
+    }
+
+    /// Color index (0-3) of pixel (x, y) of the tile at VRAM offset 'addr'.
+    fn tile_color(&self, addr: usize, x: usize, y: usize) -> u8 {
+        let lo = self.vram[addr + y * 2];
+        let hi = self.vram[addr + y * 2 + 1];
+        let bit = 7 - x;
+        ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1)
+    }
+
+    /// Pixel (x, y) of the 256x256 tilemap at VRAM offset
+    /// 'tilemap', with tile data addressing 'unsigned' (see
+    /// bg_tile_addr()). On CGB, the attributes in VRAM bank 1
+    /// select the tile bank, flipping, palette and priority.
+    fn tilemap_pixel(&self, tilemap: usize, unsigned: bool, x: usize, y: usize) -> BgPixel {
+        let map_addr = tilemap + (y / 8) * 32 + x / 8;
+        let idx = self.vram[map_addr];
+        let attr = if self.cgb {
+            self.vram[VRAM_BANK1 + map_addr]
+        } else {
+            0
+        };
+
+        let (mut tx, mut ty) = (x % 8, y % 8);
+        if attr & OAM_XFLIP != 0 {
+            tx = 7 - tx;
+        }
+        if attr & OAM_YFLIP != 0 {
+            ty = 7 - ty;
+        }
+        let bank = if attr & ATTR_BANK != 0 { VRAM_BANK1 } else { 0 };
+
+        BgPixel {
+            color: self.tile_color(bank + bg_tile_addr(idx, unsigned), tx, ty),
+            palette: attr & ATTR_CGB_PALETTE,
+            priority: attr & OAM_BG_PRIORITY != 0,
+        }
+    }
+
+    /// Color of a background/window pixel.
+    fn bg_shade(&self, p: BgPixel) -> Color {
+        if self.cgb {
+            cgb_color(&self.bg_palettes, p.palette, p.color)
+        } else {
+            palette_shade(self.bgp, p.color)
+        }
+    }
+
+    /// Color of a sprite pixel with OAM attributes 'attr'.
+    fn obj_shade(&self, attr: u8, color: u8) -> Color {
+        if self.cgb {
+            cgb_color(&self.obj_palettes, attr & ATTR_CGB_PALETTE, color)
+        } else if attr & OAM_PALETTE != 0 {
+            palette_shade(self.obp1, color)
+        } else {
+            palette_shade(self.obp0, color)
+        }
+    }
+
+    /// True if background pixel 'bg' covers an opaque sprite
+    /// pixel with OAM attributes 'attr'.
+    fn bg_over_obj(&self, bg: BgPixel, attr: u8) -> bool {
+        // On CGB, LCDC bit 0 clear puts sprites on top
+        // and the BG map attributes can claim priority.
+        let bg_priority = if self.cgb {
+            self.lcdc & LCDC_BG_ENABLE != 0 && (attr & OAM_BG_PRIORITY != 0 || bg.priority)
+        } else {
+            attr & OAM_BG_PRIORITY != 0
+        };
+        bg_priority && bg.color != 0
+    }
+
+    /// Height of sprites as selected in LCDC: 8 or 16.
+    fn obj_height(&self) -> usize {
+        if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Renders the current scanline into the framebuffer.
+    fn draw_scanline(&mut self) {
+        let ly = self.ly as usize;
+
+        // Background/window pixels, needed for sprite priority.
+        let mut bg = [BgPixel::default(); LCD_WIDTH];
+        let mut line = [SHADES[0]; LCD_WIDTH];
+
+        if self.cgb {
+            // On CGB, LCDC bit 0 only takes away the
+            // background priority over sprites.
+            self.draw_background(&mut bg);
+            self.draw_window(&mut bg);
+            for (px, &p) in line.iter_mut().zip(bg.iter()) {
+                *px = self.bg_shade(p);
+            }
+        } else if self.lcdc & LCDC_BG_ENABLE != 0 {
+            // On DMG, LCDC bit 0 blanks both background and window
+            self.draw_background(&mut bg);
+            self.draw_window(&mut bg);
+            for (px, &p) in line.iter_mut().zip(bg.iter()) {
+                *px = self.bg_shade(p);
+            }
+        } else {
+            // The window is still fetched, advancing its line
+            // counter, but comes out as color 0.
+            self.draw_window(&mut bg);
+            bg = [BgPixel::default(); LCD_WIDTH];
+        }
+
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.draw_sprites(&bg, &mut line);
+        }
+
+        self.framebuffer[ly * LCD_WIDTH..(ly + 1) * LCD_WIDTH].copy_from_slice(&line);
+    }
+
+    fn draw_background(&self, bg: &mut [BgPixel; LCD_WIDTH]) {
+        let tilemap = if self.lcdc & LCDC_BG_TILEMAP != 0 {
+            0x1C00
+        } else {
+            0x1800
+        };
+        let y = self.ly.wrapping_add(self.scy) as usize;
+
+        for (x, px) in bg.iter_mut().enumerate() {
+            let bx = (x as u8).wrapping_add(self.scx) as usize;
+            *px = self.tilemap_pixel(tilemap, self.unsigned_tiledata(), bx, y);
+        }
+    }
+
+    /// Draws the window over the background. The window has
+    /// its own line counter, which only advances on lines
+    /// where the window is visible.
+    ///
+    /// WX is the window position plus 7: below 7 the left of
+    /// the window is cut off, at 166 only its first column is
+    /// visible and above 166 it is not drawn at all.
+    fn draw_window(&mut self, bg: &mut [BgPixel; LCD_WIDTH]) {
+        if self.lcdc & LCDC_WINDOW_ENABLE == 0 || !self.window_triggered || self.wx > 166 {
+            return;
+        }
+
+        let tilemap = if self.lcdc & LCDC_WINDOW_TILEMAP != 0 {
+            0x1C00
+        } else {
+            0x1800
+        };
+        let y = self.window_line as usize;
+        let start = (self.wx as usize).saturating_sub(7);
+
+        let unsigned = self.unsigned_tiledata();
+        for (x, px) in bg.iter_mut().enumerate().skip(start) {
+            *px = self.tilemap_pixel(tilemap, unsigned, x + 7 - self.wx as usize, y);
+        }
+        self.window_line += 1;
+    }
+
+    /// Draws the sprites (objects) on the current line.
+    fn draw_sprites(&self, bg: &[BgPixel; LCD_WIDTH], line: &mut [u32; LCD_WIDTH]) {
+        let height = self.obj_height() as isize;
+        let ly = self.ly as isize;
+
+        // OAM scan: the first 10 sprites on this line, also
+        // those that are offscreen horizontally.
+        let mut sprites: Vec<&[u8]> = self
+            .oam
+            .chunks(4)
+            .filter(|s| {
+                let y = s[0] as isize - 16;
+                (y..y + height).contains(&ly)
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+
+        // On DMG, the lowest X coordinate has priority,
+        // then the first in OAM (sort is stable).
+        // On CGB, only the position in OAM counts.
+        if !self.cgb {
+            sprites.sort_by_key(|s| s[1]);
+        }
+
+        for (x, px) in line.iter_mut().enumerate() {
+            for s in &sprites {
+                let (sy, sx, tile, attr) = (s[0] as isize - 16, s[1] as isize - 8, s[2], s[3]);
+                if !(sx..sx + 8).contains(&(x as isize)) {
+                    continue;
+                }
+
+                let mut col = (x as isize - sx) as usize;
+                let mut row = (ly - sy) as usize;
+                if attr & OAM_XFLIP != 0 {
+                    col = 7 - col;
+                }
+                if attr & OAM_YFLIP != 0 {
+                    row = height as usize - 1 - row;
+                }
+                let tile = if height == 16 { tile & 0xFE } else { tile };
+                let bank = if self.cgb && attr & ATTR_BANK != 0 {
+                    VRAM_BANK1
+                } else {
+                    0
+                };
+
+                // Sprites always use 0x8000 addressing.
+                let color = self.tile_color(bank + tile as usize * 16, col, row);
+                if color == 0 {
+                    // Transparent, a lower priority sprite may show
+                    continue;
+                }
+
+                if !self.bg_over_obj(bg[x], attr) {
+                    *px = self.obj_shade(attr, color);
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// Inspection of VRAM and OAM for debug viewers
+impl Ppu {
+    /// All tiles in VRAM, 16 per row in index order (0x8000
+    /// first), as shades of their raw color indices. On CGB,
+    /// bank 1 is to the right of bank 0.
+    pub fn dump_tiles(&self) -> Image {
+        let banks = if self.cgb { 2 } else { 1 };
+        let rows = TILES_PER_BANK / TILES_PER_ROW;
+        let mut img = Image::new(banks * TILES_PER_ROW * 8, rows * 8);
+        for bank in 0..banks {
+            for tile in 0..TILES_PER_BANK {
+                let addr = bank * VRAM_BANK1 + tile * 16;
+                let ox = (bank * TILES_PER_ROW + tile % TILES_PER_ROW) * 8;
+                let oy = tile / TILES_PER_ROW * 8;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        img.pixels[(oy + y) * img.width + ox + x] =
+                            SHADES[self.tile_color(addr, x, y) as usize];
+                    }
+                }
+            }
+        }
+        img
+    }
+
+    /// A complete 256x256 background map, with tiles from
+    /// 'tiledata', in the current palettes.
+    pub fn dump_bg_map(&self, map: TileMap, tiledata: TileData) -> Image {
+        let tilemap = (map.addr() - 0x8000) as usize;
+        let mut img = Image::new(256, 256);
+        for y in 0..256 {
+            for x in 0..256 {
+                let p = self.tilemap_pixel(tilemap, tiledata == TileData::Unsigned, x, y);
+                img.pixels[y * 256 + x] = if self.cgb {
+                    cgb_color(&self.bg_palettes, p.palette, p.color)
+                } else {
+                    palette_shade(self.bgp, p.color)
+                };
+            }
+        }
+        img
+    }
+
+    /// All 40 OAM entries, with their current size and palette.
+    pub fn dump_oam(&self) -> Vec<Sprite> {
+        let height = if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        };
+        self.oam
+            .chunks(4)
+            .map(|s| {
+                let (tile, attr) = (s[2], s[3]);
+                let palette = if self.cgb {
+                    attr & ATTR_CGB_PALETTE
+                } else {
+                    (attr & OAM_PALETTE != 0).into()
+                };
+                let bank = (self.cgb && attr & ATTR_BANK != 0).into();
+                let first = if height == 16 { tile & 0xFE } else { tile };
+
+                let mut image = Image::new(8, height);
+                for y in 0..height {
+                    for x in 0..8 {
+                        let tx = if attr & OAM_XFLIP != 0 { 7 - x } else { x };
+                        let ty = if attr & OAM_YFLIP != 0 {
+                            height - 1 - y
+                        } else {
+                            y
+                        };
+                        let addr = bank as usize * VRAM_BANK1 + first as usize * 16;
+                        let color = self.tile_color(addr, tx, ty);
+                        image.pixels[y * 8 + x] = match color {
+                            0 => 0,
+                            _ if self.cgb => cgb_color(&self.obj_palettes, palette, color),
+                            _ if palette == 1 => palette_shade(self.obp1, color),
+                            _ => palette_shade(self.obp0, color),
+                        };
+                    }
+                }
+
+                Sprite {
+                    x: s[1] as i16 - 8,
+                    y: s[0] as i16 - 16,
+                    tile,
+                    attr,
+                    palette,
+                    bank,
+                    xflip: attr & OAM_XFLIP != 0,
+                    yflip: attr & OAM_YFLIP != 0,
+                    behind_bg: attr & OAM_BG_PRIORITY != 0,
+                    image,
+                }
+            })
+            .collect()
+    }
+}
+
+/// VRAM offset of background/window tile 'idx', with
+/// unsigned indices from 0x8000 or signed indices from 0x9000.
+fn bg_tile_addr(idx: u8, unsigned: bool) -> usize {
+    if unsigned {
+        // 0x8000 - 0x8FFF, unsigned index
+        idx as usize * 16
+    } else {
+        // 0x8800 - 0x97FF, signed index from 0x9000
+        (0x1000 + (idx as i8 as isize) * 16) as usize
+    }
+}
+
+/// Maps a color index through a DMG palette register.
+fn palette_shade(palette: u8, color: u8) -> u32 {
+    SHADES[((palette >> (color * 2)) & 0x03) as usize]
+}
+
+/// Maps a color index through CGB palette RAM to RGBA.
+fn cgb_color(palettes: &[u8; 64], palette: u8, color: u8) -> u32 {
+    let i = palette as usize * 8 + color as usize * 2;
+    let rgb = u16::from_le_bytes([palettes[i], palettes[i + 1]]) as u32;
+
+    // Scale 5 bits to 8 bits
+    let scale = |c: u32| (c << 3) | (c >> 2);
+    let (r, g, b) = (
+        scale(rgb & 0x1F),
+        scale((rgb >> 5) & 0x1F),
+        scale((rgb >> 10) & 0x1F),
+    );
+    r << 24 | g << 16 | b << 8 | 0xFF
+}
+
+/// Palette data write through BCPD/OCPD, incrementing the
+/// index in BCPS/OCPS if requested.
+fn write_palette(palettes: &mut [u8; 64], cps: &mut u8, val: u8) {
+    palettes[(*cps & 0x3F) as usize] = val;
+    if *cps & CPS_AUTO_INC != 0 {
+        *cps = CPS_AUTO_INC | (cps.wrapping_add(1) & 0x3F);
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Ppu {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // VRAM
+            0x8000..=0x9FFF => self.vram[self.vram_addr(addr)],
+
+            // OAM
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00],
+
+            // LCDC - LCD control
+            0xFF40 => self.lcdc,
+
+            // STAT - LCD status
+            // Bit 7 is unused and reads as 1.
+            0xFF41 => {
+                let lyc_eq = if self.ly == self.lyc { STAT_LYC_EQ } else { 0 };
+                let mode = if self.lcd_enabled() {
+                    self.mode as u8
+                } else {
+                    0
+                };
+                0x80 | self.stat | lyc_eq | mode
+            }
+
+            // SCY/SCX - Background scroll
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+
+            // LY - Current scanline
+            0xFF44 => self.ly,
+
+            // LYC - LY compare
+            0xFF45 => self.lyc,
+
+            // Palettes
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+
+            // WY/WX - Window position
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+
+            // CGB registers, not present on DMG
+            0xFF4F | 0xFF68..=0xFF6B if !self.cgb => 0xFF,
+
+            // VBK - VRAM bank
+            // Bits 1-7 are unused and read as 1.
+            0xFF4F => 0xFE | self.vbk,
+
+            // BCPS/OCPS - Palette index
+            // Bit 6 is unused and reads as 1.
+            0xFF68 => 0x40 | self.bcps,
+            0xFF6A => 0x40 | self.ocps,
+
+            // BCPD/OCPD - Palette data
+            0xFF69 => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            0xFF6B => self.obj_palettes[(self.ocps & 0x3F) as usize],
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // VRAM
+            0x8000..=0x9FFF => self.vram[self.vram_addr(addr)] = val,
+
+            // OAM
+            0xFE00..=0xFE9F => self.oam[addr as usize - 0xFE00] = val,
+
+            // LCDC - LCD control
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = val;
+
+                if was_enabled && !self.lcd_enabled() {
+                    // LCD off: LY resets and the screen goes blank
+                    self.ly = 0;
+                    self.dots = 0;
+                    self.mode = PpuMode::HBlank;
+                    self.window_triggered = false;
+                    self.window_line = 0;
+                    self.framebuffer.fill(SHADES[0]);
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = PpuMode::OamSearch;
+                }
+            }
+
+            // STAT - LCD status
+            0xFF41 => self.stat = val & STAT_WRITABLE,
+
+            // SCY/SCX - Background scroll
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
+
+            // LY - read-only
+            0xFF44 => (),
+
+            // LYC - LY compare
+            // Coincidence is re-evaluated immediately.
+            0xFF45 => {
+                self.lyc = val;
+                if self.lcd_enabled() {
+                    self.update_stat_line();
+                }
+            }
+
+            // Palettes
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val,
+
+            // WY/WX - Window position
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+
+            // CGB registers, not present on DMG
+            0xFF4F | 0xFF68..=0xFF6B if !self.cgb => (),
+
+            // VBK - VRAM bank
+            0xFF4F => self.vbk = val & 0x01,
+
+            // BCPS/OCPS - Palette index
+            0xFF68 => self.bcps = val & 0xBF,
+            0xFF6A => self.ocps = val & 0xBF,
+
+            // BCPD/OCPD - Palette data
+            0xFF69 => write_palette(&mut self.bg_palettes, &mut self.bcps, val),
+            0xFF6B => write_palette(&mut self.obj_palettes, &mut self.ocps, val),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"PPU ");
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+        for reg in [
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0,
+            self.obp1, self.wy, self.wx,
+        ] {
+            w.u8(reg);
+        }
+        w.bool(self.window_triggered);
+        w.u8(self.window_line);
+        w.u16(self.dots as u16);
+        w.u8(self.mode as u8);
+        w.bool(self.stat_line);
+        w.bool(self.frame_ready);
+        w.bool(self.intreq_vblank);
+        w.bool(self.intreq_stat);
+        for &px in self.framebuffer.iter() {
+            w.u32(px);
+        }
+        w.bool(self.cgb);
+        w.u8(self.vbk);
+        w.u8(self.bcps);
+        w.u8(self.ocps);
+        w.bytes(&self.bg_palettes);
+        w.bytes(&self.obj_palettes);
+        self.fifo.save_state(w);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"PPU ", "PPU")?;
+        r.bytes_into(&mut self.vram, "VRAM")?;
+        r.bytes_into(&mut self.oam, "OAM")?;
+        for reg in [
+            &mut self.lcdc,
+            &mut self.stat,
+            &mut self.scy,
+            &mut self.scx,
+            &mut self.ly,
+            &mut self.lyc,
+            &mut self.bgp,
+            &mut self.obp0,
+            &mut self.obp1,
+            &mut self.wy,
+            &mut self.wx,
+        ] {
+            *reg = r.u8()?;
+        }
+        if self.ly >= LINES_PER_FRAME {
+            bail!(StateErr::Mismatch("LY"));
+        }
+        self.window_triggered = r.bool()?;
+        self.window_line = r.u8()?;
+        self.dots = r.u16()?.into();
+        if self.dots >= DOTS_PER_LINE {
+            bail!(StateErr::Mismatch("PPU dot"));
+        }
+        self.mode = match r.u8()? {
+            0 => PpuMode::HBlank,
+            1 => PpuMode::VBlank,
+            2 => PpuMode::OamSearch,
+            3 => PpuMode::Transfer,
+            _ => bail!(StateErr::Mismatch("PPU mode")),
+        };
+        self.stat_line = r.bool()?;
+        self.frame_ready = r.bool()?;
+        self.intreq_vblank = r.bool()?;
+        self.intreq_stat = r.bool()?;
+        for px in self.framebuffer.iter_mut() {
+            *px = r.u32()?;
+        }
+        if r.bool()? != self.cgb {
+            bail!(StateErr::Mismatch("model"));
+        }
+        self.vbk = r.u8()?;
+        if self.vbk > 1 {
+            bail!(StateErr::Mismatch("VRAM bank"));
+        }
+        self.bcps = r.u8()?;
+        self.ocps = r.u8()?;
+        r.bytes_into(&mut self.bg_palettes, "BG palettes")?;
+        r.bytes_into(&mut self.obj_palettes, "OBJ palettes")?;
+        self.fifo.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ppu() -> Ppu {
+        let mut p = Ppu::new();
+        p.write(0xFF40, LCDC_ENABLE | LCDC_TILEDATA | LCDC_BG_ENABLE);
+        p
+    }
+
+    #[test]
+    fn ly_progression() {
+        let mut p = ppu();
+        assert_eq!(p.read(0xFF44), 0);
+
+        p.tick(DOTS_PER_LINE - 1);
+        assert_eq!(p.read(0xFF44), 0);
+        p.tick(1);
+        assert_eq!(p.read(0xFF44), 1);
+
+        p.tick(DOTS_PER_LINE * 152);
+        assert_eq!(p.read(0xFF44), 153);
+        p.tick(DOTS_PER_LINE);
+        assert_eq!(p.read(0xFF44), 0);
+    }
+
+    #[test]
+    fn modes() {
+        let mut p = ppu();
+        assert_eq!(p.read(0xFF41) & 0x03, PpuMode::OamSearch as u8);
+        p.tick(DOTS_OAM_SEARCH);
+        assert_eq!(p.read(0xFF41) & 0x03, PpuMode::Transfer as u8);
+        p.tick(DOTS_TRANSFER);
+        assert_eq!(p.read(0xFF41) & 0x03, PpuMode::HBlank as u8);
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        assert_eq!(p.read(0xFF41) & 0x03, PpuMode::OamSearch as u8);
+
+        p.tick(DOTS_PER_LINE * 143);
+        assert_eq!(p.read(0xFF41) & 0x03, PpuMode::VBlank as u8);
+        p.tick(DOTS_PER_LINE * 10);
+        assert_eq!(p.read(0xFF41) & 0x03, PpuMode::OamSearch as u8);
+    }
+
+    #[test]
+    fn vblank_intreq() {
+        let mut p = ppu();
+        p.tick(DOTS_PER_LINE * 143);
+        assert!(!p.get_clr_intreq_vblank());
+        assert!(!p.frame_ready());
+
+        p.tick(DOTS_PER_LINE);
+        assert_eq!(p.read(0xFF44), 144);
+        assert!(p.get_clr_intreq_vblank());
+        assert!(!p.get_clr_intreq_vblank());
+        assert!(p.frame_ready());
+        assert!(!p.frame_ready());
+    }
+
+    #[test]
+    fn lcd_off() {
+        let mut p = Ppu::new();
+        p.tick(DOTS_PER_LINE * 10);
+        assert_eq!(p.read(0xFF44), 0);
+
+        let mut p = ppu();
+        p.tick(DOTS_PER_LINE * 10);
+        assert_eq!(p.read(0xFF44), 10);
+        p.write(0xFF40, 0);
+        assert_eq!(p.read(0xFF44), 0);
+        assert_eq!(p.read(0xFF41) & 0x03, 0);
+        assert!(p.get_framebuffer().iter().all(|&c| c == SHADES[0]));
+    }
+
+    #[test]
+    fn lyc_stat() {
+        let mut p = ppu();
+        p.write(0xFF41, STAT_INT_LYC);
+        p.write(0xFF45, 2);
+        assert_eq!(p.read(0xFF41) & STAT_LYC_EQ, 0);
+
+        p.tick(DOTS_PER_LINE * 2);
+        assert_ne!(p.read(0xFF41) & STAT_LYC_EQ, 0);
+        assert!(p.get_clr_intreq_stat());
+
+        // Writing LYC re-evaluates immediately
+        p.write(0xFF45, 3);
+        assert_eq!(p.read(0xFF41) & STAT_LYC_EQ, 0);
+        p.write(0xFF45, 2);
+        assert_ne!(p.read(0xFF41) & STAT_LYC_EQ, 0);
+        assert!(p.get_clr_intreq_stat());
+    }
+
+    #[test]
+    fn background() {
+        let mut p = ppu();
+        p.write(0xFF47, 0b11_10_01_00);
+
+        // Tile 1: row 0 = colors 3,2,1,0,0,0,0,0
+        p.write(0x8010, 0b1010_0000);
+        p.write(0x8011, 0b1100_0000);
+        // Map (0,0) and (1,0) to tile 1
+        p.write(0x9800, 1);
+        p.write(0x9801, 1);
+
+        p.tick(DOTS_OAM_SEARCH + DOTS_TRANSFER);
+        let fb = p.get_framebuffer();
+        assert_eq!(&fb[0..4], &[SHADES[3], SHADES[2], SHADES[1], SHADES[0]]);
+        assert_eq!(&fb[8..12], &[SHADES[3], SHADES[2], SHADES[1], SHADES[0]]);
+        assert_eq!(fb[16], SHADES[0]);
+
+        // Scrolled by 1 pixel
+        p.write(0xFF43, 1);
+        p.tick(DOTS_PER_LINE);
+        let fb = p.get_framebuffer();
+        assert_eq!(
+            &fb[LCD_WIDTH..LCD_WIDTH + 3],
+            &[SHADES[0], SHADES[0], SHADES[0]]
+        );
+    }
+
+    #[test]
+    fn background_signed_tiledata() {
+        let mut p = ppu();
+        p.write(0xFF40, LCDC_ENABLE | LCDC_BG_ENABLE | LCDC_BG_TILEMAP);
+        p.write(0xFF47, 0b11_10_01_00);
+
+        // Tile -1 (0x8FF0) and tile 0 (0x9000)
+        p.write(0x8FF0, 0xFF);
+        p.write(0x8FF1, 0xFF);
+        p.write(0x9000, 0xFF);
+        p.write(0x9C00, 0xFF);
+        p.write(0x9C01, 0x00);
+
+        p.tick(DOTS_OAM_SEARCH + DOTS_TRANSFER);
+        let fb = p.get_framebuffer();
+        assert_eq!(fb[0], SHADES[3]);
+        assert_eq!(fb[8], SHADES[1]);
+    }
+
+    #[test]
+    fn background_disabled() {
+        let mut p = ppu();
+        p.write(0xFF40, LCDC_ENABLE);
+        p.write(0xFF47, 0xFF);
+        p.tick(DOTS_OAM_SEARCH + DOTS_TRANSFER);
+        assert!(p.get_framebuffer()[..LCD_WIDTH]
+            .iter()
+            .all(|&c| c == SHADES[0]));
+    }
+
+    /// Draws a single line and returns it.
+    fn line(p: &mut Ppu) -> &[u32] {
+        p.tick(DOTS_OAM_SEARCH + DOTS_TRANSFER);
+        let ly = p.ly as usize;
+        &p.get_framebuffer()[ly * LCD_WIDTH..(ly + 1) * LCD_WIDTH]
+    }
+
+    /// PPU with BG cleared (tile 0 = color 0), tile 1 filled with
+    /// color 3, tile 2 with color 1 and identity palettes.
+    fn ppu_tiles(lcdc: u8) -> Ppu {
+        let mut p = Ppu::new();
+        for i in 0..16 {
+            p.write(0x8010 + i, 0xFF);
+        }
+        for i in 0..8 {
+            p.write(0x8020 + i * 2, 0xFF);
+        }
+        p.write(0xFF47, 0b11_10_01_00);
+        p.write(0xFF48, 0b11_10_01_00);
+        p.write(0xFF49, 0b00_01_10_11);
+        p.write(0xFF40, LCDC_ENABLE | LCDC_TILEDATA | lcdc);
+        p
+    }
+
+    fn sprite(p: &mut Ppu, idx: u16, y: u8, x: u8, tile: u8, attr: u8) {
+        p.write_slice(&[y, x, tile, attr], 0xFE00 + idx * 4);
+    }
+
+    #[test]
+    fn sprites() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        sprite(&mut p, 0, 16, 8 + 4, 1, 0);
+        sprite(&mut p, 1, 16, 8 + 20, 1, OAM_PALETTE);
+
+        let l = line(&mut p);
+        assert_eq!(l[3], SHADES[0]);
+        assert!(l[4..12].iter().all(|&c| c == SHADES[3]));
+        assert_eq!(l[12], SHADES[0]);
+        assert!(l[20..28].iter().all(|&c| c == SHADES[0]));
+
+        // Disabled
+        let mut p = ppu_tiles(LCDC_BG_ENABLE);
+        sprite(&mut p, 0, 16, 8, 1, 0);
+        assert!(line(&mut p).iter().all(|&c| c == SHADES[0]));
+    }
+
+    #[test]
+    fn sprites_x_clip() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        // Partially visible at the left and right edge
+        sprite(&mut p, 0, 16, 3, 1, 0);
+        sprite(&mut p, 1, 16, 165, 1, 0);
+        let l = line(&mut p);
+        assert!(l[0..3].iter().all(|&c| c == SHADES[3]));
+        assert_eq!(l[3], SHADES[0]);
+        assert_eq!(l[156], SHADES[0]);
+        assert!(l[157..].iter().all(|&c| c == SHADES[3]));
+    }
+
+    #[test]
+    fn sprites_per_line() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        // X = 0 is invisible but counts towards the limit
+        sprite(&mut p, 0, 16, 0, 1, 0);
+        for i in 1..12 {
+            sprite(&mut p, i, 16, 8 + i as u8 * 8, 1, 0);
+        }
+        // Different line, does not count
+        sprite(&mut p, 12, 32, 8, 1, 0);
+
+        let l = line(&mut p);
+        assert!(l[..8].iter().all(|&c| c == SHADES[0]));
+        assert!(l[8..80].iter().all(|&c| c == SHADES[3]));
+        assert!(l[80..].iter().all(|&c| c == SHADES[0]));
+    }
+
+    #[test]
+    fn sprites_priority() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        // Lower X wins, even if later in OAM
+        sprite(&mut p, 0, 16, 12, 1, OAM_PALETTE);
+        sprite(&mut p, 1, 16, 8, 1, 0);
+        // Same X, first in OAM wins
+        sprite(&mut p, 2, 16, 40, 2, OAM_PALETTE);
+        sprite(&mut p, 3, 16, 40, 1, 0);
+        // Transparent pixels show the next sprite
+        sprite(&mut p, 4, 16, 60, 0, 0);
+        sprite(&mut p, 5, 16, 60, 1, OAM_PALETTE);
+
+        let l = line(&mut p);
+        assert_eq!(l[0], SHADES[3]);
+        assert_eq!(l[7], SHADES[3]);
+        assert_eq!(l[8], SHADES[0]); // OBP1 color 3
+        assert_eq!(l[32], SHADES[2]); // OBP1 color 1
+        assert_eq!(l[52], SHADES[0]); // OBP1 color 3
+    }
+
+    #[test]
+    fn sprites_bg_priority() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        p.write(0x9800, 2);
+        sprite(&mut p, 0, 16, 12, 1, OAM_BG_PRIORITY);
+
+        // Behind BG colors 1-3, in front of BG color 0
+        let l = line(&mut p);
+        assert_eq!(l[4], SHADES[1]);
+        assert_eq!(l[7], SHADES[1]);
+        assert_eq!(l[8], SHADES[3]);
+        assert_eq!(l[11], SHADES[3]);
+    }
+
+    #[test]
+    fn sprites_flip_tall() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE | LCDC_OBJ_SIZE);
+        // Tile 2 has only even rows set; tile 3 left column
+        for i in 0..8 {
+            p.write(0x8030 + i * 2, 0x80);
+        }
+        // 8x16 ignores bit 0 of the tile index
+        sprite(&mut p, 0, 16, 8, 3, 0);
+        sprite(&mut p, 1, 16, 24, 3, OAM_XFLIP | OAM_YFLIP);
+
+        let l = line(&mut p).to_vec();
+        assert_eq!(l[0], SHADES[1]);
+        assert_eq!(l[1], SHADES[1]);
+        // Y-flipped: row 15 of the pair, bottom tile
+        assert_eq!(l[16], SHADES[0]);
+        assert_eq!(l[23], SHADES[1]);
+
+        p.tick(DOTS_PER_LINE * 8 - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        let l = line(&mut p).to_vec();
+        assert_eq!(l[0], SHADES[1]);
+        assert_eq!(l[1], SHADES[0]);
+        assert_eq!(l[16], SHADES[1]);
+    }
+
+    #[test]
+    fn window() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_WINDOW_ENABLE | LCDC_WINDOW_TILEMAP);
+        // Window map (0x9C00) row 0 is tile 1, row 1 is tile 2
+        for i in 0..32 {
+            p.write(0x9C00 + i, 1);
+            p.write(0x9C20 + i, 2);
+        }
+        p.write(0xFF4A, 2);
+        p.write(0xFF4B, 7 + 100);
+
+        assert!(line(&mut p).iter().all(|&c| c == SHADES[0]));
+        p.tick(DOTS_PER_LINE * 2 - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+
+        let l = line(&mut p);
+        assert!(l[..100].iter().all(|&c| c == SHADES[0]));
+        assert!(l[100..].iter().all(|&c| c == SHADES[3]));
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+
+        // Window disabled for 8 lines: internal line counter pauses
+        p.write(0xFF4B, 200);
+        p.tick(DOTS_PER_LINE * 8);
+        p.write(0xFF4B, 7);
+        for _ in 0..6 {
+            p.tick(DOTS_PER_LINE);
+        }
+        let l = line(&mut p);
+        assert!(l.iter().all(|&c| c == SHADES[3]));
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        let l = line(&mut p);
+        assert!(l.iter().all(|&c| c == SHADES[1]));
+    }
+
+    #[test]
+    fn window_wx_edges() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_WINDOW_ENABLE | LCDC_WINDOW_TILEMAP);
+        // Window map (0x9C00) column 0 is tile 1, the rest tile 2
+        for row in 0..32 {
+            p.write(0x9C00 + row * 32, 1);
+            for col in 1..32 {
+                p.write(0x9C00 + row * 32 + col, 2);
+            }
+        }
+
+        // WX < 7: the first 7 - WX window pixels are cut off
+        p.write(0xFF4B, 3);
+        let l = line(&mut p);
+        assert!(l[..4].iter().all(|&c| c == SHADES[3]));
+        assert!(l[4..].iter().all(|&c| c == SHADES[1]));
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+
+        // WX = 166: only the first window column
+        p.write(0xFF4B, 166);
+        let l = line(&mut p);
+        assert!(l[..159].iter().all(|&c| c == SHADES[0]));
+        assert_eq!(l[159], SHADES[3]);
+    }
+
+    #[test]
+    fn window_wy_mid_frame() {
+        let mut p = ppu_tiles(LCDC_BG_ENABLE | LCDC_WINDOW_ENABLE | LCDC_WINDOW_TILEMAP);
+        for i in 0..32 {
+            p.write(0x9C00 + i, 1);
+        }
+        p.write(0xFF4B, 7);
+
+        // WY is only compared at the start of a line
+        p.write(0xFF4A, 5);
+        p.tick(DOTS_PER_LINE * 5 + 1);
+        p.write(0xFF4A, 100);
+        assert_eq!(line(&mut p)[0], SHADES[3]);
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+
+        // Stays triggered for the rest of the frame
+        assert_eq!(line(&mut p)[0], SHADES[3]);
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+
+        // Setting WY to a line already passed does not trigger
+        while p.ly != 0 {
+            p.tick(DOTS_PER_LINE);
+        }
+        p.tick(DOTS_PER_LINE * 10);
+        p.write(0xFF4A, 3);
+        assert_eq!(line(&mut p)[0], SHADES[0]);
+    }
+
+    #[test]
+    fn window_line_dmg_bg_disabled() {
+        let mut p = ppu_tiles(LCDC_WINDOW_ENABLE | LCDC_WINDOW_TILEMAP);
+        // Window map (0x9C00) rows 0-7 are tile 1, row 8 is tile 2
+        for i in 0..32 * 8 {
+            p.write(0x9C00 + i, 1);
+        }
+        for i in 0..32 {
+            p.write(0x9D00 + i, 2);
+        }
+        p.write(0xFF4B, 7);
+
+        // Blank, but the window line counter still advances
+        for _ in 0..64 {
+            assert!(line(&mut p).iter().all(|&c| c == SHADES[0]));
+            p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        }
+        p.write(
+            0xFF40,
+            LCDC_ENABLE | LCDC_TILEDATA | LCDC_BG_ENABLE | LCDC_WINDOW_ENABLE | LCDC_WINDOW_TILEMAP,
+        );
+        assert_eq!(line(&mut p)[0], SHADES[1]);
+    }
+
+    /// CGB PPU with tile 1 filled with color 3 in VRAM bank 0
+    /// and color 1 in bank 1. BG palette 0 color 0 is black,
+    /// BG palette 2 color 3 is red and OBJ palette 1 color 3
+    /// is blue.
+    fn ppu_cgb(lcdc: u8) -> Ppu {
+        let mut p = Ppu::new_cgb();
+        for i in 0..16 {
+            p.write(0x8010 + i, 0xFF);
+        }
+        p.write(0xFF4F, 1);
+        for i in 0..8 {
+            p.write(0x8010 + i * 2, 0xFF);
+        }
+        p.write(0xFF4F, 0);
+
+        p.write(0xFF68, 0x80);
+        p.write(0xFF69, 0x00);
+        p.write(0xFF69, 0x00);
+        p.write(0xFF68, 0x80 | (2 * 8 + 3 * 2));
+        p.write(0xFF69, 0x1F);
+        p.write(0xFF69, 0x00);
+        p.write(0xFF6A, 0x80 | (8 + 3 * 2));
+        p.write(0xFF6B, 0x00);
+        p.write(0xFF6B, 0x7C);
+        p.write(0xFF40, LCDC_ENABLE | LCDC_TILEDATA | lcdc);
+        p
+    }
+
+    const BLACK: u32 = 0x000000FF;
+    const RED: u32 = 0xFF0000FF;
+    const BLUE: u32 = 0x0000FFFF;
+
+    #[test]
+    fn cgb_palette_regs() {
+        let mut p = Ppu::new_cgb();
+        assert_eq!(p.read(0xFF69), 0xFF);
+
+        p.write(0xFF68, 0x80 | 0x3E);
+        assert_eq!(p.read(0xFF68), 0xFE);
+        p.write(0xFF69, 0x12);
+        p.write(0xFF69, 0x34);
+        // Auto-increment wraps around
+        assert_eq!(p.read(0xFF68), 0xC0);
+        p.write(0xFF68, 0x3E);
+        assert_eq!(p.read(0xFF69), 0x12);
+        p.write(0xFF69, 0x56);
+        assert_eq!(p.read(0xFF68), 0x7E);
+        assert_eq!(p.read(0xFF69), 0x56);
+
+        p.write(0xFF6A, 0x85);
+        p.write(0xFF6B, 0x78);
+        p.write(0xFF6A, 0x05);
+        assert_eq!(p.read(0xFF6B), 0x78);
+
+        // Not present on DMG
+        let mut p = Ppu::new();
+        p.write(0xFF68, 0x80);
+        assert_eq!(p.read(0xFF68), 0xFF);
+        assert_eq!(p.read(0xFF4F), 0xFF);
+    }
+
+    #[test]
+    fn cgb_vram_banks() {
+        let mut p = Ppu::new_cgb();
+        assert_eq!(p.read(0xFF4F), 0xFE);
+        p.write(0x8000, 0x12);
+        p.write(0xFF4F, 0xFF);
+        assert_eq!(p.read(0xFF4F), 0xFF);
+        assert_eq!(p.read(0x8000), 0x00);
+        p.write(0x8000, 0x34);
+        p.write(0xFF4F, 0);
+        assert_eq!(p.read(0x8000), 0x12);
+    }
+
+    #[test]
+    fn cgb_background_attributes() {
+        let mut p = ppu_cgb(0);
+        p.write(0x9801, 1);
+        p.write(0xFF4F, 1);
+        // Tile (1,0): palette 2
+        p.write(0x9801, 2);
+        p.write(0xFF4F, 0);
+
+        // LCDC bit 0 does not blank the BG on CGB
+        let l = line(&mut p);
+        assert!(l[..8].iter().all(|&c| c == BLACK));
+        assert!(l[8..16].iter().all(|&c| c == RED));
+
+        // Tile data from bank 1 (color 1, still white)
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        p.write(0xFF4F, 1);
+        p.write(0x9801, ATTR_BANK | 2);
+        p.write(0xFF4F, 0);
+        let l = line(&mut p);
+        assert!(l[8..16].iter().all(|&c| c == SHADES[0]));
+    }
+
+    #[test]
+    fn cgb_sprites() {
+        let mut p = ppu_cgb(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        // Earlier in OAM wins, regardless of X
+        sprite(&mut p, 0, 16, 8 + 4, 1, 1);
+        sprite(&mut p, 1, 16, 8, 1, ATTR_BANK | 1);
+
+        let l = line(&mut p);
+        assert!(l[..4].iter().all(|&c| c == BLACK));
+        assert!(l[4..12].iter().all(|&c| c == BLUE));
+    }
+
+    #[test]
+    fn cgb_bg_priority() {
+        let mut p = ppu_cgb(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        p.write(0x9800, 1);
+        p.write(0xFF4F, 1);
+        p.write(0x9800, OAM_BG_PRIORITY | 2);
+        p.write(0xFF4F, 0);
+        sprite(&mut p, 0, 16, 8, 1, 1);
+
+        // BG map attribute priority
+        let l = line(&mut p);
+        assert!(l[..8].iter().all(|&c| c == RED));
+
+        // LCDC bit 0 clear puts sprites on top
+        p.tick(DOTS_PER_LINE - DOTS_OAM_SEARCH - DOTS_TRANSFER);
+        p.write(0xFF40, LCDC_ENABLE | LCDC_TILEDATA | LCDC_OBJ_ENABLE);
+        sprite(&mut p, 0, 17, 8, 1, 1);
+        let l = line(&mut p);
+        assert!(l[..8].iter().all(|&c| c == BLUE));
+    }
+
+    #[test]
+    fn hblank_event() {
+        let mut p = ppu();
+        p.tick(DOTS_OAM_SEARCH + DOTS_TRANSFER - 1);
+        assert!(!p.get_clr_hblank());
+        p.tick(1);
+        assert!(p.get_clr_hblank());
+        assert!(!p.get_clr_hblank());
+    }
+
+    #[test]
+    fn dump_tiles() {
+        let p = ppu_tiles(0);
+        let img = p.dump_tiles();
+        assert_eq!((img.width, img.height), (128, 192));
+        // Tile 1 (color 3) right of tile 0 (color 0)
+        assert_eq!(img.pixels[7], SHADES[0]);
+        assert_eq!(img.pixels[8], SHADES[3]);
+        // Tile 2, color 1
+        assert_eq!(img.pixels[7 * 128 + 16], SHADES[1]);
+
+        let img = ppu_cgb(0).dump_tiles();
+        assert_eq!((img.width, img.height), (256, 192));
+        assert_eq!(img.pixels[128 + 8], SHADES[1]);
+    }
+
+    #[test]
+    fn dump_bg_map() {
+        let mut p = ppu_tiles(0);
+        p.write(0x9C21, 1);
+        // Signed tile 1 at 0x9010
+        for i in 0..16 {
+            p.write(0x9010 + i, 0xFF);
+        }
+        p.write(0x9C22, 2);
+
+        let img = p.dump_bg_map(TileMap::High, TileData::Unsigned);
+        assert_eq!((img.width, img.height), (256, 256));
+        assert_eq!(img.pixels[8 * 256 + 8], SHADES[3]);
+        assert_eq!(img.pixels[8 * 256 + 16], SHADES[1]);
+        let img = p.dump_bg_map(TileMap::Low, TileData::Unsigned);
+        assert!(img.pixels.iter().all(|&c| c == SHADES[0]));
+
+        let img = p.dump_bg_map(TileMap::High, TileData::Signed);
+        assert_eq!(img.pixels[8 * 256 + 8], SHADES[3]);
+        assert_eq!(img.pixels[8 * 256 + 16], SHADES[0]);
+    }
+
+    #[test]
+    fn dump_oam() {
+        let mut p = ppu_tiles(LCDC_OBJ_SIZE);
+        sprite(&mut p, 1, 20, 4, 3, OAM_XFLIP | OAM_PALETTE);
+        let oam = p.dump_oam();
+        assert_eq!(oam.len(), 40);
+
+        let s = &oam[1];
+        assert_eq!((s.x, s.y, s.tile, s.palette), (-4, 4, 3, 1));
+        assert!(s.xflip && !s.yflip && !s.behind_bg);
+        // 8x16: tiles 2 (color 1, through OBP1) and 3 (empty)
+        assert_eq!((s.image.width, s.image.height), (8, 16));
+        assert_eq!(s.image.pixels[0], SHADES[2]);
+        assert_eq!(s.image.pixels[8 * 8], 0);
+    }
+
+    /// Loads the state of 'p' into a new PPU.
+    fn reload(p: &Ppu) -> Result<Ppu> {
+        let mut w = StateWriter::new();
+        p.save_state(&mut w)?;
+        let state = w.into_inner();
+        let mut p2 = Ppu::new();
+        p2.load_state(&mut StateReader::new(&state))?;
+        Ok(p2)
+    }
+
+    #[test]
+    fn load_state_out_of_range() {
+        let mut p = ppu();
+        p.ly = LINES_PER_FRAME - 1;
+        p.dots = DOTS_PER_LINE - 1;
+        assert!(reload(&p).is_ok());
+
+        p.ly = LINES_PER_FRAME;
+        assert!(reload(&p).is_err());
+        p.ly = 0;
+        p.dots = DOTS_PER_LINE;
+        assert!(reload(&p).is_err());
+        p.dots = 0;
+        p.vbk = 2;
+        assert!(reload(&p).is_err());
+    }
+}
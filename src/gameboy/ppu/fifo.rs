@@ -0,0 +1,569 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Result};
+
+use super::super::savestate::{StateErr, StateReader, StateWriter};
+use super::*;
+
+/// Dots per background fetch: tile index, low and high
+/// tile data, 2 dots each
+const FETCH_DOTS: u8 = 6;
+
+/// Dots per sprite fetch
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// Sprite pixel in the OAM FIFO
+#[derive(Copy, Clone, Default)]
+struct ObjPixel {
+    /// Color index (0-3), 0 is transparent
+    color: u8,
+
+    /// OAM attributes
+    attr: u8,
+
+    /// Position in OAM, for priority on CGB
+    oam_idx: u8,
+}
+
+/// State of the pixel FIFO renderer within a line
+///
+/// The background fetcher fills the background FIFO 8 pixels
+/// at a time whenever it is empty; every dot one pixel is
+/// shifted out to the LCD, mixed with the OAM FIFO. Sprites
+/// pause both while their tile data is fetched.
+#[derive(Default)]
+pub(super) struct Fifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+
+    /// Dots into the current background fetch, FETCH_DOTS
+    /// when done and waiting for the FIFO to empty
+    fetch_dot: u8,
+
+    /// Tile column being fetched
+    fetch_x: u8,
+
+    /// Fetched tile index, attributes (CGB) and tile data
+    tile: u8,
+    attr: u8,
+    lo: u8,
+    hi: u8,
+
+    /// The first fetch of a line is thrown away
+    first_fetch: bool,
+
+    /// Fetching the window instead of the background
+    window: bool,
+
+    /// Pixels still to be dropped: SCX fine scroll, or the
+    /// part of the window left of the screen with WX < 7
+    discard: u8,
+
+    /// Next X coordinate to output
+    lx: u8,
+
+    /// OAM indices of the sprites on this line not fetched yet
+    sprites: Vec<u8>,
+
+    /// Dots left of a sprite fetch in progress, and the
+    /// sprite's OAM index
+    sprite_fetch: Option<(u8, u8)>,
+}
+
+impl Fifo {
+    pub(super) fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.bg.len() as u8);
+        for p in &self.bg {
+            w.u8(p.color);
+            w.u8(p.palette);
+            w.bool(p.priority);
+        }
+        w.u8(self.obj.len() as u8);
+        for p in &self.obj {
+            w.u8(p.color);
+            w.u8(p.attr);
+            w.u8(p.oam_idx);
+        }
+        for val in [
+            self.fetch_dot,
+            self.fetch_x,
+            self.tile,
+            self.attr,
+            self.lo,
+            self.hi,
+            self.discard,
+            self.lx,
+        ] {
+            w.u8(val);
+        }
+        w.bool(self.first_fetch);
+        w.bool(self.window);
+        w.bytes(&self.sprites);
+        let (left, idx) = self.sprite_fetch.unwrap_or((0, 0));
+        w.u8(left);
+        w.u8(idx);
+    }
+
+    pub(super) fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.bg.clear();
+        for _ in 0..r.u8()? {
+            self.bg.push_back(BgPixel {
+                color: r.u8()?,
+                palette: r.u8()?,
+                priority: r.bool()?,
+            });
+        }
+        self.obj.clear();
+        for _ in 0..r.u8()? {
+            self.obj.push_back(ObjPixel {
+                color: r.u8()?,
+                attr: r.u8()?,
+                oam_idx: r.u8()?,
+            });
+        }
+        for val in [
+            &mut self.fetch_dot,
+            &mut self.fetch_x,
+            &mut self.tile,
+            &mut self.attr,
+            &mut self.lo,
+            &mut self.hi,
+            &mut self.discard,
+            &mut self.lx,
+        ] {
+            *val = r.u8()?;
+        }
+        if self.fetch_dot > FETCH_DOTS || self.lx as usize > LCD_WIDTH {
+            bail!(StateErr::Mismatch("pixel FIFO"));
+        }
+        self.first_fetch = r.bool()?;
+        self.window = r.bool()?;
+        self.sprites = r.bytes()?.to_vec();
+        let (left, idx) = (r.u8()?, r.u8()?);
+        self.sprite_fetch = (left > 0).then_some((left, idx));
+        Ok(())
+    }
+}
+
+impl Ppu {
+    /// Starts pixel transfer on the current line, after OAM
+    /// search.
+    pub(super) fn fifo_start(&mut self) {
+        let height = self.obj_height() as isize;
+        let ly = self.ly as isize;
+        let sprites = (0..OAM_SIZE as u8 / 4)
+            .filter(|&i| {
+                let y = self.oam[i as usize * 4] as isize - 16;
+                (y..y + height).contains(&ly)
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+
+        let f = &mut self.fifo;
+        f.bg.clear();
+        f.obj.clear();
+        f.fetch_dot = 0;
+        f.fetch_x = 0;
+        f.first_fetch = true;
+        f.window = false;
+        f.discard = self.scx & 0x07;
+        f.lx = 0;
+        f.sprites = sprites;
+        f.sprite_fetch = None;
+    }
+
+    /// Runs a dot of pixel transfer. Returns true when the
+    /// line is complete.
+    pub(super) fn fifo_dot(&mut self) -> bool {
+        if self.fifo.lx as usize >= LCD_WIDTH {
+            return true;
+        }
+
+        if let Some((left, idx)) = self.fifo.sprite_fetch {
+            if left > 1 {
+                self.fifo.sprite_fetch = Some((left - 1, idx));
+            } else {
+                self.fifo.sprite_fetch = None;
+                self.fetch_sprite(idx);
+            }
+            return false;
+        }
+
+        // The window restarts the fetcher when reached
+        let lx = self.fifo.lx;
+        if !self.fifo.window
+            && self.lcdc & LCDC_WINDOW_ENABLE != 0
+            && self.window_triggered
+            && self.wx <= 166
+            && (lx + 7 == self.wx || (self.wx < 7 && lx == 0))
+        {
+            let f = &mut self.fifo;
+            f.window = true;
+            f.bg.clear();
+            f.fetch_dot = 0;
+            f.fetch_x = 0;
+            f.discard = 7u8.saturating_sub(self.wx);
+        }
+
+        // A sprite at this position waits for the background
+        // fetch in progress, then halts output to be fetched.
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            if let Some(pos) = self.next_sprite() {
+                if self.fifo.bg.is_empty() || self.fifo.fetch_dot < FETCH_DOTS - 1 {
+                    self.fetch_step();
+                } else {
+                    let idx = self.fifo.sprites.remove(pos);
+                    self.fifo.sprite_fetch = Some((SPRITE_FETCH_DOTS - 1, idx));
+                }
+                return false;
+            }
+        }
+
+        let done = self.shift_out();
+        if !done {
+            self.fetch_step();
+        }
+        done
+    }
+
+    /// Position in the sprite list of the next sprite to
+    /// fetch at the current X: the leftmost, then the first
+    /// in OAM.
+    fn next_sprite(&self) -> Option<usize> {
+        let lx = self.fifo.lx as usize;
+        self.fifo
+            .sprites
+            .iter()
+            .enumerate()
+            .map(|(pos, &idx)| (pos, self.oam[idx as usize * 4 + 1] as usize))
+            .filter(|&(_, x)| x <= lx + 8)
+            .min_by_key(|&(_, x)| x)
+            .map(|(pos, _)| pos)
+    }
+
+    /// Advances the background fetcher by a dot.
+    fn fetch_step(&mut self) {
+        if self.fifo.fetch_dot < FETCH_DOTS {
+            self.fifo.fetch_dot += 1;
+            match self.fifo.fetch_dot {
+                2 => self.fetch_tile(),
+                4 => self.fifo.lo = self.vram[self.fetch_data_addr()],
+                6 => self.fifo.hi = self.vram[self.fetch_data_addr() + 1],
+                _ => (),
+            }
+        }
+
+        if self.fifo.fetch_dot == FETCH_DOTS && self.fifo.bg.is_empty() {
+            let f = &mut self.fifo;
+            f.fetch_dot = 0;
+            if f.first_fetch {
+                f.first_fetch = false;
+                return;
+            }
+            for i in 0..8 {
+                let bit = if f.attr & OAM_XFLIP != 0 { i } else { 7 - i };
+                f.bg.push_back(BgPixel {
+                    color: ((f.hi >> bit) & 1) << 1 | ((f.lo >> bit) & 1),
+                    palette: f.attr & ATTR_CGB_PALETTE,
+                    priority: f.attr & OAM_BG_PRIORITY != 0,
+                });
+            }
+            f.fetch_x = f.fetch_x.wrapping_add(1);
+        }
+    }
+
+    /// Tilemap offset and line within the tilemap of the
+    /// current fetch.
+    fn fetch_map_pos(&self) -> (usize, usize) {
+        let f = &self.fifo;
+        if f.window {
+            let tilemap = if self.lcdc & LCDC_WINDOW_TILEMAP != 0 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            (
+                tilemap + (f.fetch_x & 31) as usize,
+                self.window_line as usize,
+            )
+        } else {
+            let tilemap = if self.lcdc & LCDC_BG_TILEMAP != 0 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            let x = ((self.scx >> 3).wrapping_add(f.fetch_x) & 31) as usize;
+            (tilemap + x, self.ly.wrapping_add(self.scy) as usize)
+        }
+    }
+
+    /// Reads the tile index and attributes (CGB) of the
+    /// current fetch.
+    fn fetch_tile(&mut self) {
+        let (map, y) = self.fetch_map_pos();
+        let addr = map + (y / 8) * 32;
+        self.fifo.tile = self.vram[addr];
+        self.fifo.attr = if self.cgb {
+            self.vram[VRAM_BANK1 + addr]
+        } else {
+            0
+        };
+    }
+
+    /// VRAM offset of the low byte of tile data of the
+    /// current fetch.
+    fn fetch_data_addr(&self) -> usize {
+        let (_, y) = self.fetch_map_pos();
+        let attr = self.fifo.attr;
+        let row = if attr & OAM_YFLIP != 0 {
+            7 - y % 8
+        } else {
+            y % 8
+        };
+        let bank = if attr & ATTR_BANK != 0 { VRAM_BANK1 } else { 0 };
+        bank + bg_tile_addr(self.fifo.tile, self.unsigned_tiledata()) + row * 2
+    }
+
+    /// Fetches the tile data of a sprite and merges it into
+    /// the OAM FIFO.
+    fn fetch_sprite(&mut self, idx: u8) {
+        let s = &self.oam[idx as usize * 4..][..4];
+        let (sy, sx, tile, attr) = (s[0] as isize - 16, s[1] as isize - 8, s[2], s[3]);
+        let height = self.obj_height();
+
+        let mut row = (self.ly as isize - sy) as usize;
+        if attr & OAM_YFLIP != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 { tile & 0xFE } else { tile };
+        let bank = if self.cgb && attr & ATTR_BANK != 0 {
+            VRAM_BANK1
+        } else {
+            0
+        };
+
+        // Sprites always use 0x8000 addressing.
+        let lx = self.fifo.lx as isize;
+        for i in 0..8 {
+            // Pixels left of the screen are cut off
+            let Ok(pos) = usize::try_from(sx + i as isize - lx) else {
+                continue;
+            };
+            let col = if attr & OAM_XFLIP != 0 { 7 - i } else { i };
+            let color = self.tile_color(bank + tile as usize * 16, col, row);
+
+            let f = &mut self.fifo;
+            if f.obj.len() <= pos {
+                f.obj.resize(pos + 1, ObjPixel::default());
+            }
+            // Earlier sprites win on DMG, the first in OAM on CGB
+            let slot = &mut f.obj[pos];
+            if color != 0 && (slot.color == 0 || (self.cgb && idx < slot.oam_idx)) {
+                *slot = ObjPixel {
+                    color,
+                    attr,
+                    oam_idx: idx,
+                };
+            }
+        }
+    }
+
+    /// Shifts a pixel out to the LCD, if the background FIFO
+    /// has one. Returns true after the last pixel of the line.
+    fn shift_out(&mut self) -> bool {
+        let Some(mut bg) = self.fifo.bg.pop_front() else {
+            return false;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return false;
+        }
+        let obj = self.fifo.obj.pop_front().unwrap_or_default();
+
+        // On DMG, LCDC bit 0 blanks both background and window
+        let mut color = if self.cgb || self.lcdc & LCDC_BG_ENABLE != 0 {
+            self.bg_shade(bg)
+        } else {
+            bg = BgPixel::default();
+            SHADES[0]
+        };
+        if obj.color != 0 && !self.bg_over_obj(bg, obj.attr) {
+            color = self.obj_shade(obj.attr, obj.color);
+        }
+
+        let lx = self.fifo.lx as usize;
+        self.framebuffer[self.ly as usize * LCD_WIDTH + lx] = color;
+        self.fifo.lx += 1;
+
+        if self.fifo.lx as usize == LCD_WIDTH {
+            if self.fifo.window {
+                self.window_line += 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PPU with tile 1 filled with color 3, tile 2 with color
+    /// 1, identity palettes and the BG map (0x9800) showing
+    /// tile 1 in even columns.
+    fn ppu(lcdc: u8, renderer: Renderer) -> Ppu {
+        let mut p = Ppu::new();
+        p.set_renderer(renderer);
+        for i in 0..16 {
+            p.write(0x8010 + i, 0xFF);
+        }
+        for i in 0..8 {
+            p.write(0x8020 + i * 2, 0xFF);
+        }
+        for i in (0..32 * 32).step_by(2) {
+            p.write(0x9800 + i, 1);
+        }
+        p.write(0xFF47, 0b11_10_01_00);
+        p.write(0xFF48, 0b11_10_01_00);
+        p.write(0xFF49, 0b00_01_10_11);
+        p.write(0xFF40, LCDC_ENABLE | LCDC_TILEDATA | lcdc);
+        p
+    }
+
+    fn sprite(p: &mut Ppu, idx: u16, y: u8, x: u8, tile: u8, attr: u8) {
+        p.write_slice(&[y, x, tile, attr], 0xFE00 + idx * 4);
+    }
+
+    /// Length of mode 3 on line 'ly' in dots.
+    fn mode3_len(p: &mut Ppu, ly: u8) -> usize {
+        while !(p.ly == ly && p.mode == PpuMode::Transfer) {
+            p.tick(1);
+        }
+        let mut dots = 0;
+        while p.mode == PpuMode::Transfer {
+            p.tick(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    fn frame(p: &mut Ppu) -> Vec<Color> {
+        while !p.frame_ready() {
+            p.tick(1);
+        }
+        p.get_framebuffer().to_vec()
+    }
+
+    #[test]
+    fn same_as_scanline() {
+        let lcdc = LCDC_BG_ENABLE | LCDC_OBJ_ENABLE | LCDC_WINDOW_ENABLE | LCDC_WINDOW_TILEMAP;
+        let setup = |p: &mut Ppu| {
+            for i in 0..32 {
+                p.write(0x9C00 + i, 2);
+            }
+            p.write(0xFF42, 3);
+            p.write(0xFF43, 5);
+            p.write(0xFF4A, 100);
+            p.write(0xFF4B, 60);
+            sprite(p, 0, 20, 4, 1, 0);
+            sprite(p, 1, 24, 10, 2, OAM_PALETTE);
+            sprite(p, 2, 30, 50, 1, OAM_BG_PRIORITY);
+            sprite(p, 3, 120, 100, 2, OAM_XFLIP);
+            for i in 4..16 {
+                sprite(p, i, 60, i as u8 * 9, 1, 0);
+            }
+        };
+
+        let mut scanline = ppu(lcdc, Renderer::Scanline);
+        setup(&mut scanline);
+        let mut fifo = ppu(lcdc, Renderer::Fifo);
+        setup(&mut fifo);
+        for _ in 0..2 {
+            assert!(frame(&mut scanline) == frame(&mut fifo));
+        }
+    }
+
+    #[test]
+    fn mode3_length() {
+        let mut p = ppu(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE, Renderer::Fifo);
+        assert_eq!(mode3_len(&mut p, 1), DOTS_TRANSFER);
+
+        // SCX fine scroll
+        p.write(0xFF43, 3);
+        assert_eq!(mode3_len(&mut p, 2), DOTS_TRANSFER + 3);
+        p.write(0xFF43, 8);
+        assert_eq!(mode3_len(&mut p, 3), DOTS_TRANSFER);
+
+        // Window
+        p.write(
+            0xFF40,
+            LCDC_ENABLE | LCDC_TILEDATA | LCDC_BG_ENABLE | LCDC_WINDOW_ENABLE,
+        );
+        p.write(0xFF4B, 87);
+        assert_eq!(mode3_len(&mut p, 4), DOTS_TRANSFER + 6);
+
+        // Sprites: 6 dots, plus waiting for the background
+        // fetch depending on the position within a tile
+        p.write(
+            0xFF40,
+            LCDC_ENABLE | LCDC_TILEDATA | LCDC_BG_ENABLE | LCDC_OBJ_ENABLE,
+        );
+        p.write(0xFF43, 0);
+        sprite(&mut p, 0, 16 + 10, 8, 1, 0);
+        assert_eq!(mode3_len(&mut p, 10), DOTS_TRANSFER + 11);
+        sprite(&mut p, 0, 16 + 11, 8 + 5, 1, 0);
+        assert_eq!(mode3_len(&mut p, 11), DOTS_TRANSFER + 6);
+        sprite(&mut p, 0, 16 + 12, 8 + 5, 1, 0);
+        sprite(&mut p, 1, 16 + 12, 8 + 5, 1, 0);
+        assert_eq!(mode3_len(&mut p, 12), DOTS_TRANSFER + 12);
+    }
+
+    #[test]
+    fn mid_line_palette() {
+        let mut p = ppu(LCDC_BG_ENABLE, Renderer::Fifo);
+        while !(p.ly == 1 && p.mode == PpuMode::Transfer) {
+            p.tick(1);
+        }
+        // 12 dots of fetching, then 40 pixels
+        p.tick(12 + 40);
+        p.write(0xFF47, 0);
+        while p.mode == PpuMode::Transfer {
+            p.tick(1);
+        }
+
+        let line = &p.get_framebuffer()[LCD_WIDTH..LCD_WIDTH * 2];
+        assert_eq!(line[0], SHADES[3]);
+        assert_eq!(line[32], SHADES[3]);
+        assert!(line[40..].iter().all(|&c| c == SHADES[0]));
+    }
+
+    #[test]
+    fn switch_renderer() {
+        let mut p = ppu(LCDC_BG_ENABLE, Renderer::Scanline);
+        while p.mode != PpuMode::Transfer {
+            p.tick(1);
+        }
+        p.tick(100);
+        p.set_renderer(Renderer::Fifo);
+        let mut scanline = ppu(LCDC_BG_ENABLE, Renderer::Scanline);
+        assert!(frame(&mut p) == frame(&mut scanline));
+    }
+
+    #[test]
+    fn save_state() {
+        let mut p = ppu(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE, Renderer::Fifo);
+        sprite(&mut p, 0, 16 + 1, 20, 1, 0);
+        while !(p.ly == 1 && p.mode == PpuMode::Transfer) {
+            p.tick(1);
+        }
+        p.tick(30);
+        let mut w = StateWriter::new();
+        p.save_state(&mut w).unwrap();
+        let state = w.into_inner();
+
+        let mut q = ppu(LCDC_BG_ENABLE | LCDC_OBJ_ENABLE, Renderer::Fifo);
+        q.load_state(&mut StateReader::new(&state)).unwrap();
+        assert!(frame(&mut p) == frame(&mut q));
+    }
+}
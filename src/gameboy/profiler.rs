@@ -0,0 +1,497 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use super::ppu::CYCLES_PER_FRAME;
+
+/// Emulator subsystems timed by the profiler
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Subsystem {
+    /// Everything not ticked by the bus: instruction
+    /// execution, interrupts and memory accesses
+    Cpu,
+    Ppu,
+    Timer,
+    Apu,
+    Cartridge,
+    Serial,
+
+    /// OAM DMA and VRAM DMA
+    Dma,
+}
+
+pub const SUBSYSTEMS: [Subsystem; 7] = [
+    Subsystem::Cpu,
+    Subsystem::Ppu,
+    Subsystem::Timer,
+    Subsystem::Apu,
+    Subsystem::Cartridge,
+    Subsystem::Serial,
+    Subsystem::Dma,
+];
+
+/// Time spent per subsystem, indexed by Subsystem
+pub type SubsystemTimes = [Duration; SUBSYSTEMS.len()];
+
+/// Names of the interrupts, by bit in IF/IE
+const INTERRUPT_NAMES: [&str; 5] = ["VBlank", "STAT", "Timer", "Serial", "Joypad"];
+
+/// Calls nested deeper than this are counted towards
+/// the caller.
+const MAX_DEPTH: usize = 64;
+
+/// Entries listed per table in the report
+const REPORT_ENTRIES: usize = 20;
+
+/// Code address, with the ROM bank mapped at the time
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Location {
+    pub bank: usize,
+    pub addr: u16,
+}
+
+impl std::fmt::Display for Location {
+    /// Formats as in symbol files, e.g. "01:4A2B".
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:02X}:{:04X}", self.bank, self.addr)
+    }
+}
+
+/// Entry in the call stack
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Node {
+    /// Code running outside any tracked call
+    Root,
+
+    /// Function entered by CALL or RST
+    Function(Location),
+
+    /// Interrupt handler (bit in IF/IE)
+    Interrupt(u8),
+
+    /// CPU halted or stopped, as a leaf
+    Halt,
+}
+
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Node::Root => write!(f, "main"),
+            Node::Function(loc) => write!(f, "{}", loc),
+            Node::Interrupt(int) => write!(f, "int:{}", INTERRUPT_NAMES[*int as usize]),
+            Node::Halt => write!(f, "[halt]"),
+        }
+    }
+}
+
+/// What the CPU did in the current step
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    Halt,
+    Instruction { loc: Location, opcode: u8, sp: u16 },
+    Interrupt(u8),
+}
+
+/// Executions and cycles of an instruction address
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PcStats {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// Returns true for opcodes that push a return address and
+/// jump: CALL (conditional or not) and RST.
+fn is_call(opcode: u8) -> bool {
+    matches!(opcode, 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC) || opcode & 0xC7 == 0xC7
+}
+
+/// Profiler for the emulated program and the emulator
+///
+/// Driven by the CPU (CPU::enable_profiler()): counts the
+/// executions and cycles of every instruction address, and
+/// follows calls and interrupts to attribute cycles to
+/// call stacks. Returns are detected by the stack pointer
+/// moving above the return address of a call, which also
+/// catches code that discards return addresses. Wall time
+/// is collected per frame and charged to the CPU, except for
+/// the time the bus reports spending in its subsystems (see
+/// Emulator::take_profiler()).
+pub struct Profiler {
+    pcs: HashMap<Location, PcStats>,
+
+    /// Times each function or handler was entered
+    calls: HashMap<Node, u64>,
+
+    /// Cycles per distinct call stack
+    stacks: HashMap<Vec<Node>, u64>,
+
+    /// Current call stack
+    stack: Vec<Node>,
+
+    /// Stack pointer after entering each frame of 'stack'
+    /// (except the root)
+    stack_sp: Vec<u16>,
+
+    /// Step in progress
+    step: Step,
+
+    /// Cycles into the current frame
+    frame_cycles: usize,
+
+    /// Start of the current frame
+    frame_start: Instant,
+
+    /// Completed frames
+    frames: u64,
+
+    /// Wall time per subsystem over all frames
+    times: SubsystemTimes,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            pcs: HashMap::new(),
+            calls: HashMap::new(),
+            stacks: HashMap::new(),
+            stack: vec![Node::Root],
+            stack_sp: vec![],
+            step: Step::Halt,
+            frame_cycles: 0,
+            frame_start: Instant::now(),
+            frames: 0,
+            times: SubsystemTimes::default(),
+        }
+    }
+
+    /// Records an instruction about to execute at 'loc',
+    /// with the stack pointer before it.
+    pub fn instruction(&mut self, loc: Location, opcode: u8, sp: u16) {
+        self.step = Step::Instruction { loc, opcode, sp };
+    }
+
+    /// Records an interrupt being dispatched.
+    pub fn interrupt(&mut self, int: u8) {
+        self.step = Step::Interrupt(int);
+    }
+
+    /// Completes a step that took 'cycles', leaving PC at
+    /// 'pc' (in the ROM bank at that address) and SP at
+    /// 'sp'. Returns true if a frame's worth of cycles has
+    /// passed, after which end_frame() should be called.
+    pub fn end_step(&mut self, cycles: usize, pc: Location, sp: u16) -> bool {
+        let step = std::mem::replace(&mut self.step, Step::Halt);
+        if let Step::Instruction { loc, .. } = step {
+            let stats = self.pcs.entry(loc).or_default();
+            stats.count += 1;
+            stats.cycles += cycles as u64;
+        }
+
+        // The cycles of a call or interrupt dispatch belong to
+        // the caller, those of a return to the callee.
+        if let Step::Halt = step {
+            self.stack.push(Node::Halt);
+            self.charge(cycles);
+            self.stack.pop();
+        } else {
+            self.charge(cycles);
+        }
+
+        while self.stack_sp.last().is_some_and(|&s| sp > s) {
+            self.stack.pop();
+            self.stack_sp.pop();
+        }
+
+        let entered = match step {
+            Step::Instruction {
+                opcode, sp: old_sp, ..
+            } if is_call(opcode) && sp == old_sp.wrapping_sub(2) => Some(Node::Function(pc)),
+            Step::Interrupt(int) => Some(Node::Interrupt(int)),
+            _ => None,
+        };
+        if let Some(node) = entered {
+            *self.calls.entry(node).or_default() += 1;
+            if self.stack_sp.len() < MAX_DEPTH {
+                self.stack.push(node);
+                self.stack_sp.push(sp);
+            }
+        }
+
+        self.frame_cycles += cycles;
+        self.frame_cycles >= CYCLES_PER_FRAME
+    }
+
+    /// Adds cycles to the current call stack.
+    fn charge(&mut self, cycles: usize) {
+        match self.stacks.get_mut(&self.stack[..]) {
+            Some(c) => *c += cycles as u64,
+            None => {
+                self.stacks.insert(self.stack.clone(), cycles as u64);
+            }
+        }
+    }
+
+    /// Completes a frame, charging its wall time to the CPU
+    /// until add_bus_times() moves part of it elsewhere.
+    pub fn end_frame(&mut self) {
+        let now = Instant::now();
+        self.times[Subsystem::Cpu as usize] += now - self.frame_start;
+        self.frame_start = now;
+        self.frame_cycles = self.frame_cycles.saturating_sub(CYCLES_PER_FRAME);
+        self.frames += 1;
+    }
+
+    /// Moves the time the bus spent per subsystem from the
+    /// CPU to those subsystems.
+    pub fn add_bus_times(&mut self, bus_times: SubsystemTimes) {
+        for (i, t) in bus_times.into_iter().enumerate().skip(1) {
+            self.times[i] += t;
+            let cpu = &mut self.times[Subsystem::Cpu as usize];
+            *cpu = cpu.saturating_sub(t);
+        }
+    }
+
+    /// Executions and cycles of an instruction address.
+    pub fn pc_stats(&self, loc: Location) -> PcStats {
+        self.pcs.get(&loc).copied().unwrap_or_default()
+    }
+
+    /// Completed frames
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Average wall time per frame of a subsystem.
+    pub fn frame_time(&self, subsystem: Subsystem) -> Duration {
+        self.times[subsystem as usize]
+            .checked_div(self.frames as u32)
+            .unwrap_or_default()
+    }
+
+    /// Self and total (inclusive) cycles per function or
+    /// interrupt handler.
+    fn node_cycles(&self) -> HashMap<Node, (u64, u64)> {
+        let mut out: HashMap<Node, (u64, u64)> = HashMap::new();
+        for (stack, &cycles) in &self.stacks {
+            let leaf = stack.last().copied().unwrap_or(Node::Root);
+            out.entry(leaf).or_default().0 += cycles;
+            // Recursive calls count once towards the total
+            let unique: HashSet<&Node> = stack.iter().collect();
+            for &node in unique {
+                out.entry(node).or_default().1 += cycles;
+            }
+        }
+        out
+    }
+
+    /// Self and total cycles of a function, entered at 'loc'.
+    pub fn function_cycles(&self, loc: Location) -> (u64, u64) {
+        self.node_cycles()
+            .get(&Node::Function(loc))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Self and total cycles of an interrupt handler (bit in
+    /// IF/IE).
+    pub fn interrupt_cycles(&self, int: u8) -> (u64, u64) {
+        self.node_cycles()
+            .get(&Node::Interrupt(int))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Call stacks with their cycles in the "folded" format
+    /// of flamegraph.pl and inferno, e.g.
+    /// "main;00:0150;int:VBlank 1234".
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack.iter().map(|n| n.to_string()).collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|l| format!("{}\n", l)).collect()
+    }
+
+    /// Human-readable report: time per subsystem, hottest
+    /// instruction addresses and functions.
+    pub fn report(&self) -> String {
+        let total_cycles: u64 = self.stacks.values().sum();
+        let pct = |c: u64| c as f64 * 100.0 / total_cycles.max(1) as f64;
+        let mut out = String::new();
+
+        writeln!(out, "Frames: {}, cycles: {}", self.frames, total_cycles).unwrap();
+        writeln!(out).unwrap();
+
+        let frame_total: Duration = SUBSYSTEMS.iter().map(|&s| self.frame_time(s)).sum();
+        writeln!(out, "{:<10} {:>10} {:>7}", "Subsystem", "us/frame", "%").unwrap();
+        for s in SUBSYSTEMS {
+            let t = self.frame_time(s);
+            writeln!(
+                out,
+                "{:<10} {:>10.1} {:>6.1}%",
+                format!("{:?}", s),
+                t.as_secs_f64() * 1e6,
+                t.as_secs_f64() * 100.0 / frame_total.as_secs_f64().max(f64::MIN_POSITIVE)
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+
+        let mut pcs: Vec<(&Location, &PcStats)> = self.pcs.iter().collect();
+        pcs.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        writeln!(
+            out,
+            "{:<10} {:>12} {:>12} {:>7}",
+            "Address", "Count", "Cycles", "%"
+        )
+        .unwrap();
+        for (loc, stats) in pcs.iter().take(REPORT_ENTRIES) {
+            writeln!(
+                out,
+                "{:<10} {:>12} {:>12} {:>6.1}%",
+                loc.to_string(),
+                stats.count,
+                stats.cycles,
+                pct(stats.cycles)
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+
+        let mut nodes: Vec<(Node, (u64, u64))> = self.node_cycles().into_iter().collect();
+        nodes.sort_by(|a, b| {
+            (b.1)
+                .1
+                .cmp(&(a.1).1)
+                .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+        });
+        writeln!(
+            out,
+            "{:<12} {:>10} {:>12} {:>12} {:>7}",
+            "Function", "Calls", "Self", "Total", "%"
+        )
+        .unwrap();
+        for (node, (self_cycles, total)) in nodes.iter().take(REPORT_ENTRIES) {
+            writeln!(
+                out,
+                "{:<12} {:>10} {:>12} {:>12} {:>6.1}%",
+                node.to_string(),
+                self.calls.get(node).copied().unwrap_or_default(),
+                self_cycles,
+                total,
+                pct(*total)
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loc(addr: u16) -> Location {
+        Location { bank: 0, addr }
+    }
+
+    #[test]
+    fn call_detection() {
+        assert!(is_call(0xCD));
+        assert!(is_call(0xDC));
+        for rst in (0xC7..=0xFF).step_by(8) {
+            assert!(is_call(rst));
+        }
+        assert!(!is_call(0xC9));
+        assert!(!is_call(0xC3));
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let mut p = Profiler::new();
+        // 0150: CALL 0200
+        p.instruction(loc(0x150), 0xCD, 0xFFFE);
+        p.end_step(24, loc(0x200), 0xFFFC);
+        // 0200: NOP
+        p.instruction(loc(0x200), 0x00, 0xFFFC);
+        p.end_step(4, loc(0x201), 0xFFFC);
+        // VBlank interrupt
+        p.interrupt(0);
+        p.end_step(20, loc(0x40), 0xFFFA);
+        // 0040: RETI
+        p.instruction(loc(0x40), 0xD9, 0xFFFA);
+        p.end_step(16, loc(0x201), 0xFFFC);
+        // 0201: RET
+        p.instruction(loc(0x201), 0xC9, 0xFFFC);
+        p.end_step(16, loc(0x153), 0xFFFE);
+        // Halted
+        p.end_step(4, loc(0x153), 0xFFFE);
+
+        assert_eq!(
+            p.pc_stats(loc(0x150)),
+            PcStats {
+                count: 1,
+                cycles: 24
+            }
+        );
+        assert_eq!(p.function_cycles(loc(0x200)), (40, 56));
+        assert_eq!(p.interrupt_cycles(0), (16, 16));
+        assert_eq!(
+            p.folded(),
+            "main 24\nmain;00:0200 40\nmain;00:0200;int:VBlank 16\nmain;[halt] 4\n"
+        );
+    }
+
+    #[test]
+    fn untaken_call() {
+        let mut p = Profiler::new();
+        // CALL NZ not taken
+        p.instruction(loc(0x150), 0xC4, 0xFFFE);
+        p.end_step(12, loc(0x153), 0xFFFE);
+        assert_eq!(p.folded(), "main 12\n");
+    }
+
+    #[test]
+    fn discarded_return() {
+        let mut p = Profiler::new();
+        p.instruction(loc(0x150), 0xCD, 0xFFFE);
+        p.end_step(24, loc(0x200), 0xFFFC);
+        // POP HL drops the return address
+        p.instruction(loc(0x200), 0xE1, 0xFFFC);
+        p.end_step(12, loc(0x201), 0xFFFE);
+        p.instruction(loc(0x201), 0x00, 0xFFFE);
+        p.end_step(4, loc(0x202), 0xFFFE);
+        assert_eq!(p.folded(), "main 28\nmain;00:0200 12\n");
+    }
+
+    #[test]
+    fn frames() {
+        let mut p = Profiler::new();
+        assert!(!p.end_step(CYCLES_PER_FRAME - 4, loc(0), 0));
+        assert!(p.end_step(8, loc(0), 0));
+        std::thread::sleep(Duration::from_millis(3));
+        p.end_frame();
+        assert_eq!(p.frames(), 1);
+        let cpu = p.frame_time(Subsystem::Cpu);
+        assert!(cpu >= Duration::from_millis(3));
+
+        let mut times = SubsystemTimes::default();
+        times[Subsystem::Ppu as usize] = Duration::from_millis(2);
+        p.add_bus_times(times);
+        assert_eq!(p.frame_time(Subsystem::Ppu), Duration::from_millis(2));
+        assert_eq!(p.frame_time(Subsystem::Cpu), cpu - Duration::from_millis(2));
+        assert!(!p.end_step(4, loc(0), 0));
+        assert!(p.report().contains("Frames: 1"));
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Result};
+
+use super::bus::bus::Bus;
+use super::cpu::cpu::CPU;
+
+/// Rewind buffer of save states
+///
+/// A snapshot is taken every 'interval' frames. Only the
+/// newest snapshot is kept in full; each older one is stored
+/// as the run-length encoded XOR difference to the snapshot
+/// after it. Consecutive states differ in few bytes, so these
+/// deltas are small. Once 'capacity' snapshots are stored,
+/// the oldest is dropped.
+pub struct Rewind {
+    /// Frames between snapshots
+    interval: usize,
+
+    /// Maximum amount of snapshots
+    capacity: usize,
+
+    /// Frames recorded so far
+    frame: usize,
+
+    /// Newest snapshot (frame, state)
+    newest: Option<(usize, Vec<u8>)>,
+
+    /// Older snapshots (frame, delta to the next newer one),
+    /// oldest first
+    older: VecDeque<(usize, Vec<u8>)>,
+}
+
+impl Rewind {
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        assert!(interval > 0 && capacity > 0);
+        Self {
+            interval,
+            capacity,
+            frame: 0,
+            newest: None,
+            older: VecDeque::new(),
+        }
+    }
+
+    /// Call once per emulated frame; takes a snapshot every
+    /// 'interval' frames.
+    pub fn record_frame<B: Bus>(&mut self, cpu: &CPU<B>) -> Result<()> {
+        self.frame += 1;
+        if !self.frame.is_multiple_of(self.interval) {
+            return Ok(());
+        }
+
+        let state = cpu.save_state()?;
+        if let Some((frame, prev)) = self.newest.take() {
+            self.older.push_back((frame, encode_delta(&state, &prev)));
+            if self.older.len() >= self.capacity {
+                self.older.pop_front();
+            }
+        }
+        self.newest = Some((self.frame, state));
+        Ok(())
+    }
+
+    /// Steps back to the newest snapshot at least 'n' frames
+    /// old (or the oldest one available) and restores it.
+    /// Newer snapshots are discarded. Returns the amount of
+    /// frames actually rewound.
+    pub fn rewind_frames<B: Bus>(&mut self, cpu: &mut CPU<B>, n: usize) -> Result<usize> {
+        let target = self.frame.saturating_sub(n);
+        let Some((mut frame, mut state)) = self.newest.take() else {
+            bail!("Nothing recorded to rewind to");
+        };
+        while frame > target {
+            let Some((older_frame, delta)) = self.older.pop_back() else {
+                break;
+            };
+            state = decode_delta(&state, &delta)?;
+            frame = older_frame;
+        }
+
+        cpu.load_state(&state)?;
+        let rewound = self.frame - frame;
+        self.frame = frame;
+        self.newest = Some((frame, state));
+        Ok(rewound)
+    }
+
+    /// Amount of snapshots stored.
+    pub fn len(&self) -> usize {
+        self.older.len() + usize::from(self.newest.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes used by the stored snapshots.
+    pub fn memory_usage(&self) -> usize {
+        self.newest.as_ref().map_or(0, |(_, s)| s.len())
+            + self.older.iter().map(|(_, d)| d.len()).sum::<usize>()
+    }
+
+    /// Drops all snapshots.
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.older.clear();
+    }
+}
+
+/// Undo history of single steps (see CPU::step_back())
+///
+/// For each step, only the delta from the state after it back
+/// to the state before it is kept. That is the few bytes the
+/// instruction changed: registers, the memory it wrote and the
+/// state of the peripherals that ran along. Once 'depth' steps
+/// are stored, the oldest is dropped.
+pub struct StepHistory {
+    /// Maximum amount of steps
+    depth: usize,
+
+    /// Deltas to the state before each step, oldest first
+    steps: VecDeque<Vec<u8>>,
+}
+
+impl StepHistory {
+    pub fn new(depth: usize) -> Self {
+        assert!(depth > 0);
+        Self {
+            depth,
+            steps: VecDeque::new(),
+        }
+    }
+
+    /// Records a step from state 'before' to state 'after'.
+    pub fn push(&mut self, before: &[u8], after: &[u8]) {
+        if self.steps.len() >= self.depth {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(encode_delta(after, before));
+    }
+
+    /// Drops the newest step and returns the state before it,
+    /// reconstructed from the current state.
+    pub fn pop(&mut self, current: &[u8]) -> Result<Vec<u8>> {
+        let Some(delta) = self.steps.pop_back() else {
+            bail!("No step to step back to");
+        };
+        decode_delta(current, &delta)
+    }
+
+    /// Amount of steps stored.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push(val as u8 | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut val = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let Some(&b) = data.get(*pos) else {
+            bail!("Truncated rewind delta");
+        };
+        *pos += 1;
+        val |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+    bail!("Invalid rewind delta")
+}
+
+/// Encodes 'target' as the difference to 'base': the target
+/// length, followed by pairs of (unchanged run, changed run)
+/// lengths with the XOR-ed bytes of the changed run.
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = (0..target.len())
+        .map(|i| target[i] ^ base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut out = vec![];
+    push_varint(&mut out, target.len());
+    let mut pos = 0;
+    while pos < xor.len() {
+        let same = xor[pos..].iter().take_while(|&&b| b == 0).count();
+        pos += same;
+        let changed = xor[pos..].iter().take_while(|&&b| b != 0).count();
+        push_varint(&mut out, same);
+        push_varint(&mut out, changed);
+        out.extend_from_slice(&xor[pos..pos + changed]);
+        pos += changed;
+    }
+    out
+}
+
+/// Reconstructs a state encoded by encode_delta().
+fn decode_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos)?;
+    let mut out: Vec<u8> = (0..len)
+        .map(|i| base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos)?;
+        let changed = read_varint(delta, &mut pos)?;
+        if i + changed > len || pos + changed > delta.len() {
+            bail!("Invalid rewind delta");
+        }
+        for (o, d) in out[i..i + changed]
+            .iter_mut()
+            .zip(&delta[pos..pos + changed])
+        {
+            *o ^= d;
+        }
+        i += changed;
+        pos += changed;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bus::testbus::Testbus;
+    use super::*;
+
+    #[test]
+    fn delta_roundtrip() {
+        let base: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut target = base.clone();
+        target[3] = 0xFF;
+        target[500..520].fill(0);
+        target[999] ^= 1;
+
+        let delta = encode_delta(&base, &target);
+        assert!(delta.len() < 40);
+        assert_eq!(decode_delta(&base, &delta).unwrap(), target);
+
+        // Differing lengths
+        for t in [&target[..10], &[][..], &[1, 2, 3][..]] {
+            assert_eq!(decode_delta(&base, &encode_delta(&base, t)).unwrap(), t);
+        }
+        let mut longer = base.clone();
+        longer.extend([1, 0, 2]);
+        assert_eq!(
+            decode_delta(&base, &encode_delta(&base, &longer)).unwrap(),
+            longer
+        );
+
+        assert!(decode_delta(&base, &delta[..delta.len() - 1]).is_err());
+    }
+
+    /// CPU counting up in A, one step per 'frame'
+    fn counter() -> CPU {
+        CPU::new(Box::new(Testbus::from(&[
+            0x3C, // INC A
+            0x18, 0xFD, // JR $-3
+        ])))
+    }
+
+    /// Runs a frame (INC A; JR) and records it
+    fn frame(cpu: &mut CPU, rw: &mut Rewind) {
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        rw.record_frame(cpu).unwrap();
+    }
+
+    #[test]
+    fn rewind() {
+        let mut cpu = counter();
+        let mut rw = Rewind::new(2, 100);
+        assert!(rw.rewind_frames(&mut cpu, 1).is_err());
+
+        for _ in 0..20 {
+            frame(&mut cpu, &mut rw);
+        }
+        assert_eq!(cpu.regs.a, 20);
+        assert_eq!(rw.len(), 10);
+
+        // Snapshots are taken on even frames
+        assert_eq!(rw.rewind_frames(&mut cpu, 3).unwrap(), 4);
+        assert_eq!(cpu.regs.a, 16);
+        assert_eq!(rw.len(), 8);
+
+        // Recording continues from the restored frame
+        frame(&mut cpu, &mut rw);
+        frame(&mut cpu, &mut rw);
+        assert_eq!(rw.len(), 9);
+        assert_eq!(rw.rewind_frames(&mut cpu, 0).unwrap(), 0);
+        assert_eq!(cpu.regs.a, 18);
+
+        // Rewinding past the start stops at the oldest snapshot
+        assert_eq!(rw.rewind_frames(&mut cpu, 100).unwrap(), 16);
+        assert_eq!(cpu.regs.a, 2);
+        assert_eq!(rw.len(), 1);
+    }
+
+    #[test]
+    fn capacity() {
+        let mut cpu = counter();
+        let mut rw = Rewind::new(1, 4);
+        for _ in 0..10 {
+            frame(&mut cpu, &mut rw);
+        }
+        assert_eq!(rw.len(), 4);
+        assert_eq!(rw.rewind_frames(&mut cpu, 100).unwrap(), 3);
+        assert_eq!(cpu.regs.a, 7);
+
+        // Deltas are much smaller than the full state
+        let state_len = cpu.save_state().unwrap().len();
+        for _ in 0..10 {
+            frame(&mut cpu, &mut rw);
+        }
+        assert!(rw.memory_usage() < state_len + 3 * 100);
+
+        rw.clear();
+        assert!(rw.is_empty());
+    }
+}
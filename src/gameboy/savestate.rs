@@ -0,0 +1,220 @@
+use anyhow::{bail, Result};
+use thiserror::Error;
+
+/// Magic bytes at the start of every save state
+pub const STATE_MAGIC: &[u8; 4] = b"GBRS";
+
+/// Current save state format version
+pub const STATE_VERSION: u8 = 7;
+
+/// Errors that can occur while loading a save state
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum StateErr {
+    #[error("Not a save state")]
+    BadMagic,
+
+    #[error("Unsupported save state version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Save state is truncated")]
+    Truncated,
+
+    #[error("Save state does not match this machine ({0})")]
+    Mismatch(&'static str),
+
+    #[error("Bus does not support save states")]
+    Unsupported,
+}
+
+/// Serializer for save states
+///
+/// All values are stored in little endian. Every component
+/// starts its section with a 4-byte tag, so loading a state
+/// into a differently configured machine (e.g. another
+/// cartridge type) fails rather than corrupting it.
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { buf: vec![] }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn tag(&mut self, tag: &[u8; 4]) {
+        self.buf.extend_from_slice(tag);
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.u8(val.into());
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    /// Writes a length-prefixed block of bytes.
+    pub fn bytes(&mut self, val: &[u8]) {
+        self.u32(val.len() as u32);
+        self.buf.extend_from_slice(val);
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Deserializer for save states, see StateWriter.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            bail!(StateErr::Truncated);
+        }
+        let (val, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(val)
+    }
+
+    /// Reads a tag, failing if it is not the expected one.
+    /// 'what' describes the component for error reporting.
+    pub fn tag(&mut self, tag: &[u8; 4], what: &'static str) -> Result<()> {
+        if self.take(4)? != tag {
+            bail!(StateErr::Mismatch(what));
+        }
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    /// Reads a length-prefixed block of bytes.
+    pub fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a length-prefixed block of bytes into 'dest',
+    /// which must be of the same length.
+    pub fn bytes_into(&mut self, dest: &mut [u8], what: &'static str) -> Result<()> {
+        let len = self.u32()? as usize;
+        if len != dest.len() {
+            bail!(StateErr::Mismatch(what));
+        }
+        dest.copy_from_slice(self.take(len)?);
+        Ok(())
+    }
+
+    /// Fails if any data is left unread.
+    pub fn finish(&self) -> Result<()> {
+        if !self.data.is_empty() {
+            bail!(StateErr::Mismatch("trailing data"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut w = StateWriter::new();
+        w.tag(b"TEST");
+        w.u8(0x12);
+        w.bool(true);
+        w.u16(0x3456);
+        w.u32(0x789ABCDE);
+        w.u64(0x0123456789ABCDEF);
+        w.bytes(&[1, 2, 3]);
+        let state = w.into_inner();
+
+        let mut r = StateReader::new(&state);
+        r.tag(b"TEST", "test").unwrap();
+        assert_eq!(r.u8().unwrap(), 0x12);
+        assert!(r.bool().unwrap());
+        assert_eq!(r.u16().unwrap(), 0x3456);
+        assert_eq!(r.u32().unwrap(), 0x789ABCDE);
+        assert_eq!(r.u64().unwrap(), 0x0123456789ABCDEF);
+        let mut buf = [0; 3];
+        r.bytes_into(&mut buf, "test").unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+        r.finish().unwrap();
+
+        let mut w = StateWriter::new();
+        w.bytes(&[4, 5]);
+        let state = w.into_inner();
+        let mut r = StateReader::new(&state);
+        assert_eq!(r.bytes().unwrap(), [4, 5]);
+        r.finish().unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let err = |e: anyhow::Error| e.downcast::<StateErr>().unwrap();
+
+        let mut r = StateReader::new(&[0x12]);
+        assert_eq!(err(r.u16().unwrap_err()), StateErr::Truncated);
+
+        let mut r = StateReader::new(b"ABCD");
+        assert_eq!(
+            err(r.tag(b"TEST", "test").unwrap_err()),
+            StateErr::Mismatch("test")
+        );
+
+        let mut w = StateWriter::new();
+        w.bytes(&[1, 2, 3]);
+        let state = w.into_inner();
+        let mut r = StateReader::new(&state);
+        assert_eq!(
+            err(r.bytes_into(&mut [0; 4], "ram").unwrap_err()),
+            StateErr::Mismatch("ram")
+        );
+
+        let r = StateReader::new(&[0]);
+        assert!(r.finish().is_err());
+    }
+}
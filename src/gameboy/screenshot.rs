@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::ppu::{Color, Frame, Image, LCD_HEIGHT, LCD_WIDTH};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Maximum payload of an uncompressed deflate block
+const STORED_BLOCK_MAX: usize = 0xFFFF;
+
+/// CRC-32 (ISO-HDLC) as used by PNG chunks.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| {
+        (0..8).fold(crc ^ b as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Adler-32 checksum of a zlib stream.
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &d| {
+        let a = (a + d as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+/// Wraps data in a zlib stream of uncompressed deflate
+/// blocks. Frames are small, so compression isn't worth
+/// the trouble.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK_MAX).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none().into());
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encodes RGBA pixels (row by row) as a PNG image.
+pub fn encode_png(width: usize, height: usize, pixels: &[Color]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height);
+
+    let mut ihdr = vec![];
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per sample, RGBA, deflate, no filter, no interlace
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut raw = Vec::with_capacity(height * (1 + width * 4));
+    for row in pixels.chunks(width.max(1)) {
+        // Filter type None
+        raw.push(0);
+        raw.extend(row.iter().flat_map(|px| px.to_be_bytes()));
+    }
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    chunk(&mut out, b"IHDR", &ihdr);
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// Writes RGBA pixels to a PNG file.
+pub fn write_png(path: &Path, width: usize, height: usize, pixels: &[Color]) -> Result<()> {
+    fs::write(path, encode_png(width, height, pixels))
+        .context(format!("Writing {}", path.display()))
+}
+
+/// Writes a debug viewer image to a PNG file.
+pub fn save_image(path: &Path, image: &Image) -> Result<()> {
+    write_png(path, image.width, image.height, &image.pixels)
+}
+
+/// Writes a frame to a PNG file.
+pub fn save_screenshot(path: &Path, frame: &Frame) -> Result<()> {
+    write_png(path, LCD_WIDTH, LCD_HEIGHT, frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    /// Extracts the payload of a zlib stream of stored blocks.
+    fn unstore(zlib: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        let mut pos = 2;
+        loop {
+            let last = zlib[pos] & 1 != 0;
+            let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]) as usize;
+            assert_eq!(zlib[pos + 3..pos + 5], (!(len as u16)).to_le_bytes());
+            data.extend_from_slice(&zlib[pos + 5..pos + 5 + len]);
+            pos += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(zlib[pos..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn zlib() {
+        assert_eq!(unstore(&zlib_stored(&[])), []);
+        let data: Vec<u8> = (0..STORED_BLOCK_MAX * 2 + 10).map(|i| i as u8).collect();
+        assert_eq!(unstore(&zlib_stored(&data)), data);
+    }
+
+    #[test]
+    fn png() {
+        let mut frame = [0xFFFFFFFF; LCD_WIDTH * LCD_HEIGHT];
+        frame[1] = 0x11223344;
+        let png = encode_png(LCD_WIDTH, LCD_HEIGHT, &frame);
+
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        // IHDR
+        assert_eq!(png[8..16], [0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(png[16..20], (LCD_WIDTH as u32).to_be_bytes());
+        assert_eq!(png[20..24], (LCD_HEIGHT as u32).to_be_bytes());
+        assert_eq!(png[29..33], crc32(&png[12..29]).to_be_bytes());
+        // IDAT
+        let len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(png[37..41], *b"IDAT");
+        let raw = unstore(&png[41..41 + len]);
+        assert_eq!(raw.len(), LCD_HEIGHT * (1 + LCD_WIDTH * 4));
+        assert_eq!(
+            raw[..9],
+            [0, 0xFF, 0xFF, 0xFF, 0xFF, 0x11, 0x22, 0x33, 0x44]
+        );
+        // IEND
+        assert_eq!(
+            png[png.len() - 12..],
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use super::bus::bus::Bus;
+use super::bus::gbbus::Gameboybus;
+use super::bus::hookbus::{HookBus, Hooks};
+use super::cartridge::cartridge;
+use super::cartridge::header::CartridgeHeader;
+use super::cpu::cpu::CPU;
+use super::cpu::regs::Register;
+use super::joypad::Button;
+use super::model::Model;
+use super::ppu::CYCLES_PER_FRAME;
+use super::screenshot::save_screenshot;
+
+/// Per-frame callback of a script
+pub type FrameCallback = Box<dyn FnMut(&mut ScriptApi) -> Result<()>>;
+
+/// Emulator as seen by a script from a frame callback
+pub struct ScriptApi<'a> {
+    cpu: &'a mut CPU<HookBus<Gameboybus>>,
+    frame: u64,
+}
+
+impl ScriptApi<'_> {
+    /// Frames run so far, counting the current one
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Reads memory as the CPU sees it (without ticking or
+    /// calling read hooks).
+    pub fn read(&self, addr: u16) -> u8 {
+        self.cpu.bus.peek(addr)
+    }
+
+    /// Writes memory as the CPU would (without ticking).
+    pub fn write(&mut self, addr: u16, val: u8) {
+        self.cpu.bus.write(addr, val);
+    }
+
+    pub fn register(&self, reg: Register) -> u16 {
+        self.cpu.regs.read(reg)
+    }
+
+    pub fn set_register(&mut self, reg: Register, val: u16) -> Result<()> {
+        self.cpu.regs.write(reg, val)
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.bus.inner_mut().set_button(button, pressed);
+    }
+
+    pub fn save_state(&self) -> Result<Vec<u8>> {
+        self.cpu.save_state()
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        self.cpu.load_state(state)
+    }
+
+    /// Writes the current frame to a PNG file.
+    pub fn screenshot(&self, path: &Path) -> Result<()> {
+        save_screenshot(path, self.cpu.bus.inner().frame())
+    }
+}
+
+/// Core side of a scripting engine binding
+///
+/// Runs a ROM with the callbacks a script registered: frame
+/// callbacks get full access to the emulator through
+/// ScriptApi after every frame, memory access callbacks run
+/// in the middle of an instruction and only see the access
+/// (see Hooks). A binding (e.g. to Lua or Rhai) wraps script
+/// functions in these callbacks and the ScriptApi methods in
+/// script functions.
+pub struct ScriptHost {
+    cpu: CPU<HookBus<Gameboybus>>,
+    hooks: Rc<RefCell<Hooks>>,
+    frame_callbacks: Vec<(usize, FrameCallback)>,
+
+    /// ID for the next frame callback
+    next_id: usize,
+
+    /// Frames run so far
+    frame: u64,
+}
+
+impl ScriptHost {
+    /// Loads a ROM on the model its header asks for, starting
+    /// in the post-boot state.
+    pub fn new(rom: &[u8]) -> Result<Self> {
+        let model = Model::detect(&CartridgeHeader::from_rom(rom)?);
+        let bus = Gameboybus::with_model(cartridge::load(rom)?, None, model);
+        let hooks = Rc::new(RefCell::new(Hooks::new()));
+        let mut cpu = CPU::new(HookBus::new(bus, Rc::clone(&hooks)));
+        cpu.skip_boot_rom();
+        Ok(Self {
+            cpu,
+            hooks,
+            frame_callbacks: vec![],
+            next_id: 0,
+            frame: 0,
+        })
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU<HookBus<Gameboybus>> {
+        &mut self.cpu
+    }
+
+    /// Registers a callback to run after every frame,
+    /// returning an ID for remove_frame_callback().
+    pub fn on_frame(
+        &mut self,
+        callback: impl FnMut(&mut ScriptApi) -> Result<()> + 'static,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.frame_callbacks.push((id, Box::new(callback)));
+        id
+    }
+
+    pub fn remove_frame_callback(&mut self, id: usize) {
+        self.frame_callbacks.retain(|(i, _)| *i != id);
+    }
+
+    /// Registers a callback for reads in 'range', returning
+    /// the value the CPU sees. Returns an ID for remove_hook().
+    pub fn on_read(
+        &mut self,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(u16, u8) -> u8 + 'static,
+    ) -> usize {
+        self.hooks.borrow_mut().add_read_hook(range, callback)
+    }
+
+    /// Registers a callback for writes in 'range', returning
+    /// the value to write (None: drop the write). Returns an
+    /// ID for remove_hook().
+    pub fn on_write(
+        &mut self,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(u16, u8) -> Option<u8> + 'static,
+    ) -> usize {
+        self.hooks.borrow_mut().add_write_hook(range, callback)
+    }
+
+    /// Registers a callback for instructions executed in
+    /// 'range'. Returns an ID for remove_hook().
+    pub fn on_execute(
+        &mut self,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(u16) + 'static,
+    ) -> usize {
+        self.hooks.borrow_mut().add_exec_hook(range, callback)
+    }
+
+    pub fn remove_hook(&mut self, id: usize) {
+        self.hooks.borrow_mut().remove(id);
+    }
+
+    /// Runs until the PPU completes a frame (or a frame's
+    /// worth of cycles if the LCD is off), then runs the
+    /// frame callbacks. Stops at the first callback failing.
+    pub fn run_frame(&mut self) -> Result<()> {
+        let end = self.cpu.get_cycles() + CYCLES_PER_FRAME;
+        while self.cpu.get_cycles() < end {
+            self.cpu.step()?;
+            if self.cpu.bus.inner_mut().frame_ready() {
+                break;
+            }
+        }
+        self.frame += 1;
+
+        let mut api = ScriptApi {
+            cpu: &mut self.cpu,
+            frame: self.frame,
+        };
+        for (_, callback) in &mut self.frame_callbacks {
+            callback(&mut api)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> ScriptHost {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x100..0x107].copy_from_slice(&[
+            0xFA, 0x00, 0xC0, // LD A,($C000)
+            0xEA, 0x01, 0xC0, // LD ($C001),A
+            0x76, // HALT
+        ]);
+        ScriptHost::new(&rom).unwrap()
+    }
+
+    #[test]
+    fn frame_callbacks() {
+        let mut h = host();
+        let frames = Rc::new(RefCell::new(vec![]));
+        let seen = Rc::clone(&frames);
+        let id = h.on_frame(move |api| {
+            seen.borrow_mut().push(api.frame());
+            api.write(0xC002, api.read(0xC001));
+            api.set_register(Register::B, 0x12)?;
+            api.set_button(Button::Start, true);
+            Ok(())
+        });
+        h.run_frame().unwrap();
+        h.run_frame().unwrap();
+        assert_eq!(*frames.borrow(), [1, 2]);
+        assert_eq!(h.cpu().regs.b, 0x12);
+
+        h.remove_frame_callback(id);
+        h.run_frame().unwrap();
+        assert_eq!(frames.borrow().len(), 2);
+
+        h.on_frame(|_| anyhow::bail!("Script error"));
+        assert!(h.run_frame().is_err());
+    }
+
+    #[test]
+    fn memory_callbacks() {
+        let mut h = host();
+        let executed = Rc::new(RefCell::new(vec![]));
+        let seen = Rc::clone(&executed);
+        h.on_read(0xC000..=0xC000, |_, _| 0x5A);
+        h.on_write(0xC001..=0xC001, |_, val| Some(val + 1));
+        let id = h.on_execute(0x0100..=0x0106, move |pc| seen.borrow_mut().push(pc));
+        h.run_frame().unwrap();
+
+        assert_eq!(*executed.borrow(), [0x0100, 0x0103, 0x0106]);
+        h.remove_hook(id);
+        assert_eq!(h.cpu().bus.read(0xC001), 0x5B);
+    }
+
+    #[test]
+    fn save_state() {
+        let mut h = host();
+        h.on_frame(|api| {
+            if api.frame() == 1 {
+                let state = api.save_state()?;
+                api.set_register(Register::A, 0x34)?;
+                api.load_state(&state)?;
+            }
+            Ok(())
+        });
+        h.run_frame().unwrap();
+        assert_eq!(h.cpu().regs.a, 0x00);
+    }
+}
@@ -0,0 +1,302 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use super::bus::bus::Bus;
+use super::link::SerialLink;
+use super::savestate::{StateReader, StateWriter};
+
+/// T-cycles per byte on the internal clock (8192 Hz)
+const BYTE_CYCLES: usize = 8 * 512;
+
+/// Receiver of bytes sent over the serial port
+pub trait SerialOutput {
+    fn send(&mut self, byte: u8);
+}
+
+impl<F: FnMut(u8)> SerialOutput for F {
+    fn send(&mut self, byte: u8) {
+        self(byte)
+    }
+}
+
+/// Serial output that prints to stdout
+pub struct StdoutSerial;
+
+impl SerialOutput for StdoutSerial {
+    fn send(&mut self, byte: u8) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+    }
+}
+
+/// Serial port (SB, SC)
+///
+/// Without a link partner, transfers on the internal clock
+/// complete immediately and shift in 0xFF. With a link
+/// (see set_link()), they take as long as 8 bits on the
+/// 8192 Hz clock and external clock transfers complete when
+/// the partner clocks them.
+pub struct Serial {
+    /// SB - Serial transfer data
+    sb: u8,
+
+    /// SC - Serial transfer control (bits 0 and 7)
+    sc: u8,
+
+    /// Output sink, if set
+    output: Option<Box<dyn SerialOutput>>,
+
+    /// Bytes sent while no sink is set
+    buffer: Vec<u8>,
+
+    /// Link partner, if connected
+    link: Option<Box<dyn SerialLink>>,
+
+    /// T-cycles left in an internal clock transfer
+    cycles_left: usize,
+
+    intreq: bool,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            output: None,
+            buffer: vec![],
+            link: None,
+            cycles_left: 0,
+            intreq: false,
+        }
+    }
+
+    /// Resets the serial registers, aborting a transfer.
+    /// The output and link stay connected.
+    pub fn reset(&mut self) {
+        self.sb = 0;
+        self.sc = 0;
+        self.cycles_left = 0;
+        self.intreq = false;
+        self.update_external();
+    }
+
+    /// Connects a link partner.
+    pub fn set_link(&mut self, link: Box<dyn SerialLink>) {
+        self.link = Some(link);
+        self.update_external();
+    }
+
+    /// Advances a running transfer by a number of T-cycles.
+    pub fn tick(&mut self, cycles: usize) {
+        let Some(link) = &mut self.link else {
+            return;
+        };
+        if self.cycles_left > 0 {
+            self.cycles_left = self.cycles_left.saturating_sub(cycles);
+            if self.cycles_left == 0 {
+                let received = link.exchange(self.sb);
+                self.transfer(received);
+            }
+        } else if let Some(received) = link.poll() {
+            // Only delivered while waiting on the external clock
+            self.transfer(received);
+        }
+    }
+
+    /// Tells the link whether a transfer on the external clock
+    /// is waiting.
+    fn update_external(&mut self) {
+        let sb = (self.sc == 0x80).then_some(self.sb);
+        if let Some(link) = &mut self.link {
+            link.set_external(sb);
+        }
+    }
+
+    /// Sends all further output to 'output' rather
+    /// than buffering it.
+    pub fn set_output(&mut self, output: Box<dyn SerialOutput>) {
+        self.output = Some(output);
+    }
+
+    /// Returns and clears everything buffered since
+    /// the last call.
+    pub fn take_output(&mut self) -> String {
+        String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).into_owned()
+    }
+
+    /// Returns and clears the serial interrupt request.
+    pub fn get_clr_intreq(&mut self) -> bool {
+        std::mem::take(&mut self.intreq)
+    }
+
+    /// Completes a transfer, shifting in 'received'.
+    fn transfer(&mut self, received: u8) {
+        match &mut self.output {
+            Some(out) => out.send(self.sb),
+            None => self.buffer.push(self.sb),
+        }
+        self.sb = received;
+        self.sc &= !0x80;
+        self.intreq = true;
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Serial {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // SB - Serial transfer data
+            0xFF01 => self.sb,
+
+            // SC - Serial transfer control
+            // Bits 1-6 are unused and read as 1.
+            0xFF02 => 0x7E | self.sc,
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // SB - Serial transfer data
+            0xFF01 => {
+                self.sb = val;
+                self.update_external();
+            }
+
+            // SC - Serial transfer control
+            0xFF02 => {
+                self.sc = val & 0x81;
+                self.cycles_left = 0;
+                if self.sc == 0x81 {
+                    if self.link.is_some() {
+                        self.cycles_left = BYTE_CYCLES;
+                    } else {
+                        self.transfer(0xFF);
+                    }
+                }
+                self.update_external();
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"SER ");
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u16(self.cycles_left as u16);
+        w.bool(self.intreq);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"SER ", "serial")?;
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.cycles_left = r.u16()? as usize;
+        self.intreq = r.bool()?;
+        self.update_external();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::super::link::LinkCable;
+    use super::*;
+
+    fn send(s: &mut Serial, data: &str) {
+        for c in data.bytes() {
+            s.write(0xFF01, c);
+            s.write(0xFF02, 0x81);
+        }
+    }
+
+    #[test]
+    fn buffered() {
+        let mut s = Serial::new();
+        assert_eq!(s.take_output(), "");
+
+        send(&mut s, "Passed");
+        assert_eq!(s.read(0xFF01), 0xFF);
+        assert_eq!(s.read(0xFF02), 0x7F);
+        assert!(s.get_clr_intreq());
+        assert!(!s.get_clr_intreq());
+        assert_eq!(s.take_output(), "Passed");
+        assert_eq!(s.take_output(), "");
+    }
+
+    #[test]
+    fn external_clock() {
+        let mut s = Serial::new();
+        s.write(0xFF01, b'X');
+        s.write(0xFF02, 0x80);
+        assert_eq!(s.read(0xFF01), b'X');
+        assert_eq!(s.read(0xFF02), 0xFE);
+        assert!(!s.get_clr_intreq());
+        assert_eq!(s.take_output(), "");
+    }
+
+    #[test]
+    fn link() {
+        let (a_link, b_link) = LinkCable::pair();
+        let mut a = Serial::new();
+        let mut b = Serial::new();
+        a.set_link(Box::new(a_link));
+        b.set_link(Box::new(b_link));
+
+        // B waits for A's clock
+        b.write(0xFF01, 0x42);
+        b.write(0xFF02, 0x80);
+        a.write(0xFF01, 0x11);
+        a.write(0xFF02, 0x81);
+        a.tick(BYTE_CYCLES - 1);
+        b.tick(BYTE_CYCLES - 1);
+        assert_eq!(a.read(0xFF02), 0xFF);
+        assert!(!a.get_clr_intreq());
+
+        a.tick(1);
+        assert_eq!(a.read(0xFF01), 0x42);
+        assert_eq!(a.read(0xFF02), 0x7F);
+        assert!(a.get_clr_intreq());
+        assert_eq!(b.read(0xFF01), 0x42);
+        b.tick(4);
+        assert_eq!(b.read(0xFF01), 0x11);
+        assert_eq!(b.read(0xFF02), 0x7E);
+        assert!(b.get_clr_intreq());
+        assert_eq!(a.take_output(), "\x11");
+        assert_eq!(b.take_output(), "B");
+
+        // B no longer waits
+        a.write(0xFF02, 0x81);
+        a.tick(BYTE_CYCLES);
+        assert_eq!(a.read(0xFF01), 0xFF);
+        b.tick(4);
+        assert!(!b.get_clr_intreq());
+    }
+
+    #[test]
+    fn output_sink() {
+        let mut s = Serial::new();
+        let out = Rc::new(RefCell::new(vec![]));
+        let o = out.clone();
+        s.set_output(Box::new(move |b| o.borrow_mut().push(b)));
+
+        send(&mut s, "Failed");
+        assert_eq!(*out.borrow(), b"Failed");
+        assert_eq!(s.take_output(), "");
+    }
+}
@@ -0,0 +1,246 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use super::bus::bus::Bus;
+use super::bus::gbbus::Gameboybus;
+use super::bus::hookbus::HookBus;
+use super::cartridge::header::CartridgeHeader;
+use super::cpu::cpu::CPU;
+use super::cpu::regs::RegisterFile;
+use super::emulator::Emulator;
+use super::model::Model;
+use super::ppu::CYCLES_PER_FRAME;
+
+/// LD B,B - used by Mooneye tests to signal completion
+const OP_LD_B_B: u8 = 0x40;
+
+/// Registers B, C, D, E, H, L of a passed Mooneye test
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+/// Registers B, C, D, E, H, L of a failed Mooneye test
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+/// Outcome of a test ROM run
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TestResult {
+    /// The ROM reported success
+    Passed,
+
+    /// The ROM reported failure
+    Failed,
+
+    /// The cycle limit was reached without a result. This is
+    /// the normal outcome of screenshot-based tests (e.g.
+    /// dmg-acid2), compare frame_hash() afterwards.
+    Timeout,
+}
+
+/// Runs test ROMs without a frontend.
+///
+/// Results are detected from the serial output (Blargg:
+/// "Passed"/"Failed") and from the LD B,B breakpoint with
+/// the Fibonacci (or 0x42) register signature (Mooneye).
+pub struct TestRunner {
+    emu: Emulator,
+
+    /// Everything sent over the serial port
+    serial: Rc<RefCell<String>>,
+}
+
+impl TestRunner {
+    /// Loads a ROM on the model its header asks for, starting
+    /// in the post-boot state.
+    pub fn from_rom(rom: &[u8]) -> Result<Self> {
+        let model = Model::detect(&CartridgeHeader::from_rom(rom)?);
+        Self::from_rom_model(rom, model)
+    }
+
+    /// Loads a ROM on a specific model, starting in the
+    /// post-boot state.
+    pub fn from_rom_model(rom: &[u8], model: Model) -> Result<Self> {
+        Ok(Self::new(Emulator::with_model(rom, None, model)?))
+    }
+
+    /// Takes over an emulator, capturing its serial output.
+    pub fn new(mut emu: Emulator) -> Self {
+        let serial = Rc::new(RefCell::new(String::new()));
+        let sink = Rc::clone(&serial);
+        emu.bus()
+            .set_serial_output(Box::new(move |b| sink.borrow_mut().push(b as char)));
+        Self { emu, serial }
+    }
+
+    pub fn emulator(&mut self) -> &mut Emulator {
+        &mut self.emu
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU<HookBus<Gameboybus>> {
+        self.emu.cpu()
+    }
+
+    /// Runs for (up to) a number of frames worth of cycles.
+    pub fn run_frames(&mut self, frames: usize) -> Result<TestResult> {
+        self.run_cycles(frames * CYCLES_PER_FRAME)
+    }
+
+    /// Runs until the ROM reports a result or a number of
+    /// cycles has passed. Frames are completed as they are
+    /// drawn (see Emulator::end_frame()).
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<TestResult> {
+        let end = self.emu.cpu().get_cycles() + cycles;
+        let mut serial_len = self.serial.borrow().len();
+
+        while self.emu.cpu().get_cycles() < end {
+            let cpu = self.emu.cpu();
+            if cpu.bus.peek(cpu.regs.pc) == OP_LD_B_B {
+                if let Some(result) = Self::mooneye_result(&cpu.regs) {
+                    return Ok(result);
+                }
+            }
+
+            cpu.step()?;
+            if self.emu.bus().frame_ready() {
+                self.emu.end_frame()?;
+            }
+
+            let serial = self.serial.borrow();
+            if serial.len() != serial_len {
+                serial_len = serial.len();
+                if serial.contains("Passed") {
+                    return Ok(TestResult::Passed);
+                }
+                if serial.contains("Failed") {
+                    return Ok(TestResult::Failed);
+                }
+            }
+        }
+        Ok(TestResult::Timeout)
+    }
+
+    fn mooneye_result(regs: &RegisterFile) -> Option<TestResult> {
+        let signature = [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l];
+        match signature {
+            MOONEYE_PASS => Some(TestResult::Passed),
+            MOONEYE_FAIL => Some(TestResult::Failed),
+            _ => None,
+        }
+    }
+
+    /// Everything sent over the serial port so far.
+    pub fn serial_output(&self) -> String {
+        self.serial.borrow().clone()
+    }
+
+    /// FNV-1a hash of the current framebuffer, to compare
+    /// against a known-good screen.
+    pub fn frame_hash(&self) -> u64 {
+        self.emu.frame_hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a ROM-only cartridge running 'code' at 0x0100.
+    fn rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    #[test]
+    fn mooneye_pass() {
+        let mut t = TestRunner::from_rom(&rom(&[
+            0x06, 3, // LD B,3
+            0x0E, 5, // LD C,5
+            0x16, 8, // LD D,8
+            0x1E, 13, // LD E,13
+            0x26, 21, // LD H,21
+            0x2E, 34,   // LD L,34
+            0x40, // LD B,B
+            0x18, 0xFE, // JR $-2
+        ]))
+        .unwrap();
+        assert_eq!(t.run_frames(1).unwrap(), TestResult::Passed);
+        assert_eq!(t.cpu().regs.pc, 0x010C);
+    }
+
+    #[test]
+    fn mooneye_fail() {
+        let mut t = TestRunner::from_rom(&rom(&[
+            0x3E, 0x42, // LD A,$42
+            0x47, // LD B,A
+            0x4F, // LD C,A
+            0x57, // LD D,A
+            0x5F, // LD E,A
+            0x67, // LD H,A
+            0x6F, // LD L,A
+            0x40, // LD B,B
+            0x18, 0xFE, // JR $-2
+        ]))
+        .unwrap();
+        assert_eq!(t.run_frames(1).unwrap(), TestResult::Failed);
+    }
+
+    #[test]
+    fn serial_result() {
+        // Prints "Passed" over serial, then loops
+        let mut cart = rom(&[
+            0x21, 0x00, 0x02, // LD HL,$0200
+            0x2A, // LD A,(HL+)
+            0xB7, // OR A
+            0x28, 0x08, // JR Z,$+8
+            0xE0, 0x01, // LDH ($01),A
+            0x3E, 0x81, // LD A,$81
+            0xE0, 0x02, // LDH ($02),A
+            0x18, 0xF3, // JR $-13
+            0x18, 0xFE, // JR $-2
+        ]);
+        cart[0x200..0x20B].copy_from_slice(b"Test\nPassed");
+        let mut t = TestRunner::from_rom(&cart).unwrap();
+        assert_eq!(t.run_frames(1).unwrap(), TestResult::Passed);
+        assert_eq!(t.serial_output(), "Test\nPassed");
+
+        cart[0x205..0x20B].copy_from_slice(b"Failed");
+        let mut t = TestRunner::from_rom(&cart).unwrap();
+        assert_eq!(t.run_frames(1).unwrap(), TestResult::Failed);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut t = TestRunner::from_rom(&rom(&[0x18, 0xFE])).unwrap(); // JR $-2
+        assert_eq!(t.run_frames(2).unwrap(), TestResult::Timeout);
+        assert!(t.cpu().get_cycles() >= 2 * CYCLES_PER_FRAME);
+        assert_eq!(t.serial_output(), "");
+    }
+
+    #[test]
+    fn gameshark() {
+        let mut t = TestRunner::from_rom(&rom(&[0x18, 0xFE])).unwrap(); // JR $-2
+        t.emulator().add_cheat("010238CD").unwrap();
+        assert_eq!(t.run_frames(2).unwrap(), TestResult::Timeout);
+        assert_eq!(t.cpu().bus.read(0xCD38), 0x02);
+    }
+
+    #[test]
+    fn frame_hash() {
+        let run = |code: &[u8]| {
+            let mut t = TestRunner::from_rom(&rom(code)).unwrap();
+            assert_eq!(t.run_frames(3).unwrap(), TestResult::Timeout);
+            t.frame_hash()
+        };
+        let blank = run(&[0x18, 0xFE]); // JR $-2
+        assert_eq!(blank, run(&[0x00, 0x18, 0xFD])); // NOP, JR $-3
+
+        // BGP = $FF (black) differs from the post-boot palette
+        let black = run(&[
+            0x3E, 0xFF, // LD A,$FF
+            0xE0, 0x47, // LDH ($47),A
+            0x18, 0xFE, // JR $-2
+        ]);
+        assert_ne!(blank, black);
+    }
+}
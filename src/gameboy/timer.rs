@@ -0,0 +1,278 @@
+use anyhow::Result;
+
+use super::bus::bus::Bus;
+use super::savestate::{StateReader, StateWriter};
+
+/// TAC bit 2 - Timer enable
+const TAC_ENABLE: u8 = 1 << 2;
+
+/// T-cycles between overflow of TIMA and the reload from TMA
+const RELOAD_DELAY: usize = 4;
+
+/// Programmable timer (DIV, TIMA, TMA, TAC)
+///
+/// DIV is the upper byte of a 16-bit counter that increments
+/// every T-cycle. TIMA increments on the falling edge of the
+/// counter bit selected by TAC.
+pub struct Timer {
+    /// Internal 16-bit divider, DIV is the upper byte
+    div: u16,
+
+    /// TIMA - Timer counter
+    tima: u8,
+
+    /// TMA - Timer modulo
+    tma: u8,
+
+    /// TAC - Timer control (bits 0-2)
+    tac: u8,
+
+    /// T-cycles left until TIMA is reloaded after an overflow
+    reload: Option<usize>,
+
+    intreq: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            div: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload: None,
+            intreq: false,
+        }
+    }
+
+    /// Returns and clears the timer interrupt request.
+    pub fn get_clr_intreq(&mut self) -> bool {
+        std::mem::take(&mut self.intreq)
+    }
+
+    /// Sets the internal 16-bit divider, without
+    /// edge detection.
+    pub fn set_divider(&mut self, div: u16) {
+        self.div = div;
+    }
+
+    /// Divider bit whose falling edge increments TIMA
+    fn tac_bit(&self) -> u16 {
+        match self.tac & 0x03 {
+            0 => 1 << 9, // 4096 Hz
+            1 => 1 << 3, // 262144 Hz
+            2 => 1 << 5, // 65536 Hz
+            3 => 1 << 7, // 16384 Hz
+            _ => unreachable!(),
+        }
+    }
+
+    /// Current input of the TIMA edge detector
+    fn tima_input(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.div & self.tac_bit() != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            // TIMA reads 0 until the reload happens
+            self.reload = Some(RELOAD_DELAY);
+        }
+    }
+
+    /// Updates the divider, incrementing TIMA on a falling edge.
+    fn set_div(&mut self, div: u16) {
+        let before = self.tima_input();
+        self.div = div;
+        if before && !self.tima_input() {
+            self.increment_tima();
+        }
+    }
+
+    /// Advances the timer by a number of T-cycles.
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            if let Some(left) = self.reload {
+                if left == 1 {
+                    self.reload = None;
+                    self.tima = self.tma;
+                    self.intreq = true;
+                } else {
+                    self.reload = Some(left - 1);
+                }
+            }
+
+            self.set_div(self.div.wrapping_add(1));
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Timer {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // DIV - Divider
+            0xFF04 => (self.div >> 8) as u8,
+
+            // TIMA - Timer counter
+            0xFF05 => self.tima,
+
+            // TMA - Timer modulo
+            0xFF06 => self.tma,
+
+            // TAC - Timer control
+            // Bits 3-7 are unused and read as 1.
+            0xFF07 => 0xF8 | self.tac,
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // DIV - Divider
+            // Any write resets the whole counter, which may
+            // cause a falling edge on the TIMA input.
+            0xFF04 => self.set_div(0),
+
+            // TIMA - Timer counter
+            // Writing during the reload delay cancels the reload.
+            0xFF05 => {
+                self.tima = val;
+                self.reload = None;
+            }
+
+            // TMA - Timer modulo
+            0xFF06 => self.tma = val,
+
+            // TAC - Timer control
+            // Changing the selection or disabling the timer
+            // may cause a falling edge on the TIMA input.
+            0xFF07 => {
+                let before = self.tima_input();
+                self.tac = val & 0x07;
+                if before && !self.tima_input() {
+                    self.increment_tima();
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) -> Result<()> {
+        w.tag(b"TIMR");
+        w.u16(self.div);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+        // 0 = no reload pending
+        w.u8(self.reload.unwrap_or(0) as u8);
+        w.bool(self.intreq);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        r.tag(b"TIMR", "timer")?;
+        self.div = r.u16()?;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()?;
+        self.reload = match r.u8()? {
+            0 => None,
+            n => Some(n.into()),
+        };
+        self.intreq = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div() {
+        let mut t = Timer::new();
+        t.tick(255);
+        assert_eq!(t.read(0xFF04), 0);
+        t.tick(1);
+        assert_eq!(t.read(0xFF04), 1);
+        t.tick(256 * 10);
+        assert_eq!(t.read(0xFF04), 11);
+
+        t.write(0xFF04, 0xAB);
+        assert_eq!(t.read(0xFF04), 0);
+    }
+
+    #[test]
+    fn tima_rates() {
+        for (tac, period) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let mut t = Timer::new();
+            t.write(0xFF07, tac);
+            t.tick(period - 1);
+            assert_eq!(t.read(0xFF05), 0, "TAC {:02X}", tac);
+            t.tick(1);
+            assert_eq!(t.read(0xFF05), 1, "TAC {:02X}", tac);
+            t.tick(period * 9);
+            assert_eq!(t.read(0xFF05), 10, "TAC {:02X}", tac);
+        }
+    }
+
+    #[test]
+    fn tima_disabled() {
+        let mut t = Timer::new();
+        t.write(0xFF07, 0x01);
+        t.tick(1024);
+        assert_eq!(t.read(0xFF05), 0);
+        assert_eq!(t.read(0xFF07), 0xF9);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut t = Timer::new();
+        t.write(0xFF06, 0x42);
+        t.write(0xFF05, 0xFF);
+        t.write(0xFF07, 0x05);
+
+        t.tick(16);
+        // Reload is delayed by one M-cycle
+        assert_eq!(t.read(0xFF05), 0);
+        assert!(!t.get_clr_intreq());
+        t.tick(RELOAD_DELAY);
+        assert_eq!(t.read(0xFF05), 0x42);
+        assert!(t.get_clr_intreq());
+        assert!(!t.get_clr_intreq());
+    }
+
+    #[test]
+    fn overflow_cancel() {
+        let mut t = Timer::new();
+        t.write(0xFF06, 0x42);
+        t.write(0xFF05, 0xFF);
+        t.write(0xFF07, 0x05);
+
+        t.tick(16);
+        t.write(0xFF05, 0x10);
+        t.tick(RELOAD_DELAY);
+        assert_eq!(t.read(0xFF05), 0x10);
+        assert!(!t.get_clr_intreq());
+    }
+
+    #[test]
+    fn div_reset_edge() {
+        let mut t = Timer::new();
+        t.write(0xFF07, 0x05);
+        t.tick(8);
+        assert_eq!(t.read(0xFF05), 0);
+        // Bit 3 is set, resetting DIV produces a falling edge
+        t.write(0xFF04, 0);
+        assert_eq!(t.read(0xFF05), 1);
+    }
+}
@@ -0,0 +1,243 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use super::ppu::{Color, Frame, LCD_HEIGHT, LCD_WIDTH, SHADES};
+
+/// Colors for the 4 DMG shades (lightest to darkest)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Palette(pub [Color; 4]);
+
+impl Palette {
+    /// Neutral grey, as the PPU outputs
+    pub const GREY: Self = Self(SHADES);
+
+    /// Green tint of the original Game Boy LCD
+    pub const GREEN: Self = Self([0x9BBC0FFF, 0x8BAC0FFF, 0x306230FF, 0x0F380FFF]);
+
+    /// Game Boy Pocket
+    pub const POCKET: Self = Self([0xC4CFA1FF, 0x8B956DFF, 0x4D533CFF, 0x1F1F1FFF]);
+}
+
+impl FromStr for Palette {
+    type Err = Error;
+
+    /// Parses a preset name (grey, green, pocket) or 4
+    /// comma-separated RGB colors, e.g. "e0f8d0,88c070,346856,081820".
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "grey" | "gray" => return Ok(Self::GREY),
+            "green" => return Ok(Self::GREEN),
+            "pocket" => return Ok(Self::POCKET),
+            _ => (),
+        }
+
+        let colors: Vec<&str> = s.split(',').map(|c| c.trim()).collect();
+        if colors.len() != 4 {
+            bail!(
+                "Unknown palette '{}' (expected grey, green, pocket or 4 RGB colors)",
+                s
+            );
+        }
+        let mut palette = [0; 4];
+        for (out, c) in palette.iter_mut().zip(colors) {
+            let hex = c.trim_start_matches('#');
+            if hex.len() != 6 {
+                bail!("Invalid color '{}' (expected RRGGBB)", c);
+            }
+            *out = u32::from_str_radix(hex, 16)? << 8 | 0xFF;
+        }
+        Ok(Self(palette))
+    }
+}
+
+/// Scales a color channel by 'factor' (0.0 - 1.0).
+fn scale_rgb(c: Color, factor: f32) -> Color {
+    let [r, g, b, a] = c.to_be_bytes();
+    let s = |v: u8| (v as f32 * factor) as u8;
+    u32::from_be_bytes([s(r), s(g), s(b), a])
+}
+
+/// Blends 'a' into 'b' by 'amount' (0.0 - 1.0).
+fn blend(a: Color, b: Color, amount: f32) -> Color {
+    let (a, b) = (a.to_be_bytes(), b.to_be_bytes());
+    let mix = |i: usize| (a[i] as f32 * amount + b[i] as f32 * (1.0 - amount)) as u8;
+    u32::from_be_bytes([mix(0), mix(1), mix(2), b[3]])
+}
+
+/// Post-processing of frames between the PPU and a frontend
+///
+/// In order: DMG palette, LCD ghosting (blending in the
+/// previous output) and integer scaling with scanlines.
+pub struct PostProcess {
+    /// Colors of the DMG shades, not applied in CGB mode
+    palette: Palette,
+
+    /// Weight of the previous frame (0.0 = off)
+    ghosting: f32,
+
+    /// Output pixels per LCD pixel in each direction
+    scale: usize,
+
+    /// Darkening of the last row of every scaled LCD line
+    /// (0.0 = off)
+    scanlines: f32,
+
+    /// Last frame after palette and ghosting, unscaled
+    prev: Vec<Color>,
+
+    out: Vec<Color>,
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        Self {
+            palette: Palette::GREY,
+            ghosting: 0.0,
+            scale: 1,
+            scanlines: 0.0,
+            prev: vec![],
+            out: vec![],
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Blends this much of the previous frame into each
+    /// frame (0.0 - 1.0), like the slow pixels of a DMG LCD.
+    pub fn set_ghosting(&mut self, amount: f32) {
+        self.ghosting = amount.clamp(0.0, 1.0);
+        self.prev.clear();
+    }
+
+    /// Scales the output by an integer factor (at least 1).
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale.max(1);
+    }
+
+    /// Darkens the last output row of every LCD line by this
+    /// much (0.0 - 1.0). Needs a scale of 2 or more.
+    pub fn set_scanlines(&mut self, amount: f32) {
+        self.scanlines = amount.clamp(0.0, 1.0);
+    }
+
+    /// Width and height of the output.
+    pub fn output_size(&self) -> (usize, usize) {
+        (LCD_WIDTH * self.scale, LCD_HEIGHT * self.scale)
+    }
+
+    /// Processes a frame; 'cgb' leaves colors alone. Returns
+    /// output_size() RGBA pixels, row by row.
+    pub fn process(&mut self, frame: &Frame, cgb: bool) -> &[Color] {
+        let mut pixels: Vec<Color> = if cgb || self.palette == Palette::GREY {
+            frame.to_vec()
+        } else {
+            frame
+                .iter()
+                .map(|&px| match SHADES.iter().position(|&s| s == px) {
+                    Some(i) => self.palette.0[i],
+                    None => px,
+                })
+                .collect()
+        };
+
+        if self.ghosting > 0.0 && self.prev.len() == pixels.len() {
+            for (px, &prev) in pixels.iter_mut().zip(&self.prev) {
+                *px = blend(prev, *px, self.ghosting);
+            }
+        }
+
+        let (width, height) = self.output_size();
+        self.out.resize(width * height, 0);
+        for (y, row) in self.out.chunks_mut(width).enumerate() {
+            let src = &pixels[y / self.scale * LCD_WIDTH..][..LCD_WIDTH];
+            let dim = self.scale > 1 && self.scanlines > 0.0 && y % self.scale == self.scale - 1;
+            for (x, px) in row.iter_mut().enumerate() {
+                let c = src[x / self.scale];
+                *px = if dim {
+                    scale_rgb(c, 1.0 - self.scanlines)
+                } else {
+                    c
+                };
+            }
+        }
+
+        self.prev = pixels;
+        &self.out
+    }
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(color: Color) -> Box<Frame> {
+        Box::new([color; LCD_WIDTH * LCD_HEIGHT])
+    }
+
+    #[test]
+    fn palette_from_str() {
+        assert_eq!("Green".parse::<Palette>().unwrap(), Palette::GREEN);
+        assert_eq!(
+            "e0f8d0, 88c070,#346856,081820".parse::<Palette>().unwrap(),
+            Palette([0xE0F8D0FF, 0x88C070FF, 0x346856FF, 0x081820FF])
+        );
+        for s in ["blue", "e0f8d0,88c070,346856", "e0f8d0,88c070,346856,08182"] {
+            assert!(s.parse::<Palette>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn identity() {
+        let mut pp = PostProcess::new();
+        let f = frame(SHADES[2]);
+        assert_eq!(pp.process(&f, false), &f[..]);
+    }
+
+    #[test]
+    fn palette() {
+        let mut pp = PostProcess::new();
+        pp.set_palette(Palette::GREEN);
+        assert_eq!(pp.process(&frame(SHADES[3]), false)[0], Palette::GREEN.0[3]);
+        // CGB colors are left alone
+        assert_eq!(pp.process(&frame(SHADES[3]), true)[0], SHADES[3]);
+    }
+
+    #[test]
+    fn ghosting() {
+        let mut pp = PostProcess::new();
+        pp.set_ghosting(0.5);
+        assert_eq!(pp.process(&frame(0xFFFFFFFF), false)[0], 0xFFFFFFFF);
+        assert_eq!(pp.process(&frame(0x000000FF), false)[0], 0x7F7F7FFF);
+        assert_eq!(pp.process(&frame(0x000000FF), false)[0], 0x3F3F3FFF);
+    }
+
+    #[test]
+    fn scale_scanlines() {
+        let mut pp = PostProcess::new();
+        pp.set_scale(3);
+        pp.set_scanlines(0.5);
+        assert_eq!(pp.output_size(), (LCD_WIDTH * 3, LCD_HEIGHT * 3));
+
+        let mut f = frame(0xFFFFFFFF);
+        f[1] = 0x000000FF;
+        let out = pp.process(&f, false).to_vec();
+        let w = LCD_WIDTH * 3;
+        assert_eq!(out.len(), w * LCD_HEIGHT * 3);
+        assert_eq!(
+            out[..6],
+            [0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0x000000FF, 0x000000FF, 0x000000FF]
+        );
+        assert_eq!(out[w], 0xFFFFFFFF);
+        assert_eq!(out[2 * w], 0x7F7F7FFF);
+        assert_eq!(out[3 * w], 0xFFFFFFFF);
+    }
+}
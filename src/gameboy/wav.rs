@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::apu::CHANNELS;
+
+/// Size of the RIFF/WAVE header
+const HEADER_SIZE: u32 = 44;
+
+/// 16-bit PCM WAV writer for interleaved stereo samples
+///
+/// The sizes in the header are filled in by finish().
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+
+    /// Bytes of sample data written
+    data_len: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: usize) -> Result<Self> {
+        let file = File::create(path).context(format!("Creating {}", path.display()))?;
+        Self::new(BufWriter::new(file), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: usize) -> Result<Self> {
+        let channels: u16 = 2;
+        let block_align = channels * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&(sample_rate as u32).to_le_bytes())?;
+        out.write_all(&(sample_rate as u32 * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        // Bits per sample
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { out, data_len: 0 })
+    }
+
+    /// Appends interleaved stereo samples between -1.0 and 1.0.
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        for &s in samples {
+            let s = (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&s.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    /// Completes the header and returns the output.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(HEADER_SIZE - 8 + self.data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.out.write_all(&self.data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Records audio output to WAV files: the mixed output and
+/// optionally each channel to a file of its own, named after
+/// the mixed one ("out.wav" -> "out-ch1.wav").
+///
+/// Channel files need channel capture enabled on the APU
+/// (Apu::set_capture_channels()).
+pub struct AudioRecorder {
+    mixed: WavWriter<BufWriter<File>>,
+    channels: Option<Vec<WavWriter<BufWriter<File>>>>,
+}
+
+/// Path of the file for a single channel (0-3).
+fn channel_path(path: &Path, channel: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-ch{}.wav", stem, channel + 1))
+}
+
+impl AudioRecorder {
+    pub fn create(path: &Path, sample_rate: usize, per_channel: bool) -> Result<Self> {
+        let channels = per_channel
+            .then(|| {
+                (0..CHANNELS)
+                    .map(|ch| WavWriter::create(&channel_path(path, ch), sample_rate))
+                    .collect::<Result<_>>()
+            })
+            .transpose()?;
+        Ok(Self {
+            mixed: WavWriter::create(path, sample_rate)?,
+            channels,
+        })
+    }
+
+    /// Appends mixed samples and, if recording them, the
+    /// samples of each channel.
+    pub fn write(&mut self, mixed: &[f32], channels: &[Vec<f32>; CHANNELS]) -> Result<()> {
+        self.mixed.write(mixed)?;
+        if let Some(writers) = &mut self.channels {
+            for (w, samples) in writers.iter_mut().zip(channels) {
+                w.write(samples)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes all files.
+    pub fn finish(self) -> Result<()> {
+        self.mixed.finish()?;
+        for w in self.channels.into_iter().flatten() {
+            w.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn wav() {
+        let mut w = WavWriter::new(Cursor::new(vec![]), 32768).unwrap();
+        w.write(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        let out = w.finish().unwrap().into_inner();
+
+        assert_eq!(out.len(), HEADER_SIZE as usize + 8);
+        assert_eq!(out[..4], *b"RIFF");
+        assert_eq!(out[4..8], (HEADER_SIZE - 8 + 8).to_le_bytes());
+        assert_eq!(out[8..16], *b"WAVEfmt ");
+        // Stereo at 32768 Hz, 4 bytes per frame
+        assert_eq!(out[22..24], 2u16.to_le_bytes());
+        assert_eq!(out[24..28], 32768u32.to_le_bytes());
+        assert_eq!(out[28..32], (32768u32 * 4).to_le_bytes());
+        assert_eq!(out[36..40], *b"data");
+        assert_eq!(out[40..44], 8u32.to_le_bytes());
+        assert_eq!(out[44..], [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn channel_paths() {
+        assert_eq!(
+            channel_path(Path::new("dir/out.wav"), 0),
+            Path::new("dir/out-ch1.wav")
+        );
+        assert_eq!(channel_path(Path::new("out"), 3), Path::new("out-ch4.wav"));
+    }
+}
@@ -0,0 +1,387 @@
+//! Runs the SM83 single-instruction JSON test vectors
+//! (one file per opcode, each an array of tests with an
+//! initial state, final state and per M-cycle bus activity).
+//!
+//! Point SM83_TESTS at the directory with the .json files
+//! to run the full suite; an embedded sample always runs.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use gbrust::gameboy::bus::bus::Bus;
+use gbrust::gameboy::bus::testbus::Testbus;
+use gbrust::gameboy::cpu::cpu::CPU;
+use gbrust::gameboy::cpu::regs::{Register, RegisterFile};
+
+/// Minimal JSON value, enough for the test vectors
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn parse(s: &str) -> Result<Json> {
+        let mut p = Parser {
+            s: s.as_bytes(),
+            pos: 0,
+        };
+        let v = p.value()?;
+        p.skip_ws();
+        if p.pos != p.s.len() {
+            bail!("Trailing data at {}", p.pos);
+        }
+        Ok(v)
+    }
+
+    fn get(&self, key: &str) -> Result<&Json> {
+        match self {
+            Json::Obj(m) => m.get(key).ok_or_else(|| anyhow!("Missing key '{}'", key)),
+            _ => bail!("Not an object looking up '{}'", key),
+        }
+    }
+
+    fn arr(&self) -> Result<&[Json]> {
+        match self {
+            Json::Arr(a) => Ok(a),
+            _ => bail!("Not an array: {:?}", self),
+        }
+    }
+
+    fn num(&self) -> Result<usize> {
+        match self {
+            Json::Num(n) => Ok(*n as usize),
+            _ => bail!("Not a number: {:?}", self),
+        }
+    }
+
+    fn field(&self, key: &str) -> Result<usize> {
+        self.get(key)?.num()
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Result<u8> {
+        self.skip_ws();
+        self.s
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| anyhow!("Unexpected end of JSON"))
+    }
+
+    fn expect(&mut self, c: u8) -> Result<()> {
+        if self.peek()? != c {
+            bail!("Expected '{}' at {}", c as char, self.pos);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, lit: &str, v: Json) -> Result<Json> {
+        if !self.s[self.pos..].starts_with(lit.as_bytes()) {
+            bail!("Invalid literal at {}", self.pos);
+        }
+        self.pos += lit.len();
+        Ok(v)
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        match self.peek()? {
+            b'{' => {
+                self.pos += 1;
+                let mut m = BTreeMap::new();
+                if self.peek()? == b'}' {
+                    self.pos += 1;
+                    return Ok(Json::Obj(m));
+                }
+                loop {
+                    let Json::Str(k) = self.value()? else {
+                        bail!("Expected key at {}", self.pos);
+                    };
+                    self.expect(b':')?;
+                    m.insert(k, self.value()?);
+                    match self.peek()? {
+                        b',' => self.pos += 1,
+                        b'}' => {
+                            self.pos += 1;
+                            return Ok(Json::Obj(m));
+                        }
+                        _ => bail!("Expected ',' or '}}' at {}", self.pos),
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut a = vec![];
+                if self.peek()? == b']' {
+                    self.pos += 1;
+                    return Ok(Json::Arr(a));
+                }
+                loop {
+                    a.push(self.value()?);
+                    match self.peek()? {
+                        b',' => self.pos += 1,
+                        b']' => {
+                            self.pos += 1;
+                            return Ok(Json::Arr(a));
+                        }
+                        _ => bail!("Expected ',' or ']' at {}", self.pos),
+                    }
+                }
+            }
+            b'"' => {
+                self.pos += 1;
+                let start = self.pos;
+                while *self.s.get(self.pos).context("Unterminated string")? != b'"' {
+                    if self.s[self.pos] == b'\\' {
+                        bail!("Escapes are not supported (at {})", self.pos);
+                    }
+                    self.pos += 1;
+                }
+                self.pos += 1;
+                Ok(Json::Str(
+                    String::from_utf8_lossy(&self.s[start..self.pos - 1]).into_owned(),
+                ))
+            }
+            b't' => self.literal("true", Json::Bool(true)),
+            b'f' => self.literal("false", Json::Bool(false)),
+            b'n' => self.literal("null", Json::Null),
+            _ => {
+                let start = self.pos;
+                while self.pos < self.s.len()
+                    && matches!(
+                        self.s[self.pos],
+                        b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'
+                    )
+                {
+                    self.pos += 1;
+                }
+                let n = std::str::from_utf8(&self.s[start..self.pos])?;
+                Ok(Json::Num(n.parse().with_context(|| {
+                    format!("Invalid number '{}' at {}", n, start)
+                })?))
+            }
+        }
+    }
+}
+
+/// Registers in the order of the test vectors' state
+const REGS: [(&str, Register); 8] = [
+    ("a", Register::A),
+    ("b", Register::B),
+    ("c", Register::C),
+    ("d", Register::D),
+    ("e", Register::E),
+    ("f", Register::F),
+    ("h", Register::H),
+    ("l", Register::L),
+];
+
+/// Runs one test vector, returning a description of the
+/// first mismatch.
+///
+/// Only writes are compared against the cycle list: the
+/// CPU decodes whole instructions up front rather than
+/// fetching one byte per M-cycle, and reads IE/IF itself.
+fn run_test(test: &Json) -> Result<()> {
+    let initial = test.get("initial")?;
+    let expected = test.get("final")?;
+
+    let mut bus = Testbus::new();
+    for entry in initial.get("ram")?.arr()? {
+        let entry = entry.arr()?;
+        bus.write(entry[0].num()? as u16, entry[1].num()? as u8);
+    }
+    let log = bus.enable_log();
+
+    let mut cpu = CPU::new(Box::new(bus));
+    let mut regs = RegisterFile::new();
+    for (name, reg) in REGS {
+        regs.write(reg, initial.field(name)? as u16)?;
+    }
+    regs.pc = initial.field("pc")? as u16;
+    regs.sp = initial.field("sp")? as u16;
+    cpu.regs = regs;
+    cpu.set_ime(initial.field("ime")? != 0);
+    if let Ok(ie) = initial.field("ie") {
+        cpu.bus.write(0xFFFF, ie as u8);
+    }
+    log.borrow_mut().clear();
+
+    cpu.step()?;
+
+    for (name, reg) in REGS {
+        let (exp, got) = (expected.field(name)?, cpu.regs.read(reg) as usize);
+        if exp != got {
+            bail!("{}: expected {:02X}, got {:02X}", name, exp, got);
+        }
+    }
+    for (name, got) in [("pc", cpu.regs.pc), ("sp", cpu.regs.sp)] {
+        let exp = expected.field(name)?;
+        if exp != got as usize {
+            bail!("{}: expected {:04X}, got {:04X}", name, exp, got);
+        }
+    }
+    if let Ok(ime) = expected.field("ime") {
+        if (ime != 0) != cpu.ime() {
+            bail!("ime: expected {}, got {}", ime, cpu.ime());
+        }
+    }
+    for entry in expected.get("ram")?.arr()? {
+        let entry = entry.arr()?;
+        let (addr, exp) = (entry[0].num()? as u16, entry[1].num()? as u8);
+        let got = cpu.bus.read(addr);
+        if exp != got {
+            bail!("{:04X}: expected {:02X}, got {:02X}", addr, exp, got);
+        }
+    }
+
+    let cycles = test.get("cycles")?.arr()?;
+    if cpu.get_cycles() != cycles.len() * 4 {
+        bail!(
+            "cycles: expected {}, got {}",
+            cycles.len() * 4,
+            cpu.get_cycles()
+        );
+    }
+    let mut exp_writes = vec![];
+    for c in cycles {
+        let Json::Arr(c) = c else { continue };
+        if let Json::Str(pins) = &c[2] {
+            if pins.contains('w') {
+                exp_writes.push((c[0].num()? as u16, c[1].num()? as u8));
+            }
+        }
+    }
+    let writes: Vec<_> = log
+        .borrow()
+        .iter()
+        .filter(|a| a.write)
+        .map(|a| (a.addr, a.val))
+        .collect();
+    if exp_writes != writes {
+        bail!("writes: expected {:X?}, got {:X?}", exp_writes, writes);
+    }
+    Ok(())
+}
+
+/// Runs all tests in a file, returning the failures.
+fn run_file(json: &str) -> Result<Vec<String>> {
+    let mut failures = vec![];
+    for test in Json::parse(json)?.arr()? {
+        if let Err(e) = run_test(test) {
+            let name = match test.get("name")? {
+                Json::Str(s) => s.clone(),
+                _ => String::new(),
+            };
+            failures.push(format!("{}: {:#}", name, e));
+        }
+    }
+    Ok(failures)
+}
+
+/// LD (HL),A and PUSH BC in the test vector format
+const SAMPLE: &str = r#"[
+  {
+    "name": "77 0000",
+    "initial": {"a": 18, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0,
+                "pc": 256, "sp": 65534, "ime": 0, "ie": 0, "ram": [[256, 119]]},
+    "final": {"a": 18, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0,
+              "pc": 257, "sp": 65534, "ime": 0, "ram": [[256, 119], [49152, 18]]},
+    "cycles": [[256, 119, "r-m"], [49152, 18, "-wm"]]
+  },
+  {
+    "name": "c5 0000",
+    "initial": {"a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0,
+                "pc": 512, "sp": 53248, "ime": 1, "ie": 0, "ram": [[512, 197]]},
+    "final": {"a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0,
+              "pc": 513, "sp": 53246, "ime": 1, "ram": [[53247, 18], [53246, 52]]},
+    "cycles": [[512, 197, "r-m"], null, [53247, 18, "-wm"], [53246, 52, "-wm"]]
+  }
+]"#;
+
+#[test]
+fn json_parser() {
+    assert_eq!(
+        Json::parse(r#" {"a": [1, -2.5, null], "b": true, "c": "x"} "#).unwrap(),
+        Json::Obj(BTreeMap::from([
+            (
+                "a".to_string(),
+                Json::Arr(vec![Json::Num(1.0), Json::Num(-2.5), Json::Null])
+            ),
+            ("b".to_string(), Json::Bool(true)),
+            ("c".to_string(), Json::Str("x".to_string())),
+        ]))
+    );
+    assert!(Json::parse("[1,").is_err());
+    assert!(Json::parse("[1] 2").is_err());
+}
+
+#[test]
+fn sample() {
+    assert_eq!(run_file(SAMPLE).unwrap(), Vec::<String>::new());
+
+    // A wrong expectation is reported
+    let broken = SAMPLE.replace("[49152, 18, \"-wm\"]", "[49152, 19, \"-wm\"]");
+    let failures = run_file(&broken).unwrap();
+    assert_eq!(failures.len(), 1);
+    assert!(
+        failures[0].starts_with("77 0000: writes"),
+        "{}",
+        failures[0]
+    );
+}
+
+#[test]
+fn sm83_suite() {
+    let Ok(dir) = std::env::var("SM83_TESTS") else {
+        eprintln!("SM83_TESTS not set, skipping");
+        return;
+    };
+
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+
+    let mut failed = 0;
+    for path in &paths {
+        let json = fs::read_to_string(path).unwrap();
+        let failures = run_file(&json).unwrap();
+        if let Some(first) = failures.first() {
+            println!(
+                "{}: {} failed, first: {}",
+                path.display(),
+                failures.len(),
+                first
+            );
+            failed += 1;
+        }
+    }
+    assert_eq!(
+        failed,
+        0,
+        "{} of {} files had failures",
+        failed,
+        paths.len()
+    );
+}